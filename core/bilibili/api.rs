@@ -0,0 +1,51 @@
+use crate::error::Core;
+use reqwest::{Client, Response, StatusCode};
+use serde_json::Value;
+use std::future::Future;
+
+/// What the Bilibili API answered to one request.
+#[derive(Clone, Debug)]
+pub enum Answer {
+    /// HTTP 412, which Bilibili sends instead of data when requests come
+    /// too often.
+    Throttled,
+    Json(Value),
+}
+
+/// The requests made to Bilibili. The HTTP client sends them over the
+/// network; [`MockApi`](super::mock::MockApi) answers them from memory.
+pub trait BilibiliApi: Sync {
+    /// Sends a GET request to `url`.
+    fn get_json(&self, url: &str) -> impl Future<Output = Result<Answer, Core>> + Send;
+
+    /// Posts `form`, URL-encoded, to `url`.
+    fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, String)],
+    ) -> impl Future<Output = Result<Answer, Core>> + Send;
+
+    /// Where `url` redirects to, such as the video page of a b23.tv link.
+    fn final_url(&self, url: &str) -> impl Future<Output = Result<String, Core>> + Send;
+}
+
+impl BilibiliApi for Client {
+    async fn get_json(&self, url: &str) -> Result<Answer, Core> {
+        answer(self.get(url).send().await?).await
+    }
+
+    async fn post_form(&self, url: &str, form: &[(&str, String)]) -> Result<Answer, Core> {
+        answer(self.post(url).form(form).send().await?).await
+    }
+
+    async fn final_url(&self, url: &str) -> Result<String, Core> {
+        Ok(self.get(url).send().await?.url().to_string())
+    }
+}
+
+async fn answer(response: Response) -> Result<Answer, Core> {
+    if response.status() == StatusCode::PRECONDITION_FAILED {
+        return Ok(Answer::Throttled);
+    }
+    Ok(Answer::Json(response.json().await?))
+}
@@ -0,0 +1,113 @@
+use super::api::BilibiliApi;
+use super::limiter;
+use crate::error::Core;
+use crate::paths;
+use crate::settings::NetworkSettings;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static TTL: OnceLock<Duration> = OnceLock::new();
+static MEMORY: OnceLock<Mutex<HashMap<String, Entry>>> = OnceLock::new();
+
+/// An API answer and when it was fetched, in seconds since the epoch.
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    fetched_at: u64,
+    response: Value,
+}
+
+/// Applies the cache lifetime of the settings. Like the rate limit, only
+/// the first call counts.
+pub fn configure(network: &NetworkSettings) {
+    if TTL.set(Duration::from_secs(network.metadata_ttl)).is_err() {
+        log::warn!("The metadata cache lifetime was already set, ignoring the new one");
+    }
+}
+
+fn ttl() -> Duration {
+    *TTL.get_or_init(|| Duration::from_secs(NetworkSettings::default().metadata_ttl))
+}
+
+fn memory() -> MutexGuard<'static, HashMap<String, Entry>> {
+    MEMORY
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Like [`limiter::get`], but answers from the cache while the answer
+/// last stored under `key` is fresh, first in memory and then on disk.
+/// Only successful answers are cached.
+pub async fn get<T: DeserializeOwned>(
+    key: &str,
+    api: &impl BilibiliApi,
+    url: &str,
+) -> Result<T, Core> {
+    let ttl = ttl();
+    let cached = if ttl.is_zero() {
+        None
+    } else {
+        lookup(key, ttl)
+    };
+    let response = if let Some(response) = cached {
+        response
+    } else {
+        let response: Value = limiter::get(api, url).await?;
+        if !ttl.is_zero() && response["code"].as_i64() == Some(0) {
+            store(key, &response);
+        }
+        response
+    };
+    serde_json::from_value(response).map_err(|e| Core::Network(format!("Unexpected response: {e}")))
+}
+
+fn lookup(key: &str, ttl: Duration) -> Option<Value> {
+    let now = now();
+    let is_fresh = |entry: &Entry| now.saturating_sub(entry.fetched_at) < ttl.as_secs();
+    if let Some(entry) = memory().get(key).filter(|entry| is_fresh(entry)) {
+        return Some(entry.response.clone());
+    }
+    let entry: Entry = serde_json::from_slice(&std::fs::read(entry_file(key).ok()?).ok()?).ok()?;
+    if !is_fresh(&entry) {
+        return None;
+    }
+    let response = entry.response.clone();
+    memory().insert(key.to_string(), entry);
+    Some(response)
+}
+
+fn store(key: &str, response: &Value) {
+    let entry = Entry {
+        fetched_at: now(),
+        response: response.clone(),
+    };
+    if let Err(e) = write_entry(key, &entry) {
+        log::warn!("Failed to write {} to the metadata cache: {}", key, e);
+    }
+    memory().insert(key.to_string(), entry);
+}
+
+fn write_entry(key: &str, entry: &Entry) -> Result<(), Core> {
+    let file = entry_file(key)?;
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_vec(entry).map_err(|e| Core::Io(e.to_string()))?;
+    std::fs::write(file, json)?;
+    Ok(())
+}
+
+fn entry_file(key: &str) -> Result<PathBuf, Core> {
+    Ok(paths::metadata_cache_dir()?.join(format!("{key}.json")))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,217 @@
+use super::api::{Answer, BilibiliApi};
+use crate::error::Core;
+use crate::settings::{NetworkSettings, RetryPolicy};
+use serde::de::DeserializeOwned;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Code Bilibili answers with instead of data when its risk control
+/// suspects a bot.
+const RISK_CONTROL_CODE: i64 = -352;
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+/// Token bucket shared by every API request of the process, which also
+/// holds all of them back while Bilibili is throttling.
+struct Limiter {
+    qps: f64,
+    throttle: RetryPolicy,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    paused_until: Instant,
+}
+
+impl Limiter {
+    fn new(network: &NetworkSettings) -> Self {
+        let now = Instant::now();
+        Limiter {
+            qps: network.qps,
+            throttle: network.throttle.clone(),
+            bucket: Mutex::new(Bucket {
+                tokens: network.qps.max(1.0),
+                refilled: now,
+                paused_until: now,
+            }),
+        }
+    }
+
+    fn bucket(&self) -> MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes a token, returning how long to wait before using it. Tokens
+    /// may be owed, so callers queue up in the order they asked.
+    fn reserve(&self) -> Duration {
+        let mut bucket = self.bucket();
+        let now = Instant::now();
+        let paused = bucket.paused_until.saturating_duration_since(now);
+        if self.qps <= 0.0 {
+            return paused;
+        }
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.qps;
+        bucket.tokens = (bucket.tokens + refill).min(self.qps.max(1.0));
+        bucket.refilled = now;
+        bucket.tokens -= 1.0;
+        let wait = if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / self.qps)
+        } else {
+            Duration::ZERO
+        };
+        wait.max(paused)
+    }
+
+    /// Holds every request back for `delay`.
+    fn pause(&self, delay: Duration) {
+        let mut bucket = self.bucket();
+        bucket.paused_until = bucket.paused_until.max(Instant::now() + delay);
+    }
+}
+
+/// Applies the rate limit of the settings. Only the first call counts, so
+/// it belongs before the first request; without it the defaults apply.
+pub fn configure(network: &NetworkSettings) {
+    if LIMITER.set(Limiter::new(network)).is_err() {
+        log::warn!("The rate limit was already set, ignoring the new one");
+    }
+}
+
+fn limiter() -> &'static Limiter {
+    LIMITER.get_or_init(|| Limiter::new(&NetworkSettings::default()))
+}
+
+/// One request to the Bilibili API.
+enum Request<'a> {
+    Get(&'a str),
+    Post(&'a str, &'a [(&'a str, String)]),
+}
+
+impl Limiter {
+    /// Sends `request` once the rate limit allows, and parses the JSON
+    /// answer. When Bilibili turns it down as too frequent (HTTP 412 or
+    /// code -352), all requests back off and this one is retried.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        api: &impl BilibiliApi,
+        request: &Request<'_>,
+    ) -> Result<T, Core> {
+        let mut failures = 0;
+        loop {
+            tokio::time::sleep(self.reserve()).await;
+            let answer = match request {
+                Request::Get(url) => api.get_json(url).await?,
+                Request::Post(url, form) => api.post_form(url, form).await?,
+            };
+            if let Answer::Json(json) = answer {
+                if json["code"].as_i64() != Some(RISK_CONTROL_CODE) {
+                    return serde_json::from_value(json)
+                        .map_err(|e| Core::Network(format!("Unexpected response: {e}")));
+                }
+            }
+
+            failures += 1;
+            if failures >= self.throttle.attempts {
+                return Err(Core::Network(
+                    "Bilibili is throttling requests, try again later".to_string(),
+                ));
+            }
+            let delay = self.throttle.backoff(failures);
+            log::warn!("Throttled by Bilibili, backing off for {:?}", delay);
+            self.pause(delay);
+        }
+    }
+}
+
+/// Sends a GET request to `url` under the rate limit of the process, see
+/// [`Limiter::call`].
+pub async fn get<T: DeserializeOwned>(api: &impl BilibiliApi, url: &str) -> Result<T, Core> {
+    limiter().call(api, &Request::Get(url)).await
+}
+
+/// Posts `form` to `url` under the rate limit of the process, see
+/// [`Limiter::call`].
+pub async fn post<T: DeserializeOwned>(
+    api: &impl BilibiliApi,
+    url: &str,
+    form: &[(&str, String)],
+) -> Result<T, Core> {
+    limiter().call(api, &Request::Post(url, form)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limiter, Request};
+    use crate::bilibili::api::Answer;
+    use crate::bilibili::mock::MockApi;
+    use crate::settings::{NetworkSettings, RetryPolicy};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    const URL: &str = "https://api.bilibili.com/x/web-interface/nav";
+
+    fn limiter(qps: f64, attempts: u32) -> Limiter {
+        Limiter::new(&NetworkSettings {
+            qps,
+            throttle: RetryPolicy {
+                attempts,
+                backoff_ms: 1,
+            },
+            ..NetworkSettings::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_are_retried() {
+        let api = MockApi::new()
+            .answer(URL, Answer::Throttled)
+            .json(URL, json!({ "code": -352 }))
+            .json(URL, json!({ "code": 0, "data": 1 }));
+        let answer: Value = limiter(0.0, 4)
+            .call(&api, &Request::Get(URL))
+            .await
+            .unwrap();
+        assert_eq!(answer["data"], 1);
+        assert_eq!(api.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_configured_attempts() {
+        let api = MockApi::new().answer(URL, Answer::Throttled);
+        let result: Result<Value, _> = limiter(0.0, 2).call(&api, &Request::Get(URL)).await;
+        assert!(result.is_err());
+        assert_eq!(api.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn posts_the_form() {
+        let api = MockApi::new().json(URL, json!({ "code": 0 }));
+        let form = [("aid", "170001".to_string())];
+        let _: Value = limiter(0.0, 1)
+            .call(&api, &Request::Post(URL, &form))
+            .await
+            .unwrap();
+        let requests = api.requests();
+        assert_eq!(
+            requests[0].form,
+            [("aid".to_string(), "170001".to_string())]
+        );
+    }
+
+    #[test]
+    fn requests_beyond_the_burst_wait_for_tokens() {
+        let limiter = limiter(2.0, 1);
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+        assert!(limiter.reserve() > Duration::from_millis(400));
+    }
+
+    #[test]
+    fn a_pause_holds_every_request_back() {
+        let limiter = limiter(0.0, 1);
+        limiter.pause(Duration::from_secs(30));
+        assert!(limiter.reserve() > Duration::from_secs(29));
+    }
+}
@@ -0,0 +1,105 @@
+use super::api::{Answer, BilibiliApi};
+use crate::error::Core;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+
+/// A [`BilibiliApi`] for tests, answering from memory. A request gets the
+/// next answer queued on the first route whose pattern its URL contains;
+/// the last answer of a route is repeated. Every request is recorded.
+#[derive(Default)]
+pub struct MockApi {
+    routes: Mutex<Vec<(String, VecDeque<Answer>)>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+
+/// A request [`MockApi`] received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockRequest {
+    pub url: String,
+    /// The posted form, empty for GET requests.
+    pub form: Vec<(String, String)>,
+}
+
+impl MockApi {
+    pub fn new() -> Self {
+        MockApi::default()
+    }
+
+    /// Queues `answer` for the URLs containing `pattern`.
+    #[must_use]
+    pub fn answer(self, pattern: &str, answer: Answer) -> Self {
+        {
+            let mut routes = self.routes.lock().unwrap_or_else(PoisonError::into_inner);
+            match routes.iter_mut().find(|(p, _)| p == pattern) {
+                Some((_, answers)) => answers.push_back(answer),
+                None => routes.push((pattern.to_string(), VecDeque::from([answer]))),
+            }
+        }
+        self
+    }
+
+    /// Queues the JSON `json` for the URLs containing `pattern`.
+    #[must_use]
+    pub fn json(self, pattern: &str, json: Value) -> Self {
+        self.answer(pattern, Answer::Json(json))
+    }
+
+    /// Makes the URLs containing `pattern` redirect to `target`.
+    #[must_use]
+    pub fn redirect(self, pattern: &str, target: &str) -> Self {
+        self.json(pattern, Value::String(target.to_string()))
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn respond(&self, url: &str, form: &[(&str, String)]) -> Result<Answer, Core> {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(MockRequest {
+                url: url.to_string(),
+                form: form
+                    .iter()
+                    .map(|(name, value)| ((*name).to_string(), value.clone()))
+                    .collect(),
+            });
+        let mut routes = self.routes.lock().unwrap_or_else(PoisonError::into_inner);
+        let answers = routes
+            .iter_mut()
+            .find(|(pattern, _)| url.contains(pattern.as_str()))
+            .map(|(_, answers)| answers)
+            .ok_or_else(|| Core::Network(format!("No mock answer for {url}")))?;
+        let answer = if answers.len() > 1 {
+            answers.pop_front()
+        } else {
+            answers.front().cloned()
+        };
+        answer.ok_or_else(|| Core::Network(format!("No mock answer for {url}")))
+    }
+}
+
+impl BilibiliApi for MockApi {
+    async fn get_json(&self, url: &str) -> Result<Answer, Core> {
+        self.respond(url, &[])
+    }
+
+    async fn post_form(&self, url: &str, form: &[(&str, String)]) -> Result<Answer, Core> {
+        self.respond(url, form)
+    }
+
+    async fn final_url(&self, url: &str) -> Result<String, Core> {
+        match self.respond(url, &[])? {
+            Answer::Json(Value::String(target)) => Ok(target),
+            answer => Err(Core::Network(format!(
+                "Unexpected mock answer for {url}: {answer:?}"
+            ))),
+        }
+    }
+}
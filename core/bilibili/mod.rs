@@ -0,0 +1,188 @@
+pub mod api;
+pub mod cache;
+pub mod limiter;
+pub mod mock;
+
+use crate::error::Core;
+use crate::settings::BlacklistSettings;
+use crate::storage::{Track, TrackKind};
+use api::BilibiliApi;
+use serde::Deserialize;
+
+const RELATED_API_URL: &str = "https://api.bilibili.com/x/web-interface/archive/related";
+
+const XOR_CODE: u64 = 23_442_827_791_579;
+/// Av numbers are below this.
+pub const MAX_AID: u64 = 1 << 51;
+const BASE: u64 = 58;
+const ALPHABET: &[u8] = b"FcwAPNKTMug3GV5Lj7EJnHpWsx4tb8haYeviqBz6rkCy12mUSDQX9RdoZf";
+
+/// The music zone and its sub-zones, such as 原创音乐, 翻唱 and 演奏.
+const MUSIC_TIDS: [u32; 12] = [3, 28, 29, 30, 31, 54, 59, 130, 193, 194, 243, 244];
+
+#[derive(Deserialize)]
+struct RelatedResponse {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    data: Vec<RelatedVideo>,
+}
+
+#[derive(Deserialize)]
+struct RelatedVideo {
+    bvid: String,
+    cid: u64,
+    title: String,
+    owner: Owner,
+    duration: u64,
+    #[serde(default)]
+    tid: u32,
+}
+
+#[derive(Deserialize)]
+struct Owner {
+    #[serde(default)]
+    mid: u64,
+    name: String,
+}
+
+/// The bvid of the video with av number `aid`.
+pub fn aid_to_bvid(aid: u64) -> String {
+    let mut bvid = *b"BV1000000000";
+    let mut tmp = (MAX_AID | aid) ^ XOR_CODE;
+    let mut index = bvid.len() - 1;
+    while tmp > 0 {
+        #[allow(clippy::cast_possible_truncation)]
+        let digit = (tmp % BASE) as usize;
+        bvid[index] = ALPHABET[digit];
+        tmp /= BASE;
+        index -= 1;
+    }
+    bvid.swap(3, 9);
+    bvid.swap(4, 7);
+    String::from_utf8_lossy(&bvid).into_owned()
+}
+
+/// The av number of the video `bvid`, which some APIs take instead, or
+/// `None` when it is not a valid bvid.
+pub fn bvid_to_aid(bvid: &str) -> Option<u64> {
+    let mut bytes: [u8; 12] = bvid.as_bytes().try_into().ok()?;
+    bytes.swap(3, 9);
+    bytes.swap(4, 7);
+    let mut tmp: u64 = 0;
+    for byte in &bytes[3..] {
+        let digit = ALPHABET.iter().position(|letter| letter == byte)?;
+        tmp = tmp.checked_mul(BASE)?.checked_add(digit as u64)?;
+    }
+    Some((tmp & (MAX_AID - 1)) ^ XOR_CODE)
+}
+
+/// Videos Bilibili recommends next to `bvid`, in its order, keeping only
+/// those of the music zone when `music_only` is set and leaving out those
+/// on the `blacklist`.
+pub async fn fetch_related(
+    api: &impl BilibiliApi,
+    bvid: &str,
+    music_only: bool,
+    blacklist: &BlacklistSettings,
+) -> Result<Vec<Track>, Core> {
+    log::info!("Fetching videos related to {}", bvid);
+    let url = format!("{RELATED_API_URL}?bvid={bvid}");
+    let response: RelatedResponse = limiter::get(api, &url).await?;
+    if response.code != 0 {
+        return Err(Core::Network(format!(
+            "Failed to fetch related videos: {}",
+            response.message
+        )));
+    }
+    Ok(response
+        .data
+        .into_iter()
+        .filter(|video| !music_only || MUSIC_TIDS.contains(&video.tid))
+        .filter(|video| !blacklist.blocks(video.owner.mid, &video.title))
+        .map(|video| Track {
+            bvid: video.bvid,
+            cid: video.cid.to_string(),
+            title: video.title,
+            owner: video.owner.name,
+            duration: video.duration,
+            kind: TrackKind::Video,
+            season: None,
+            disabled: false,
+            skip_intro: 0,
+            skip_outro: 0,
+            start: 0,
+            end: 0,
+            note: String::new(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aid_to_bvid, bvid_to_aid, fetch_related};
+    use crate::bilibili::mock::MockApi;
+    use crate::settings::BlacklistSettings;
+    use serde_json::json;
+
+    fn video(bvid: &str, mid: u64, tid: u32) -> serde_json::Value {
+        json!({
+            "bvid": bvid,
+            "cid": 1,
+            "title": format!("title of {bvid}"),
+            "owner": { "mid": mid, "name": "up" },
+            "duration": 200,
+            "tid": tid,
+        })
+    }
+
+    #[tokio::test]
+    async fn related_videos_are_filtered() {
+        let api = MockApi::new().json(
+            "archive/related?bvid=BV17x411w7KC",
+            json!({
+                "code": 0,
+                "data": [
+                    video("BV1aaaaaaaaa", 1, 28),
+                    video("BV1bbbbbbbbb", 2, 17),
+                    video("BV1ccccccccc", 3, 130),
+                ],
+            }),
+        );
+        let blacklist = BlacklistSettings {
+            owners: vec![3],
+            ..BlacklistSettings::default()
+        };
+        let related = fetch_related(&api, "BV17x411w7KC", true, &blacklist)
+            .await
+            .unwrap();
+        let bvids: Vec<&str> = related.iter().map(|track| track.bvid.as_str()).collect();
+        assert_eq!(bvids, ["BV1aaaaaaaaa"]);
+
+        let related = fetch_related(&api, "BV17x411w7KC", false, &BlacklistSettings::default())
+            .await
+            .unwrap();
+        assert_eq!(related.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_refused_request_is_an_error() {
+        let api = MockApi::new().json("archive/related", json!({ "code": -400, "message": "bad" }));
+        let related = fetch_related(&api, "BV1", false, &BlacklistSettings::default()).await;
+        assert!(related.is_err());
+    }
+
+    #[test]
+    fn aids_and_bvids_convert_both_ways() {
+        for (aid, bvid) in [
+            (170_001, "BV17x411w7KC"),
+            (111_298_867_365_120, "BV1L9Uoa9EUx"),
+        ] {
+            assert_eq!(aid_to_bvid(aid), bvid);
+            assert_eq!(bvid_to_aid(bvid), Some(aid));
+        }
+        assert_eq!(bvid_to_aid("BV17x411w7K"), None);
+        assert_eq!(bvid_to_aid("BV17x411w7K0"), None);
+    }
+}
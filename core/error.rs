@@ -0,0 +1,57 @@
+use std::io;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone)]
+pub enum Core {
+    #[error("I/O error: {0}")]
+    Io(String),
+
+    #[error("Environment variable error: {0}")]
+    EnvVar(String),
+
+    #[error("TOML parsing error: {0}")]
+    TomlParsing(String),
+
+    #[error("TOML serialization error: {0}")]
+    TomlSerialize(String),
+
+    #[error("SQLite error: {0}")]
+    Sqlite(String),
+
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    #[error("Network error: {0}")]
+    Network(String),
+}
+
+impl From<io::Error> for Core {
+    fn from(error: io::Error) -> Self {
+        Core::Io(error.to_string())
+    }
+}
+
+impl From<toml::de::Error> for Core {
+    fn from(error: toml::de::Error) -> Self {
+        Core::TomlParsing(error.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for Core {
+    fn from(error: toml::ser::Error) -> Self {
+        Core::TomlSerialize(error.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Core {
+    fn from(error: reqwest::Error) -> Self {
+        Core::Network(error.to_string())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Core {
+    fn from(error: rusqlite::Error) -> Self {
+        Core::Sqlite(error.to_string())
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::Core;
+use crate::paths;
+use crate::settings::Settings;
+use crate::storage::Track;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Control requests understood by the daemon, mirroring the methods of the
+/// `org.rosesong.Player` D-Bus interface.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    TestConnection,
+    Play,
+    PlayBvid { bvid: String },
+    PlaySeason { id: String },
+    PlayLater,
+    Pause,
+    Next,
+    Previous,
+    Stop,
+    Quit,
+    SetMode { mode: String },
+    SetShuffle { shuffle: bool },
+    SetRepeat { repeat: String },
+    SetRadio { enabled: bool },
+    SetChannels { channels: String },
+    SetPitch { semitones: i32 },
+    SetPreview { seconds: u64 },
+    Seek { position: f64 },
+    SetVolume { volume: f64 },
+    AdjustVolume { delta: f64 },
+    VolumeUp,
+    VolumeDown,
+    PlaylistChange,
+    PlaylistIsEmpty,
+    Status,
+    PerfStats,
+    DebugInfo,
+    AudioTracks,
+    SetAudioTrack { index: u32 },
+}
+
+impl Request {
+    /// Whether the request only asks about the player, leaving playback
+    /// alone.
+    pub fn is_query(&self) -> bool {
+        matches!(
+            self,
+            Request::TestConnection
+                | Request::Status
+                | Request::PerfStats
+                | Request::DebugInfo
+                | Request::AudioTracks
+        )
+    }
+}
+
+/// Snapshot of what the daemon is playing, returned by `Request::Status`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// One of `idle` (the playlist is empty), `stopped`, `loading`,
+    /// `playing` or `paused`.
+    pub state: String,
+    /// One of `Loop`, `Shuffle` or `Repeat`, the closest of the older
+    /// play modes to `shuffle` and `repeat`.
+    pub mode: String,
+    #[serde(default)]
+    pub shuffle: bool,
+    /// One of `off`, `one` or `all`.
+    #[serde(default)]
+    pub repeat: String,
+    /// Whether radio mode continues with related videos.
+    #[serde(default)]
+    pub radio: bool,
+    /// One of `stereo`, `left`, `right` or `mix`.
+    #[serde(default)]
+    pub channels: String,
+    /// Pitch shift in semitones.
+    #[serde(default)]
+    pub pitch: i32,
+    pub track: Option<Track>,
+    /// Playback position in seconds.
+    pub position: f64,
+    /// Track length in seconds, or `0` when unknown.
+    pub duration: f64,
+    /// Whole seconds until the track stops, counting a trimmed end, outro
+    /// or preview, or `0` when unknown.
+    #[serde(default)]
+    pub remaining: u64,
+    /// Output volume between `0` and `1`.
+    pub volume: f64,
+    /// Name of the playlist being played.
+    pub playlist: String,
+    /// Format of the audio stream being played, once it is known.
+    #[serde(default)]
+    pub stream: Option<StreamInfo>,
+    /// The track that plays after this one in the current play mode, when
+    /// it is known.
+    #[serde(default)]
+    pub next_track: Option<Track>,
+    /// The last time a track failed to play, since the daemon started.
+    #[serde(default)]
+    pub last_error: Option<PlaybackError>,
+}
+
+/// A track that failed to play, and so was skipped or stopped playback.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PlaybackError {
+    /// Unix time in seconds.
+    pub time: i64,
+    pub track: Option<Track>,
+    pub message: String,
+}
+
+/// Format of an audio stream, from the Bilibili API and the decoder.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StreamInfo {
+    /// Codec, such as `mp4a.40.2` from the API or `MPEG-4 AAC` from the
+    /// decoder.
+    pub codec: String,
+    /// Bitrate in bits per second.
+    pub bitrate: Option<u32>,
+    /// Sample rate in Hz.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// What the audio device is fed, once the backend has negotiated it.
+    #[serde(default)]
+    pub output: Option<OutputFormat>,
+}
+
+/// Format the audio output plays at, after any conversion or resampling.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct OutputFormat {
+    /// Sample format, such as `S16LE` or `floatp`.
+    pub format: String,
+    /// Sample rate in Hz.
+    pub sample_rate: Option<u32>,
+}
+
+/// Latency of recent track starts, returned by `Request::PerfStats`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PerfStats {
+    /// Getting and verifying the stream URL.
+    pub fetch: LatencySummary,
+    /// From handing the stream to the backend until audio plays.
+    pub pipeline: LatencySummary,
+    /// From the command or end of track until audio plays.
+    pub total: LatencySummary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub sum_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+/// Memory use of the daemon and how its streams download, returned by
+/// `Request::DebugInfo`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DebugInfo {
+    /// Resident set size in bytes, where the platform reports it.
+    pub rss_bytes: Option<u64>,
+    pub playlist_tracks: usize,
+    /// Entries in the resume position cache.
+    pub saved_positions: usize,
+    /// Samples kept for `PerfStats`.
+    pub latency_samples: usize,
+    /// Bytes per second the stream is downloaded at, where the backend
+    /// measures it.
+    #[serde(default)]
+    pub download_rate: Option<u64>,
+    /// Seconds of audio downloaded ahead of the playback position.
+    #[serde(default)]
+    pub buffered: Option<f64>,
+    /// Times playback stalled waiting for data since the daemon started.
+    #[serde(default)]
+    pub stalls: u64,
+    /// Times the watchdog reloaded a stalled stream.
+    #[serde(default)]
+    pub reconnects: u64,
+    /// CDN host of the stream being played.
+    #[serde(default)]
+    pub cdn_host: String,
+}
+
+/// One of the audio streams of the track being played, as listed by
+/// `Request::AudioTracks`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AudioTrack {
+    /// Bilibili quality id, such as `30280` for 192K.
+    pub id: u32,
+    pub codec: String,
+    /// Bitrate in bits per second.
+    pub bitrate: Option<u32>,
+    /// Whether this is the stream being played.
+    pub selected: bool,
+}
+
+/// A JSON-RPC 2.0 request as sent over the control socket, one per line.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: u64,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+pub const JSONRPC_VERSION: &str = "2.0";
+pub const PARSE_ERROR: i64 = -32700;
+pub const SERVER_ERROR: i64 = -32000;
+/// Answer to a request the connecting user may not make.
+pub const PERMISSION_DENIED: i64 = -32001;
+
+impl RpcRequest {
+    pub fn new(id: u64, request: Request) -> Self {
+        RpcRequest {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            request,
+        }
+    }
+}
+
+impl RpcResponse {
+    pub fn result(id: u64, result: serde_json::Value) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<u64>, code: i64, message: String) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(RpcError { code, message }),
+        }
+    }
+}
+
+/// Name of the daemon's control pipe on Windows.
+pub const PIPE_NAME: &str = r"\\.\pipe\rosesong";
+
+/// Environment variable naming the control socket of a shared daemon.
+pub const SOCKET_ENV: &str = "ROSESONG_SOCKET";
+
+/// Path of the daemon's control socket: the one named by `ROSESONG_SOCKET`
+/// or the settings, or else the private one in the runtime directory.
+pub fn socket_path() -> Result<PathBuf, Core> {
+    if let Some(path) = std::env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    if let Some(path) = shared_socket() {
+        return Ok(path);
+    }
+    Ok(paths::runtime_dir()?.join("rosesong.sock"))
+}
+
+/// Socket path of `[service]` in the settings, if one is set.
+fn shared_socket() -> Option<PathBuf> {
+    let settings = Settings::load().ok()?;
+    (!settings.service.socket.is_empty()).then(|| PathBuf::from(settings.service.socket))
+}
+
+/// Whether the control socket is shared with other users rather than
+/// private to the one running the daemon.
+pub fn is_socket_shared() -> bool {
+    std::env::var_os(SOCKET_ENV).is_some_and(|path| !path.is_empty()) || shared_socket().is_some()
+}
@@ -0,0 +1,9 @@
+#![allow(clippy::missing_errors_doc, clippy::must_use_candidate)]
+
+pub mod bilibili;
+pub mod error;
+pub mod ipc;
+pub mod paths;
+pub mod settings;
+pub mod state;
+pub mod storage;
@@ -0,0 +1,83 @@
+use crate::error::Core;
+use std::path::PathBuf;
+
+/// The platform's config directory, or `~/.config/rosesong` where earlier
+/// versions kept everything if only that one exists, as happens when
+/// `XDG_CONFIG_HOME` points elsewhere or on macOS.
+pub fn config_dir() -> Result<PathBuf, Core> {
+    let dir = dirs::config_dir()
+        .map(|dir| dir.join("rosesong"))
+        .ok_or_else(|| Core::EnvVar("Failed to locate the user config directory".to_string()))?;
+    if dir.exists() {
+        return Ok(dir);
+    }
+    let legacy = dirs::home_dir().map(|home| home.join(".config").join("rosesong"));
+    Ok(legacy.filter(|legacy| legacy.is_dir()).unwrap_or(dir))
+}
+
+/// Directory for runtime files such as the control socket, falling back to
+/// the config directory where the platform has no runtime directory.
+pub fn runtime_dir() -> Result<PathBuf, Core> {
+    match dirs::runtime_dir() {
+        Some(dir) => Ok(dir),
+        None => config_dir(),
+    }
+}
+
+pub fn logs_dir() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("logs"))
+}
+
+pub fn playlists_dir() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("playlists"))
+}
+
+pub fn playlist_file() -> Result<PathBuf, Core> {
+    Ok(playlists_dir()?.join("playlist.toml"))
+}
+
+/// TOML file of the playlist called `name`; `playlist` is the default one.
+pub fn named_playlist_file(name: &str) -> Result<PathBuf, Core> {
+    Ok(playlists_dir()?.join(format!("{name}.toml")))
+}
+
+/// Tracks queued with `rsg later`, each played once and then dropped.
+pub fn later_file() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("later.toml"))
+}
+
+pub fn database_file() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("rosesong.db"))
+}
+
+pub fn named_database_file(name: &str) -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join(format!("rosesong-{name}.db")))
+}
+
+/// Cached API answers, which can be deleted at any time.
+pub fn metadata_cache_dir() -> Result<PathBuf, Core> {
+    dirs::cache_dir()
+        .map(|dir| dir.join("rosesong").join("metadata"))
+        .ok_or_else(|| Core::EnvVar("Failed to locate the user cache directory".to_string()))
+}
+
+/// Earlier versions of the playlist called `name`, for undoing changes.
+pub fn history_dir(name: &str) -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("history").join(name))
+}
+
+pub fn settings_file() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("settings.toml"))
+}
+
+pub fn state_file() -> Result<PathBuf, Core> {
+    Ok(config_dir()?.join("state.toml"))
+}
+
+/// Creates the config, log and playlist directories if they are missing.
+pub fn ensure_dirs() -> Result<(), Core> {
+    for dir in [logs_dir()?, playlists_dir()?] {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
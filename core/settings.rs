@@ -0,0 +1,504 @@
+use crate::error::Core;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// User settings read from `~/.config/rosesong/settings.toml`.
+///
+/// Every field has a default so a missing file or a partial file is valid.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub storage: StorageSettings,
+    pub player: PlayerSettings,
+    pub network: NetworkSettings,
+    /// Rules switching the playing playlist by time of day, first match wins.
+    pub schedule: Vec<ScheduleRule>,
+    pub hooks: HookSettings,
+    pub osd: OsdSettings,
+    pub import: ImportSettings,
+    pub output: OutputSettings,
+    pub radio: RadioSettings,
+    pub blacklist: BlacklistSettings,
+    pub subsonic: SubsonicSettings,
+    pub service: ServiceSettings,
+    pub mpd: MpdSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    /// Changes of each playlist that `rsg undo` can take back.
+    pub history_size: usize,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            backend: StorageBackend::default(),
+            history_size: 20,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Toml,
+    Sqlite,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct PlayerSettings {
+    pub backend: PlayerBackendKind,
+    /// Grab the keyboard media keys through GNOME Settings Daemon.
+    pub media_keys: bool,
+    /// Volume change in percent for `volume_up` and `volume_down`.
+    pub volume_step: u8,
+    /// Tracks at least this many seconds long resume from where they were
+    /// left off; `0` disables resuming.
+    pub resume_min_duration: u64,
+    /// Serve latency metrics in the Prometheus format on this local port;
+    /// `0` disables it.
+    pub metrics_port: u16,
+    /// Reload the track when its position has not moved for this many
+    /// seconds while playing; `0` disables the watchdog.
+    pub watchdog_timeout: u64,
+    /// Minutes playback may stay paused before `pause_action` is taken;
+    /// `0` keeps it paused forever.
+    pub pause_timeout: u64,
+    pub pause_action: PauseAction,
+    /// Which stream to play when a video has several audio streams.
+    pub audio_track: AudioTrackPolicy,
+    /// Pause before the system suspends, through logind. Linux only.
+    pub pause_on_sleep: bool,
+    /// Continue playing after resume if it was playing before suspend.
+    pub resume_after_sleep: bool,
+    /// Show a desktop notification when a track fails to play. Linux only.
+    pub notify_errors: bool,
+    /// Report the progress of the videos played to the watch history of
+    /// the account logged in through the `Cookie` header.
+    pub report_history: bool,
+    /// What happens when the playing track is removed from the playlist.
+    pub on_remove_current: RemovePolicy,
+    /// Start playing once tracks are added to an empty playlist.
+    pub autoplay_on_add: bool,
+    /// Jump over the silence at the start of each track. Not supported by
+    /// the rodio backend.
+    pub skip_silence: bool,
+    /// Milliseconds the sound fades out over before playback stops or the
+    /// daemon quits; `0` stops at once.
+    pub fade_out_ms: u64,
+    /// Fade out before pausing too.
+    pub fade_on_pause: bool,
+    pub shuffle: ShuffleSettings,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            backend: PlayerBackendKind::default(),
+            media_keys: false,
+            volume_step: 5,
+            resume_min_duration: 600,
+            metrics_port: 0,
+            watchdog_timeout: 15,
+            pause_timeout: 0,
+            pause_action: PauseAction::default(),
+            audio_track: AudioTrackPolicy::default(),
+            pause_on_sleep: true,
+            resume_after_sleep: true,
+            notify_errors: false,
+            report_history: false,
+            on_remove_current: RemovePolicy::default(),
+            autoplay_on_add: true,
+            skip_silence: false,
+            fade_out_ms: 700,
+            fade_on_pause: false,
+            shuffle: ShuffleSettings::default(),
+        }
+    }
+}
+
+/// Rules for picking the next track while shuffling. They give way when no
+/// track satisfies them, so shuffling never gets stuck.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ShuffleSettings {
+    /// Never play two tracks of the same uploader in a row.
+    pub avoid_same_owner: bool,
+    /// Do not repeat any of this many last played tracks, even across
+    /// rounds of shuffling.
+    pub no_repeat_recent: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerBackendKind {
+    #[default]
+    GStreamer,
+    Mpv,
+    Rodio,
+    /// Plays nothing and only keeps time, for machines without sound.
+    Null,
+}
+
+impl PlayerBackendKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gstreamer" => Some(PlayerBackendKind::GStreamer),
+            "mpv" => Some(PlayerBackendKind::Mpv),
+            "rodio" => Some(PlayerBackendKind::Rodio),
+            "null" => Some(PlayerBackendKind::Null),
+            _ => None,
+        }
+    }
+}
+
+/// What happens once playback has been paused for `pause_timeout`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseAction {
+    /// Stop the track to free the audio pipeline; the daemon keeps running
+    /// and `play` continues from the same position.
+    #[default]
+    Release,
+    /// Exit the daemon.
+    Quit,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioTrackPolicy {
+    /// The first stream the API lists.
+    #[default]
+    First,
+    /// The stream with the highest bitrate.
+    Highest,
+    /// The stream with the lowest bitrate, to save data.
+    Lowest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovePolicy {
+    /// Skip to the track that followed it right away.
+    #[default]
+    Next,
+    /// Play it to the end, then continue with the track that followed it.
+    Finish,
+}
+
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 BiliDroid/..* (bbcallen@gmail.com)";
+pub const DEFAULT_REFERER: &str = "https://www.bilibili.com";
+
+/// How the player talks to Bilibili: request headers, timeouts and retries.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// Sent as `User-Agent` by the API requests and every player backend.
+    pub user_agent: String,
+    /// Sent as `Referer`; Bilibili refuses streams without it.
+    pub referer: String,
+    /// Extra headers sent along with the two above.
+    pub headers: BTreeMap<String, String>,
+    /// Seconds before a single HTTP request is abandoned; `0` for no limit.
+    pub request_timeout: u64,
+    /// Requests per second to the Bilibili API, counted across all of them;
+    /// `0` for no limit.
+    pub qps: f64,
+    /// Retries of an API request Bilibili turned down as too frequent. All
+    /// requests wait out the backoff, not only the retried one.
+    pub throttle: RetryPolicy,
+    /// Seconds the metadata of a video is answered from the cache instead
+    /// of asking Bilibili again; `0` turns the cache off.
+    pub metadata_ttl: u64,
+    /// Retries of the playurl API request.
+    pub fetch: RetryPolicy,
+    /// Retries when the returned stream URL cannot be downloaded, each of
+    /// which fetches a fresh URL.
+    pub verify: RetryPolicy,
+    /// Seconds to spend on one track across all attempts; `0` for no limit.
+    pub timeout: u64,
+    /// Reload a track that failed to load once `NetworkManager` reports the
+    /// connection is back. Linux only.
+    pub resume_on_reconnect: bool,
+    /// Ask yt-dlp for the stream once all attempts through the API failed.
+    pub ytdlp_fallback: bool,
+    /// The yt-dlp program, looked up in `PATH` unless it is a path.
+    pub ytdlp_path: String,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            referer: DEFAULT_REFERER.to_string(),
+            headers: BTreeMap::new(),
+            request_timeout: 10,
+            qps: 4.0,
+            throttle: RetryPolicy {
+                attempts: 4,
+                backoff_ms: 5000,
+            },
+            metadata_ttl: 86400,
+            fetch: RetryPolicy::default(),
+            verify: RetryPolicy::default(),
+            timeout: 0,
+            resume_on_reconnect: true,
+            ytdlp_fallback: false,
+            ytdlp_path: "yt-dlp".to_string(),
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// All headers to send with requests to Bilibili, as name and value.
+    pub fn request_headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![
+            ("User-Agent".to_string(), self.user_agent.clone()),
+            ("Referer".to_string(), self.referer.clone()),
+        ];
+        headers.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        headers
+    }
+
+    /// The `bili_jct` cookie of the logged-in account, which the write APIs
+    /// take as csrf token.
+    pub fn csrf_token(&self) -> Option<&str> {
+        let cookie = self
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+            .map(|(_, value)| value.as_str())?;
+        cookie
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == "bili_jct")
+            .map(|(_, value)| value)
+    }
+}
+
+/// Up to `attempts` tries, waiting `backoff_ms` after the first failure and
+/// twice as long after each further one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after the `failures`-th failure.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        Duration::from_millis(self.backoff_ms)
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+    }
+}
+
+/// Plays `playlist` on `days` (all days when empty) between `start` and
+/// `end`, given as `HH:MM`. A rule whose end is before its start runs past
+/// midnight.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleRule {
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+    pub playlist: String,
+}
+
+/// Shell commands run on player events, with the track in `ROSESONG_*`
+/// environment variables.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct HookSettings {
+    pub on_track_change: Option<String>,
+    pub on_pause: Option<String>,
+    pub on_error: Option<String>,
+}
+
+impl HookSettings {
+    pub fn is_empty(&self) -> bool {
+        self.on_track_change.is_none() && self.on_pause.is_none() && self.on_error.is_none()
+    }
+}
+
+/// On-screen display output for third-party OSD and lyrics overlays.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct OsdSettings {
+    /// Named pipe to write a line of OSD text to whenever it changes;
+    /// created when missing. Linux only.
+    pub fifo: Option<String>,
+}
+
+/// How `rsg add` merges imported tracks into an existing playlist.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ImportSettings {
+    /// Where tracks not yet in the playlist are placed.
+    pub new_tracks: InsertPosition,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertPosition {
+    /// After all existing tracks.
+    #[default]
+    End,
+    /// Before all existing tracks.
+    Start,
+    /// Right after the track that precedes it in the imported list, so a
+    /// re-imported favorites folder keeps its order.
+    Source,
+}
+
+/// Where the player sends its audio. Bit-perfect output needs a sink that
+/// bypasses the sound server's mixer and the volume left at 100.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub sink: OutputSink,
+    /// ALSA device such as `hw:0,0`, or the name of a `PipeWire` node; the
+    /// default device when unset.
+    pub device: Option<String>,
+    /// Ask for exclusive access and never resample, so the device plays
+    /// the stream at its own sample rate.
+    pub exclusive: bool,
+    /// Ask `PipeWire` to switch the device to the sample rate of each
+    /// stream, which it does when the rate is in `default.clock.allowed-rates`.
+    pub match_rate: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputSink {
+    /// Whatever the backend picks, usually the sound server.
+    #[default]
+    Auto,
+    /// Straight to an ALSA device, bypassing the sound server.
+    Alsa,
+    /// A `PipeWire` stream, which can target a node in its pro-audio profile.
+    PipeWire,
+}
+
+/// Radio mode keeps playing videos Bilibili recommends next to the track
+/// that just finished, adding them to the playlist after it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RadioSettings {
+    /// Start the daemon with radio mode on.
+    pub enabled: bool,
+    /// Only continue with videos of the music zone.
+    pub music_only: bool,
+}
+
+impl Default for RadioSettings {
+    fn default() -> Self {
+        RadioSettings {
+            enabled: false,
+            music_only: true,
+        }
+    }
+}
+
+/// Videos left out when importing and never picked by radio mode.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct BlacklistSettings {
+    /// Ids (mid) of uploaders whose videos are left out.
+    pub owners: Vec<u64>,
+    /// Videos with any of these in their title are left out, ignoring case.
+    pub keywords: Vec<String>,
+}
+
+impl BlacklistSettings {
+    /// Whether a video titled `title` by the uploader `mid` is left out.
+    pub fn blocks(&self, mid: u64, title: &str) -> bool {
+        if self.owners.contains(&mid) {
+            return true;
+        }
+        let title = title.to_lowercase();
+        self.keywords
+            .iter()
+            .any(|keyword| !keyword.is_empty() && title.contains(&keyword.to_lowercase()))
+    }
+}
+
+/// A Subsonic server, such as Navidrome, that `rsg subsonic` matches the
+/// playlist against.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SubsonicSettings {
+    /// Address of the server, such as `http://localhost:4533`.
+    pub url: String,
+    pub user: String,
+    pub password: String,
+}
+
+/// One daemon shared by the users of a machine, such as a home server.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ServiceSettings {
+    /// Control socket other users connect to, such as
+    /// `/run/rosesong/rosesong.sock`; empty keeps the private one in the
+    /// runtime directory.
+    pub socket: String,
+    /// Users, and groups written as `@group`, who may control playback
+    /// through the shared socket. Everyone else may only query it.
+    pub controllers: Vec<String>,
+}
+
+/// Server speaking the MPD protocol, so MPD clients such as MALP or
+/// ncmpcpp can control playback.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MpdSettings {
+    /// Port to listen on, usually 6600; 0 turns the server off.
+    pub port: u16,
+    /// Address to listen on; `0.0.0.0` lets phones on the local network in.
+    pub address: String,
+    /// Password clients must send before anything else; empty lets every
+    /// client in.
+    pub password: String,
+}
+
+impl Default for MpdSettings {
+    fn default() -> Self {
+        MpdSettings {
+            port: 0,
+            address: "127.0.0.1".to_string(),
+            password: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Result<Self, Core> {
+        let path = paths::settings_file()?;
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+}
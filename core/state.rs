@@ -0,0 +1,39 @@
+use crate::error::Core;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Daemon state that should survive a restart or crash, stored in
+/// `state.toml` next to the settings.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RuntimeState {
+    /// Track that was playing, used to resume at the same place.
+    pub current_bvid: Option<String>,
+    /// Play mode as accepted by `Request::SetMode`, such as `shuffle-all`.
+    pub play_mode: Option<String>,
+    /// Last position in seconds of long tracks, keyed by cid.
+    pub positions: BTreeMap<String, u64>,
+}
+
+impl RuntimeState {
+    /// Reads the saved state, falling back to the default when the file is
+    /// missing or unreadable so a corrupt file never blocks startup.
+    pub fn load() -> Self {
+        paths::state_file()
+            .and_then(|path| Ok(std::fs::read_to_string(path)?))
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the state through a temporary file so a crash mid-write keeps
+    /// the previous state intact.
+    pub fn save(&self) -> Result<(), Core> {
+        let path = paths::state_file()?;
+        let temp_path = path.with_extension("toml.tmp");
+        std::fs::write(&temp_path, toml::to_string(self)?)?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
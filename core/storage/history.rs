@@ -0,0 +1,203 @@
+use super::{PlaylistInfo, PlaylistStore, Track};
+use crate::error::Core;
+use std::path::{Path, PathBuf};
+
+/// The tracks of a playlist before and after a step through its history.
+pub type Step = (Vec<Track>, Vec<Track>);
+
+/// Which way through the history a step goes.
+#[derive(Clone, Copy, Debug)]
+pub enum Stack {
+    Undo,
+    Redo,
+}
+
+impl Stack {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stack::Undo => "undo",
+            Stack::Redo => "redo",
+        }
+    }
+}
+
+/// Where the versions of a playlist's history are kept.
+pub trait Versions: Send + Sync + std::fmt::Debug {
+    /// Puts `tracks` on top of `stack`, dropping the oldest versions so at
+    /// most `size` remain.
+    fn push(&self, stack: Stack, tracks: &[Track], size: usize) -> Result<(), Core>;
+
+    /// Takes the newest version off `stack`.
+    fn pop(&self, stack: Stack) -> Result<Option<Vec<Track>>, Core>;
+
+    fn clear(&self, stack: Stack) -> Result<(), Core>;
+}
+
+/// Versions kept as one JSON file each, so recording a change writes only
+/// the version it replaces.
+#[derive(Debug)]
+pub struct VersionFiles {
+    dir: PathBuf,
+}
+
+impl VersionFiles {
+    pub fn new(dir: PathBuf) -> Self {
+        VersionFiles { dir }
+    }
+
+    fn stack_dir(&self, stack: Stack) -> PathBuf {
+        self.dir.join(stack.name())
+    }
+
+    /// Numbered versions in `stack`, oldest first.
+    fn entries(&self, stack: Stack) -> Result<Vec<(u64, PathBuf)>, Core> {
+        let dir = self.stack_dir(stack);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries: Vec<(u64, PathBuf)> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter_map(|path| {
+                let number = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((number, path))
+            })
+            .collect();
+        entries.sort_by_key(|(number, _)| *number);
+        Ok(entries)
+    }
+}
+
+impl Versions for VersionFiles {
+    fn push(&self, stack: Stack, tracks: &[Track], size: usize) -> Result<(), Core> {
+        let entries = self.entries(stack)?;
+        let next = entries.last().map_or(0, |(number, _)| number + 1);
+        let dir = self.stack_dir(stack);
+        std::fs::create_dir_all(&dir)?;
+        let json = serde_json::to_vec(tracks).map_err(|e| Core::Io(e.to_string()))?;
+        std::fs::write(dir.join(format!("{next}.json")), json)?;
+        let excess = (entries.len() + 1).saturating_sub(size);
+        for (_, path) in entries.iter().take(excess) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn pop(&self, stack: Stack) -> Result<Option<Vec<Track>>, Core> {
+        let Some((_, path)) = self.entries(stack)?.pop() else {
+            return Ok(None);
+        };
+        let tracks = read_version(&path)?;
+        std::fs::remove_file(path)?;
+        Ok(Some(tracks))
+    }
+
+    fn clear(&self, stack: Stack) -> Result<(), Core> {
+        for (_, path) in self.entries(stack)? {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Earlier and undone versions of one playlist.
+#[derive(Debug)]
+pub struct History {
+    versions: Box<dyn Versions>,
+    size: usize,
+}
+
+impl History {
+    pub fn new(versions: Box<dyn Versions>, size: usize) -> Self {
+        History { versions, size }
+    }
+
+    /// Remembers `tracks` as the version before a new change, which makes
+    /// the undone versions unreachable.
+    fn record(&self, tracks: &[Track]) -> Result<(), Core> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        self.versions.push(Stack::Undo, tracks, self.size)?;
+        self.versions.clear(Stack::Redo)
+    }
+
+    /// Puts the version before the last change back into `store`, keeping
+    /// the current one for [`History::redo`]. Returns the tracks before and
+    /// after, or `None` when there is nothing to undo.
+    pub fn undo(&self, store: &dyn PlaylistStore) -> Result<Option<Step>, Core> {
+        self.step(store, Stack::Undo, Stack::Redo)
+    }
+
+    /// Puts the last undone version back into `store`.
+    pub fn redo(&self, store: &dyn PlaylistStore) -> Result<Option<Step>, Core> {
+        self.step(store, Stack::Redo, Stack::Undo)
+    }
+
+    fn step(
+        &self,
+        store: &dyn PlaylistStore,
+        from: Stack,
+        to: Stack,
+    ) -> Result<Option<Step>, Core> {
+        let Some(tracks) = self.versions.pop(from)? else {
+            return Ok(None);
+        };
+        let current = store.load()?;
+        self.versions.push(to, &current, self.size)?;
+        store.save(&tracks)?;
+        Ok(Some((current, tracks)))
+    }
+}
+
+fn read_version(path: &Path) -> Result<Vec<Track>, Core> {
+    serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|e| Core::Io(format!("Invalid history entry {}: {e}", path.display())))
+}
+
+/// A store that records the version each save replaces in its history.
+#[derive(Debug)]
+pub struct Journaled {
+    store: Box<dyn PlaylistStore>,
+    history: History,
+}
+
+impl Journaled {
+    pub fn new(store: Box<dyn PlaylistStore>, history: History) -> Self {
+        Journaled { store, history }
+    }
+}
+
+impl PlaylistStore for Journaled {
+    fn load(&self) -> Result<Vec<Track>, Core> {
+        self.store.load()
+    }
+
+    fn save(&self, tracks: &[Track]) -> Result<(), Core> {
+        // A playlist that cannot be loaded has no version worth recording
+        if let Ok(old) = self.store.load() {
+            if old != tracks {
+                if let Err(e) = self.history.record(&old) {
+                    log::warn!("Failed to record the playlist history: {}", e);
+                }
+            }
+        }
+        self.store.save(tracks)
+    }
+
+    fn fingerprint(&self) -> Result<u64, Core> {
+        self.store.fingerprint()
+    }
+
+    fn info(&self) -> Result<PlaylistInfo, Core> {
+        self.store.info()
+    }
+
+    fn set_info(&self, info: &PlaylistInfo) -> Result<(), Core> {
+        self.store.set_info(info)
+    }
+
+    fn is_empty(&self) -> Result<bool, Core> {
+        self.store.is_empty()
+    }
+}
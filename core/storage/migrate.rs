@@ -0,0 +1,63 @@
+use crate::error::Core;
+use toml::{Table, Value};
+
+/// Version written to new playlist files. Files without a `version` field
+/// predate versioning and count as version 1.
+pub const PLAYLIST_VERSION: u32 = 3;
+
+/// Step `i` upgrades a playlist from version `i + 1` to `i + 2`. Append a
+/// step and bump `PLAYLIST_VERSION` whenever the file format changes.
+const MIGRATIONS: [fn(&mut Table); 2] = [fill_track_defaults, add_track_kind];
+
+/// Upgrades a parsed playlist file to `PLAYLIST_VERSION`, returning the
+/// version it was written with.
+pub fn migrate(table: &mut Table) -> Result<u32, Core> {
+    let version = match table.get("version") {
+        None => 1,
+        Some(value) => value
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| Core::TomlParsing(format!("Invalid playlist version: {value}")))?,
+    };
+    if version > PLAYLIST_VERSION {
+        return Err(Core::Unsupported(format!(
+            "Playlist version {version} was written by a newer rosesong, this one reads up to version {PLAYLIST_VERSION}"
+        )));
+    }
+    for step in &MIGRATIONS[version as usize - 1..] {
+        step(table);
+    }
+    table.insert(
+        "version".to_string(),
+        Value::Integer(i64::from(PLAYLIST_VERSION)),
+    );
+    Ok(version)
+}
+
+/// Version 1 tracks may lack `title`, `owner` and `duration`.
+fn fill_track_defaults(table: &mut Table) {
+    let Some(Value::Array(tracks)) = table.get_mut("tracks") else {
+        return;
+    };
+    for track in tracks.iter_mut().filter_map(Value::as_table_mut) {
+        for key in ["title", "owner"] {
+            track
+                .entry(key)
+                .or_insert_with(|| Value::String(String::new()));
+        }
+        track.entry("duration").or_insert(Value::Integer(0));
+    }
+}
+
+/// Version 3 adds `kind`; every earlier track is a video.
+fn add_track_kind(table: &mut Table) {
+    let Some(Value::Array(tracks)) = table.get_mut("tracks") else {
+        return;
+    };
+    for track in tracks.iter_mut().filter_map(Value::as_table_mut) {
+        track
+            .entry("kind")
+            .or_insert_with(|| Value::String("video".to_string()));
+    }
+}
@@ -0,0 +1,297 @@
+mod history;
+mod migrate;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod toml_file;
+
+use crate::error::Core;
+use crate::paths;
+use crate::settings::{StorageBackend, StorageSettings};
+use serde::{Deserialize, Serialize};
+
+pub use history::Step;
+use history::{History, Journaled, VersionFiles, Versions};
+pub use migrate::PLAYLIST_VERSION;
+pub use toml_file::TomlStore;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Track {
+    pub bvid: String,
+    pub cid: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub duration: u64,
+    #[serde(default)]
+    pub kind: TrackKind,
+    /// The collection or season the track was imported from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season: Option<SeasonPosition>,
+    /// Kept in the playlist but skipped during playback.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    /// Seconds of talk or silence skipped at the start of the track.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skip_intro: u64,
+    /// Seconds cut off the end of the track.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skip_outro: u64,
+    /// Second the part of the track worth playing starts at.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub start: u64,
+    /// Second the part of the track worth playing ends at; `0` plays to the
+    /// end.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub end: u64,
+    /// A remark of the user's, such as what the track suits.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+}
+
+impl Track {
+    /// Second playback starts at, after both the trimmed start and the
+    /// intro.
+    pub fn play_start(&self) -> u64 {
+        self.start.max(self.skip_intro)
+    }
+
+    /// Second where playback moves on to the next track, at the trimmed end
+    /// or where the outro starts, whichever comes first. The outro needs the
+    /// duration, so tracks without one only stop at a trimmed end.
+    pub fn play_end(&self) -> Option<u64> {
+        let end = (self.end > 0).then_some(self.end);
+        let outro = (self.skip_outro > 0 && self.duration > self.skip_outro)
+            .then(|| self.duration - self.skip_outro);
+        end.into_iter().chain(outro).min()
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Where a track sits in a collection (合集) or a bangumi season. Bangumi
+/// season ids are stored as `ss<season_id>` so they never clash with
+/// collection ids.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SeasonPosition {
+    pub id: String,
+    /// 1-based position in the season.
+    pub index: u32,
+    /// Number of tracks in the season.
+    pub total: u32,
+}
+
+/// Where a track comes from. Audio tracks are songs of the Bilibili audio
+/// section (au 号), stored with `bvid` set to `au<sid>` and `cid` to the sid.
+/// Episodes of bangumi, documentaries and courses (ep/ss 号) have `bvid` set
+/// to `ep<ep_id>`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackKind {
+    #[default]
+    Video,
+    Audio,
+    Episode,
+}
+
+impl TrackKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrackKind::Video => "video",
+            TrackKind::Audio => "audio",
+            TrackKind::Episode => "episode",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "video" => Some(TrackKind::Video),
+            "audio" => Some(TrackKind::Audio),
+            "episode" => Some(TrackKind::Episode),
+            _ => None,
+        }
+    }
+}
+
+/// What a playlist is about, kept apart from its tracks.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlaylistInfo {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Link or path of a cover image.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cover: String,
+}
+
+impl PlaylistInfo {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty() && self.description.is_empty() && self.cover.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PlaylistFile {
+    #[serde(default)]
+    version: u32,
+    #[serde(flatten)]
+    info: PlaylistInfo,
+    #[serde(default)]
+    tracks: Vec<Track>,
+}
+
+/// Persistent storage for the playlist, shared by the daemon and `rsg`.
+pub trait PlaylistStore: Send + Sync + std::fmt::Debug {
+    fn load(&self) -> Result<Vec<Track>, Core>;
+
+    fn save(&self, tracks: &[Track]) -> Result<(), Core>;
+
+    /// Cheap value that changes whenever the stored playlist changes, used to
+    /// skip reloading an unchanged playlist.
+    fn fingerprint(&self) -> Result<u64, Core>;
+
+    fn info(&self) -> Result<PlaylistInfo, Core>;
+
+    fn set_info(&self, info: &PlaylistInfo) -> Result<(), Core>;
+
+    fn is_empty(&self) -> Result<bool, Core> {
+        Ok(self.load()?.is_empty())
+    }
+}
+
+/// Name of the playlist used when no other playlist is selected.
+pub const DEFAULT_PLAYLIST: &str = "playlist";
+
+/// Opens the playlist store selected in the settings, creating it if needed.
+pub fn open(settings: &StorageSettings) -> Result<Box<dyn PlaylistStore>, Core> {
+    open_named(settings, DEFAULT_PLAYLIST)
+}
+
+/// Opens the playlist called `name` in the configured backend, creating it
+/// if needed. Each change is recorded so it can be undone.
+pub fn open_named(settings: &StorageSettings, name: &str) -> Result<Box<dyn PlaylistStore>, Core> {
+    Ok(Box::new(Journaled::new(
+        open_backend(settings, name)?,
+        history(settings, name)?,
+    )))
+}
+
+/// Opens the queue of tracks to play once, kept as TOML whatever the
+/// configured backend and without history.
+pub fn open_later() -> Result<Box<dyn PlaylistStore>, Core> {
+    paths::ensure_dirs()?;
+    Ok(Box::new(TomlStore::new(paths::later_file()?)?))
+}
+
+/// Takes back the last change of the playlist called `name`. Returns the
+/// tracks before and after, or `None` when there is nothing to undo.
+pub fn undo(settings: &StorageSettings, name: &str) -> Result<Option<Step>, Core> {
+    history(settings, name)?.undo(open_backend(settings, name)?.as_ref())
+}
+
+/// Makes the last undone change of the playlist called `name` again.
+pub fn redo(settings: &StorageSettings, name: &str) -> Result<Option<Step>, Core> {
+    history(settings, name)?.redo(open_backend(settings, name)?.as_ref())
+}
+
+/// The history of the playlist called `name`, kept in the database with
+/// the sqlite backend and as files next to the TOML playlist otherwise.
+fn history(settings: &StorageSettings, name: &str) -> Result<History, Core> {
+    check_name(name)?;
+    let versions: Box<dyn Versions> = match settings.backend {
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            paths::ensure_dirs()?;
+            Box::new(sqlite::SqliteVersions::open(&database_path(name)?)?)
+        }
+        _ => Box::new(VersionFiles::new(paths::history_dir(name)?)),
+    };
+    Ok(History::new(versions, settings.history_size))
+}
+
+#[cfg(feature = "sqlite")]
+fn database_path(name: &str) -> Result<std::path::PathBuf, Core> {
+    if name == DEFAULT_PLAYLIST {
+        paths::database_file()
+    } else {
+        paths::named_database_file(name)
+    }
+}
+
+fn check_name(name: &str) -> Result<(), Core> {
+    if name.is_empty() || name.contains(['/', '\\', '.']) {
+        return Err(Core::Unsupported(format!("Invalid playlist name: {name}")));
+    }
+    Ok(())
+}
+
+fn open_backend(settings: &StorageSettings, name: &str) -> Result<Box<dyn PlaylistStore>, Core> {
+    check_name(name)?;
+    paths::ensure_dirs()?;
+    let toml_path = paths::named_playlist_file(name)?;
+    match settings.backend {
+        StorageBackend::Toml => Ok(Box::new(TomlStore::new(toml_path)?)),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => Ok(Box::new(sqlite::SqliteStore::open(
+            &database_path(name)?,
+            &toml_path,
+        )?)),
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => Err(Core::Unsupported(
+            "rosesong was built without the sqlite feature".to_string(),
+        )),
+    }
+}
+
+pub fn parse_toml(content: &str) -> Result<Vec<Track>, Core> {
+    Ok(parse_versioned_toml(content)?.0.tracks)
+}
+
+/// Parses a playlist file into what it is about and its tracks.
+pub fn parse_playlist(content: &str) -> Result<(PlaylistInfo, Vec<Track>), Core> {
+    let playlist = parse_versioned_toml(content)?.0;
+    Ok((playlist.info, playlist.tracks))
+}
+
+/// Parses a playlist file without upgrading it, keeping where the first
+/// error is so it can be pointed at. Older versions only lack fields that
+/// have defaults, so they check the same.
+pub fn check_toml(content: &str) -> Result<Vec<Track>, toml::de::Error> {
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(toml::from_str::<PlaylistFile>(content)?.tracks)
+}
+
+/// Parses a playlist file of any supported version, also returning the
+/// version it was written with.
+fn parse_versioned_toml(content: &str) -> Result<(PlaylistFile, u32), Core> {
+    if content.trim().is_empty() {
+        return Ok((PlaylistFile::default(), PLAYLIST_VERSION));
+    }
+    let mut table: toml::Table = toml::from_str(content)?;
+    let version = migrate::migrate(&mut table)?;
+    let playlist: PlaylistFile = toml::Value::Table(table).try_into()?;
+    Ok((playlist, version))
+}
+
+pub fn to_toml(tracks: &[Track]) -> Result<String, Core> {
+    playlist_to_toml(&PlaylistInfo::default(), tracks)
+}
+
+/// Like [`to_toml`], with `info` at the top of the file.
+pub fn playlist_to_toml(info: &PlaylistInfo, tracks: &[Track]) -> Result<String, Core> {
+    if info.is_empty() && tracks.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(toml::to_string(&PlaylistFile {
+        version: PLAYLIST_VERSION,
+        info: info.clone(),
+        tracks: tracks.to_vec(),
+    })?)
+}
@@ -0,0 +1,363 @@
+use super::history::{Stack, Versions};
+use super::{parse_playlist, PlaylistInfo, PlaylistStore, SeasonPosition, Track, TrackKind};
+use crate::error::Core;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Tracks are keyed by position, as a playlist may hold the same video more
+/// than once. The history keeps each version of the playlist as JSON.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tracks (
+    position INTEGER PRIMARY KEY,
+    bvid TEXT NOT NULL,
+    cid TEXT NOT NULL,
+    title TEXT NOT NULL DEFAULT '',
+    owner TEXT NOT NULL DEFAULT '',
+    duration INTEGER NOT NULL DEFAULT 0,
+    kind TEXT NOT NULL DEFAULT 'video',
+    season_id TEXT,
+    season_index INTEGER,
+    season_total INTEGER,
+    disabled INTEGER NOT NULL DEFAULT 0,
+    skip_intro INTEGER NOT NULL DEFAULT 0,
+    skip_outro INTEGER NOT NULL DEFAULT 0,
+    trim_start INTEGER NOT NULL DEFAULT 0,
+    trim_end INTEGER NOT NULL DEFAULT 0,
+    note TEXT NOT NULL DEFAULT ''
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS info (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    stack TEXT NOT NULL,
+    tracks TEXT NOT NULL
+);
+";
+
+const TRACK_COLUMNS: &str = "position, bvid, cid, title, owner, duration, kind, season_id,
+    season_index, season_total, disabled, skip_intro, skip_outro, trim_start, trim_end, note";
+
+fn open_database(database_path: &Path) -> Result<Connection, Core> {
+    let connection = Connection::open(database_path)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    connection.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Stores the playlist in `rosesong.db`.
+///
+/// A database that was never written is seeded from `playlist.toml` so
+/// switching backends keeps the existing playlist.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(database_path: &Path, toml_path: &Path) -> Result<Self, Core> {
+        let store = SqliteStore {
+            connection: Mutex::new(open_database(database_path)?),
+        };
+        if store.fingerprint()? == 0 && toml_path.exists() {
+            let (info, tracks) = parse_playlist(&std::fs::read_to_string(toml_path)?)?;
+            if !tracks.is_empty() {
+                log::info!("Importing {} tracks from playlist.toml", tracks.len());
+                store.save(&tracks)?;
+            }
+            if !info.is_empty() {
+                store.set_info(&info)?;
+            }
+        }
+        Ok(store)
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        lock(&self.connection)
+    }
+}
+
+impl PlaylistStore for SqliteStore {
+    fn load(&self) -> Result<Vec<Track>, Core> {
+        load_tracks(&self.connection())
+    }
+
+    /// Writes only the rows that changed, so editing a few tracks of a
+    /// large playlist leaves the rest alone.
+    fn save(&self, tracks: &[Track]) -> Result<(), Core> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let stored = load_tracks(&transaction)?;
+        if stored == tracks {
+            return Ok(());
+        }
+        {
+            let mut upsert = transaction.prepare(&format!(
+                "INSERT OR REPLACE INTO tracks ({TRACK_COLUMNS})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"
+            ))?;
+            for (position, track) in tracks.iter().enumerate() {
+                if stored.get(position) == Some(track) {
+                    continue;
+                }
+                upsert.execute(params![
+                    position,
+                    track.bvid,
+                    track.cid,
+                    track.title,
+                    track.owner,
+                    track.duration,
+                    track.kind.as_str(),
+                    track.season.as_ref().map(|season| &season.id),
+                    track.season.as_ref().map(|season| season.index),
+                    track.season.as_ref().map(|season| season.total),
+                    track.disabled,
+                    track.skip_intro,
+                    track.skip_outro,
+                    track.start,
+                    track.end,
+                    track.note
+                ])?;
+            }
+        }
+        transaction.execute("DELETE FROM tracks WHERE position >= ?1", [tracks.len()])?;
+        bump_revision(&transaction)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> Result<u64, Core> {
+        let revision: i64 = self.connection().query_row(
+            "SELECT COALESCE(MAX(value), 0) FROM meta WHERE key = 'revision'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(revision.unsigned_abs())
+    }
+
+    fn info(&self) -> Result<PlaylistInfo, Core> {
+        let connection = self.connection();
+        let value = |key: &str| -> Result<String, Core> {
+            let value = connection
+                .query_row("SELECT value FROM info WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            Ok(value.unwrap_or_default())
+        };
+        Ok(PlaylistInfo {
+            name: value("name")?,
+            description: value("description")?,
+            cover: value("cover")?,
+        })
+    }
+
+    fn set_info(&self, info: &PlaylistInfo) -> Result<(), Core> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        for (key, value) in [
+            ("name", &info.name),
+            ("description", &info.description),
+            ("cover", &info.cover),
+        ] {
+            transaction.execute(
+                "INSERT OR REPLACE INTO info (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+        }
+        bump_revision(&transaction)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn is_empty(&self) -> Result<bool, Core> {
+        let count: i64 = self
+            .connection()
+            .query_row("SELECT COUNT(*) FROM tracks", [], |row| row.get(0))?;
+        Ok(count == 0)
+    }
+}
+
+/// Marks the playlist as changed for [`PlaylistStore::fingerprint`].
+fn bump_revision(connection: &Connection) -> Result<(), Core> {
+    connection.execute(
+        "INSERT INTO meta (key, value) VALUES ('revision', 1)
+         ON CONFLICT(key) DO UPDATE SET value = value + 1",
+        [],
+    )?;
+    Ok(())
+}
+
+fn load_tracks(connection: &Connection) -> Result<Vec<Track>, Core> {
+    let mut statement = connection.prepare(
+        "SELECT bvid, cid, title, owner, duration, kind, season_id, season_index, season_total,
+                disabled, skip_intro, skip_outro, trim_start, trim_end, note
+         FROM tracks ORDER BY position",
+    )?;
+    let tracks = statement
+        .query_map([], |row| {
+            Ok(Track {
+                bvid: row.get(0)?,
+                cid: row.get(1)?,
+                title: row.get(2)?,
+                owner: row.get(3)?,
+                duration: row.get(4)?,
+                kind: TrackKind::parse(&row.get::<_, String>(5)?).unwrap_or_default(),
+                season: row
+                    .get::<_, Option<String>>(6)?
+                    .map(|id| -> rusqlite::Result<_> {
+                        Ok(SeasonPosition {
+                            id,
+                            index: row.get(7)?,
+                            total: row.get(8)?,
+                        })
+                    })
+                    .transpose()?,
+                disabled: row.get(9)?,
+                skip_intro: row.get(10)?,
+                skip_outro: row.get(11)?,
+                start: row.get(12)?,
+                end: row.get(13)?,
+                note: row.get(14)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tracks)
+}
+
+/// The history of the playlist, kept in its database.
+#[derive(Debug)]
+pub struct SqliteVersions {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteVersions {
+    pub fn open(database_path: &Path) -> Result<Self, Core> {
+        Ok(SqliteVersions {
+            connection: Mutex::new(open_database(database_path)?),
+        })
+    }
+}
+
+impl Versions for SqliteVersions {
+    fn push(&self, stack: Stack, tracks: &[Track], size: usize) -> Result<(), Core> {
+        let json = serde_json::to_string(tracks).map_err(|e| Core::Io(e.to_string()))?;
+        let mut connection = lock(&self.connection);
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO history (stack, tracks) VALUES (?1, ?2)",
+            params![stack.name(), json],
+        )?;
+        transaction.execute(
+            "DELETE FROM history WHERE stack = ?1 AND id NOT IN
+                 (SELECT id FROM history WHERE stack = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![stack.name(), size],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn pop(&self, stack: Stack) -> Result<Option<Vec<Track>>, Core> {
+        let connection = lock(&self.connection);
+        let newest: Option<(i64, String)> = connection
+            .query_row(
+                "SELECT id, tracks FROM history WHERE stack = ?1 ORDER BY id DESC LIMIT 1",
+                [stack.name()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((id, json)) = newest else {
+            return Ok(None);
+        };
+        let tracks = serde_json::from_str(&json)
+            .map_err(|e| Core::Io(format!("Invalid history entry {id}: {e}")))?;
+        connection.execute("DELETE FROM history WHERE id = ?1", [id])?;
+        Ok(Some(tracks))
+    }
+
+    fn clear(&self, stack: Stack) -> Result<(), Core> {
+        lock(&self.connection).execute("DELETE FROM history WHERE stack = ?1", [stack.name()])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqliteStore, SqliteVersions};
+    use crate::storage::history::{Stack, Versions};
+    use crate::storage::{PlaylistStore, Track};
+    use std::path::PathBuf;
+
+    fn track(bvid: &str) -> Track {
+        Track {
+            bvid: bvid.to_string(),
+            cid: "1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A database path of its own for each test, removed beforehand.
+    fn database(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rosesong-sqlite-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn duplicate_tracks_are_kept_in_order() {
+        let path = database("duplicates");
+        let store = SqliteStore::open(&path, &path.with_extension("toml")).unwrap();
+        let tracks = [track("BV1a"), track("BV1b"), track("BV1a")];
+        store.save(&tracks).unwrap();
+        assert_eq!(store.load().unwrap(), tracks);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn only_changed_rows_are_written() {
+        let path = database("incremental");
+        let store = SqliteStore::open(&path, &path.with_extension("toml")).unwrap();
+        let mut tracks: Vec<Track> = ["BV1a", "BV1b", "BV1c", "BV1d"].map(track).into();
+        store.save(&tracks).unwrap();
+        tracks[2].disabled = true;
+        tracks.pop();
+        let before = store.connection().total_changes();
+        store.save(&tracks).unwrap();
+        // The changed track, the removed one and the revision
+        assert_eq!(store.connection().total_changes() - before, 3);
+        assert_eq!(store.load().unwrap(), tracks);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn history_is_kept_in_the_database() {
+        let path = database("history");
+        let versions = SqliteVersions::open(&path).unwrap();
+        for bvid in ["BV1a", "BV1b", "BV1c"] {
+            versions.push(Stack::Undo, &[track(bvid)], 2).unwrap();
+        }
+        versions.push(Stack::Redo, &[track("BV1d")], 2).unwrap();
+        assert_eq!(
+            versions.pop(Stack::Undo).unwrap(),
+            Some(vec![track("BV1c")])
+        );
+        assert_eq!(
+            versions.pop(Stack::Undo).unwrap(),
+            Some(vec![track("BV1b")])
+        );
+        assert_eq!(versions.pop(Stack::Undo).unwrap(), None);
+        versions.clear(Stack::Redo).unwrap();
+        assert_eq!(versions.pop(Stack::Redo).unwrap(), None);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,124 @@
+use super::{
+    parse_toml, parse_versioned_toml, playlist_to_toml, PlaylistInfo, PlaylistStore, Track,
+    PLAYLIST_VERSION,
+};
+use crate::error::Core;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+/// Stores the playlist in `playlists/playlist.toml`.
+///
+/// Each save keeps the file it replaces as `playlist.toml.bak`, which is
+/// loaded instead when the playlist has been edited into an invalid file.
+#[derive(Debug)]
+pub struct TomlStore {
+    path: PathBuf,
+}
+
+impl TomlStore {
+    pub fn new(path: PathBuf) -> Result<Self, Core> {
+        if !path.exists() {
+            std::fs::write(&path, "")?;
+        }
+        Ok(TomlStore { path })
+    }
+
+    fn backup_path(&self) -> PathBuf {
+        self.path.with_extension("toml.bak")
+    }
+
+    /// Tracks of the backup, for a playlist that failed to load with
+    /// `error`, which is returned when there is no usable backup either.
+    fn load_backup(&self, error: Core) -> Result<Vec<Track>, Core> {
+        let backup = self.backup_path();
+        let Some(tracks) = std::fs::read_to_string(&backup)
+            .ok()
+            .and_then(|content| parse_toml(&content).ok())
+        else {
+            return Err(error);
+        };
+        log::error!(
+            "Failed to load {}, using the backup {} instead: {}",
+            self.path.display(),
+            backup.display(),
+            error
+        );
+        Ok(tracks)
+    }
+
+    fn write(&self, info: &PlaylistInfo, tracks: &[Track]) -> Result<(), Core> {
+        if let Ok(old) = std::fs::read_to_string(&self.path) {
+            if parse_versioned_toml(&old).is_ok() {
+                if !old.trim().is_empty() {
+                    std::fs::write(self.backup_path(), &old)?;
+                }
+            } else {
+                // Hand edits that broke the file are kept, not overwritten
+                let invalid = self.path.with_extension("toml.invalid");
+                std::fs::write(&invalid, &old)?;
+                log::warn!(
+                    "Replacing the invalid {}, kept as {}",
+                    self.path.display(),
+                    invalid.display()
+                );
+            }
+        }
+        // Renaming over the file means no reader ever sees half of it
+        let temporary = self.path.with_extension("toml.tmp");
+        std::fs::write(&temporary, playlist_to_toml(info, tracks)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+impl PlaylistStore for TomlStore {
+    fn load(&self) -> Result<Vec<Track>, Core> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let (tracks, version) = match parse_versioned_toml(&content) {
+            Ok((playlist, version)) => (playlist.tracks, version),
+            Err(e) => return self.load_backup(e),
+        };
+        if version < PLAYLIST_VERSION {
+            // Keep the old file around in case the upgrade loses something
+            let backup = self.path.with_extension(format!("toml.v{version}.bak"));
+            std::fs::write(&backup, &content)?;
+            self.save(&tracks)?;
+            log::info!(
+                "Upgraded {} from version {version} to {PLAYLIST_VERSION}, backup at {}",
+                self.path.display(),
+                backup.display()
+            );
+        }
+        Ok(tracks)
+    }
+
+    fn save(&self, tracks: &[Track]) -> Result<(), Core> {
+        let info = self.info().unwrap_or_default();
+        self.write(&info, tracks)
+    }
+
+    fn fingerprint(&self) -> Result<u64, Core> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    fn info(&self) -> Result<PlaylistInfo, Core> {
+        let content = std::fs::read_to_string(&self.path)?;
+        match parse_versioned_toml(&content) {
+            Ok((playlist, _)) => Ok(playlist.info),
+            Err(e) => std::fs::read_to_string(self.backup_path())
+                .ok()
+                .and_then(|content| parse_versioned_toml(&content).ok())
+                .map(|(playlist, _)| playlist.info)
+                .ok_or(e),
+        }
+    }
+
+    fn set_info(&self, info: &PlaylistInfo) -> Result<(), Core> {
+        let tracks = self.load()?;
+        self.write(info, &tracks)
+    }
+}
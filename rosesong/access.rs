@@ -0,0 +1,115 @@
+use crate::error::App;
+use crate::transport::Peer;
+use log::warn;
+use rosesong_core::ipc::{self, Request};
+use rosesong_core::settings::ServiceSettings;
+use std::collections::HashSet;
+
+/// Who may control playback through a shared control socket: root, the
+/// user running the daemon and the configured controllers. Anyone may
+/// query it. Names are looked up in `/etc/passwd` and `/etc/group` once,
+/// at startup.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    shared: bool,
+    uids: HashSet<u32>,
+    gids: HashSet<u32>,
+}
+
+impl AccessPolicy {
+    /// Fails where a shared socket is configured but its peers cannot be
+    /// checked, rather than letting everyone control playback.
+    pub fn new(service: &ServiceSettings) -> Result<Self, App> {
+        let shared = ipc::is_socket_shared();
+        if shared && !cfg!(target_os = "linux") {
+            return Err(App::Init(
+                "A shared control socket needs Linux to check who may control playback".to_string(),
+            ));
+        }
+        let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+        let group = std::fs::read_to_string("/etc/group").unwrap_or_default();
+        let mut policy = AccessPolicy {
+            shared,
+            ..AccessPolicy::default()
+        };
+        for controller in &service.controllers {
+            let found = match controller.strip_prefix('@') {
+                Some(name) => policy.add_group(name, &passwd, &group),
+                None => policy.add_user(controller, &passwd),
+            };
+            if !found {
+                warn!(
+                    "Unknown controller {} in [service], ignoring it",
+                    controller
+                );
+            }
+        }
+        Ok(policy)
+    }
+
+    fn add_user(&mut self, name: &str, passwd: &str) -> bool {
+        let Some(uid) = name.parse().ok().or_else(|| find_id(passwd, name)) else {
+            return false;
+        };
+        self.uids.insert(uid);
+        true
+    }
+
+    /// Adds the group `name` and its members, so those who only have it as
+    /// a supplementary group count too.
+    fn add_group(&mut self, name: &str, passwd: &str, group: &str) -> bool {
+        let Some(entry) = group
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.len() >= 4 && fields[0] == name)
+        else {
+            return false;
+        };
+        let Ok(gid) = entry[2].parse() else {
+            return false;
+        };
+        self.gids.insert(gid);
+        for member in entry[3].split(',').filter(|member| !member.is_empty()) {
+            self.add_user(member, passwd);
+        }
+        true
+    }
+
+    /// Whether `peer` may make `request`.
+    pub fn allows(&self, peer: Option<Peer>, request: &Request) -> bool {
+        if !self.shared || request.is_query() {
+            return true;
+        }
+        self.can_control(peer)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn can_control(&self, peer: Option<Peer>) -> bool {
+        // A peer whose credentials could not be read may be anyone
+        let Some(peer) = peer else {
+            return false;
+        };
+        // SAFETY: geteuid cannot fail and has no preconditions
+        let owner = unsafe { libc::geteuid() };
+        peer.uid == 0
+            || peer.uid == owner
+            || self.uids.contains(&peer.uid)
+            || self.gids.contains(&peer.gid)
+    }
+
+    /// Elsewhere a shared socket is refused by `new`, so this is never
+    /// asked; should it be, nobody is let through.
+    #[cfg(not(target_os = "linux"))]
+    fn can_control(&self, _peer: Option<Peer>) -> bool {
+        false
+    }
+}
+
+/// Id in the third field of the `/etc/passwd` style line for `name`.
+fn find_id(file: &str, name: &str) -> Option<u32> {
+    file.lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 3 && fields[0] == name)?[2]
+        .parse()
+        .ok()
+}
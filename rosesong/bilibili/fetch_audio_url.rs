@@ -1,16 +1,251 @@
 use crate::error::App;
-use reqwest::Client;
+use rosesong_core::bilibili::api::BilibiliApi;
+use rosesong_core::bilibili::limiter;
+use rosesong_core::settings::AudioTrackPolicy;
+use rosesong_core::storage::{Track, TrackKind};
 use serde_json::Value;
 
 const BASE_API_URL: &str = "https://api.bilibili.com/x/player/playurl?fnval=16";
+const EPISODE_API_URL: &str = "https://api.bilibili.com/pgc/player/web/playurl?fnval=16";
+const SONG_API_URL: &str =
+    "https://www.bilibili.com/audio/music-service-c/web/url?privilege=2&quality=2";
 
-pub async fn fetch_audio_url(client: &Client, bvid: &str, cid: &str) -> Result<String, App> {
+/// One of the audio streams of a track, with the format the API reports.
+#[derive(Clone, Debug)]
+pub struct AudioStream {
+    /// Quality id, such as `30280` for 192K.
+    pub id: u32,
+    pub url: String,
+    pub codec: String,
+    /// Bitrate in bits per second.
+    pub bandwidth: Option<u32>,
+    /// The same stream on other CDN hosts.
+    pub backup_urls: Vec<String>,
+}
+
+impl AudioStream {
+    /// When the URL expires, in seconds since the epoch, from the
+    /// `deadline` parameter Bilibili signs its stream URLs with.
+    pub fn deadline(&self) -> Option<u64> {
+        reqwest::Url::parse(&self.url)
+            .ok()?
+            .query_pairs()
+            .find(|(name, _)| name == "deadline")
+            .and_then(|(_, value)| value.parse().ok())
+    }
+
+    /// The URL on CDN mirror `mirror`, where `0` is the main URL and the
+    /// backups follow, wrapping around after the last.
+    pub fn mirror_url(&self, mirror: usize) -> &str {
+        match mirror % (self.backup_urls.len() + 1) {
+            0 => &self.url,
+            index => &self.backup_urls[index - 1],
+        }
+    }
+}
+
+/// The host part of `url`, for logs.
+pub fn host(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Which of a track's audio streams to play.
+#[derive(Clone, Copy, Debug)]
+pub enum StreamChoice {
+    Policy(AudioTrackPolicy),
+    /// Picked by the user; falls back to the first stream when out of range.
+    Index(usize),
+}
+
+impl StreamChoice {
+    pub fn pick(self, streams: &[AudioStream]) -> usize {
+        let by_bandwidth = streams
+            .iter()
+            .enumerate()
+            .map(|(index, stream)| (stream.bandwidth.unwrap_or_default(), index));
+        match self {
+            StreamChoice::Index(index) if index < streams.len() => index,
+            StreamChoice::Index(_) | StreamChoice::Policy(AudioTrackPolicy::First) => 0,
+            StreamChoice::Policy(AudioTrackPolicy::Highest) => by_bandwidth
+                .max_by_key(|(bandwidth, _)| *bandwidth)
+                .map_or(0, |(_, index)| index),
+            StreamChoice::Policy(AudioTrackPolicy::Lowest) => by_bandwidth
+                .min_by_key(|(bandwidth, _)| *bandwidth)
+                .map_or(0, |(_, index)| index),
+        }
+    }
+}
+
+/// All audio streams the API offers for the track, in the API's order.
+pub async fn fetch_track_streams(
+    api: &impl BilibiliApi,
+    track: &Track,
+) -> Result<Vec<AudioStream>, App> {
+    match track.kind {
+        TrackKind::Video => fetch_audio_streams(api, &track.bvid, &track.cid).await,
+        TrackKind::Audio => fetch_song_stream(api, &track.cid).await,
+        TrackKind::Episode => {
+            let ep_id = track.bvid.trim_start_matches("ep");
+            fetch_episode_streams(api, ep_id, &track.cid).await
+        }
+    }
+}
+
+async fn fetch_audio_streams(
+    api: &impl BilibiliApi,
+    bvid: &str,
+    cid: &str,
+) -> Result<Vec<AudioStream>, App> {
     let url = format!("{BASE_API_URL}&bvid={bvid}&cid={cid}");
     log::info!("Fetching audio URL");
-    let response = client.get(&url).send().await?;
-    let json: Value = response.json().await?;
-    json["data"]["dash"]["audio"][0]["baseUrl"]
-        .as_str()
-        .map(std::string::ToString::to_string)
-        .ok_or_else(|| App::DataParsing("解析音频URL失败".to_string()))
+    let json: Value = limiter::get(api, &url).await?;
+    parse_dash_audio(&json["data"])
+}
+
+/// Episodes use the PGC playurl API, which answers in `result` instead of
+/// `data`. Members-only episodes need a logged-in `Cookie` header.
+async fn fetch_episode_streams(
+    api: &impl BilibiliApi,
+    ep_id: &str,
+    cid: &str,
+) -> Result<Vec<AudioStream>, App> {
+    let url = format!("{EPISODE_API_URL}&ep_id={ep_id}&cid={cid}");
+    log::info!("Fetching episode audio URL");
+    let json: Value = limiter::get(api, &url).await?;
+    if let Some(message) = json["code"]
+        .as_i64()
+        .filter(|code| *code != 0)
+        .and(json["message"].as_str())
+    {
+        return Err(App::DataParsing(format!("获取剧集音频失败: {message}")));
+    }
+    parse_dash_audio(&json["result"])
+}
+
+fn parse_dash_audio(data: &Value) -> Result<Vec<AudioStream>, App> {
+    let streams: Vec<AudioStream> = data["dash"]["audio"]
+        .as_array()
+        .map(|audio| audio.iter().filter_map(parse_stream).collect())
+        .unwrap_or_default();
+    if streams.is_empty() {
+        return Err(App::DataParsing("解析音频URL失败".to_string()));
+    }
+    Ok(streams)
+}
+
+/// A song of the audio section has one stream; the other CDN links are
+/// mirrors of it.
+async fn fetch_song_stream(api: &impl BilibiliApi, sid: &str) -> Result<Vec<AudioStream>, App> {
+    let url = format!("{SONG_API_URL}&sid={sid}");
+    log::info!("Fetching song URL");
+    let json: Value = limiter::get(api, &url).await?;
+    let mut cdns = json["data"]["cdns"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(std::string::ToString::to_string);
+    let url = cdns
+        .next()
+        .ok_or_else(|| App::DataParsing("解析音频URL失败".to_string()))?;
+    Ok(vec![AudioStream {
+        id: json["data"]["type"]
+            .as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .unwrap_or_default(),
+        url,
+        codec: String::new(),
+        bandwidth: None,
+        backup_urls: cdns.collect(),
+    }])
+}
+
+fn parse_stream(audio: &Value) -> Option<AudioStream> {
+    Some(AudioStream {
+        id: audio["id"]
+            .as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .unwrap_or_default(),
+        url: audio["baseUrl"].as_str()?.to_string(),
+        codec: audio["codecs"].as_str().unwrap_or_default().to_string(),
+        bandwidth: audio["bandwidth"]
+            .as_u64()
+            .and_then(|bandwidth| u32::try_from(bandwidth).ok()),
+        backup_urls: audio["backupUrl"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(std::string::ToString::to_string)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch_track_streams, StreamChoice};
+    use rosesong_core::bilibili::mock::MockApi;
+    use rosesong_core::settings::AudioTrackPolicy;
+    use rosesong_core::storage::{Track, TrackKind};
+    use serde_json::json;
+
+    fn track(bvid: &str, cid: &str, kind: TrackKind) -> Track {
+        Track {
+            bvid: bvid.to_string(),
+            cid: cid.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_video_resolves_to_its_dash_audio_streams() {
+        let api = MockApi::new().json(
+            "x/player/playurl?fnval=16&bvid=BV17x411w7KC&cid=279786",
+            json!({ "code": 0, "data": { "dash": { "audio": [
+                { "id": 30216, "baseUrl": "https://a.example/64k", "codecs": "mp4a.40.2",
+                  "bandwidth": 67_000, "backupUrl": ["https://b.example/64k"] },
+                { "id": 30280, "baseUrl": "https://a.example/192k", "codecs": "mp4a.40.2",
+                  "bandwidth": 192_000 },
+            ]}}}),
+        );
+        let streams = fetch_track_streams(&api, &track("BV17x411w7KC", "279786", TrackKind::Video))
+            .await
+            .unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].mirror_url(1), "https://b.example/64k");
+        let highest = StreamChoice::Policy(AudioTrackPolicy::Highest).pick(&streams);
+        assert_eq!(streams[highest].id, 30280);
+    }
+
+    #[tokio::test]
+    async fn songs_and_episodes_use_their_own_apis() {
+        let api = MockApi::new()
+            .json(
+                "audio/music-service-c/web/url?privilege=2&quality=2&sid=99",
+                json!({ "code": 0, "data": { "type": 2, "cdns": ["https://a.example/song", "https://b.example/song"] } }),
+            )
+            .json(
+                "pgc/player/web/playurl?fnval=16&ep_id=123&cid=5",
+                json!({ "code": -10403, "message": "大会员专享" }),
+            );
+        let streams = fetch_track_streams(&api, &track("au99", "99", TrackKind::Audio))
+            .await
+            .unwrap();
+        assert_eq!(streams[0].url, "https://a.example/song");
+        assert_eq!(streams[0].backup_urls, ["https://b.example/song"]);
+
+        let episode = fetch_track_streams(&api, &track("ep123", "5", TrackKind::Episode)).await;
+        assert!(episode.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_video_without_audio_is_an_error() {
+        let api = MockApi::new().json("x/player/playurl", json!({ "code": 0, "data": {} }));
+        let streams = fetch_track_streams(&api, &track("BV1", "1", TrackKind::Video)).await;
+        assert!(streams.is_err());
+    }
 }
@@ -0,0 +1,148 @@
+use crate::error::App;
+use crate::player::network::build_client;
+use crate::player::playlist::Track;
+use crate::player::Audio;
+use log::{info, warn};
+use rosesong_core::bilibili::api::BilibiliApi;
+use rosesong_core::bilibili::{bvid_to_aid, limiter};
+use rosesong_core::settings::NetworkSettings;
+use rosesong_core::storage::TrackKind;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::watch;
+
+const HISTORY_API_URL: &str = "https://api.bilibili.com/x/v2/history/report";
+/// How often the progress of the playing video is reported, as often as the
+/// web player does.
+const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+struct ReportResponse {
+    code: i64,
+    #[serde(default)]
+    message: String,
+}
+
+/// Reports how far the videos played got to the watch history of the
+/// account logged in through the `Cookie` header, so the Bilibili app lists
+/// them as watched and continues where playback stopped. Runs until the
+/// stop signal fires.
+pub async fn run_history_reporter(
+    network: NetworkSettings,
+    audio: Audio,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let csrf = network.csrf_token().map(str::to_string).ok_or_else(|| {
+        App::Init("report_history needs a Cookie with bili_jct in [network.headers]".to_string())
+    })?;
+    let client = build_client(&network, &network.request_headers())?;
+    let mut changes = audio.subscribe_changes();
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    info!("Reporting playback progress to the Bilibili watch history");
+
+    // The video last seen playing or paused, with its position then
+    let mut last: Option<(Track, u64, bool)> = None;
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        let tick = tokio::select! {
+            _ = interval.tick() => true,
+            Ok(()) = changes.changed() => false,
+            _ = stop_receiver.changed() => break,
+        };
+        let status = audio.status().await;
+        let playing = status.state == "playing";
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let current = status
+            .track
+            .filter(|track| track.kind != TrackKind::Audio)
+            .filter(|_| playing || status.state == "paused")
+            .map(|track| (track, status.position as u64, playing));
+
+        let was_playing = last.as_ref().is_some_and(|(_, _, playing)| *playing);
+        let left = last.take().filter(|(track, _, _)| {
+            current
+                .as_ref()
+                .map_or(true, |(current, _, _)| current.bvid != track.bvid)
+        });
+        if let Some((track, position, _)) = left {
+            report(&client, &csrf, &track, position).await;
+        }
+        if let Some((track, position, playing)) = &current {
+            if (tick && *playing) || (!playing && was_playing) {
+                report(&client, &csrf, track, *position).await;
+            }
+        }
+        last = current;
+    }
+
+    Ok(())
+}
+
+/// Reports that `track` was played up to `progress` seconds.
+async fn report(api: &impl BilibiliApi, csrf: &str, track: &Track, progress: u64) {
+    let Some(aid) = bvid_to_aid(&track.bvid) else {
+        return;
+    };
+    let form = [
+        ("aid", aid.to_string()),
+        ("cid", track.cid.clone()),
+        ("progress", progress.to_string()),
+        ("csrf", csrf.to_string()),
+    ];
+    match limiter::post::<ReportResponse>(api, HISTORY_API_URL, &form).await {
+        Ok(response) if response.code == 0 => {}
+        Ok(response) => warn!(
+            "Bilibili refused the progress of {}: {}",
+            track.bvid, response.message
+        ),
+        Err(e) => warn!("Failed to report the progress of {}: {}", track.bvid, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{report, HISTORY_API_URL};
+    use rosesong_core::bilibili::mock::MockApi;
+    use rosesong_core::storage::Track;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn progress_is_reported_by_av_number() {
+        let api = MockApi::new().json(HISTORY_API_URL, json!({ "code": 0 }));
+        let track = Track {
+            bvid: "BV17x411w7KC".to_string(),
+            cid: "279786".to_string(),
+            ..Default::default()
+        };
+        report(&api, "token", &track, 42).await;
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 1);
+        let form: Vec<(&str, &str)> = requests[0]
+            .form
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            form,
+            [
+                ("aid", "170001"),
+                ("cid", "279786"),
+                ("progress", "42"),
+                ("csrf", "token"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tracks_without_an_av_number_are_not_reported() {
+        let api = MockApi::new();
+        let track = Track {
+            bvid: "ep123".to_string(),
+            cid: "5".to_string(),
+            ..Default::default()
+        };
+        report(&api, "token", &track, 42).await;
+        assert!(api.requests().is_empty());
+    }
+}
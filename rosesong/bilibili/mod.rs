@@ -1 +1,3 @@
 pub mod fetch_audio_url;
+pub mod history;
+pub mod ytdlp;
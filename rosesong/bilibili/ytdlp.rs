@@ -0,0 +1,62 @@
+use crate::bilibili::fetch_audio_url::AudioStream;
+use crate::error::App;
+use rosesong_core::storage::{Track, TrackKind};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Page yt-dlp extracts `track` from. yt-dlp only knows parts of a
+/// multi-part video by number, which tracks do not keep, so those play
+/// their first part.
+pub fn page_url(track: &Track) -> String {
+    match track.kind {
+        TrackKind::Video => format!("https://www.bilibili.com/video/{}", track.bvid),
+        TrackKind::Audio => format!("https://www.bilibili.com/audio/au{}", track.cid),
+        TrackKind::Episode => format!("https://www.bilibili.com/bangumi/play/{}", track.bvid),
+    }
+}
+
+/// Asks the yt-dlp at `program` for the best audio stream of `track`,
+/// sending `headers` the way the player does.
+pub async fn fetch_stream(
+    program: &str,
+    track: &Track,
+    headers: &[(String, String)],
+) -> Result<AudioStream, App> {
+    let mut command = Command::new(program);
+    command
+        .args(["--format", "bestaudio", "--no-playlist", "--no-warnings"])
+        .args(["--print", "%(acodec)s %(abr)s %(url)s"]);
+    for (name, value) in headers {
+        command.arg("--add-header").arg(format!("{name}:{value}"));
+    }
+    let output = command
+        .arg(page_url(track))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| App::Fetch(format!("Failed to run {program}: {e}")))?;
+    if !output.status.success() {
+        return Err(App::Fetch(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.trim().splitn(3, ' ');
+    let (Some(codec), Some(kbps), Some(url)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(App::Fetch(format!(
+            "Unexpected output of {program}: {stdout}"
+        )));
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let bandwidth = kbps.parse::<f64>().ok().map(|kbps| (kbps * 1000.0) as u32);
+    Ok(AudioStream {
+        id: 0,
+        url: url.to_string(),
+        codec: codec.to_string(),
+        bandwidth,
+        backup_urls: Vec::new(),
+    })
+}
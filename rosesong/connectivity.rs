@@ -0,0 +1,48 @@
+use crate::error::App;
+use crate::player::Command;
+use futures_util::StreamExt;
+use log::info;
+use tokio::sync::{mpsc, watch};
+use zbus::{proxy, Connection};
+
+/// `NM_STATE_CONNECTED_GLOBAL`: full internet access.
+const CONNECTED_GLOBAL: u32 = 70;
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait NetworkManager {
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+}
+
+/// Asks the player to reload an interrupted track whenever `NetworkManager`
+/// reports the internet connection is back, until the stop signal fires.
+pub async fn run_connectivity_listener(
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let connection = Connection::system().await?;
+    let network_manager = NetworkManagerProxy::new(&connection).await?;
+    let mut state_changes = network_manager.receive_state_changed().await;
+    let mut online = network_manager.state().await? >= CONNECTED_GLOBAL;
+    info!("Listening for network changes");
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            Some(change) = state_changes.next() => {
+                let now_online = change.get().await? >= CONNECTED_GLOBAL;
+                if now_online && !online {
+                    info!("Network connection is back");
+                    command_sender.send(Command::Reconnect).await?;
+                }
+                online = now_online;
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+    Ok(())
+}
@@ -1,17 +1,38 @@
-use std::sync::Arc;
+use std::time::Duration;
 
-use log::info;
-use tokio::sync::{mpsc, watch, Mutex};
-use zbus::{fdo, interface, ConnectionBuilder};
+use log::{info, warn};
+use rosesong_core::ipc::{Request, Status};
+use tokio::sync::{mpsc, watch};
+use zbus::{fdo, interface, ConnectionBuilder, InterfaceRef, SignalContext};
 
-use crate::player::playlist::PlayMode;
-use crate::player::Command;
+use crate::osd;
+use crate::player::backend::{Channels, MAX_PITCH};
+use crate::player::playlist::{PlayMode, Repeat};
+use crate::player::{Audio, Command};
+use crate::socket::RequestHandler;
+
+/// How long quitting waits for playback to stop beyond its fade-out.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Clone)]
 pub struct PlayerDBus {
     tx: mpsc::Sender<Command>,
+    audio: Audio,
     stop_signal: watch::Sender<()>,
-    playlist_empty: Arc<Mutex<bool>>,
+}
+
+impl PlayerDBus {
+    pub fn new(
+        command_sender: mpsc::Sender<Command>,
+        audio: Audio,
+        stop_signal: watch::Sender<()>,
+    ) -> Self {
+        PlayerDBus {
+            tx: command_sender,
+            audio,
+            stop_signal,
+        }
+    }
 }
 
 #[interface(name = "org.rosesong.Player")]
@@ -29,6 +50,19 @@ impl PlayerDBus {
         Ok(())
     }
 
+    /// Plays the tracks of season `id` in order, starting from its first.
+    async fn play_season(&self, id: String) -> fdo::Result<()> {
+        self.tx.send(Command::PlaySeason(id)).await.unwrap();
+        Ok(())
+    }
+
+    /// Plays the tracks queued with `rsg later`, each once, then goes back
+    /// to the playlist.
+    async fn play_later(&self) -> fdo::Result<()> {
+        self.tx.send(Command::PlayLater).await.unwrap();
+        Ok(())
+    }
+
     async fn pause(&self) -> fdo::Result<()> {
         self.tx.send(Command::Pause).await.unwrap();
         Ok(())
@@ -44,66 +78,388 @@ impl PlayerDBus {
         Ok(())
     }
 
+    /// Stops playback but keeps the daemon running, so `play` resumes
+    /// without starting it again.
     async fn stop(&self) -> fdo::Result<()> {
         self.tx.send(Command::Stop).await.unwrap();
+        Ok(())
+    }
+
+    async fn quit(&self) -> fdo::Result<()> {
+        self.tx.send(Command::Stop).await.unwrap();
+        self.audio.wait_stopped(QUIT_TIMEOUT).await;
         self.stop_signal.send(()).unwrap();
         Ok(())
     }
 
     async fn set_mode(&self, mode: String) -> fdo::Result<()> {
-        let mode = match mode.as_str() {
-            "Loop" => PlayMode::Loop,
-            "Shuffle" => PlayMode::Shuffle,
-            "Repeat" => PlayMode::Repeat,
-            _ => return Err(fdo::Error::Failed("Invalid mode".into())),
-        };
+        let mode =
+            PlayMode::parse(&mode).ok_or_else(|| fdo::Error::Failed("Invalid mode".into()))?;
         self.tx.send(Command::SetPlayMode(mode)).await.unwrap();
         Ok(())
     }
 
-    async fn playlist_change(&self) -> fdo::Result<()> {
-        let mut playlist_empty = self.playlist_empty.lock().await;
-        if *playlist_empty {
-            *playlist_empty = false;
-            self.tx.send(Command::PlaylistIsEmpty).await.unwrap();
-        } else {
-            self.tx.send(Command::ReloadPlaylist).await.unwrap();
+    async fn set_shuffle(&self, shuffle: bool) -> fdo::Result<()> {
+        self.tx.send(Command::SetShuffle(shuffle)).await.unwrap();
+        Ok(())
+    }
+
+    /// Turns radio mode, which continues with related videos, on or off.
+    async fn set_radio(&self, enabled: bool) -> fdo::Result<()> {
+        self.tx.send(Command::SetRadio(enabled)).await.unwrap();
+        Ok(())
+    }
+
+    /// Plays both channels, only the `left` or `right` one, or a `mix` of
+    /// them, or `stereo` as they are.
+    async fn set_channels(&self, channels: String) -> fdo::Result<()> {
+        let channels = Channels::parse(&channels)
+            .ok_or_else(|| fdo::Error::InvalidArgs("Invalid channels".into()))?;
+        self.tx.send(Command::SetChannels(channels)).await.unwrap();
+        Ok(())
+    }
+
+    /// Shifts the pitch by up to 6 semitones either way, keeping the tempo.
+    async fn set_pitch(&self, semitones: i32) -> fdo::Result<()> {
+        if !(-MAX_PITCH..=MAX_PITCH).contains(&semitones) {
+            return Err(fdo::Error::InvalidArgs("Invalid pitch".into()));
         }
+        self.tx.send(Command::SetPitch(semitones)).await.unwrap();
         Ok(())
     }
 
+    /// Sets the repeat policy to `off`, `one` or `all`.
+    async fn set_repeat(&self, repeat: String) -> fdo::Result<()> {
+        let repeat = Repeat::parse(&repeat)
+            .ok_or_else(|| fdo::Error::InvalidArgs("Invalid repeat".into()))?;
+        self.tx.send(Command::SetRepeat(repeat)).await.unwrap();
+        Ok(())
+    }
+
+    /// Plays only the first `seconds` of every track, or whole tracks when 0.
+    async fn set_preview(&self, seconds: u64) -> fdo::Result<()> {
+        let preview = (seconds > 0).then(|| Duration::from_secs(seconds));
+        self.tx.send(Command::SetPreview(preview)).await.unwrap();
+        Ok(())
+    }
+
+    async fn seek(&self, position: f64) -> fdo::Result<()> {
+        if !position.is_finite() || position < 0.0 {
+            return Err(fdo::Error::InvalidArgs("Invalid position".into()));
+        }
+        self.tx
+            .send(Command::Seek(Duration::from_secs_f64(position)))
+            .await
+            .unwrap();
+        Ok(())
+    }
+
+    async fn set_volume(&self, volume: f64) -> fdo::Result<()> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(fdo::Error::InvalidArgs(
+                "Volume must be between 0 and 1".into(),
+            ));
+        }
+        self.tx.send(Command::SetVolume(volume)).await.unwrap();
+        Ok(())
+    }
+
+    /// Changes the volume by `delta`, clamped to between 0 and 1.
+    async fn adjust_volume(&self, delta: f64) -> fdo::Result<()> {
+        if !delta.is_finite() {
+            return Err(fdo::Error::InvalidArgs("Invalid volume change".into()));
+        }
+        self.tx.send(Command::AdjustVolume(delta)).await.unwrap();
+        Ok(())
+    }
+
+    /// Raises the volume by the configured `volume_step`.
+    async fn volume_up(&self) -> fdo::Result<()> {
+        self.tx.send(Command::VolumeUp).await.unwrap();
+        Ok(())
+    }
+
+    /// Lowers the volume by the configured `volume_step`.
+    async fn volume_down(&self) -> fdo::Result<()> {
+        self.tx.send(Command::VolumeDown).await.unwrap();
+        Ok(())
+    }
+
+    /// Reloads the playlist from the store. Emptying it stops playback and
+    /// enters the `idle` state until tracks are added again.
+    async fn playlist_change(&self) -> fdo::Result<()> {
+        self.tx.send(Command::ReloadPlaylist).await.unwrap();
+        Ok(())
+    }
+
+    /// Same as `playlist_change`, kept for older clients.
     async fn playlist_is_empty(&self) -> fdo::Result<()> {
-        self.tx.send(Command::Stop).await.unwrap();
-        let mut playlist_empty = self.playlist_empty.lock().await;
-        *playlist_empty = true;
+        self.playlist_change().await
+    }
+
+    /// Current playback status as a JSON-encoded `Status`.
+    async fn status(&self) -> fdo::Result<String> {
+        serde_json::to_string(&self.audio.status().await)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Latency of recent track starts as a JSON-encoded `PerfStats`.
+    fn perf_stats(&self) -> fdo::Result<String> {
+        serde_json::to_string(&self.audio.perf_stats())
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Memory use of the daemon as a JSON-encoded `DebugInfo`.
+    async fn debug_info(&self) -> fdo::Result<String> {
+        serde_json::to_string(&self.audio.debug_info().await)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Audio streams of the current track as a JSON-encoded list of
+    /// `AudioTrack`.
+    async fn audio_tracks(&self) -> fdo::Result<String> {
+        serde_json::to_string(&self.audio.audio_tracks().await)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Reloads the current track with the audio stream at `index` in
+    /// `audio_tracks`, continuing from the same position.
+    async fn set_audio_track(&self, index: u32) -> fdo::Result<()> {
+        let index = index as usize;
+        if index >= self.audio.audio_tracks().await.len() {
+            return Err(fdo::Error::InvalidArgs("No such audio track".into()));
+        }
+        self.tx.send(Command::SetAudioTrack(index)).await.unwrap();
         Ok(())
     }
+
+    /// Emitted with the new JSON-encoded `Status` when the playback state,
+    /// track or play mode changes.
+    #[zbus(signal)]
+    async fn status_changed(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+
+    /// Emitted with the text an OSD should show, empty when nothing plays.
+    #[zbus(signal)]
+    async fn osd_text(ctxt: &SignalContext<'_>, text: &str) -> zbus::Result<()>;
+
+    /// Emitted with `true` when the playlist becomes empty and the player
+    /// goes idle, and with `false` once tracks are added again.
+    #[zbus(signal)]
+    async fn playlist_empty(ctxt: &SignalContext<'_>, empty: bool) -> zbus::Result<()>;
+
+    /// Emitted with a JSON-encoded `PlaybackError` when a track fails to
+    /// load, before it is skipped.
+    #[zbus(signal)]
+    async fn playback_error(ctxt: &SignalContext<'_>, error: &str) -> zbus::Result<()>;
+
+    /// `Playing`, `Paused` or `Stopped`, as in MPRIS.
+    #[zbus(property)]
+    async fn playback_status(&self) -> String {
+        playback_status(&self.audio.status().await).to_string()
+    }
+
+    /// Output volume between `0` and `1`.
+    #[zbus(property)]
+    async fn volume(&self) -> f64 {
+        self.audio.status().await.volume
+    }
+
+    /// `None`, `Track` or `Playlist` for the repeat policy, as in MPRIS.
+    #[zbus(property)]
+    async fn loop_status(&self) -> String {
+        loop_status(&self.audio.status().await).to_string()
+    }
+
+    #[zbus(property)]
+    async fn shuffle(&self) -> bool {
+        self.audio.status().await.shuffle
+    }
+
+    /// The track being played as a JSON-encoded `Track`, or an empty string.
+    #[zbus(property)]
+    async fn current_track(&self) -> String {
+        current_track(&self.audio.status().await)
+    }
+
+    /// Whole seconds until the current track stops, or `0` when unknown.
+    /// Like the MPRIS `Position` it changes all the time without emitting
+    /// `PropertiesChanged`, so poll it or follow `StatusChanged`.
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn remaining(&self) -> u64 {
+        self.audio.status().await.remaining
+    }
+
+    /// The track that plays next as a JSON-encoded `Track`, or an empty
+    /// string when it is not known.
+    #[zbus(property)]
+    async fn next_track(&self) -> String {
+        next_track(&self.audio.status().await)
+    }
+}
+
+fn playback_status(status: &Status) -> &'static str {
+    match status.state.as_str() {
+        "playing" | "loading" => "Playing",
+        "paused" => "Paused",
+        _ => "Stopped",
+    }
+}
+
+fn loop_status(status: &Status) -> &'static str {
+    match Repeat::parse(&status.repeat) {
+        Some(Repeat::Off) => "None",
+        Some(Repeat::One) => "Track",
+        _ => "Playlist",
+    }
+}
+
+fn current_track(status: &Status) -> String {
+    status
+        .track
+        .as_ref()
+        .and_then(|track| serde_json::to_string(track).ok())
+        .unwrap_or_default()
+}
+
+fn next_track(status: &Status) -> String {
+    status
+        .next_track
+        .as_ref()
+        .and_then(|track| serde_json::to_string(track).ok())
+        .unwrap_or_default()
+}
+
+/// Emits the signals and `PropertiesChanged` for what differs between the
+/// `last` status and `status`.
+async fn emit_changes(iface: &InterfaceRef<PlayerDBus>, last: &Status, status: &Status) {
+    let ctxt = iface.signal_context();
+    let player = iface.get().await;
+    let result = async {
+        PlayerDBus::status_changed(ctxt, &serde_json::to_string(status).unwrap_or_default())
+            .await?;
+        let text = osd::osd_text(status);
+        if text != osd::osd_text(last) {
+            PlayerDBus::osd_text(ctxt, &text).await?;
+        }
+        let idle = status.state == "idle";
+        if idle != (last.state == "idle") {
+            PlayerDBus::playlist_empty(ctxt, idle).await?;
+        }
+        if playback_status(status) != playback_status(last) {
+            player.playback_status_changed(ctxt).await?;
+        }
+        if status.volume.to_bits() != last.volume.to_bits() {
+            player.volume_changed(ctxt).await?;
+        }
+        if loop_status(status) != loop_status(last) {
+            player.loop_status_changed(ctxt).await?;
+        }
+        if status.shuffle != last.shuffle {
+            player.shuffle_changed(ctxt).await?;
+        }
+        if status.track != last.track {
+            player.current_track_changed(ctxt).await?;
+        }
+        if status.next_track != last.next_track {
+            player.next_track_changed(ctxt).await?;
+        }
+        zbus::Result::Ok(())
+    };
+    if let Err(e) = result.await {
+        warn!("Failed to emit status signals: {}", e);
+    }
 }
 
 pub async fn run_dbus_server(
-    command_sender: mpsc::Sender<Command>,
+    player_dbus: PlayerDBus,
     stop_signal: watch::Sender<()>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let player_dbus = PlayerDBus {
-        tx: command_sender,
-        stop_signal: stop_signal.clone(),
-        playlist_empty: Arc::new(Mutex::new(false)),
-    };
-
-    let _connection = ConnectionBuilder::session()?
+    let mut changes = player_dbus.audio.subscribe_changes();
+    let mut errors = player_dbus.audio.subscribe_errors();
+    let connection = ConnectionBuilder::session()?
         .name("org.rosesong.Player")?
         .serve_at("/org/rosesong/Player", player_dbus)?
         .build()
         .await?;
+    let iface = connection
+        .object_server()
+        .interface::<_, PlayerDBus>("/org/rosesong/Player")
+        .await?;
 
     let mut stop_receiver = stop_signal.subscribe();
+    let mut last_status = Status::default();
 
-    // Wait for the stop signal
-    tokio::select! {
-        _ = stop_receiver.changed() => {
-            info!("Stop signal received, shutting down DBus server...");
+    loop {
+        tokio::select! {
+            _ = stop_receiver.changed() => {
+                info!("Stop signal received, shutting down DBus server...");
+                break;
+            }
+            Ok(()) = changes.changed() => {
+                let status = iface.get().await.audio.status().await;
+                emit_changes(&iface, &last_status, &status).await;
+                last_status = status;
+            }
+            Ok(error) = errors.recv() => {
+                let error = serde_json::to_string(&error).unwrap_or_default();
+                if let Err(e) = PlayerDBus::playback_error(iface.signal_context(), &error).await {
+                    warn!("Failed to emit the playback error signal: {}", e);
+                }
+            }
         }
     }
 
     Ok(())
 }
+
+impl RequestHandler for PlayerDBus {
+    async fn handle(&self, request: Request) -> Result<serde_json::Value, String> {
+        let result = match request {
+            Request::TestConnection => {
+                self.test_connection();
+                Ok(())
+            }
+            Request::Play => self.play().await,
+            Request::PlayBvid { bvid } => self.play_bvid(bvid).await,
+            Request::PlaySeason { id } => self.play_season(id).await,
+            Request::PlayLater => self.play_later().await,
+            Request::Pause => self.pause().await,
+            Request::Next => self.next().await,
+            Request::Previous => self.previous().await,
+            Request::Stop => self.stop().await,
+            Request::Quit => self.quit().await,
+            Request::SetMode { mode } => self.set_mode(mode).await,
+            Request::SetShuffle { shuffle } => self.set_shuffle(shuffle).await,
+            Request::SetRepeat { repeat } => self.set_repeat(repeat).await,
+            Request::SetRadio { enabled } => self.set_radio(enabled).await,
+            Request::SetChannels { channels } => self.set_channels(channels).await,
+            Request::SetPitch { semitones } => self.set_pitch(semitones).await,
+            Request::SetPreview { seconds } => self.set_preview(seconds).await,
+            Request::Seek { position } => self.seek(position).await,
+            Request::SetVolume { volume } => self.set_volume(volume).await,
+            Request::AdjustVolume { delta } => self.adjust_volume(delta).await,
+            Request::VolumeUp => self.volume_up().await,
+            Request::VolumeDown => self.volume_down().await,
+            Request::PlaylistChange => self.playlist_change().await,
+            Request::PlaylistIsEmpty => self.playlist_is_empty().await,
+            Request::Status => {
+                return serde_json::to_value(self.audio.status().await).map_err(|e| e.to_string());
+            }
+            Request::PerfStats => {
+                return serde_json::to_value(self.audio.perf_stats()).map_err(|e| e.to_string());
+            }
+            Request::AudioTracks => {
+                return serde_json::to_value(self.audio.audio_tracks().await)
+                    .map_err(|e| e.to_string());
+            }
+            Request::SetAudioTrack { index } => self.set_audio_track(index).await,
+            Request::DebugInfo => {
+                return serde_json::to_value(self.audio.debug_info().await)
+                    .map_err(|e| e.to_string());
+            }
+        };
+        result
+            .map(|()| serde_json::Value::Null)
+            .map_err(|e| e.to_string())
+    }
+}
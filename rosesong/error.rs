@@ -1,6 +1,8 @@
 use flexi_logger::FlexiLoggerError;
+#[cfg(feature = "gstreamer")]
 use glib::BoolError;
 use reqwest::header::InvalidHeaderValue;
+use rosesong_core::error::Core;
 use std::io;
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
@@ -37,26 +39,35 @@ pub enum App {
     #[error("Fetch error: {0}")]
     Fetch(String),
 
+    #[error("Loading was cancelled by a newer command")]
+    Cancelled,
+
     #[error("Logger initialization error: {0}")]
     Logger(String),
 
     #[error("Channel send error: {0}")]
     Send(String),
 
+    #[cfg_attr(not(any(feature = "gstreamer", feature = "rodio")), allow(dead_code))]
     #[error("GStreamer element error: {0}")]
     Element(String),
 
     #[error("GStreamer pipeline error: {0}")]
     Pipeline(String),
 
+    #[cfg_attr(not(feature = "gstreamer"), allow(dead_code))]
     #[error("GStreamer link error: {0}")]
     Link(String),
 
+    #[cfg_attr(not(feature = "gstreamer"), allow(dead_code))]
     #[error("GStreamer state error: {0}")]
     State(String),
 
     #[error("ZBus error: {0}")]
     ZBus(String),
+
+    #[error("Storage error: {0}")]
+    Storage(String),
 }
 
 impl From<reqwest::Error> for App {
@@ -107,6 +118,7 @@ impl<T> From<SendError<T>> for App {
     }
 }
 
+#[cfg(feature = "gstreamer")]
 impl From<BoolError> for App {
     fn from(_: BoolError) -> Self {
         App::Init("Failed to perform an operation on GStreamer pipeline".to_string())
@@ -118,3 +130,12 @@ impl From<ZbusError> for App {
         App::ZBus(error.to_string())
     }
 }
+
+impl From<Core> for App {
+    fn from(error: Core) -> Self {
+        match error {
+            Core::Network(message) => App::Network(message),
+            error => App::Storage(error.to_string()),
+        }
+    }
+}
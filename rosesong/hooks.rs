@@ -0,0 +1,95 @@
+use crate::player::Audio;
+use log::{error, info, warn};
+use rosesong_core::ipc::Status;
+use rosesong_core::settings::HookSettings;
+use tokio::process::Command;
+use tokio::sync::{broadcast, watch};
+use tokio::task;
+
+/// Runs the configured hook commands on track changes, pauses and load
+/// errors until the stop signal fires.
+pub async fn run_hooks(hooks: HookSettings, audio: Audio, stop_signal: watch::Sender<()>) {
+    let mut stop_receiver = stop_signal.subscribe();
+    let mut changes = audio.subscribe_changes();
+    let mut errors = audio.subscribe_errors();
+    let mut last_bvid = None;
+    let mut last_state = String::new();
+    loop {
+        tokio::select! {
+            Ok(()) = changes.changed() => {
+                let status = audio.status().await;
+                let bvid = status.track.as_ref().map(|track| track.bvid.clone());
+                if bvid.is_some() && bvid != last_bvid {
+                    last_bvid.clone_from(&bvid);
+                    run_hook(hooks.on_track_change.as_deref(), "track_change", &status, None);
+                }
+                if status.state == "paused" && last_state != "paused" {
+                    run_hook(hooks.on_pause.as_deref(), "pause", &status, None);
+                }
+                last_state.clone_from(&status.state);
+            }
+            received = errors.recv() => match received {
+                Ok(error) => {
+                    let status = audio.status().await;
+                    run_hook(hooks.on_error.as_deref(), "error", &status, Some(&error.message));
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Skipped {} on_error hooks", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = stop_receiver.changed() => break,
+        }
+    }
+}
+
+/// Starts `command` through the shell without waiting for it, so a slow
+/// hook cannot hold up playback.
+fn run_hook(command: Option<&str>, event: &str, status: &Status, error: Option<&str>) {
+    let Some(command) = command else {
+        return;
+    };
+    #[cfg(windows)]
+    let mut hook = {
+        let mut hook = Command::new("cmd");
+        hook.arg("/C").arg(command);
+        hook
+    };
+    #[cfg(not(windows))]
+    let mut hook = {
+        let mut hook = Command::new("sh");
+        hook.arg("-c").arg(command);
+        hook
+    };
+    hook.env("ROSESONG_EVENT", event)
+        .env("ROSESONG_STATE", &status.state)
+        .env("ROSESONG_PLAYLIST", &status.playlist)
+        .env("ROSESONG_POSITION", format!("{:.0}", status.position));
+    if let Some(track) = &status.track {
+        hook.env("ROSESONG_BVID", &track.bvid)
+            .env("ROSESONG_CID", &track.cid)
+            .env("ROSESONG_TITLE", &track.title)
+            .env("ROSESONG_OWNER", &track.owner)
+            .env("ROSESONG_DURATION", track.duration.to_string());
+    }
+    if let Some(error) = error {
+        hook.env("ROSESONG_ERROR", error);
+    }
+
+    info!("Running {} hook: {}", event, command);
+    match hook.spawn() {
+        Ok(mut child) => {
+            let event = event.to_string();
+            task::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if !status.success() => {
+                        warn!("{} hook exited with {}", event, status);
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to wait for {} hook: {}", event, e),
+                }
+            });
+        }
+        Err(e) => error!("Failed to run {} hook: {}", event, e),
+    }
+}
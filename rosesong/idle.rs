@@ -0,0 +1,51 @@
+use crate::player::{Audio, Command};
+use log::info;
+use rosesong_core::settings::{PauseAction, Settings};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Takes the configured `pause_action` once playback has stayed paused for
+/// `pause_timeout`, until the stop signal fires.
+pub async fn run_pause_timer(
+    settings: Settings,
+    audio: Audio,
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) {
+    let timeout = Duration::from_secs(settings.player.pause_timeout * 60);
+    let mut stop_receiver = stop_signal.subscribe();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut paused_since = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop_receiver.changed() => break,
+        }
+
+        if !audio.is_paused().await {
+            paused_since = None;
+            continue;
+        }
+        let since = *paused_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < timeout {
+            continue;
+        }
+        paused_since = None;
+
+        match settings.player.pause_action {
+            PauseAction::Release => {
+                info!("Paused for {:?}, releasing the pipeline", since.elapsed());
+                if command_sender.send(Command::ReleasePaused).await.is_err() {
+                    break;
+                }
+            }
+            PauseAction::Quit => {
+                info!("Paused for {:?}, exiting", since.elapsed());
+                stop_signal.send_replace(());
+                break;
+            }
+        }
+    }
+}
@@ -1,56 +1,79 @@
+mod access;
 mod bilibili;
+#[cfg(not(any(target_os = "macos", windows)))]
+mod connectivity;
 mod dbus;
 mod error;
+#[cfg(feature = "hooks")]
+mod hooks;
+mod idle;
+#[cfg(all(feature = "media-keys", not(any(target_os = "macos", windows))))]
+mod media_keys;
+mod memory;
+#[cfg(feature = "metrics")]
+mod metrics_server;
+#[cfg(feature = "mpd")]
+mod mpd;
+#[cfg(not(any(target_os = "macos", windows)))]
+mod notifications;
+mod osd;
 mod player;
-mod temp_dbus;
+#[cfg(feature = "schedule")]
+mod scheduler;
+#[cfg(not(any(target_os = "macos", windows)))]
+mod sleep;
+mod socket;
+mod supervisor;
+mod transport;
 
 use crate::error::App;
-use crate::player::playlist::PlayMode;
-use crate::player::Audio;
+use crate::player::playlist::{PlayMode, PlaylistManager, Repeat};
+use crate::player::{Audio, Command};
+use clap::Parser;
 use flexi_logger::{Cleanup, Criterion, Duplicate, FileSpec, Logger, Naming};
 use log::{error, warn};
-use player::playlist::load;
-use std::path::Path;
+use rosesong_core::bilibili::limiter;
+use rosesong_core::paths;
+use rosesong_core::settings::Settings;
+use rosesong_core::state::RuntimeState;
+use rosesong_core::storage::{self, PlaylistStore, DEFAULT_PLAYLIST};
 use std::process;
 use std::sync::Arc;
-use tokio::fs;
+use std::time::Duration;
 use tokio::{
     sync::{mpsc, watch, Mutex},
     task,
 };
 
+/// Time left after the stop signal to answer the request that asked to
+/// quit.
+const EXIT_GRACE: Duration = Duration::from_millis(100);
+
+/// Overrides of what the daemon plays first, for systemd units and scripts.
+/// Without them it resumes the track and play mode saved at the last exit.
+#[derive(Parser)]
+#[command(version, about = "RoseSong 守护进程")]
+struct Args {
+    #[arg(long, value_name = "NAME", help = "播放指定歌单，而不是默认歌单")]
+    playlist: Option<String>,
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "从歌单中的第 N 首开始播放")]
+    index: Option<u64>,
+    #[arg(long, value_parser = ["loop", "shuffle", "repeat"], help = "播放模式")]
+    mode: Option<String>,
+    #[arg(long, value_parser = ["off", "one", "all"], help = "循环策略，可与 --mode shuffle 组合")]
+    repeat: Option<String>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), App> {
-    let home_dir = std::env::var("HOME").map_err(|e| {
-        App::Io(
-            std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Failed to get HOME environment variable: {e}"),
-            )
-            .to_string(),
-        )
-    })?;
-
-    // Define the required directories
-    let required_dirs = [
-        format!("{home_dir}/.config/rosesong/logs"),
-        format!("{home_dir}/.config/rosesong/playlists"),
-    ];
+    let args = Args::parse();
 
     // Ensure all directories exist
-    for dir in &required_dirs {
-        fs::create_dir_all(dir).await?;
-    }
-
-    // Check if playlist.toml exists, if not, create an empty one
-    let playlist_path = format!("{home_dir}/.config/rosesong/playlists/playlist.toml");
-    if !Path::new(&playlist_path).exists() {
-        fs::write(&playlist_path, "").await?;
-    }
+    paths::ensure_dirs()?;
 
     // Logger setup
     Logger::try_with_str("info")?
-        .log_to_file(FileSpec::default().directory(&required_dirs[0]))
+        .log_to_file(FileSpec::default().directory(paths::logs_dir()?))
         .rotate(
             Criterion::Size(1_000_000),
             Naming::Timestamps,
@@ -58,26 +81,70 @@ async fn main() -> Result<(), App> {
         )
         .duplicate_to_stderr(Duplicate::None)
         .start()?;
+    // Panics would otherwise only reach stderr, which a daemon usually lacks
+    std::panic::set_hook(Box::new(|info| error!("{}", info)));
 
-    // Check if the playlist is empty
-    {
-        let playlist_content = fs::read_to_string(&playlist_path).await?;
-        if playlist_content.trim().is_empty() {
-            warn!("Current playlist is empty");
-            let (stop_sender, stop_receiver) = watch::channel(());
-            let _ = start_temp_dbus_listener(stop_sender).await;
-            wait_for_stop_signal(stop_receiver).await;
-            let playlist_content = fs::read_to_string(&playlist_path).await?;
-            if playlist_content.trim().is_empty() {
-                process::exit(0);
-            }
-        }
+    let settings = Settings::load()?;
+    warn_disabled_features(&settings);
+    limiter::configure(&settings.network);
+    let playlist_name = args.playlist.as_deref().unwrap_or(DEFAULT_PLAYLIST);
+    let store: Arc<dyn PlaylistStore> =
+        Arc::from(storage::open_named(&settings.storage, playlist_name)?);
+
+    // An empty playlist leaves the player idle until tracks are added
+    let playlist = Arc::new(PlaylistManager::new(playlist_name, store));
+    playlist.load().await?;
+
+    // Resume the track and play mode saved before the last shutdown or crash
+    let state = RuntimeState::load();
+    let mut play_mode = state
+        .play_mode
+        .as_deref()
+        .and_then(PlayMode::parse)
+        .unwrap_or_default();
+    if let Some(mode) = args.mode.as_deref() {
+        play_mode = PlayMode {
+            shuffle: mode == "shuffle",
+            repeat: if mode == "repeat" {
+                Repeat::One
+            } else {
+                Repeat::All
+            },
+        };
     }
+    if let Some(repeat) = args.repeat.as_deref().and_then(Repeat::parse) {
+        play_mode.repeat = repeat;
+    }
+    let initial_track_index = if let Some(index) = args.index {
+        let len = playlist.len().await;
+        let index = usize::try_from(index - 1).unwrap_or(usize::MAX);
+        if index >= len {
+            warn!(
+                "Index {} is past the end of the playlist, starting from the first track",
+                index + 1
+            );
+            0
+        } else {
+            index
+        }
+    } else {
+        match &state.current_bvid {
+            Some(bvid) => playlist.find_track_index(bvid).await.unwrap_or(0),
+            None => 0,
+        }
+    };
 
-    load(&playlist_path).await?;
     let (stop_sender, stop_receiver) = watch::channel(());
-    let _audio_player = start_player_and_dbus_listener(stop_sender).await?;
+    let _audio_player = start_player_and_dbus_listener(
+        &stop_sender,
+        playlist,
+        play_mode,
+        initial_track_index,
+        &settings,
+    )
+    .await?;
     wait_for_stop_signal(stop_receiver).await;
+    tokio::time::sleep(EXIT_GRACE).await;
     process::exit(0);
 }
 
@@ -85,53 +152,275 @@ async fn wait_for_stop_signal(mut stop_receiver: watch::Receiver<()>) {
     stop_receiver.changed().await.unwrap();
 }
 
-async fn start_temp_dbus_listener(
-    stop_signal: watch::Sender<()>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let stop_receiver = stop_signal.subscribe();
-
-    task::spawn({
-        let stop_signal = stop_signal.clone();
-        async move {
-            let result = temp_dbus::run_temp_dbus_server(stop_signal).await;
-            if let Err(e) = result {
-                error!("Temp DBus listener error: {}", e);
-            }
-        }
-    });
-
-    // Wait for the stop signal
-    wait_for_stop_signal(stop_receiver).await;
-
-    Ok(())
-}
-
-async fn start_player_and_dbus_listener(stop_signal: watch::Sender<()>) -> Result<Audio, App> {
-    let play_mode = PlayMode::Loop;
-    let initial_track_index = 0;
+async fn start_player_and_dbus_listener(
+    stop_signal: &watch::Sender<()>,
+    playlist: Arc<PlaylistManager>,
+    play_mode: PlayMode,
+    initial_track_index: usize,
+    settings: &Settings,
+) -> Result<Audio, App> {
+    let access = access::AccessPolicy::new(&settings.service)?;
     let (command_sender, command_receiver) = mpsc::channel(1);
 
     let audio_player = Audio::new(
         play_mode,
         initial_track_index,
         Arc::new(Mutex::new(command_receiver)),
+        playlist,
+        settings,
     )
     .await?;
 
+    spawn_optional_tasks(settings, &audio_player, &command_sender, stop_signal);
+
+    let player_dbus =
+        dbus::PlayerDBus::new(command_sender, audio_player.clone(), stop_signal.clone());
     task::spawn({
-        let command_sender = command_sender.clone();
         let stop_signal = stop_signal.clone();
         async move {
-            let _ = dbus::run_dbus_server(command_sender, stop_signal).await;
+            let socket_server = async {
+                if let Err(e) =
+                    socket::run_socket_server(player_dbus.clone(), access, stop_signal.clone())
+                        .await
+                {
+                    error!("Control socket error: {}", e);
+                }
+            };
+
+            #[cfg(not(any(target_os = "macos", windows)))]
+            let dbus_server = async {
+                if let Err(e) =
+                    dbus::run_dbus_server(player_dbus.clone(), stop_signal.clone()).await
+                {
+                    error!("DBus server error: {}", e);
+                }
+            };
+            #[cfg(any(target_os = "macos", windows))]
+            let dbus_server = async {};
+
+            tokio::join!(dbus_server, socket_server);
         }
     });
 
     task::spawn({
         let audio_player = audio_player.clone();
         async move {
-            audio_player.play_playlist().await.unwrap();
+            if let Err(e) = audio_player.play_playlist().await {
+                error!("Failed to start playback: {}", e);
+            }
         }
     });
 
     Ok(audio_player)
 }
+
+/// Starts the background tasks enabled in the settings.
+fn spawn_optional_tasks(
+    settings: &Settings,
+    audio_player: &Audio,
+    command_sender: &mpsc::Sender<Command>,
+    stop_signal: &watch::Sender<()>,
+) {
+    #[cfg(all(feature = "media-keys", not(any(target_os = "macos", windows))))]
+    if settings.player.media_keys {
+        task::spawn({
+            let command_sender = command_sender.clone();
+            let stop_signal = stop_signal.clone();
+            async move {
+                if let Err(e) = media_keys::run_media_keys(command_sender, stop_signal).await {
+                    error!("Failed to register media keys: {}", e);
+                }
+            }
+        });
+    }
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    spawn_system_listeners(settings, audio_player, command_sender, stop_signal);
+
+    task::spawn(memory::run_memory_trimmer(
+        audio_player.clone(),
+        stop_signal.clone(),
+    ));
+
+    #[cfg(any(feature = "metrics", feature = "mpd"))]
+    spawn_servers(settings, audio_player, command_sender, stop_signal);
+
+    if settings.player.report_history {
+        task::spawn({
+            let reporter = bilibili::history::run_history_reporter(
+                settings.network.clone(),
+                audio_player.clone(),
+                stop_signal.clone(),
+            );
+            async move {
+                if let Err(e) = reporter.await {
+                    error!("Failed to report playback progress: {}", e);
+                }
+            }
+        });
+    }
+
+    if settings.player.pause_timeout != 0 {
+        task::spawn(idle::run_pause_timer(
+            settings.clone(),
+            audio_player.clone(),
+            command_sender.clone(),
+            stop_signal.clone(),
+        ));
+    }
+
+    #[cfg(all(feature = "osd", target_os = "linux"))]
+    if let Some(fifo) = &settings.osd.fifo {
+        task::spawn(osd::run_osd_fifo(
+            fifo.into(),
+            audio_player.clone(),
+            stop_signal.clone(),
+        ));
+    }
+
+    #[cfg(feature = "hooks")]
+    if !settings.hooks.is_empty() {
+        task::spawn(hooks::run_hooks(
+            settings.hooks.clone(),
+            audio_player.clone(),
+            stop_signal.clone(),
+        ));
+    }
+
+    #[cfg(feature = "schedule")]
+    if !settings.schedule.is_empty() {
+        task::spawn(scheduler::run_scheduler(
+            settings.clone(),
+            audio_player.clone(),
+            command_sender.clone(),
+            stop_signal.clone(),
+        ));
+    }
+}
+
+/// Starts the tasks that talk to the system over D-Bus: logind, the
+/// notification daemon and `NetworkManager`.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn spawn_system_listeners(
+    settings: &Settings,
+    audio_player: &Audio,
+    command_sender: &mpsc::Sender<Command>,
+    stop_signal: &watch::Sender<()>,
+) {
+    if settings.player.pause_on_sleep {
+        task::spawn({
+            let sleep_listener = sleep::run_sleep_listener(
+                audio_player.clone(),
+                settings.player.resume_after_sleep,
+                command_sender.clone(),
+                stop_signal.clone(),
+            );
+            async move {
+                if let Err(e) = sleep_listener.await {
+                    error!("Failed to listen for system sleep: {}", e);
+                }
+            }
+        });
+    }
+
+    if settings.player.notify_errors {
+        task::spawn({
+            let notifier =
+                notifications::run_error_notifier(audio_player.clone(), stop_signal.clone());
+            async move {
+                if let Err(e) = notifier.await {
+                    error!("Failed to show desktop notifications: {}", e);
+                }
+            }
+        });
+    }
+
+    if settings.network.resume_on_reconnect {
+        task::spawn({
+            let listener = connectivity::run_connectivity_listener(
+                command_sender.clone(),
+                stop_signal.clone(),
+            );
+            async move {
+                if let Err(e) = listener.await {
+                    error!("Failed to listen for network changes: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Starts the servers other programs reach over the network.
+#[cfg(any(feature = "metrics", feature = "mpd"))]
+#[cfg_attr(not(feature = "mpd"), allow(unused_variables))]
+fn spawn_servers(
+    settings: &Settings,
+    audio_player: &Audio,
+    command_sender: &mpsc::Sender<Command>,
+    stop_signal: &watch::Sender<()>,
+) {
+    #[cfg(feature = "metrics")]
+    if settings.player.metrics_port != 0 {
+        task::spawn({
+            let metrics = audio_player.metrics();
+            let port = settings.player.metrics_port;
+            let stop_signal = stop_signal.clone();
+            async move {
+                if let Err(e) = metrics_server::run_metrics_server(port, metrics, stop_signal).await
+                {
+                    error!("Metrics server error: {}", e);
+                }
+            }
+        });
+    }
+
+    #[cfg(feature = "mpd")]
+    if settings.mpd.port != 0 {
+        task::spawn({
+            let server = mpd::run_mpd_server(
+                settings.mpd.clone(),
+                audio_player.clone(),
+                command_sender.clone(),
+                stop_signal.clone(),
+            );
+            async move {
+                if let Err(e) = server.await {
+                    error!("MPD server error: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Logs the settings that need a cargo feature this build was compiled
+/// without, since they would otherwise be ignored silently.
+fn warn_disabled_features(settings: &Settings) {
+    let features = [
+        (
+            "media-keys",
+            cfg!(feature = "media-keys"),
+            settings.player.media_keys,
+        ),
+        (
+            "metrics",
+            cfg!(feature = "metrics"),
+            settings.player.metrics_port != 0,
+        ),
+        ("mpd", cfg!(feature = "mpd"), settings.mpd.port != 0),
+        ("osd", cfg!(feature = "osd"), settings.osd.fifo.is_some()),
+        ("hooks", cfg!(feature = "hooks"), !settings.hooks.is_empty()),
+        (
+            "schedule",
+            cfg!(feature = "schedule"),
+            !settings.schedule.is_empty(),
+        ),
+    ];
+    for (feature, compiled, configured) in features {
+        if configured && !compiled {
+            warn!(
+                "Settings for the \"{}\" feature are ignored, rebuild with --features {}",
+                feature, feature
+            );
+        }
+    }
+}
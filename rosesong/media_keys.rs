@@ -0,0 +1,63 @@
+use crate::error::App;
+use crate::player::Command;
+use futures_util::StreamExt;
+use log::{info, warn};
+use tokio::sync::{mpsc, watch};
+use zbus::{proxy, Connection};
+
+const APPLICATION: &str = "rosesong";
+
+#[proxy(
+    interface = "org.gnome.SettingsDaemon.MediaKeys",
+    default_service = "org.gnome.SettingsDaemon.MediaKeys",
+    default_path = "/org/gnome/SettingsDaemon/MediaKeys"
+)]
+trait MediaKeys {
+    fn grab_media_player_keys(&self, application: &str, time: u32) -> zbus::Result<()>;
+
+    fn release_media_player_keys(&self, application: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn media_player_key_pressed(&self, application: String, key: String) -> zbus::Result<()>;
+}
+
+/// Grabs the media keys from GNOME Settings Daemon and turns key presses into
+/// player commands until the stop signal fires.
+pub async fn run_media_keys(
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let connection = Connection::session().await?;
+    let media_keys = MediaKeysProxy::new(&connection).await?;
+    let mut key_presses = media_keys.receive_media_player_key_pressed().await?;
+    media_keys.grab_media_player_keys(APPLICATION, 0).await?;
+    info!("Media keys grabbed");
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            Some(signal) = key_presses.next() => {
+                let args = signal.args()?;
+                if args.application != APPLICATION {
+                    continue;
+                }
+                let command = match args.key.as_str() {
+                    "Play" => Command::TogglePause,
+                    "Pause" => Command::Pause,
+                    "Stop" => Command::Stop,
+                    "Next" => Command::Next,
+                    "Previous" => Command::Previous,
+                    key => {
+                        warn!("Ignoring media key {}", key);
+                        continue;
+                    }
+                };
+                command_sender.send(command).await?;
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    let _ = media_keys.release_media_player_keys(APPLICATION).await;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use crate::player::Audio;
+use log::info;
+use std::time::Duration;
+use tokio::sync::watch;
+
+const TRIM_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Resident set size of the daemon in bytes, where the platform reports it.
+pub fn rss_bytes() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        // SAFETY: sysconf only reads a system constant
+        let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+        Some(pages * page_size)
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Hands memory freed by the allocator back to the OS and flushes the log
+/// buffer.
+fn release_free_memory() {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    // SAFETY: malloc_trim has no preconditions
+    unsafe {
+        libc::malloc_trim(0);
+    }
+    log::logger().flush();
+}
+
+/// Periodically shrinks the player caches and releases free memory, so a
+/// daemon that runs for weeks does not slowly grow. Idle HTTP connections
+/// are already dropped by the client's pool timeout.
+pub async fn run_memory_trimmer(audio: Audio, stop_signal: watch::Sender<()>) {
+    let mut interval = tokio::time::interval(TRIM_INTERVAL);
+    interval.tick().await;
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let before = rss_bytes();
+                audio.shrink_caches().await;
+                release_free_memory();
+                info!("Trimmed memory, RSS {:?} -> {:?} bytes", before, rss_bytes());
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+}
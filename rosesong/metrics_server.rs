@@ -0,0 +1,52 @@
+use crate::error::App;
+use crate::player::metrics::LatencyMetrics;
+use log::{info, warn};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task;
+
+/// Serves the latency metrics to Prometheus on `127.0.0.1:port`, answering
+/// every request with the current snapshot, until the stop signal fires.
+pub async fn run_metrics_server(
+    port: u16,
+    metrics: Arc<LatencyMetrics>,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+    info!("Serving metrics on 127.0.0.1:{}", port);
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let metrics = Arc::clone(&metrics);
+                task::spawn(async move {
+                    if let Err(e) = respond(stream, &metrics).await {
+                        warn!("Metrics connection error: {}", e);
+                    }
+                });
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, metrics: &LatencyMetrics) -> Result<(), App> {
+    // The request itself does not matter, only that one has arrived
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+    let body = metrics.prometheus();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,846 @@
+use crate::bilibili::ytdlp::page_url;
+use crate::error::App;
+use crate::player::playlist::{Repeat, Track};
+use crate::player::{Audio, Command};
+use futures_util::StreamExt;
+use log::{info, warn};
+use rosesong_core::ipc::Status;
+use rosesong_core::settings::MpdSettings;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+
+/// Protocol version announced to clients.
+const GREETING: &str = "OK MPD 0.23.0\n";
+/// How often an idle client is checked for changes that send no
+/// notification, such as playlist edits and seeks.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest line a client may send; the connection is closed on longer ones.
+const MAX_LINE_LENGTH: usize = 4096;
+/// Most commands a command list may hold before it is refused.
+const MAX_COMMAND_LIST_LENGTH: usize = 1024;
+
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PASSWORD: u32 = 3;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+/// Commands that do something, as listed by `commands`. Those about the
+/// music database are answered too, with nothing, since there is none.
+const COMMANDS: &[&str] = &[
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "currentsong",
+    "getvol",
+    "idle",
+    "next",
+    "noidle",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "volume",
+];
+
+/// Serves the MPD protocol on `settings.address:settings.port`, mapping
+/// the common commands onto the player, until the stop signal fires.
+///
+/// TCP carries no peer credentials, so the [`AccessPolicy`] of a shared
+/// control socket does not apply here: every client that knows the
+/// password, or every client when none is set, may control playback.
+///
+/// [`AccessPolicy`]: crate::access::AccessPolicy
+pub async fn run_mpd_server(
+    settings: MpdSettings,
+    audio: Audio,
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let listener = TcpListener::bind((settings.address.as_str(), settings.port)).await?;
+    info!(
+        "Serving the MPD protocol on {}:{}",
+        settings.address, settings.port
+    );
+    let password: Arc<str> = Arc::from(settings.password);
+    let started = Instant::now();
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                let client = Client {
+                    audio: audio.clone(),
+                    commands: command_sender.clone(),
+                    session: Session::new(Arc::clone(&password)),
+                    started,
+                };
+                task::spawn(async move {
+                    if let Err(e) = client.serve(stream).await {
+                        warn!("MPD connection from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// A failed command, sent back as `ACK [code@index] {command} message`.
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Ack {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The answer to `line`, the command at `index` of a command list.
+    fn line(&self, line: &str, index: usize) -> String {
+        let name = line.split_whitespace().next().unwrap_or_default();
+        format!("ACK [{}@{index}] {{{name}}} {}\n", self.code, self.message)
+    }
+}
+
+/// What a command prints before its final `OK`.
+type Reply = Result<String, Ack>;
+
+/// The lines a client sends, each at most [`MAX_LINE_LENGTH`] bytes.
+type Lines = FramedRead<OwnedReadHalf, LinesCodec>;
+
+/// Next line from the client, or `None` once it hangs up. Safe to cancel.
+async fn next_line(lines: &mut Lines) -> Result<Option<String>, App> {
+    match lines.next().await.transpose() {
+        Ok(line) => Ok(line),
+        Err(LinesCodecError::Io(e)) => Err(e.into()),
+        Err(LinesCodecError::MaxLineLengthExceeded) => Err(App::DataParsing(format!(
+            "line longer than {MAX_LINE_LENGTH} bytes"
+        ))),
+    }
+}
+
+/// What to do with a line a client sent.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Run it on its own.
+    Run,
+    /// Nothing yet, it opened or went into a command list.
+    Listed,
+    /// Run a finished command list, answering each command with `list_OK`
+    /// if the flag is set.
+    RunList(Vec<String>, bool),
+    /// Answer with this, the command list being dropped.
+    Refuse(String),
+}
+
+/// What a connection remembers between commands.
+struct Session {
+    password: Arc<str>,
+    /// Whether the client may do anything besides sending the password.
+    authorized: bool,
+    /// The commands of an open command list, and whether each is answered
+    /// with `list_OK`.
+    list: Option<(Vec<String>, bool)>,
+}
+
+impl Session {
+    fn new(password: Arc<str>) -> Self {
+        Session {
+            authorized: password.is_empty(),
+            password,
+            list: None,
+        }
+    }
+
+    /// Decides what to do with `command`, collecting it if a command list
+    /// is open.
+    fn step(&mut self, command: &str) -> Step {
+        if let Some((commands, _)) = self.list.as_mut().filter(|_| command != "command_list_end") {
+            if commands.len() < MAX_COMMAND_LIST_LENGTH {
+                commands.push(command.to_string());
+                return Step::Listed;
+            }
+            self.list = None;
+            Step::Refuse(
+                Ack::new(ACK_ERROR_ARG, "Command list too long")
+                    .line(command, MAX_COMMAND_LIST_LENGTH),
+            )
+        } else if let Some((commands, list_ok)) = self.list.take() {
+            Step::RunList(commands, list_ok)
+        } else if command == "command_list_begin" || command == "command_list_ok_begin" {
+            self.list = Some((Vec::new(), command == "command_list_ok_begin"));
+            Step::Listed
+        } else {
+            Step::Run
+        }
+    }
+
+    /// Refuses every command but a few harmless ones until the client has
+    /// sent the password.
+    fn permit(&self, name: &str) -> Result<(), Ack> {
+        if self.authorized || matches!(name, "password" | "ping" | "commands" | "notcommands") {
+            Ok(())
+        } else {
+            Err(Ack::new(
+                ACK_ERROR_PERMISSION,
+                format!("you don't have permission for \"{name}\""),
+            ))
+        }
+    }
+
+    fn check_password(&mut self, args: &[String]) -> Reply {
+        if args
+            .first()
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), self.password.as_bytes()))
+        {
+            self.authorized = true;
+            Ok(String::new())
+        } else {
+            Err(Ack::new(ACK_ERROR_PASSWORD, "incorrect password"))
+        }
+    }
+}
+
+/// One connected MPD client.
+struct Client {
+    audio: Audio,
+    commands: mpsc::Sender<Command>,
+    session: Session,
+    started: Instant,
+}
+
+impl Client {
+    async fn serve(mut self, stream: TcpStream) -> Result<(), App> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE_LENGTH));
+        writer.write_all(GREETING.as_bytes()).await?;
+        while let Some(line) = next_line(&mut lines).await? {
+            let command = line.trim();
+            let response = match self.session.step(command) {
+                Step::Listed => continue,
+                Step::RunList(commands, list_ok) => self.run_list(&commands, list_ok).await,
+                Step::Refuse(response) => response,
+                Step::Run if command == "close" => break,
+                Step::Run if command == "idle" || command.starts_with("idle ") => {
+                    match self.idle(command, &mut lines).await? {
+                        Some(response) => response,
+                        None => break,
+                    }
+                }
+                Step::Run => match self.execute(command).await {
+                    Ok(output) => format!("{output}OK\n"),
+                    Err(ack) => ack.line(command, 0),
+                },
+            };
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs `commands` in order, stopping at the first that fails.
+    async fn run_list(&mut self, commands: &[String], list_ok: bool) -> String {
+        let mut response = String::new();
+        for (index, command) in commands.iter().enumerate() {
+            match self.execute(command).await {
+                Ok(output) => {
+                    response.push_str(&output);
+                    if list_ok {
+                        response.push_str("list_OK\n");
+                    }
+                }
+                Err(ack) => {
+                    response.push_str(&ack.line(command, index));
+                    return response;
+                }
+            }
+        }
+        response.push_str("OK\n");
+        response
+    }
+
+    /// Waits until one of the subsystems named in `command` changes, or any
+    /// when it names none, and tells which did. Any line from the client,
+    /// normally `noidle`, ends the wait. Returns `None` once it hangs up.
+    async fn idle(&self, command: &str, lines: &mut Lines) -> Result<Option<String>, App> {
+        let wanted: Vec<String> = split_words(command)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(1)
+            .collect();
+        let mut notifications = self.audio.subscribe_changes();
+        let mut interval = tokio::time::interval(IDLE_POLL_INTERVAL);
+        let before = self.snapshot().await;
+        loop {
+            tokio::select! {
+                line = next_line(lines) => {
+                    return Ok(line?.map(|_| "OK\n".to_string()));
+                }
+                Ok(()) = notifications.changed() => {}
+                _ = interval.tick() => {}
+            }
+            let mut response = String::new();
+            for subsystem in before.changes(&self.snapshot().await) {
+                if wanted.is_empty() || wanted.iter().any(|w| w == subsystem) {
+                    let _ = writeln!(response, "changed: {subsystem}");
+                }
+            }
+            if !response.is_empty() {
+                response.push_str("OK\n");
+                return Ok(Some(response));
+            }
+        }
+    }
+
+    async fn snapshot(&self) -> Snapshot {
+        let status = self.audio.status().await;
+        Snapshot {
+            playlist: playlist_version(&status, &self.audio.tracks().await),
+            state: status.state,
+            bvid: status.track.map(|track| track.bvid),
+            volume: status.volume.to_bits(),
+            shuffle: status.shuffle,
+            repeat: status.repeat,
+        }
+    }
+
+    async fn execute(&mut self, line: &str) -> Reply {
+        let words = split_words(line).map_err(|message| Ack::new(ACK_ERROR_ARG, message))?;
+        let Some((name, args)) = words.split_first() else {
+            return Err(Ack::new(ACK_ERROR_UNKNOWN, "No command given"));
+        };
+        let name = name.as_str();
+        self.session.permit(name)?;
+        match name {
+            "password" => self.session.check_password(args),
+            "commands" => {
+                let mut out = String::new();
+                for command in COMMANDS {
+                    let _ = writeln!(out, "command: {command}");
+                }
+                Ok(out)
+            }
+            "tagtypes" if args.is_empty() => Ok("tagtype: Artist\ntagtype: Title\n".to_string()),
+            "outputs" => Ok(
+                "outputid: 0\noutputname: rosesong\nplugin: rosesong\noutputenabled: 1\n"
+                    .to_string(),
+            ),
+            "replay_gain_status" => Ok("replay_gain_mode: off\n".to_string()),
+            "count" => Ok("songs: 0\nplaytime: 0\n".to_string()),
+            "status" => Ok(self.status().await),
+            "stats" => Ok(self.stats().await),
+            "getvol" => Ok(format!(
+                "volume: {}\n",
+                percent(self.audio.status().await.volume)
+            )),
+            "currentsong" | "playlistinfo" | "playlistid" | "plchanges" | "plchangesposid" => {
+                self.list_songs(name, args).await
+            }
+            // Nothing to do or nothing to list: there is no music database,
+            // output setting or client messaging
+            "ping" | "clearerror" | "tagtypes" | "binarylimit" | "crossfade" | "mixrampdb"
+            | "mixrampdelay" | "replay_gain_mode" | "noidle" | "notcommands" | "urlhandlers"
+            | "decoders" | "lsinfo" | "list" | "listall" | "listallinfo" | "listfiles" | "find"
+            | "search" | "listplaylists" | "listmounts" | "listneighbors" | "channels"
+            | "readmessages" | "subscribe" | "unsubscribe" => Ok(String::new()),
+            _ => self.control(name, args).await,
+        }
+    }
+
+    async fn status(&self) -> String {
+        let status = self.audio.status().await;
+        let tracks = self.audio.tracks().await;
+        let repeat = Repeat::parse(&status.repeat).unwrap_or(Repeat::Off);
+        let state = match status.state.as_str() {
+            "playing" | "loading" => "play",
+            "paused" => "pause",
+            _ => "stop",
+        };
+        let mut out = String::new();
+        let _ = writeln!(out, "volume: {}", percent(status.volume));
+        let _ = writeln!(out, "repeat: {}", u8::from(repeat != Repeat::Off));
+        let _ = writeln!(out, "random: {}", u8::from(status.shuffle));
+        let _ = writeln!(out, "single: {}", u8::from(repeat == Repeat::One));
+        let _ = writeln!(out, "consume: 0");
+        let _ = writeln!(out, "playlist: {}", playlist_version(&status, &tracks));
+        let _ = writeln!(out, "playlistlength: {}", tracks.len());
+        let _ = writeln!(out, "state: {state}");
+        let Some(track) = &status.track else {
+            return out;
+        };
+        if let Some(pos) = position_of(&tracks, &track.bvid) {
+            let _ = writeln!(out, "song: {pos}\nsongid: {}", pos + 1);
+        }
+        if let Some(pos) = status
+            .next_track
+            .as_ref()
+            .and_then(|next| position_of(&tracks, &next.bvid))
+        {
+            let _ = writeln!(out, "nextsong: {pos}\nnextsongid: {}", pos + 1);
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (elapsed, duration) = (status.position as u64, status.duration as u64);
+        let _ = writeln!(out, "time: {elapsed}:{duration}");
+        let _ = writeln!(out, "elapsed: {:.3}", status.position);
+        let _ = writeln!(out, "duration: {:.3}", status.duration);
+        if let Some(bitrate) = status.stream.as_ref().and_then(|stream| stream.bitrate) {
+            let _ = writeln!(out, "bitrate: {}", bitrate / 1000);
+        }
+        out
+    }
+
+    async fn stats(&self) -> String {
+        let tracks = self.audio.tracks().await;
+        let artists: HashSet<&str> = tracks.iter().map(|track| track.owner.as_str()).collect();
+        let playtime: u64 = tracks.iter().map(|track| track.duration).sum();
+        format!(
+            "artists: {}\nalbums: 0\nsongs: {}\nuptime: {}\nplaytime: 0\ndb_playtime: {playtime}\ndb_update: 0\n",
+            artists.len(),
+            tracks.len(),
+            self.started.elapsed().as_secs()
+        )
+    }
+
+    /// Answers the commands listing songs of the playlist, which is the
+    /// queue here; song ids are positions plus one.
+    async fn list_songs(&self, name: &str, args: &[String]) -> Reply {
+        let tracks = self.audio.tracks().await;
+        let range = match name {
+            "currentsong" => {
+                let Some(track) = self.audio.status().await.track else {
+                    return Ok(String::new());
+                };
+                let mut out = String::new();
+                song(&mut out, &track, position_of(&tracks, &track.bvid));
+                return Ok(out);
+            }
+            "playlistid" => match args.first() {
+                Some(id) => {
+                    let pos = parse_number::<usize>(Some(id))?
+                        .checked_sub(1)
+                        .filter(|pos| *pos < tracks.len())
+                        .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song"))?;
+                    pos..pos + 1
+                }
+                None => 0..tracks.len(),
+            },
+            "playlistinfo" => match args.first() {
+                Some(range) => parse_range(range)?,
+                None => 0..tracks.len(),
+            },
+            // Every song counts as changed, which clients accept
+            _ => match args.get(1) {
+                Some(range) => parse_range(range)?,
+                None => 0..tracks.len(),
+            },
+        };
+        if name == "playlistinfo" && range.start >= tracks.len() && !tracks.is_empty() {
+            return Err(Ack::new(ACK_ERROR_ARG, "Bad song index"));
+        }
+        let mut out = String::new();
+        let end = range.end.min(tracks.len());
+        for (pos, track) in tracks.iter().enumerate().take(end).skip(range.start) {
+            if name == "plchangesposid" {
+                let _ = writeln!(out, "cpos: {pos}\nId: {}", pos + 1);
+            } else {
+                song(&mut out, track, Some(pos));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Runs the commands that control playback.
+    async fn control(&self, name: &str, args: &[String]) -> Reply {
+        let arg = args.first();
+        let status = self.audio.status().await;
+        let repeat = Repeat::parse(&status.repeat).unwrap_or(Repeat::Off);
+        let command = match name {
+            "play" | "playid" if arg.is_none() => Command::Play,
+            "play" => Command::PlayBvid(self.track_at(parse_number(arg)?).await?.bvid),
+            "playid" => {
+                let pos = parse_number::<usize>(arg)?
+                    .checked_sub(1)
+                    .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song"))?;
+                Command::PlayBvid(self.track_at(pos).await?.bvid)
+            }
+            "pause" => {
+                // Without an argument, pause toggles
+                let pause = match arg {
+                    Some(_) => parse_bool(arg)?,
+                    None => status.state != "paused",
+                };
+                if pause {
+                    Command::Pause
+                } else {
+                    Command::Play
+                }
+            }
+            "stop" => Command::Stop,
+            "next" => Command::Next,
+            "previous" => Command::Previous,
+            "seekcur" => {
+                let time = arg.ok_or_else(|| Ack::new(ACK_ERROR_ARG, "Time expected"))?;
+                let relative = time.starts_with(['+', '-']);
+                let time: f64 = parse_number(Some(time))?;
+                let target = if relative {
+                    status.position + time
+                } else {
+                    time
+                };
+                Command::Seek(seek_target(target, status.duration)?)
+            }
+            "seek" | "seekid" => {
+                let mut pos: usize = parse_number(arg)?;
+                if name == "seekid" {
+                    pos = pos
+                        .checked_sub(1)
+                        .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song"))?;
+                }
+                let tracks = self.audio.tracks().await;
+                let current = status
+                    .track
+                    .as_ref()
+                    .and_then(|track| position_of(&tracks, &track.bvid));
+                if current != Some(pos) {
+                    return Err(Ack::new(
+                        ACK_ERROR_ARG,
+                        "Only the current song can be sought",
+                    ));
+                }
+                let time: f64 = parse_number(args.get(1))?;
+                Command::Seek(seek_target(time, status.duration)?)
+            }
+            "setvol" => Command::SetVolume(f64::from(parse_number::<u8>(arg)?.min(100)) / 100.0),
+            "volume" => Command::AdjustVolume(f64::from(parse_number::<i8>(arg)?) / 100.0),
+            "random" => Command::SetShuffle(parse_bool(arg)?),
+            "repeat" => Command::SetRepeat(match (parse_bool(arg)?, repeat) {
+                (false, _) => Repeat::Off,
+                (true, Repeat::One) => Repeat::One,
+                (true, _) => Repeat::All,
+            }),
+            "single" => Command::SetRepeat(match arg.map(String::as_str) {
+                Some("1" | "oneshot") => Repeat::One,
+                Some("0") if repeat == Repeat::One => Repeat::All,
+                Some("0") => repeat,
+                _ => return Err(Ack::new(ACK_ERROR_ARG, "Boolean (0/1) expected")),
+            }),
+            "consume" if !parse_bool(arg)? => return Ok(String::new()),
+            "consume" => {
+                return Err(Ack::new(ACK_ERROR_ARG, "Consume mode is not supported"));
+            }
+            _ => {
+                return Err(Ack::new(
+                    ACK_ERROR_UNKNOWN,
+                    format!("unknown command \"{name}\""),
+                ));
+            }
+        };
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| Ack::new(ACK_ERROR_SYSTEM, "The player has stopped"))?;
+        Ok(String::new())
+    }
+
+    async fn track_at(&self, pos: usize) -> Result<Track, Ack> {
+        self.audio
+            .tracks()
+            .await
+            .into_iter()
+            .nth(pos)
+            .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song"))
+    }
+}
+
+/// What idle clients are told about when it changes.
+struct Snapshot {
+    state: String,
+    bvid: Option<String>,
+    volume: u64,
+    shuffle: bool,
+    repeat: String,
+    playlist: u32,
+}
+
+impl Snapshot {
+    /// The subsystems, in MPD's terms, that differ in `other`.
+    fn changes(&self, other: &Snapshot) -> Vec<&'static str> {
+        [
+            ("playlist", self.playlist != other.playlist),
+            (
+                "player",
+                self.state != other.state || self.bvid != other.bvid,
+            ),
+            ("mixer", self.volume != other.volume),
+            (
+                "options",
+                self.shuffle != other.shuffle || self.repeat != other.repeat,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(subsystem, _)| subsystem)
+        .collect()
+    }
+}
+
+/// Number that changes whenever the playlist, or which one is played, does.
+fn playlist_version(status: &Status, tracks: &[Track]) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    status.playlist.hash(&mut hasher);
+    for track in tracks {
+        track.bvid.hash(&mut hasher);
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let version = hasher.finish() as u32;
+    version
+}
+
+fn position_of(tracks: &[Track], bvid: &str) -> Option<usize> {
+    tracks.iter().position(|track| track.bvid == bvid)
+}
+
+/// Writes the tags of `track`, at `pos` in the playlist when it is there.
+fn song(out: &mut String, track: &Track, pos: Option<usize>) {
+    let one_line = |text: &str| text.replace(['\r', '\n'], " ");
+    let _ = writeln!(out, "file: {}", page_url(track));
+    let _ = writeln!(out, "Title: {}", one_line(&track.title));
+    let _ = writeln!(out, "Artist: {}", one_line(&track.owner));
+    let _ = writeln!(out, "Time: {}", track.duration);
+    let _ = writeln!(out, "duration: {}.000", track.duration);
+    if let Some(pos) = pos {
+        let _ = writeln!(out, "Pos: {pos}\nId: {}", pos + 1);
+    }
+}
+
+fn percent(volume: f64) -> u32 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let percent = (volume * 100.0).round() as u32;
+    percent
+}
+
+/// Splits a command line into its words. A word may be quoted, and inside
+/// the quotes a backslash escapes the character after it.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+        let mut word = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err("Missing closing '\"'".to_string()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn parse_number<T: FromStr>(arg: Option<&String>) -> Result<T, Ack> {
+    arg.and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| Ack::new(ACK_ERROR_ARG, "Number expected"))
+}
+
+/// Where to seek for `time` seconds into a track `duration` seconds long,
+/// refusing times that are not finite numbers.
+fn seek_target(time: f64, duration: f64) -> Result<Duration, Ack> {
+    if !time.is_finite() {
+        return Err(Ack::new(ACK_ERROR_ARG, "Number expected"));
+    }
+    let time = if duration.is_finite() && duration > 0.0 {
+        time.min(duration)
+    } else {
+        time
+    };
+    Duration::try_from_secs_f64(time.max(0.0))
+        .map_err(|_| Ack::new(ACK_ERROR_ARG, "Time out of range"))
+}
+
+/// Compares the password in time that depends only on the lengths, so
+/// timing a wrong guess tells nothing about how much of it was right.
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn parse_bool(arg: Option<&String>) -> Result<bool, Ack> {
+    match arg.map(String::as_str) {
+        Some("1") => Ok(true),
+        Some("0") => Ok(false),
+        _ => Err(Ack::new(ACK_ERROR_ARG, "Boolean (0/1) expected")),
+    }
+}
+
+/// Parses a song position `POS` or a range `START:END`, where a missing
+/// end means up to the last song.
+fn parse_range(arg: &str) -> Result<Range<usize>, Ack> {
+    let bad = || Ack::new(ACK_ERROR_ARG, format!("Bad song index: {arg}"));
+    match arg.split_once(':') {
+        Some((start, "")) => Ok(start.parse().map_err(|_| bad())?..usize::MAX),
+        Some((start, end)) => {
+            Ok(start.parse().map_err(|_| bad())?..end.parse().map_err(|_| bad())?)
+        }
+        None => {
+            let pos: usize = arg.parse().map_err(|_| bad())?;
+            Ok(pos..pos.checked_add(1).ok_or_else(bad)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, split_words, Session, Step, MAX_COMMAND_LIST_LENGTH};
+    use std::sync::Arc;
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line).unwrap()
+    }
+
+    #[test]
+    fn words_are_split_on_whitespace_and_may_be_quoted() {
+        assert_eq!(words("  play   3 "), ["play", "3"]);
+        assert_eq!(words(r#"password "two words""#), ["password", "two words"]);
+        assert_eq!(words(r#"password """#), ["password", ""]);
+        assert_eq!(
+            words(r#"find "say \"hi\" \\ now""#),
+            ["find", r#"say "hi" \ now"#]
+        );
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn an_unclosed_quote_is_an_error() {
+        assert!(split_words(r#"password "secret"#).is_err());
+        assert!(split_words(r#"password "secret\""#).is_err());
+    }
+
+    #[test]
+    fn ranges_are_a_position_or_start_and_end() {
+        assert_eq!(parse_range("3").ok(), Some(3..4));
+        assert_eq!(parse_range("2:5").ok(), Some(2..5));
+        assert_eq!(parse_range("2:").ok(), Some(2..usize::MAX));
+        assert!(parse_range("").is_err());
+        assert!(parse_range("a:5").is_err());
+        assert!(parse_range("2:b").is_err());
+        assert!(parse_range("-1").is_err());
+        assert!(parse_range(&usize::MAX.to_string()).is_err());
+        assert!(parse_range("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn a_command_list_is_collected_until_its_end() {
+        let mut session = Session::new(Arc::from(""));
+        assert_eq!(session.step("status"), Step::Run);
+        assert_eq!(session.step("command_list_ok_begin"), Step::Listed);
+        assert_eq!(session.step("play"), Step::Listed);
+        assert_eq!(session.step("close"), Step::Listed);
+        assert_eq!(
+            session.step("command_list_end"),
+            Step::RunList(vec!["play".to_string(), "close".to_string()], true)
+        );
+        assert_eq!(session.step("command_list_end"), Step::Run);
+
+        assert_eq!(session.step("command_list_begin"), Step::Listed);
+        assert_eq!(
+            session.step("command_list_end"),
+            Step::RunList(Vec::new(), false)
+        );
+    }
+
+    #[test]
+    fn an_overlong_command_list_is_dropped() {
+        let mut session = Session::new(Arc::from(""));
+        session.step("command_list_begin");
+        for _ in 0..MAX_COMMAND_LIST_LENGTH {
+            assert_eq!(session.step("ping"), Step::Listed);
+        }
+        assert_eq!(
+            session.step("ping"),
+            Step::Refuse("ACK [2@1024] {ping} Command list too long\n".to_string())
+        );
+        assert_eq!(session.step("command_list_end"), Step::Run);
+    }
+
+    #[test]
+    fn only_the_password_and_harmless_commands_are_allowed_before_it() {
+        let mut session = Session::new(Arc::from("secret"));
+        for name in ["password", "ping", "commands", "notcommands"] {
+            assert!(session.permit(name).is_ok(), "{name}");
+        }
+        assert!(session.permit("play").is_err());
+        assert!(session.permit("status").is_err());
+
+        assert!(session.check_password(&[]).is_err());
+        assert!(session.check_password(&["secre".to_string()]).is_err());
+        assert!(session.check_password(&["secrets".to_string()]).is_err());
+        assert!(session.permit("play").is_err());
+
+        assert!(session.check_password(&["secret".to_string()]).is_ok());
+        assert!(session.permit("play").is_ok());
+    }
+
+    #[test]
+    fn without_a_password_everything_is_allowed() {
+        let session = Session::new(Arc::from(""));
+        assert!(session.permit("play").is_ok());
+    }
+}
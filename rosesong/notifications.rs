@@ -0,0 +1,77 @@
+use crate::error::App;
+use crate::player::Audio;
+use log::{info, warn};
+use std::collections::HashMap;
+use tokio::sync::{broadcast, watch};
+use zbus::zvariant::Value;
+use zbus::{proxy, Connection};
+
+const APPLICATION: &str = "rosesong";
+/// How long a notification stays up, in milliseconds.
+const EXPIRE_TIMEOUT: i32 = 8000;
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Shows a desktop notification each time a track fails to play, replacing
+/// the previous one so a run of failures does not pile up. Runs until the
+/// stop signal fires.
+pub async fn run_error_notifier(audio: Audio, stop_signal: watch::Sender<()>) -> Result<(), App> {
+    let connection = Connection::session().await?;
+    let notifications = NotificationsProxy::new(&connection).await?;
+    let mut errors = audio.subscribe_errors();
+    let mut last_id = 0;
+    info!("Showing playback errors as desktop notifications");
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            received = errors.recv() => match received {
+                Ok(error) => {
+                    let summary = match &error.track {
+                        Some(track) => format!("无法播放 {}", track.title),
+                        None => "无法播放".to_string(),
+                    };
+                    match notifications
+                        .notify(
+                            APPLICATION,
+                            last_id,
+                            "dialog-error",
+                            &summary,
+                            &error.message,
+                            &[],
+                            HashMap::new(),
+                            EXPIRE_TIMEOUT,
+                        )
+                        .await
+                    {
+                        Ok(id) => last_id = id,
+                        Err(e) => warn!("Failed to show a notification: {}", e),
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    Ok(())
+}
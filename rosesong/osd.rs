@@ -0,0 +1,66 @@
+use rosesong_core::ipc::Status;
+
+/// Text an OSD should show for `status`: the title and owner of the track,
+/// or nothing when playback is stopped.
+#[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+pub fn osd_text(status: &Status) -> String {
+    match &status.track {
+        Some(track) if status.state != "stopped" => format!("{} - {}", track.title, track.owner),
+        _ => String::new(),
+    }
+}
+
+/// Writes the OSD text as one line to the named pipe at `path` whenever it
+/// changes, until the stop signal fires. Lines are dropped while no reader
+/// has the pipe open, so a missing OSD never blocks the daemon.
+#[cfg(all(feature = "osd", target_os = "linux"))]
+pub async fn run_osd_fifo(
+    path: std::path::PathBuf,
+    audio: crate::player::Audio,
+    stop_signal: tokio::sync::watch::Sender<()>,
+) {
+    use log::{error, info};
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if !path.exists() {
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            error!("Invalid OSD fifo path {:?}", path);
+            return;
+        };
+        // SAFETY: c_path is a valid NUL-terminated string
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            error!(
+                "Failed to create OSD fifo {:?}: {}",
+                path,
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        info!("Created OSD fifo {:?}", path);
+    }
+
+    let mut stop_receiver = stop_signal.subscribe();
+    let mut changes = audio.subscribe_changes();
+    let mut last_text = None;
+    loop {
+        tokio::select! {
+            Ok(()) = changes.changed() => {}
+            _ = stop_receiver.changed() => break,
+        }
+        let text = osd_text(&audio.status().await);
+        if last_text.as_ref() == Some(&text) {
+            continue;
+        }
+        // Opening without a reader fails with ENXIO instead of blocking
+        let written = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .and_then(|mut fifo| writeln!(fifo, "{text}"));
+        if written.is_ok() {
+            last_text = Some(text);
+        }
+    }
+}
@@ -0,0 +1,630 @@
+use super::{
+    BufferStats, Channels, Filters, PlayerBackend, APP_NAME, PIPEWIRE_ROLE, PULSE_ROLE,
+    SILENCE_THRESHOLD_DB,
+};
+use crate::error::App;
+use futures_util::stream::StreamExt;
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, MessageView, Pipeline, SeekFlags};
+use log::{error, info, warn};
+use rosesong_core::ipc::{OutputFormat, StreamInfo};
+use rosesong_core::settings::{OutputSettings, OutputSink};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task;
+
+/// How often the `level` element measures while silence is skipped.
+const LEVEL_INTERVAL: Duration = Duration::from_millis(100);
+/// How far each jump over silence goes.
+const SILENCE_STEP: Duration = Duration::from_secs(1);
+/// Silence this far into a track is part of the music.
+const MAX_INTRO: Duration = Duration::from_secs(30);
+/// How much of the stream is downloaded ahead of playback, enough for a
+/// whole track at the usual bitrates.
+const BUFFER_SIZE_BYTES: u32 = 8 * 1024 * 1024;
+
+const MISSING_DECODER_HINT: &str = "No GStreamer decoder for this stream, Dolby (E-AC-3) \
+    and some other codecs need gst-libav or gst-plugins-bad";
+
+#[derive(Debug)]
+pub struct GStreamerBackend {
+    pipeline: Pipeline,
+    volume: Arc<Mutex<f64>>,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    headers: Vec<(String, String)>,
+    output: OutputSettings,
+    filters: Mutex<Filters>,
+    intro: Arc<Mutex<IntroSkip>>,
+}
+
+/// Progress of jumping over the silence at the start of a track.
+#[derive(Debug, Default)]
+struct IntroSkip {
+    active: bool,
+    /// Where the last jump went; levels measured before it are stale.
+    target: Duration,
+    /// Start of the last stretch measured as silent.
+    last_silent: Option<Duration>,
+}
+
+impl GStreamerBackend {
+    pub fn new(
+        eos_sender: mpsc::Sender<()>,
+        headers: &[(String, String)],
+        output: &OutputSettings,
+        filters: Filters,
+    ) -> Result<Self, App> {
+        gstreamer::init().map_err(|e| App::Init(e.to_string()))?;
+        // Sound servers name the stream after the application
+        gstreamer::glib::set_application_name(APP_NAME);
+        let pipeline = gstreamer::Pipeline::new();
+        info!("GStreamer created successfully.");
+
+        let bus = pipeline
+            .bus()
+            .ok_or_else(|| App::Pipeline("Failed to get GStreamer bus".to_string()))?;
+        let stream_info = Arc::new(Mutex::new(None));
+        let intro = Arc::new(Mutex::new(IntroSkip::default()));
+        task::spawn(bus.stream().for_each({
+            let stream_info = Arc::clone(&stream_info);
+            let intro = Arc::clone(&intro);
+            let pipeline_weak = pipeline.downgrade();
+            move |msg| {
+                let eos_sender = eos_sender.clone();
+                let stream_info = Arc::clone(&stream_info);
+                let intro = Arc::clone(&intro);
+                let pipeline_weak = pipeline_weak.clone();
+                async move {
+                    match msg.view() {
+                        MessageView::Eos(_) => {
+                            info!("EOS message received, sending signal.");
+                            if eos_sender.send(()).await.is_err() {
+                                error!("Failed to send EOS signal");
+                            }
+                        }
+                        MessageView::Error(err) => {
+                            if err.error().matches(gstreamer::CoreError::MissingPlugin)
+                                || err.error().matches(gstreamer::StreamError::CodecNotFound)
+                            {
+                                error!("{}: {}", MISSING_DECODER_HINT, err);
+                            } else {
+                                error!("Error from GStreamer pipeline: {}", err);
+                            }
+                        }
+                        MessageView::Tag(tag) => {
+                            let tags = tag.tags();
+                            let mut stream_info =
+                                stream_info.lock().unwrap_or_else(PoisonError::into_inner);
+                            let stream_info = stream_info.get_or_insert_with(StreamInfo::default);
+                            if let Some(codec) = tags.get::<gstreamer::tags::AudioCodec>() {
+                                stream_info.codec = codec.get().to_string();
+                            }
+                            if let Some(bitrate) = tags
+                                .get::<gstreamer::tags::Bitrate>()
+                                .or_else(|| tags.get::<gstreamer::tags::NominalBitrate>())
+                            {
+                                stream_info.bitrate = Some(bitrate.get());
+                            }
+                        }
+                        MessageView::Element(element) => {
+                            if let (Some(level), Some(pipeline)) = (
+                                element.structure().filter(|s| s.name() == "level"),
+                                pipeline_weak.upgrade(),
+                            ) {
+                                skip_silence(&pipeline, &intro, level);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }));
+
+        Ok(GStreamerBackend {
+            pipeline,
+            volume: Arc::new(Mutex::new(1.0)),
+            stream_info,
+            headers: headers.to_vec(),
+            output: output.clone(),
+            filters: Mutex::new(filters),
+            intro,
+        })
+    }
+
+    fn set_state(&self, state: gstreamer::State) -> Result<(), App> {
+        self.pipeline
+            .set_state(state)
+            .map_err(|_| App::State(format!("Failed to set pipeline to {state:?}")))?;
+        Ok(())
+    }
+
+    fn volume_element(&self) -> Option<gstreamer::Element> {
+        self.pipeline.by_name("volume")
+    }
+
+    /// Format negotiated on the sink's input, after conversion and
+    /// resampling.
+    fn output_format(&self) -> Option<OutputFormat> {
+        let caps = self
+            .pipeline
+            .by_name("output")?
+            .static_pad("sink")?
+            .current_caps()?;
+        let structure = caps.structure(0)?;
+        Some(OutputFormat {
+            format: structure.get::<String>("format").unwrap_or_default(),
+            sample_rate: structure
+                .get::<i32>("rate")
+                .ok()
+                .and_then(|rate| u32::try_from(rate).ok()),
+        })
+    }
+
+    fn current_volume(&self) -> f64 {
+        *self
+            .volume
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl PlayerBackend for GStreamerBackend {
+    fn load(&self, url: &str, paused: bool) -> Result<(), App> {
+        self.stop()?;
+        for element in self.pipeline.children() {
+            self.pipeline
+                .remove(&element)
+                .map_err(|_| App::Element("Failed to remove element from pipeline".to_string()))?;
+        }
+        self.set_state(gstreamer::State::Ready)?;
+        *self
+            .stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        let filters = *self.filters.lock().unwrap_or_else(PoisonError::into_inner);
+        *self.intro.lock().unwrap_or_else(PoisonError::into_inner) = IntroSkip {
+            active: filters.skip_silence,
+            ..IntroSkip::default()
+        };
+        set_pipeline_uri_with_headers(
+            &self.pipeline,
+            url,
+            &self.headers,
+            self.current_volume(),
+            &self.output,
+            filters,
+            Arc::clone(&self.stream_info),
+        )?;
+        self.set_state(if paused {
+            gstreamer::State::Paused
+        } else {
+            gstreamer::State::Playing
+        })
+    }
+
+    fn play(&self) -> Result<(), App> {
+        self.set_state(gstreamer::State::Playing)
+    }
+
+    fn pause(&self) -> Result<(), App> {
+        self.set_state(gstreamer::State::Paused)
+    }
+
+    fn stop(&self) -> Result<(), App> {
+        self.set_state(gstreamer::State::Null)
+    }
+
+    fn seek(&self, position: Duration) -> Result<(), App> {
+        let position =
+            ClockTime::from_nseconds(u64::try_from(position.as_nanos()).unwrap_or(u64::MAX));
+        self.pipeline
+            .seek_simple(SeekFlags::FLUSH | SeekFlags::KEY_UNIT, position)
+            .map_err(|_| App::Pipeline("Failed to seek".to_string()))
+    }
+
+    fn set_volume(&self, volume: f64) -> Result<(), App> {
+        *self
+            .volume
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = volume;
+        if let Some(element) = self.volume_element() {
+            element.set_property("volume", volume);
+        }
+        Ok(())
+    }
+
+    fn set_channels(&self, channels: Channels) -> Result<(), App> {
+        self.filters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .channels = channels;
+        if let Some(mapper) = self.pipeline.by_name("channels") {
+            mapper.set_property("mix-matrix", mix_matrix(channels));
+        }
+        Ok(())
+    }
+
+    /// The `pitch` element is only part of pipelines built with a shift, so
+    /// shifting a stream that plays at its own pitch takes a reload.
+    fn set_pitch(&self, semitones: i32) -> Result<bool, App> {
+        let mut filters = self.filters.lock().unwrap_or_else(PoisonError::into_inner);
+        filters.pitch = semitones;
+        match self.pipeline.by_name("pitch") {
+            Some(pitch) => {
+                #[allow(clippy::cast_possible_truncation)]
+                pitch.set_property("pitch", filters.pitch_ratio() as f32);
+                Ok(true)
+            }
+            None => Ok(semitones == 0),
+        }
+    }
+
+    fn position(&self) -> Option<Duration> {
+        self.pipeline
+            .query_position::<ClockTime>()
+            .map(|position| Duration::from_nanos(position.nseconds()))
+    }
+
+    fn stream_info(&self) -> Option<StreamInfo> {
+        let mut stream_info = self
+            .stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()?;
+        stream_info.output = self.output_format();
+        Some(stream_info)
+    }
+
+    /// The stream is not timestamped before it is demuxed, so the buffered
+    /// time is worked out from the queued bytes and the bitrate.
+    fn buffer_stats(&self) -> BufferStats {
+        let Some(buffer) = self.pipeline.by_name("buffer") else {
+            return BufferStats::default();
+        };
+        let bitrate = self
+            .stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .and_then(|stream_info| stream_info.bitrate)
+            .filter(|bitrate| *bitrate > 0);
+        let buffered = numeric_property(&buffer, "current-level-time")
+            .filter(|nanoseconds| *nanoseconds > 0)
+            .map(Duration::from_nanos)
+            .or_else(|| {
+                let bytes = numeric_property(&buffer, "current-level-bytes")?;
+                #[allow(clippy::cast_precision_loss)]
+                let seconds = bytes as f64 * 8.0 / f64::from(bitrate?);
+                Some(Duration::from_secs_f64(seconds))
+            });
+        BufferStats {
+            download_rate: numeric_property(&buffer, "avg-in-rate"),
+            buffered,
+        }
+    }
+}
+
+/// A numeric property of `element`, or `None` when it has no such property
+/// or it is negative.
+fn numeric_property(element: &gstreamer::Element, name: &str) -> Option<u64> {
+    element.find_property(name)?;
+    let value = element.property_value(name);
+    value
+        .get::<u64>()
+        .ok()
+        .or_else(|| value.get::<u32>().ok().map(u64::from))
+        .or_else(|| {
+            value
+                .get::<i64>()
+                .ok()
+                .and_then(|value| u64::try_from(value).ok())
+        })
+}
+
+fn set_pipeline_uri_with_headers(
+    pipeline: &Pipeline,
+    url: &str,
+    headers: &[(String, String)],
+    volume: f64,
+    output: &OutputSettings,
+    filters: Filters,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+) -> Result<(), App> {
+    let source = gstreamer::ElementFactory::make("souphttpsrc")
+        .build()
+        .map_err(|_| App::Element("Failed to create souphttpsrc element".to_string()))?;
+    source.set_property("location", url);
+
+    let mut extra_headers = gstreamer::Structure::new_empty("headers");
+    for (name, value) in headers {
+        extra_headers.set(name.as_str(), value);
+    }
+    source.set_property("extra-headers", &extra_headers);
+
+    // Downloads ahead in its own thread, so a slow moment of the CDN is
+    // bridged and the download rate and buffer level can be read from it
+    let buffer = gstreamer::ElementFactory::make("queue2")
+        .name("buffer")
+        .property("max-size-bytes", BUFFER_SIZE_BYTES)
+        .property("max-size-buffers", 0_u32)
+        .property("max-size-time", 0_u64)
+        .build()
+        .map_err(|_| App::Element("Failed to create queue2 element".to_string()))?;
+
+    let decodebin = gstreamer::ElementFactory::make("decodebin")
+        .build()
+        .map_err(|_| App::Element("Failed to create decodebin element".to_string()))?;
+
+    pipeline
+        .add_many([&source, &buffer, &decodebin])
+        .map_err(|_| App::Pipeline("Failed to add elements to pipeline".to_string()))?;
+    gstreamer::Element::link_many([&source, &buffer, &decodebin])
+        .map_err(|_| App::Link("Failed to link source to decodebin".to_string()))?;
+
+    let pipeline_weak = pipeline.downgrade();
+    let output = output.clone();
+
+    decodebin.connect_pad_added(move |_, src_pad| {
+        let mut sample_rate = None;
+        let mut stereo = false;
+        if let Some(caps) = src_pad.current_caps() {
+            if let Some(structure) = caps.structure(0) {
+                let mut stream_info = stream_info.lock().unwrap_or_else(PoisonError::into_inner);
+                let stream_info = stream_info.get_or_insert_with(StreamInfo::default);
+                sample_rate = structure
+                    .get::<i32>("rate")
+                    .ok()
+                    .and_then(|rate| u32::try_from(rate).ok());
+                stream_info.sample_rate = sample_rate;
+                stream_info.channels = structure
+                    .get::<i32>("channels")
+                    .ok()
+                    .and_then(|channels| u32::try_from(channels).ok());
+                stereo = stream_info.channels == Some(2);
+                info!("Decoded stream caps: {}", caps);
+            }
+        }
+
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            let audioconvert = gstreamer::ElementFactory::make("audioconvert")
+                .build()
+                .expect("Failed to create audioconvert element");
+            let audioresample = gstreamer::ElementFactory::make("audioresample")
+                .build()
+                .expect("Failed to create audioresample element");
+            let volume_element = gstreamer::ElementFactory::make("volume")
+                .name("volume")
+                .property("volume", volume)
+                .build()
+                .expect("Failed to create volume element");
+            let sink = make_sink(&output, sample_rate);
+
+            let mut elements = vec![audioconvert.clone()];
+            if stereo {
+                // A second converter, so the first can still turn the decoded
+                // format into one the matrix takes
+                elements.push(
+                    gstreamer::ElementFactory::make("audioconvert")
+                        .name("channels")
+                        .property("mix-matrix", mix_matrix(filters.channels))
+                        .build()
+                        .expect("Failed to create audioconvert element"),
+                );
+            }
+            if filters.skip_silence {
+                elements.extend(make_level());
+            }
+            if filters.pitch != 0 {
+                elements.extend(make_pitch(filters));
+            }
+            // Without a resampler the sink has to take the stream's own rate
+            if !output.exclusive {
+                elements.push(audioresample);
+            }
+            elements.extend([volume_element, sink]);
+            pipeline
+                .add_many(&elements)
+                .expect("Failed to add elements to pipeline");
+
+            for element in &elements {
+                element
+                    .sync_state_with_parent()
+                    .expect("Failed to sync_state_with_parent");
+            }
+
+            let audio_pad = audioconvert
+                .static_pad("sink")
+                .expect("Failed to get static pad");
+            src_pad.link(&audio_pad).expect("Failed to link pads");
+
+            gstreamer::Element::link_many(&elements).expect("Failed to link audio elements");
+
+            info!("Pipeline elements linked successfully");
+        } else {
+            error!("Failed to upgrade pipeline reference");
+        }
+    });
+
+    Ok(())
+}
+
+/// A `pitch` element shifting by `filters.pitch`, followed by a converter
+/// back to a format the sink takes, or nothing when the soundtouch plugin
+/// is missing.
+fn make_pitch(filters: Filters) -> Vec<gstreamer::Element> {
+    #[allow(clippy::cast_possible_truncation)]
+    let pitch = gstreamer::ElementFactory::make("pitch")
+        .name("pitch")
+        .property("pitch", filters.pitch_ratio() as f32)
+        .build();
+    match pitch {
+        Ok(pitch) => vec![
+            pitch,
+            gstreamer::ElementFactory::make("audioconvert")
+                .build()
+                .expect("Failed to create audioconvert element"),
+        ],
+        Err(e) => {
+            warn!(
+                "Changing the pitch needs the soundtouch plugin of gst-plugins-bad: {}",
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// A `level` element reporting the loudness for [`skip_silence`], or
+/// nothing when its plugin is missing.
+fn make_level() -> Option<gstreamer::Element> {
+    gstreamer::ElementFactory::make("level")
+        .name("level")
+        .property(
+            "interval",
+            u64::try_from(LEVEL_INTERVAL.as_nanos()).unwrap_or(u64::MAX),
+        )
+        .property("post-messages", true)
+        .build()
+        .map_err(|e| warn!("Skipping silence needs the level plugin: {}", e))
+        .ok()
+}
+
+/// Jumps ahead while the start of the track measures as silent. Once sound
+/// is heard it goes back to the last silent spot, so nothing audible is
+/// skipped, and stops for the rest of the track.
+fn skip_silence(pipeline: &Pipeline, intro: &Mutex<IntroSkip>, level: &gstreamer::StructureRef) {
+    let mut intro = intro.lock().unwrap_or_else(PoisonError::into_inner);
+    if !intro.active {
+        return;
+    }
+    let (Ok(time), Ok(rms)) = (
+        level.get::<u64>("stream-time"),
+        level.get::<glib::ValueArray>("rms"),
+    ) else {
+        return;
+    };
+    let time = Duration::from_nanos(time);
+    if time < intro.target {
+        return;
+    }
+    if time > MAX_INTRO {
+        intro.active = false;
+        return;
+    }
+    let loudest = rms
+        .iter()
+        .filter_map(|channel| channel.get::<f64>().ok())
+        .fold(f64::NEG_INFINITY, f64::max);
+    let position = if loudest < SILENCE_THRESHOLD_DB {
+        intro.last_silent = Some(time);
+        intro.target = time + SILENCE_STEP;
+        intro.target
+    } else {
+        intro.active = false;
+        match intro.last_silent {
+            Some(last_silent) => {
+                info!("Skipped {:?} of silence", last_silent);
+                last_silent
+            }
+            None => return,
+        }
+    };
+    let position = ClockTime::from_nseconds(u64::try_from(position.as_nanos()).unwrap_or(u64::MAX));
+    if pipeline
+        .seek_simple(SeekFlags::FLUSH | SeekFlags::KEY_UNIT, position)
+        .is_err()
+    {
+        warn!("Failed to skip silence, playing it");
+        intro.active = false;
+    }
+}
+
+/// `mix-matrix` of `audioconvert` for `channels`: one row per output
+/// channel, weighting the left and right input channels.
+fn mix_matrix(channels: Channels) -> gstreamer::Array {
+    let rows: [[f32; 2]; 2] = match channels {
+        Channels::Stereo => [[1.0, 0.0], [0.0, 1.0]],
+        Channels::Left => [[1.0, 0.0], [1.0, 0.0]],
+        Channels::Right => [[0.0, 1.0], [0.0, 1.0]],
+        Channels::Mix => [[0.5, 0.5], [0.5, 0.5]],
+    };
+    gstreamer::Array::new(rows.map(gstreamer::Array::new))
+}
+
+/// The sink selected in `[output]`, named `output`, falling back to
+/// `autoaudiosink` when its plugin is missing. A `PipeWire` sink asks for
+/// `sample_rate` when `match_rate` is set. Sound server streams are marked
+/// as music.
+fn make_sink(output: &OutputSettings, sample_rate: Option<u32>) -> gstreamer::Element {
+    let sink = match output.sink {
+        OutputSink::Auto => None,
+        OutputSink::Alsa => {
+            let mut builder = gstreamer::ElementFactory::make("alsasink").name("output");
+            if let Some(device) = &output.device {
+                builder = builder.property("device", device);
+            }
+            Some(builder.build())
+        }
+        OutputSink::PipeWire => {
+            let mut builder = gstreamer::ElementFactory::make("pipewiresink").name("output");
+            if let Some(device) = &output.device {
+                builder = builder.property("target-object", device);
+            }
+            let mut properties = stream_properties(PIPEWIRE_ROLE);
+            if output.exclusive {
+                properties.set("node.exclusive", true);
+                properties.set("resample.disable", true);
+            }
+            if let Some(rate) = sample_rate.filter(|_| output.match_rate) {
+                info!("Asking PipeWire for a sample rate of {} Hz", rate);
+                properties.set("node.rate", format!("1/{rate}"));
+            }
+            builder = builder.property("stream-properties", properties);
+            Some(builder.build())
+        }
+    };
+    match sink {
+        Some(Ok(sink)) => sink,
+        Some(Err(e)) => {
+            error!(
+                "Failed to create the {:?} sink, falling back to autoaudiosink: {}",
+                output.sink, e
+            );
+            make_auto_sink()
+        }
+        None => make_auto_sink(),
+    }
+}
+
+fn make_auto_sink() -> gstreamer::Element {
+    let sink = gstreamer::ElementFactory::make("autoaudiosink")
+        .name("output")
+        .build()
+        .expect("Failed to create autoaudiosink element");
+    // The actual sink is only picked when the pipeline starts
+    if let Some(bin) = sink.downcast_ref::<gstreamer::Bin>() {
+        bin.connect_element_added(|_, child| {
+            let factory = child.factory().map(|factory| factory.name().to_string());
+            match factory.as_deref() {
+                Some("pulsesink") => {
+                    child.set_property("stream-properties", stream_properties(PULSE_ROLE));
+                }
+                Some("pipewiresink") => {
+                    child.set_property("stream-properties", stream_properties(PIPEWIRE_ROLE));
+                }
+                _ => {}
+            }
+        });
+    }
+    sink
+}
+
+/// Stream properties giving the output its own entry in volume panels,
+/// playing in `role`.
+fn stream_properties(role: &str) -> gstreamer::Structure {
+    gstreamer::Structure::builder("props")
+        .field("media.role", role)
+        .field("application.name", APP_NAME)
+        .build()
+}
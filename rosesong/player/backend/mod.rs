@@ -0,0 +1,189 @@
+#[cfg(feature = "gstreamer")]
+mod gstreamer;
+#[cfg(unix)]
+mod mpv;
+mod null;
+#[cfg(feature = "rodio")]
+mod rodio;
+
+use crate::error::App;
+#[cfg(feature = "rodio")]
+use log::warn;
+use rosesong_core::ipc::StreamInfo;
+#[cfg(feature = "rodio")]
+use rosesong_core::settings::OutputSink;
+use rosesong_core::settings::{OutputSettings, PlayerBackendKind};
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[cfg(feature = "gstreamer")]
+pub use self::gstreamer::GStreamerBackend;
+#[cfg(unix)]
+pub use self::mpv::MpvBackend;
+pub use self::null::NullBackend;
+#[cfg(feature = "rodio")]
+pub use self::rodio::RodioBackend;
+
+/// How the two channels of a stereo stream reach the output, for videos
+/// that put the backing track on one side and the vocals on the other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Channels {
+    #[default]
+    Stereo,
+    /// Only the left channel, on both speakers.
+    Left,
+    Right,
+    /// Both channels mixed down to mono.
+    Mix,
+}
+
+impl Channels {
+    pub fn parse(channels: &str) -> Option<Self> {
+        match channels.to_ascii_lowercase().as_str() {
+            "stereo" => Some(Channels::Stereo),
+            "left" => Some(Channels::Left),
+            "right" => Some(Channels::Right),
+            "mix" | "mono" => Some(Channels::Mix),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channels::Stereo => "stereo",
+            Channels::Left => "left",
+            Channels::Right => "right",
+            Channels::Mix => "mix",
+        }
+    }
+}
+
+/// Largest pitch shift in semitones, either way.
+pub const MAX_PITCH: i32 = 6;
+
+/// Level below which the start of a track counts as silent.
+pub const SILENCE_THRESHOLD_DB: f64 = -50.0;
+
+/// Name the player shows up under in the sound server's volume panel.
+pub const APP_NAME: &str = "RoseSong";
+/// `media.role` of the output stream, which per-application volume and
+/// role based ducking go by. `PulseAudio` spells roles in lower case.
+pub const PIPEWIRE_ROLE: &str = "Music";
+pub const PULSE_ROLE: &str = "music";
+
+/// Environment variable naming the backend to use instead of the one in the
+/// settings, such as `null` on a machine without sound.
+pub const BACKEND_ENV: &str = "ROSESONG_BACKEND";
+
+/// The adjustments applied between the decoder and the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filters {
+    pub channels: Channels,
+    /// Pitch shift in semitones, keeping the tempo.
+    pub pitch: i32,
+    /// Jump over the silence at the start of each track.
+    pub skip_silence: bool,
+}
+
+impl Filters {
+    /// Factor the frequencies are multiplied with for `pitch`.
+    pub fn pitch_ratio(self) -> f64 {
+        2f64.powf(f64::from(self.pitch) / 12.0)
+    }
+}
+
+/// How far the download of the loaded stream is ahead of playback, as far
+/// as the backend can tell.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferStats {
+    /// Bytes per second the stream is currently downloaded at.
+    pub download_rate: Option<u64>,
+    /// Audio downloaded but not played yet.
+    pub buffered: Option<Duration>,
+}
+
+/// Audio output used by the player. Implementations send `()` on the EOS
+/// channel they were created with when a track finishes.
+pub trait PlayerBackend: Send + Sync + Debug {
+    /// Replaces whatever is loaded with `url` and starts playing it, or
+    /// leaves it `paused` without letting a sound out.
+    fn load(&self, url: &str, paused: bool) -> Result<(), App>;
+
+    fn play(&self) -> Result<(), App>;
+
+    fn pause(&self) -> Result<(), App>;
+
+    fn stop(&self) -> Result<(), App>;
+
+    fn seek(&self, position: Duration) -> Result<(), App>;
+
+    /// Sets the output volume, where `1.0` is unchanged.
+    fn set_volume(&self, volume: f64) -> Result<(), App>;
+
+    /// Maps the channels of the playing stream and of the later ones.
+    /// Streams that are not stereo play unchanged.
+    fn set_channels(&self, channels: Channels) -> Result<(), App>;
+
+    /// Shifts the pitch by `semitones` without changing the tempo. Returns
+    /// whether the playing stream changed too; when not, it has to be loaded
+    /// again to pick up the new pitch.
+    fn set_pitch(&self, semitones: i32) -> Result<bool, App>;
+
+    /// Length of the track loaded next, for backends that do not decode
+    /// the stream.
+    fn set_duration(&self, _duration: Duration) {}
+
+    /// Position in the loaded track, if anything is loaded.
+    fn position(&self) -> Option<Duration>;
+
+    /// Format of the loaded stream as far as the decoder has reported it.
+    fn stream_info(&self) -> Option<StreamInfo>;
+
+    /// Download progress of the loaded stream; backends that read it on
+    /// demand report nothing.
+    fn buffer_stats(&self) -> BufferStats {
+        BufferStats::default()
+    }
+}
+
+/// Creates the backend of `kind`, sending `headers` with every stream
+/// request and playing through `output` with `filters` applied.
+pub async fn create(
+    kind: PlayerBackendKind,
+    eos_sender: mpsc::Sender<()>,
+    headers: &[(String, String)],
+    output: &OutputSettings,
+    filters: Filters,
+) -> Result<Box<dyn PlayerBackend>, App> {
+    let kind = match std::env::var(BACKEND_ENV) {
+        Ok(name) if !name.is_empty() => PlayerBackendKind::parse(&name)
+            .ok_or_else(|| App::Init(format!("Unknown backend {name} in {BACKEND_ENV}")))?,
+        _ => kind,
+    };
+    match kind {
+        #[cfg(feature = "gstreamer")]
+        PlayerBackendKind::GStreamer => Ok(Box::new(GStreamerBackend::new(
+            eos_sender, headers, output, filters,
+        )?)),
+        #[cfg(unix)]
+        PlayerBackendKind::Mpv => Ok(Box::new(
+            MpvBackend::spawn(eos_sender, headers, output, filters).await?,
+        )),
+        #[cfg(feature = "rodio")]
+        PlayerBackendKind::Rodio => {
+            if output.sink != OutputSink::Auto || output.exclusive {
+                warn!("The rodio backend always plays to the default device, ignoring [output]");
+            }
+            if filters.skip_silence {
+                warn!("The rodio backend cannot detect silence, ignoring skip_silence");
+            }
+            Ok(Box::new(RodioBackend::new(eos_sender, headers)?))
+        }
+        PlayerBackendKind::Null => Ok(Box::new(NullBackend::new(eos_sender))),
+        #[allow(unreachable_patterns)]
+        _ => Err(App::Init(format!(
+            "rosesong was built without the {kind:?} backend"
+        ))),
+    }
+}
@@ -0,0 +1,342 @@
+use super::{
+    BufferStats, Channels, Filters, PlayerBackend, APP_NAME, PIPEWIRE_ROLE, PULSE_ROLE,
+    SILENCE_THRESHOLD_DB,
+};
+use crate::error::App;
+use log::{error, info, warn};
+use rosesong_core::ipc::{OutputFormat, StreamInfo};
+use rosesong_core::paths;
+use rosesong_core::settings::{OutputSettings, OutputSink};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const CONNECT_ATTEMPTS: u32 = 50;
+const CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Plays through an `mpv` child process controlled over its JSON IPC socket.
+#[derive(Debug)]
+pub struct MpvBackend {
+    child: Mutex<Child>,
+    socket: Mutex<UnixStream>,
+    socket_path: PathBuf,
+    position: Arc<Mutex<Option<Duration>>>,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    buffer: Arc<Mutex<BufferStats>>,
+    filters: Mutex<Filters>,
+}
+
+impl MpvBackend {
+    pub async fn spawn(
+        eos_sender: mpsc::Sender<()>,
+        headers: &[(String, String)],
+        output: &OutputSettings,
+        filters: Filters,
+    ) -> Result<Self, App> {
+        // Kept next to the control socket rather than in the shared temp
+        // directory, where another user could take the name first
+        let socket_path =
+            paths::runtime_dir()?.join(format!("rosesong-mpv-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let mut command = Command::new("mpv");
+        command
+            .arg("--idle=yes")
+            .arg("--no-video")
+            .arg("--no-terminal")
+            .arg(format!("--input-ipc-server={}", socket_path.display()));
+        command.args(output_args(output));
+        // mpv has no option for the role, both sound servers take it from
+        // the environment
+        command
+            .arg(format!("--audio-client-name={APP_NAME}"))
+            .env("PULSE_PROP", format!("media.role={PULSE_ROLE}"))
+            .env(
+                "PIPEWIRE_PROPS",
+                format!("{{ media.role = {PIPEWIRE_ROLE} }}"),
+            );
+        let chain = filter_chain(filters);
+        if !chain.is_empty() {
+            command.arg(format!("--af={chain}"));
+        }
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| App::Init(format!("Failed to start mpv: {e}")))?;
+
+        let socket = connect(&socket_path).await?;
+        let reader = socket.try_clone()?;
+        let position = Arc::new(Mutex::new(None));
+        let stream_info = Arc::new(Mutex::new(None));
+        let buffer = Arc::new(Mutex::new(BufferStats::default()));
+        spawn_event_reader(
+            reader,
+            eos_sender,
+            Arc::clone(&position),
+            Arc::clone(&stream_info),
+            Arc::clone(&buffer),
+        );
+        info!("mpv started successfully.");
+
+        let backend = MpvBackend {
+            child: Mutex::new(child),
+            socket: Mutex::new(socket),
+            socket_path,
+            position,
+            stream_info,
+            buffer,
+            filters: Mutex::new(filters),
+        };
+        backend.command(&json!(["observe_property", 1, "time-pos"]))?;
+        backend.command(&json!(["observe_property", 2, "audio-codec-name"]))?;
+        backend.command(&json!(["observe_property", 3, "audio-params"]))?;
+        backend.command(&json!(["observe_property", 4, "audio-bitrate"]))?;
+        backend.command(&json!(["observe_property", 5, "audio-out-params"]))?;
+        backend.command(&json!(["observe_property", 6, "demuxer-cache-state"]))?;
+        backend.set_headers(headers)?;
+        Ok(backend)
+    }
+
+    /// Sends the request headers over the socket; on the command line any
+    /// local user could read the cookie from the process list.
+    fn set_headers(&self, headers: &[(String, String)]) -> Result<(), App> {
+        let mut fields = Vec::new();
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case("user-agent") {
+                self.set_property("user-agent", &json!(value))?;
+            } else {
+                fields.push(format!("{name}: {value}"));
+            }
+        }
+        self.set_property("http-header-fields", &json!(fields))
+    }
+
+    fn command(&self, command: &Value) -> Result<(), App> {
+        let mut line = json!({ "command": command }).to_string();
+        line.push('\n');
+        self.socket
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write_all(line.as_bytes())
+            .map_err(|e| App::Pipeline(format!("Failed to send command to mpv: {e}")))
+    }
+
+    fn set_property(&self, name: &str, value: &Value) -> Result<(), App> {
+        self.command(&json!(["set_property", name, value]))
+    }
+
+    /// Replaces the audio filter chain with the one for `update`d filters.
+    fn update_filters(&self, update: impl FnOnce(&mut Filters)) -> Result<(), App> {
+        let mut filters = self.filters.lock().unwrap_or_else(PoisonError::into_inner);
+        update(&mut filters);
+        self.set_property("af", &json!(filter_chain(*filters)))
+    }
+}
+
+/// The `af` option for `filters`; the pitch shift uses librubberband and
+/// silence is cut by ffmpeg's `silenceremove` before either.
+fn filter_chain(filters: Filters) -> String {
+    let silence = filters.skip_silence.then(|| {
+        format!("lavfi=[silenceremove=start_periods=1:start_threshold={SILENCE_THRESHOLD_DB}dB]")
+    });
+    let pan = match filters.channels {
+        Channels::Stereo => None,
+        Channels::Left => Some("lavfi=[pan=stereo|c0=c0|c1=c0]"),
+        Channels::Right => Some("lavfi=[pan=stereo|c0=c1|c1=c1]"),
+        Channels::Mix => Some("lavfi=[pan=stereo|c0=0.5*c0+0.5*c1|c1=0.5*c0+0.5*c1]"),
+    };
+    let pitch =
+        (filters.pitch != 0).then(|| format!("rubberband=pitch-scale={}", filters.pitch_ratio()));
+    silence
+        .into_iter()
+        .chain(pan.map(str::to_string))
+        .chain(pitch)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Options selecting the audio output; an exclusive output also keeps mpv
+/// from resampling.
+fn output_args(output: &OutputSettings) -> Vec<String> {
+    let ao = match output.sink {
+        OutputSink::Auto => None,
+        OutputSink::Alsa => Some("alsa"),
+        OutputSink::PipeWire => Some("pipewire"),
+    };
+    let mut args = Vec::new();
+    if let Some(ao) = ao {
+        args.push(format!("--ao={ao}"));
+        if let Some(device) = &output.device {
+            args.push(format!("--audio-device={ao}/{device}"));
+        }
+    }
+    if output.exclusive {
+        args.push("--audio-exclusive=yes".to_string());
+        args.push("--audio-samplerate=0".to_string());
+    }
+    args
+}
+
+async fn connect(socket_path: &PathBuf) -> Result<UnixStream, App> {
+    for _ in 0..CONNECT_ATTEMPTS {
+        if let Ok(stream) = UnixStream::connect(socket_path) {
+            return Ok(stream);
+        }
+        tokio::time::sleep(CONNECT_INTERVAL).await;
+    }
+    Err(App::Init(
+        "Timed out connecting to mpv IPC socket".to_string(),
+    ))
+}
+
+fn spawn_event_reader(
+    reader: UnixStream,
+    eos_sender: mpsc::Sender<()>,
+    position: Arc<Mutex<Option<Duration>>>,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    buffer: Arc<Mutex<BufferStats>>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else { break };
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            match message["event"].as_str() {
+                Some("end-file") if message["reason"] == "eof" => {
+                    info!("EOS message received, sending signal.");
+                    if eos_sender.blocking_send(()).is_err() {
+                        error!("Failed to send EOS signal");
+                    }
+                }
+                Some("end-file") if message["reason"] == "error" => {
+                    error!("mpv failed to play file: {}", message["file_error"]);
+                }
+                Some("property-change") if message["name"] == "time-pos" => {
+                    *position.lock().unwrap_or_else(PoisonError::into_inner) = message["data"]
+                        .as_f64()
+                        .filter(|seconds| *seconds >= 0.0)
+                        .map(Duration::from_secs_f64);
+                }
+                Some("property-change") if message["name"] == "demuxer-cache-state" => {
+                    let data = &message["data"];
+                    *buffer.lock().unwrap_or_else(PoisonError::into_inner) = BufferStats {
+                        download_rate: data["raw-input-rate"].as_u64(),
+                        buffered: data["cache-duration"]
+                            .as_f64()
+                            .filter(|seconds| *seconds >= 0.0)
+                            .map(Duration::from_secs_f64),
+                    };
+                }
+                Some("property-change") => {
+                    update_stream_info(&stream_info, &message);
+                }
+                _ => {}
+            }
+        }
+        warn!("mpv IPC connection closed");
+    });
+}
+
+fn update_stream_info(stream_info: &Mutex<Option<StreamInfo>>, message: &Value) {
+    let data = &message["data"];
+    if data.is_null() {
+        return;
+    }
+    let mut stream_info = stream_info.lock().unwrap_or_else(PoisonError::into_inner);
+    let stream_info = stream_info.get_or_insert_with(StreamInfo::default);
+    let as_u32 = |value: &Value| value.as_u64().and_then(|value| u32::try_from(value).ok());
+    match message["name"].as_str() {
+        Some("audio-codec-name") => {
+            stream_info.codec = data.as_str().unwrap_or_default().to_string();
+        }
+        Some("audio-params") => {
+            stream_info.sample_rate = as_u32(&data["samplerate"]);
+            stream_info.channels = as_u32(&data["channel-count"]);
+            info!("mpv audio params: {}", data);
+        }
+        Some("audio-out-params") => {
+            stream_info.output = Some(OutputFormat {
+                format: data["format"].as_str().unwrap_or_default().to_string(),
+                sample_rate: as_u32(&data["samplerate"]),
+            });
+        }
+        Some("audio-bitrate") => stream_info.bitrate = as_u32(data),
+        _ => {}
+    }
+}
+
+impl PlayerBackend for MpvBackend {
+    fn load(&self, url: &str, paused: bool) -> Result<(), App> {
+        *self
+            .stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        // Pausing carries over to the next file
+        self.set_property("pause", &json!(paused))?;
+        self.command(&json!(["loadfile", url, "replace"]))
+    }
+
+    fn play(&self) -> Result<(), App> {
+        self.set_property("pause", &json!(false))
+    }
+
+    fn pause(&self) -> Result<(), App> {
+        self.set_property("pause", &json!(true))
+    }
+
+    fn stop(&self) -> Result<(), App> {
+        self.command(&json!(["stop"]))
+    }
+
+    fn seek(&self, position: Duration) -> Result<(), App> {
+        self.command(&json!(["seek", position.as_secs_f64(), "absolute"]))
+    }
+
+    fn set_volume(&self, volume: f64) -> Result<(), App> {
+        self.set_property("volume", &json!(volume * 100.0))
+    }
+
+    fn set_channels(&self, channels: Channels) -> Result<(), App> {
+        self.update_filters(|filters| filters.channels = channels)
+    }
+
+    fn set_pitch(&self, semitones: i32) -> Result<bool, App> {
+        self.update_filters(|filters| filters.pitch = semitones)?;
+        Ok(true)
+    }
+
+    fn position(&self) -> Option<Duration> {
+        *self.position.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stream_info(&self) -> Option<StreamInfo> {
+        self.stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn buffer_stats(&self) -> BufferStats {
+        *self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for MpvBackend {
+    fn drop(&mut self) {
+        let _ = self
+            .child
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .kill();
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
@@ -0,0 +1,155 @@
+use super::{Channels, PlayerBackend};
+use crate::error::App;
+use log::{error, info};
+use rosesong_core::ipc::StreamInfo;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task;
+
+const EOS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Backend that plays nothing: the position follows the clock and the
+/// track ends once its length has passed, so the player can run on
+/// machines without sound. The stream is never downloaded.
+#[derive(Debug)]
+pub struct NullBackend {
+    clock: Arc<Mutex<Clock>>,
+    /// Length of the track loaded next.
+    duration: Mutex<Option<Duration>>,
+}
+
+/// Where the pretend playback is.
+#[derive(Debug, Default)]
+struct Clock {
+    loaded: bool,
+    /// Position when playback last started, paused or was sought.
+    position: Duration,
+    /// When it started playing from `position`, `None` while paused.
+    since: Option<Instant>,
+    /// Length of the loaded track, when known; without it the track plays
+    /// until something else is loaded.
+    duration: Option<Duration>,
+}
+
+impl Clock {
+    fn position(&self) -> Duration {
+        self.position + self.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    fn set_position(&mut self, position: Duration) {
+        self.position = position;
+        if self.since.is_some() {
+            self.since = Some(Instant::now());
+        }
+    }
+}
+
+impl NullBackend {
+    pub fn new(eos_sender: mpsc::Sender<()>) -> Self {
+        let clock = Arc::new(Mutex::new(Clock::default()));
+        task::spawn({
+            let clock = Arc::clone(&clock);
+            async move {
+                let mut interval = tokio::time::interval(EOS_POLL_INTERVAL);
+                while !eos_sender.is_closed() {
+                    interval.tick().await;
+                    if !finish(&clock) {
+                        continue;
+                    }
+                    info!("EOS message received, sending signal.");
+                    if eos_sender.send(()).await.is_err() {
+                        error!("Failed to send EOS signal");
+                    }
+                }
+            }
+        });
+        info!("Null output created, nothing will be heard");
+        NullBackend {
+            clock,
+            duration: Mutex::new(None),
+        }
+    }
+
+    fn clock(&self) -> std::sync::MutexGuard<'_, Clock> {
+        self.clock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Unloads the track once it has played to its end, returning whether it
+/// did.
+fn finish(clock: &Mutex<Clock>) -> bool {
+    let mut clock = clock.lock().unwrap_or_else(PoisonError::into_inner);
+    let ended = clock.loaded
+        && clock
+            .duration
+            .is_some_and(|duration| clock.position() >= duration);
+    if ended {
+        *clock = Clock::default();
+    }
+    ended
+}
+
+impl PlayerBackend for NullBackend {
+    fn load(&self, _url: &str, paused: bool) -> Result<(), App> {
+        let duration = *self.duration.lock().unwrap_or_else(PoisonError::into_inner);
+        *self.clock() = Clock {
+            loaded: true,
+            position: Duration::ZERO,
+            since: (!paused).then(Instant::now),
+            duration,
+        };
+        Ok(())
+    }
+
+    fn play(&self) -> Result<(), App> {
+        let mut clock = self.clock();
+        if clock.loaded && clock.since.is_none() {
+            clock.since = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), App> {
+        let mut clock = self.clock();
+        clock.position = clock.position();
+        clock.since = None;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), App> {
+        *self.clock() = Clock::default();
+        Ok(())
+    }
+
+    fn seek(&self, position: Duration) -> Result<(), App> {
+        self.clock().set_position(position);
+        Ok(())
+    }
+
+    fn set_volume(&self, _volume: f64) -> Result<(), App> {
+        Ok(())
+    }
+
+    fn set_channels(&self, _channels: Channels) -> Result<(), App> {
+        Ok(())
+    }
+
+    fn set_pitch(&self, _semitones: i32) -> Result<bool, App> {
+        Ok(true)
+    }
+
+    fn set_duration(&self, duration: Duration) {
+        *self.duration.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(duration).filter(|duration| !duration.is_zero());
+    }
+
+    fn position(&self) -> Option<Duration> {
+        let clock = self.clock();
+        clock.loaded.then(|| clock.position())
+    }
+
+    fn stream_info(&self) -> Option<StreamInfo> {
+        None
+    }
+}
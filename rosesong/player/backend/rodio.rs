@@ -0,0 +1,320 @@
+use super::{Channels, PlayerBackend};
+use crate::error::App;
+use crate::player::http_stream::HttpStream;
+use crate::player::network::header_map;
+use log::{error, info};
+use reqwest::header::HeaderMap;
+use rodio::source::SeekError;
+use rodio::{Decoder, OutputStream, Sink, Source};
+use rosesong_core::ipc::StreamInfo;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc as tokio_mpsc;
+
+const EOS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+enum Request {
+    /// Plays the stream at the URL, or holds it paused.
+    Load(String, bool),
+    Play,
+    Pause,
+    Stop,
+    Seek(Duration),
+    SetVolume(f32),
+}
+
+/// Pure Rust backend decoding with symphonia and playing through rodio.
+///
+/// rodio's output stream cannot leave the thread that created it, so all
+/// playback happens on a dedicated audio thread fed through a channel.
+#[derive(Debug)]
+pub struct RodioBackend {
+    requests: Mutex<mpsc::Sender<Request>>,
+    position: Arc<Mutex<Option<Duration>>>,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    /// The `Channels` streams are mapped with, as read by `ChannelMap`.
+    channels: Arc<AtomicU8>,
+}
+
+impl RodioBackend {
+    pub fn new(
+        eos_sender: tokio_mpsc::Sender<()>,
+        headers: &[(String, String)],
+    ) -> Result<Self, App> {
+        let headers = header_map(headers)?;
+        let (requests, receiver) = mpsc::channel();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let position = Arc::new(Mutex::new(None));
+        let stream_info = Arc::new(Mutex::new(None));
+        let channels = Arc::new(AtomicU8::new(channels_to_u8(Channels::Stereo)));
+        thread::spawn({
+            let position = Arc::clone(&position);
+            let stream_info = Arc::clone(&stream_info);
+            let channels = Arc::clone(&channels);
+            move || {
+                run_audio_thread(
+                    &receiver,
+                    &headers,
+                    &eos_sender,
+                    &ready_sender,
+                    &position,
+                    &stream_info,
+                    &channels,
+                );
+            }
+        });
+        ready_receiver
+            .recv()
+            .map_err(|_| App::Init("rodio audio thread exited".to_string()))??;
+        info!("rodio output created successfully.");
+        Ok(RodioBackend {
+            requests: Mutex::new(requests),
+            position,
+            stream_info,
+            channels,
+        })
+    }
+
+    fn send(&self, request: Request) -> Result<(), App> {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .send(request)
+            .map_err(|_| App::Pipeline("rodio audio thread is not running".to_string()))
+    }
+}
+
+fn run_audio_thread(
+    receiver: &mpsc::Receiver<Request>,
+    headers: &HeaderMap,
+    eos_sender: &tokio_mpsc::Sender<()>,
+    ready_sender: &mpsc::Sender<Result<(), App>>,
+    position: &Mutex<Option<Duration>>,
+    stream_info: &Mutex<Option<StreamInfo>>,
+    channels: &Arc<AtomicU8>,
+) {
+    let (_stream, handle) = match OutputStream::try_default() {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = ready_sender.send(Err(App::Init(format!("Failed to open audio output: {e}"))));
+            return;
+        }
+    };
+    let _ = ready_sender.send(Ok(()));
+
+    let mut sink: Option<Sink> = None;
+    let mut volume = 1.0;
+    loop {
+        match receiver.recv_timeout(EOS_POLL_INTERVAL) {
+            Ok(Request::Load(url, paused)) => {
+                if let Some(old) = sink.take() {
+                    old.stop();
+                }
+                let opened = open_sink(&handle, &url, headers, volume, channels, paused);
+                let mut info = stream_info.lock().unwrap_or_else(PoisonError::into_inner);
+                *info = None;
+                match opened {
+                    Ok((new_sink, new_info)) => {
+                        sink = Some(new_sink);
+                        *info = Some(new_info);
+                    }
+                    Err(e) => error!("Failed to play stream: {}", e),
+                }
+            }
+            Ok(Request::Play) => {
+                if let Some(sink) = &sink {
+                    sink.play();
+                }
+            }
+            Ok(Request::Pause) => {
+                if let Some(sink) = &sink {
+                    sink.pause();
+                }
+            }
+            Ok(Request::Stop) => {
+                if let Some(old) = sink.take() {
+                    old.stop();
+                }
+            }
+            Ok(Request::Seek(position)) => {
+                if let Some(Err(e)) = sink.as_ref().map(|sink| sink.try_seek(position)) {
+                    error!("Failed to seek: {}", e);
+                }
+            }
+            Ok(Request::SetVolume(new_volume)) => {
+                volume = new_volume;
+                if let Some(sink) = &sink {
+                    sink.set_volume(volume);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if sink.as_ref().is_some_and(Sink::empty) {
+            sink = None;
+            info!("EOS message received, sending signal.");
+            if eos_sender.blocking_send(()).is_err() {
+                error!("Failed to send EOS signal");
+            }
+        }
+        *position.lock().unwrap_or_else(PoisonError::into_inner) = sink.as_ref().map(Sink::get_pos);
+    }
+}
+
+fn open_sink(
+    handle: &rodio::OutputStreamHandle,
+    url: &str,
+    headers: &HeaderMap,
+    volume: f32,
+    channels: &Arc<AtomicU8>,
+    paused: bool,
+) -> Result<(Sink, StreamInfo), App> {
+    let stream = HttpStream::open(url, headers.clone()).map_err(|e| App::Network(e.to_string()))?;
+    let decoder = Decoder::new(stream).map_err(|e| App::Element(e.to_string()))?;
+    let info = StreamInfo {
+        sample_rate: Some(decoder.sample_rate()),
+        channels: Some(u32::from(decoder.channels())),
+        ..StreamInfo::default()
+    };
+    info!(
+        "Decoding stream at {} Hz, {} channels",
+        decoder.sample_rate(),
+        decoder.channels()
+    );
+    let sink = Sink::try_new(handle).map_err(|e| App::Element(e.to_string()))?;
+    sink.set_volume(volume);
+    // Paused before anything is queued, so not a sample plays
+    if paused {
+        sink.pause();
+    }
+    sink.append(ChannelMap {
+        inner: decoder,
+        channels: Arc::clone(channels),
+        pending: None,
+    });
+    Ok((sink, info))
+}
+
+fn channels_to_u8(channels: Channels) -> u8 {
+    match channels {
+        Channels::Stereo => 0,
+        Channels::Left => 1,
+        Channels::Right => 2,
+        Channels::Mix => 3,
+    }
+}
+
+/// Maps the samples of a stereo source as `channels` currently says, so a
+/// change applies to the stream already playing.
+struct ChannelMap<S> {
+    inner: S,
+    channels: Arc<AtomicU8>,
+    /// Right sample of the frame whose left one was just returned.
+    pending: Option<i16>,
+}
+
+impl<S: Source<Item = i16>> Iterator for ChannelMap<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if let Some(right) = self.pending.take() {
+            return Some(right);
+        }
+        let left = self.inner.next()?;
+        if self.inner.channels() != 2 {
+            return Some(left);
+        }
+        let right = self.inner.next()?;
+        let (left, right) = match self.channels.load(Ordering::Relaxed) {
+            1 => (left, left),
+            2 => (right, right),
+            3 => {
+                let mixed = left / 2 + right / 2;
+                (mixed, mixed)
+            }
+            _ => (left, right),
+        };
+        self.pending = Some(right);
+        Some(left)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for ChannelMap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.pending = None;
+        self.inner.try_seek(position)
+    }
+}
+
+impl PlayerBackend for RodioBackend {
+    fn load(&self, url: &str, paused: bool) -> Result<(), App> {
+        self.send(Request::Load(url.to_string(), paused))
+    }
+
+    fn play(&self) -> Result<(), App> {
+        self.send(Request::Play)
+    }
+
+    fn pause(&self) -> Result<(), App> {
+        self.send(Request::Pause)
+    }
+
+    fn stop(&self) -> Result<(), App> {
+        self.send(Request::Stop)
+    }
+
+    fn seek(&self, position: Duration) -> Result<(), App> {
+        self.send(Request::Seek(position))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn set_volume(&self, volume: f64) -> Result<(), App> {
+        self.send(Request::SetVolume(volume as f32))
+    }
+
+    fn set_channels(&self, channels: Channels) -> Result<(), App> {
+        self.channels
+            .store(channels_to_u8(channels), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_pitch(&self, semitones: i32) -> Result<bool, App> {
+        if semitones == 0 {
+            return Ok(true);
+        }
+        Err(App::Element(
+            "The rodio backend cannot change the pitch, use gstreamer or mpv".to_string(),
+        ))
+    }
+
+    fn position(&self) -> Option<Duration> {
+        *self.position.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stream_info(&self) -> Option<StreamInfo> {
+        self.stream_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
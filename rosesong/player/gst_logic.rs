@@ -1,197 +1,573 @@
+use crate::bilibili::fetch_audio_url::{host, StreamChoice};
 use crate::error::App;
-use crate::player::network::{fetch_and_verify_audio_url, set_pipeline_uri_with_headers};
-use crate::player::playlist::{
-    get_current_track, load, move_to_next_track, move_to_previous_track, set_current_track_index,
-    PlayMode, CURRENT_TRACK_INDEX, PLAYLIST,
-};
-use futures_util::stream::StreamExt;
-use gstreamer::prelude::*;
-use gstreamer::MessageView;
-use gstreamer::Pipeline;
-use log::{error, info};
+use crate::player::backend::{self, BufferStats, Channels, PlayerBackend};
+use crate::player::health::StreamHealth;
+use crate::player::metrics::{Latency, LatencyMetrics};
+use crate::player::network::{build_client, fetch_and_verify_audio_url};
+use crate::player::playlist::{PlayMode, PlaylistManager, Repeat, Track};
+use crate::player::state::{Event, PlaybackState};
+use crate::supervisor::supervise;
+use log::{error, info, warn};
 use reqwest::Client;
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use rosesong_core::bilibili::fetch_related;
+use rosesong_core::ipc::{AudioTrack, DebugInfo, PerfStats, PlaybackError, Status, StreamInfo};
+use rosesong_core::settings::{
+    AudioTrackPolicy, BlacklistSettings, NetworkSettings, RemovePolicy, Settings,
+};
+use rosesong_core::state::RuntimeState;
+use rosesong_core::storage::{PlaylistStore, TrackKind};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, RwLock};
 use tokio::task;
+use tokio_util::sync::CancellationToken;
 
 pub enum Command {
     Play,
     PlayBvid(String),
+    /// Play the tracks of a season in order, by season id.
+    PlaySeason(String),
+    /// Play the tracks queued with `rsg later`, then go back to the playlist.
+    PlayLater,
     Pause,
+    #[cfg_attr(
+        any(not(feature = "media-keys"), target_os = "macos", windows),
+        allow(dead_code)
+    )]
+    TogglePause,
     Next,
     Previous,
     Stop,
+    ReleasePaused,
+    /// The network is back; reload the track if loading it had failed.
+    Reconnect,
+    SetAudioTrack(usize),
     SetPlayMode(PlayMode),
+    SetShuffle(bool),
+    SetRepeat(Repeat),
+    SetRadio(bool),
+    SetChannels(Channels),
+    /// Pitch shift in semitones.
+    SetPitch(i32),
+    SetPreview(Option<Duration>),
+    #[cfg_attr(not(feature = "schedule"), allow(dead_code))]
+    SwitchPlaylist(String, Arc<dyn PlaylistStore>),
+    Seek(Duration),
+    SetVolume(f64),
+    AdjustVolume(f64),
+    VolumeUp,
+    VolumeDown,
     ReloadPlaylist,
-    PlaylistIsEmpty,
+}
+
+impl Command {
+    /// Whether the command makes a track that is still loading irrelevant,
+    /// so the load should be abandoned instead of waited for.
+    fn supersedes_load(&self) -> bool {
+        matches!(
+            self,
+            Command::PlayBvid(_)
+                | Command::PlaySeason(_)
+                | Command::PlayLater
+                | Command::Next
+                | Command::Previous
+                | Command::Stop
+                | Command::SetAudioTrack(_)
+                | Command::SwitchPlaylist(..)
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Audio {
-    pipeline: Arc<Pipeline>,
+    backend: Arc<dyn PlayerBackend>,
     client: Arc<Client>,
     play_mode: Arc<RwLock<PlayMode>>,
+    state: Arc<RwLock<PlaybackState>>,
     command_receiver: Arc<Mutex<mpsc::Receiver<Command>>>,
-    eos_sender: mpsc::Sender<()>,
+    playlist: Arc<PlaylistManager>,
+    changes: Arc<watch::Sender<()>>,
+    /// Tracks that failed to load, for the `on_error` hook, the D-Bus
+    /// signal and desktop notifications.
+    errors: Arc<broadcast::Sender<PlaybackError>>,
+    last_error: Arc<StdMutex<Option<PlaybackError>>>,
+    /// Whether the last track failed to load, so `Reconnect` retries it.
+    interrupted: Arc<AtomicBool>,
+    volume: Arc<RwLock<f64>>,
+    volume_step: f64,
+    /// How long the sound fades out over before stopping.
+    fade_out: Duration,
+    fade_on_pause: bool,
+    channels: Arc<RwLock<Channels>>,
+    pitch: Arc<RwLock<i32>>,
+    preview: Arc<RwLock<Option<Duration>>>,
+    resume_min_duration: u64,
+    positions: Arc<RwLock<BTreeMap<String, u64>>>,
+    /// Where to continue a track that was stopped on purpose, by
+    /// `ReleasePaused` or to switch its audio track, on the next start.
+    continue_at: Arc<RwLock<Option<ContinueAt>>>,
+    /// When the URL of the loaded stream expires, in seconds since the
+    /// epoch, if Bilibili said so.
+    url_deadline: Arc<RwLock<Option<u64>>>,
+    stream_policy: AudioTrackPolicy,
+    remove_policy: RemovePolicy,
+    autoplay_on_add: bool,
+    /// Continue with a related video after each track instead of the
+    /// playlist.
+    radio: Arc<AtomicBool>,
+    radio_music_only: bool,
+    /// Videos radio mode never continues with.
+    blacklist: Arc<BlacklistSettings>,
+    /// Cid and index of the audio stream picked with `SetAudioTrack`.
+    stream_choice: Arc<RwLock<Option<(String, usize)>>>,
+    /// Cid and CDN mirror of the stream the watchdog moved it to after it
+    /// stalled.
+    mirror: Arc<RwLock<Option<(String, usize)>>>,
+    /// Audio streams offered for the track being played.
+    streams: Arc<RwLock<Vec<AudioTrack>>>,
+    stream: Arc<RwLock<Option<StreamInfo>>>,
+    network: Arc<NetworkSettings>,
+    load_session: Arc<StdMutex<LoadSession>>,
+    load_lock: Arc<Mutex<()>>,
+    metrics: Arc<LatencyMetrics>,
+    health: Arc<StreamHealth>,
+    watchdog_timeout: Duration,
+}
+
+/// Where a track loaded again continues.
+#[derive(Clone, Debug)]
+struct ContinueAt {
+    cid: String,
+    /// Second to continue at.
+    position: u64,
+    /// Leave the track paused, as it was before.
+    paused: bool,
+}
+
+/// One attempt at loading a track. Starting a new one cancels the previous
+/// so only the most recent command ends up playing.
+#[derive(Clone, Debug)]
+struct LoadSession {
+    id: u64,
+    token: CancellationToken,
+    started: Instant,
 }
 
+impl LoadSession {
+    fn new(id: u64) -> Self {
+        LoadSession {
+            id,
+            token: CancellationToken::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+const VOLUME_FADE: Duration = Duration::from_millis(100);
+/// How often the volume changes while it fades.
+const FADE_STEP: Duration = Duration::from_millis(10);
+const END_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const POSITION_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+const RESUME_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const RESUME_POLL_ATTEMPTS: u32 = 50;
+const ERROR_CHANNEL_CAPACITY: usize = 8;
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the position has to stand still while playing to count as a
+/// stall.
+const STALL_THRESHOLD: Duration = Duration::from_secs(3);
+const STARTUP_PROBE_INTERVAL: Duration = Duration::from_millis(20);
+const STARTUP_PROBE_ATTEMPTS: u32 = 500;
+/// How long before it expires a stream URL is replaced on resume or seek,
+/// leaving time for the backend to reconnect with it.
+const URL_REFRESH_MARGIN: Duration = Duration::from_secs(2 * 60);
+
 impl Audio {
     pub async fn new(
         play_mode: PlayMode,
         initial_track_index: usize,
         command_receiver: Arc<Mutex<mpsc::Receiver<Command>>>,
+        playlist: Arc<PlaylistManager>,
+        settings: &Settings,
     ) -> Result<Self, App> {
-        gstreamer::init().map_err(|e| App::Init(e.to_string()))?;
-        let pipeline = Arc::new(gstreamer::Pipeline::new());
-        let client = Arc::new(Client::new());
-        set_current_track_index(initial_track_index).await?;
         let (eos_sender, eos_receiver) = mpsc::channel(1);
+        let headers = settings.network.request_headers();
+        let backend = Arc::from(
+            backend::create(
+                settings.player.backend,
+                eos_sender.clone(),
+                &headers,
+                &settings.output,
+                backend::Filters {
+                    skip_silence: settings.player.skip_silence,
+                    ..backend::Filters::default()
+                },
+            )
+            .await?,
+        );
+        playlist.set_shuffle_rules(settings.player.shuffle.clone());
+        let client = Arc::new(build_client(&settings.network, &headers)?);
+        playlist.set_current_index(initial_track_index);
 
-        info!("GStreamer created successfully.");
         let audio_player = Self {
-            pipeline,
+            backend,
             client,
             play_mode: Arc::new(RwLock::new(play_mode)),
+            state: Arc::new(RwLock::new(PlaybackState::Stopped)),
             command_receiver,
-            eos_sender,
+            playlist,
+            changes: Arc::new(watch::channel(()).0),
+            errors: Arc::new(broadcast::channel(ERROR_CHANNEL_CAPACITY).0),
+            last_error: Arc::new(StdMutex::new(None)),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            volume: Arc::new(RwLock::new(1.0)),
+            volume_step: f64::from(settings.player.volume_step) / 100.0,
+            fade_out: Duration::from_millis(settings.player.fade_out_ms),
+            fade_on_pause: settings.player.fade_on_pause,
+            channels: Arc::new(RwLock::new(Channels::default())),
+            pitch: Arc::new(RwLock::new(0)),
+            preview: Arc::new(RwLock::new(None)),
+            resume_min_duration: settings.player.resume_min_duration,
+            network: Arc::new(settings.network.clone()),
+            load_session: Arc::new(StdMutex::new(LoadSession::new(0))),
+            load_lock: Arc::new(Mutex::new(())),
+            metrics: Arc::new(LatencyMetrics::default()),
+            health: Arc::new(StreamHealth::default()),
+            watchdog_timeout: Duration::from_secs(settings.player.watchdog_timeout),
+            positions: Arc::new(RwLock::new(RuntimeState::load().positions)),
+            continue_at: Arc::new(RwLock::new(None)),
+            url_deadline: Arc::new(RwLock::new(None)),
+            stream_policy: settings.player.audio_track,
+            remove_policy: settings.player.on_remove_current,
+            autoplay_on_add: settings.player.autoplay_on_add,
+            radio: Arc::new(AtomicBool::new(settings.radio.enabled)),
+            radio_music_only: settings.radio.music_only,
+            blacklist: Arc::new(settings.blacklist.clone()),
+            stream_choice: Arc::new(RwLock::new(None)),
+            mirror: Arc::new(RwLock::new(None)),
+            streams: Arc::new(RwLock::new(Vec::new())),
+            stream: Arc::new(RwLock::new(None)),
         };
 
         audio_player.start_eos_listener(eos_receiver);
+        audio_player.start_state_saver();
+        audio_player.start_end_watcher(eos_sender);
+        audio_player.start_position_tracker();
+        audio_player.start_watchdog();
 
         Ok(audio_player)
     }
 
-    fn start_eos_listener(&self, mut eos_receiver: mpsc::Receiver<()>) {
-        let pipeline = Arc::clone(&self.pipeline);
-        let client = Arc::clone(&self.client);
-        let play_mode = Arc::clone(&self.play_mode);
+    /// Applies `event` to the playback state, returning the new state or
+    /// `None` when the event is not valid in the current state.
+    async fn transition(&self, event: Event) -> Option<PlaybackState> {
+        let mut state = self.state.write().await;
+        if let Some(next) = state.on(event) {
+            info!("Playback state {:?} -> {:?} ({:?})", *state, next, event);
+            *state = next;
+            self.changes.send_replace(());
+            Some(next)
+        } else {
+            warn!("Ignoring {:?} in playback state {:?}", event, *state);
+            None
+        }
+    }
 
-        task::spawn(async move {
-            while let Some(()) = eos_receiver.recv().await {
-                info!("Track finished playing. Handling EOS...");
+    fn start_eos_listener(&self, eos_receiver: mpsc::Receiver<()>) {
+        let audio = self.clone();
+        let eos_receiver = Arc::new(Mutex::new(eos_receiver));
 
-                let current_play_mode = *play_mode.read().await;
-                if current_play_mode != PlayMode::Repeat {
-                    if let Err(e) = move_to_next_track(current_play_mode).await {
-                        error!("Error moving to next track: {}", e);
+        supervise("EOS listener", move || {
+            let audio = audio.clone();
+            let eos_receiver = Arc::clone(&eos_receiver);
+            async move {
+                let mut eos_receiver = eos_receiver.lock().await;
+                while let Some(()) = eos_receiver.recv().await {
+                    info!("Track finished playing. Handling EOS...");
+                    if audio.transition(Event::EndOfStream).await.is_none() {
                         continue;
                     }
+                    audio.forget_position().await;
+                    if audio.playlist.len().await == 0 {
+                        audio.enter_idle().await;
+                        continue;
+                    }
+
+                    // Repeat-one stays on the same track unless it was removed
+                    let current_play_mode = *audio.play_mode.read().await;
+                    if current_play_mode.repeat != Repeat::One
+                        && audio.radio.load(Ordering::Relaxed)
+                        && audio.continue_radio().await
+                    {
+                        if let Err(e) = audio.play_track().await {
+                            error!("Failed to play related video: {}", e);
+                        }
+                        continue;
+                    }
+                    match audio.playlist.move_to_next_track(current_play_mode).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => {
+                            info!("Reached the end of the playlist with repeat off");
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("Error moving to next track: {}", e);
+                            continue;
+                        }
+                    }
+
+                    if let Err(e) = audio.play_track().await {
+                        error!("Failed to play next track: {}", e);
+                    }
                 }
+            }
+        });
+    }
 
-                if let Err(e) = play_track(&pipeline, &client).await {
-                    error!("Failed to play next track: {}", e);
+    /// Persists the current track and play mode after every change so a
+    /// restarted daemon picks up where it left off.
+    fn start_state_saver(&self) {
+        let audio = self.clone();
+
+        supervise("State saver", move || {
+            let audio = audio.clone();
+            async move {
+                let mut changes = audio.subscribe_changes();
+                while changes.changed().await.is_ok() {
+                    audio.save_state().await;
                 }
             }
         });
     }
 
-    pub async fn play_playlist(&self) -> Result<(), App> {
-        let pipeline = Arc::clone(&self.pipeline);
-        let client = Arc::clone(&self.client);
-        let play_mode = Arc::clone(&self.play_mode);
-        let command_receiver = Arc::clone(&self.command_receiver);
-        let eos_sender = self.eos_sender.clone();
+    async fn save_state(&self) {
+        let state = RuntimeState {
+            current_bvid: self
+                .playlist
+                .current_track()
+                .await
+                .ok()
+                .map(|track| track.bvid),
+            play_mode: Some(self.play_mode.read().await.name()),
+            positions: self.positions.read().await.clone(),
+        };
+        if let Err(e) = state.save() {
+            error!("Failed to save runtime state: {}", e);
+        }
+    }
 
-        self.listen_to_bus(&eos_sender.clone())?;
-        Audio::listen_for_commands(command_receiver, pipeline, client, play_mode, &eos_sender);
+    fn is_resumable(&self, track: &Track) -> bool {
+        self.resume_min_duration > 0 && track.duration >= self.resume_min_duration
+    }
 
-        play_track(&self.pipeline, &self.client).await?;
-        Ok(())
+    /// Records the position of long tracks while they play so they can be
+    /// resumed later, even after a restart.
+    fn start_position_tracker(&self) {
+        let audio = self.clone();
+
+        supervise("Position tracker", move || {
+            let audio = audio.clone();
+            async move {
+                let mut interval = tokio::time::interval(POSITION_SAVE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if *audio.state.read().await != PlaybackState::Playing {
+                        continue;
+                    }
+                    let Ok(track) = audio.playlist.current_track().await else {
+                        continue;
+                    };
+                    if !audio.is_resumable(&track) {
+                        continue;
+                    }
+                    let Some(position) = audio.backend.position() else {
+                        continue;
+                    };
+                    let position = position.as_secs();
+                    let previous = audio.positions.write().await.insert(track.cid, position);
+                    if previous != Some(position) {
+                        audio.save_state().await;
+                    }
+                }
+            }
+        });
     }
 
-    fn listen_to_bus(&self, eos_sender: &mpsc::Sender<()>) -> Result<(), App> {
-        let bus = self
-            .pipeline
-            .bus()
-            .ok_or_else(|| App::Pipeline("Failed to get GStreamer bus".to_string()))?;
+    /// Counts and logs stalls, and reloads the current track from its next
+    /// CDN mirror when it is supposedly playing but its position has not
+    /// moved for `watchdog_timeout`, which happens when the pipeline gets
+    /// stuck without reporting an error or the CDN is too slow.
+    fn start_watchdog(&self) {
+        let audio = self.clone();
 
-        task::spawn({
-            let eos_sender = eos_sender.clone();
-            bus.stream().for_each(move |msg| {
-                let eos_sender = eos_sender.clone();
-                async move {
-                    match msg.view() {
-                        MessageView::Eos(_) => {
-                            info!("EOS message received, sending signal.");
-                            if eos_sender.send(()).await.is_err() {
-                                error!("Failed to send EOS signal");
-                            }
+        supervise("Watchdog", move || {
+            let audio = audio.clone();
+            async move {
+                let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+                let mut last_position = None;
+                let mut stalled_since = Instant::now();
+                let mut stalled = false;
+                loop {
+                    interval.tick().await;
+                    let position = audio.backend.position();
+                    if *audio.state.read().await != PlaybackState::Playing
+                        || position != last_position
+                    {
+                        if stalled && position != last_position {
+                            info!("Playback recovered after {:?}", stalled_since.elapsed());
                         }
-                        MessageView::Error(err) => {
-                            error!("Error from GStreamer pipeline: {}", err);
+                        last_position = position;
+                        stalled_since = Instant::now();
+                        stalled = false;
+                        continue;
+                    }
+                    if !stalled && stalled_since.elapsed() >= STALL_THRESHOLD {
+                        stalled = true;
+                        audio.health.record_stall();
+                        warn!(
+                            "Playback stalled at {:?}, {}",
+                            position,
+                            describe_buffer(audio.backend.buffer_stats())
+                        );
+                    }
+                    if audio.watchdog_timeout.is_zero()
+                        || stalled_since.elapsed() < audio.watchdog_timeout
+                    {
+                        continue;
+                    }
+
+                    let track = audio.playlist.current_track().await;
+                    warn!(
+                        "Watchdog: {:?} stuck at {:?} for {:?}, reloading the track",
+                        track.as_ref().map(|track| &track.bvid),
+                        position,
+                        stalled_since.elapsed()
+                    );
+                    if let Ok(track) = track {
+                        if let Some(position) = position {
+                            *audio.continue_at.write().await = Some(ContinueAt {
+                                cid: track.cid.clone(),
+                                position: position.as_secs(),
+                                paused: false,
+                            });
                         }
-                        _ => (),
+                        audio.switch_mirror(track.cid).await;
+                    }
+                    audio.health.record_reconnect();
+                    stalled_since = Instant::now();
+                    if let Err(e) = audio.play_track().await {
+                        error!("Watchdog failed to reload the track: {}", e);
                     }
                 }
-            })
+            }
         });
-        Ok(())
     }
 
-    fn listen_for_commands(
-        command_receiver: Arc<Mutex<mpsc::Receiver<Command>>>,
-        pipeline: Arc<Pipeline>,
-        client: Arc<Client>,
-        play_mode: Arc<RwLock<PlayMode>>,
-        _eos_sender: &mpsc::Sender<()>,
-    ) {
-        task::spawn(async move {
-            let mut command_receiver = command_receiver.lock().await;
-            loop {
-                if let Some(command) = command_receiver.recv().await {
-                    match command {
-                        Command::Play => {
-                            info!("Resume playback");
-                            if let Err(e) = pipeline.set_state(gstreamer::State::Playing) {
-                                error!("Failed to play: {}", e);
-                            }
-                        }
-                        Command::PlayBvid(new_bvid) => {
-                            info!("Play {}", new_bvid);
-                            if let Err(e) = handle_play_bvid(&new_bvid, &pipeline, &client).await {
-                                error!("Failed to play track: {}", e);
-                            }
-                        }
-                        Command::Pause => {
-                            info!("Pause");
-                            if let Err(e) = pipeline.set_state(gstreamer::State::Paused) {
-                                error!("Failed to pause: {}", e);
-                            }
-                        }
-                        Command::Next => {
-                            info!("Play next song");
-                            if let Err(e) =
-                                handle_next_track(play_mode.clone(), &pipeline, &client).await
-                            {
-                                error!("Failed to play next track: {}", e);
-                            }
-                        }
-                        Command::Previous => {
-                            info!("Play previous song");
-                            if let Err(e) =
-                                handle_previous_track(play_mode.clone(), &pipeline, &client).await
-                            {
-                                error!("Failed to play previous track: {}", e);
-                            }
-                        }
-                        Command::Stop => {
-                            if let Err(e) = pipeline.set_state(gstreamer::State::Null) {
-                                error!("Failed to stop: {}", e);
-                            }
-                        }
-                        Command::SetPlayMode(new_mode) => {
-                            let mut write_guard = play_mode.write().await;
-                            *write_guard = new_mode;
-                        }
-                        Command::ReloadPlaylist => {
-                            if let Err(e) = handle_reload_playlist().await {
-                                error!("Failed to reload playlist: {}", e);
-                            }
+    /// Moves the stream of the track `cid` on to its next CDN mirror for
+    /// the next time it loads.
+    async fn switch_mirror(&self, cid: String) {
+        let mut mirror = self.mirror.write().await;
+        let next = match &*mirror {
+            Some((mirror_cid, index)) if *mirror_cid == cid => index + 1,
+            _ => 1,
+        };
+        *mirror = Some((cid, next));
+    }
+
+    /// Drops the saved position of the current track once it has finished,
+    /// so the next time it starts from the beginning.
+    async fn forget_position(&self) {
+        let Ok(track) = self.playlist.current_track().await else {
+            return;
+        };
+        if self.positions.write().await.remove(&track.cid).is_some() {
+            self.save_state().await;
+        }
+    }
+
+    /// Ends each track early, as if it had reached its end, at its trimmed
+    /// end or outro or, in preview mode, once it has played for the preview
+    /// length after its start.
+    fn start_end_watcher(&self, eos_sender: mpsc::Sender<()>) {
+        let audio = self.clone();
+
+        supervise("End watcher", move || {
+            let audio = audio.clone();
+            let eos_sender = eos_sender.clone();
+            async move {
+                let mut interval = tokio::time::interval(END_POLL_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if *audio.state.read().await != PlaybackState::Playing {
+                        continue;
+                    }
+                    let Some(position) = audio.backend.position() else {
+                        continue;
+                    };
+                    let Ok(track) = audio.playlist.current_track().await else {
+                        continue;
+                    };
+                    let Some(end) = audio.early_end(&track).await else {
+                        continue;
+                    };
+                    if position >= end {
+                        info!("Stopping {} at {:?}, moving on", track.bvid, end);
+                        if eos_sender.send(()).await.is_err() {
+                            break;
                         }
-                        Command::PlaylistIsEmpty => {
-                            if let Err(e) = handle_playlist_is_empty(&pipeline, &client).await {
-                                error!("Failed to play track after reloading playlist: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Where `track` stops before its natural end: at its trimmed end or
+    /// outro, or at the end of the preview.
+    async fn early_end(&self, track: &Track) -> Option<Duration> {
+        let preview = self
+            .preview
+            .read()
+            .await
+            .map(|preview| preview + Duration::from_secs(track.play_start()));
+        let trimmed = track.play_end().map(Duration::from_secs);
+        preview.into_iter().chain(trimmed).min()
+    }
+
+    pub async fn play_playlist(&self) -> Result<(), App> {
+        self.listen_for_commands();
+
+        self.play_track().await
+    }
+
+    /// Handles commands one at a time. While one is being handled the next
+    /// is already received, so a skip can cancel a track that is still
+    /// loading instead of queueing behind it.
+    fn listen_for_commands(&self) {
+        let audio = self.clone();
+        supervise("Command listener", move || {
+            let audio = audio.clone();
+            async move {
+                let mut command_receiver = audio.command_receiver.lock().await;
+                let mut pending = None;
+                loop {
+                    let command = match pending.take() {
+                        Some(command) => command,
+                        None => match command_receiver.recv().await {
+                            Some(command) => command,
+                            None => break,
+                        },
+                    };
+                    let handling = audio.handle_command(command);
+                    tokio::pin!(handling);
+                    loop {
+                        tokio::select! {
+                            () = &mut handling => break,
+                            Some(next) = command_receiver.recv(), if pending.is_none() => {
+                                if next.supersedes_load() {
+                                    audio.cancel_load();
+                                }
+                                pending = Some(next);
                             }
                         }
                     }
@@ -199,135 +575,903 @@ impl Audio {
             }
         });
     }
-}
 
-async fn handle_play_bvid(new_bvid: &str, pipeline: &Pipeline, client: &Client) -> Result<(), App> {
-    let new_index;
-    {
-        let playlist = PLAYLIST.read().await;
-        let playlist = playlist.as_ref().unwrap();
-        new_index = playlist.find_track_index(new_bvid);
+    fn cancel_load(&self) {
+        self.load_session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .token
+            .cancel();
     }
 
-    if let Some(index) = new_index {
-        set_current_track_index(index).await.ok();
-    } else {
-        error!("Track with bvid {} not found in the playlist", new_bvid);
+    /// Cancels any load in progress and starts a new session.
+    fn begin_load(&self) -> LoadSession {
+        let mut current = self
+            .load_session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        current.token.cancel();
+        *current = LoadSession::new(current.id.wrapping_add(1));
+        current.clone()
     }
 
-    play_track(pipeline, client).await
-}
+    fn is_current_load(&self, session: &LoadSession) -> bool {
+        self.load_session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .id
+            == session.id
+    }
 
-async fn handle_next_track(
-    play_mode: Arc<RwLock<PlayMode>>,
-    pipeline: &Pipeline,
-    client: &Client,
-) -> Result<(), App> {
-    let current_play_mode = *play_mode.read().await;
-    let mode = if current_play_mode == PlayMode::Repeat {
-        PlayMode::Loop
-    } else {
-        current_play_mode
-    };
-    move_to_next_track(mode).await?;
-    play_track(pipeline, client).await
-}
+    async fn handle_command(&self, command: Command) {
+        match command {
+            Command::Play => {
+                info!("Resume playback");
+                if let Err(e) = self.handle_play().await {
+                    error!("Failed to play: {}", e);
+                }
+            }
+            Command::PlayBvid(new_bvid) => {
+                info!("Play {}", new_bvid);
+                if let Err(e) = self.handle_play_bvid(&new_bvid).await {
+                    error!("Failed to play track: {}", e);
+                }
+            }
+            Command::PlaySeason(id) => {
+                if let Err(e) = self.handle_play_season(&id).await {
+                    error!("Failed to play season: {}", e);
+                }
+            }
+            Command::PlayLater => self.play_later().await,
+            Command::Pause => {
+                info!("Pause");
+                if let Err(e) = self.handle_pause().await {
+                    error!("Failed to pause: {}", e);
+                }
+            }
+            Command::TogglePause => {
+                if let Err(e) = self.handle_toggle_pause().await {
+                    error!("Failed to toggle pause: {}", e);
+                }
+            }
+            Command::Next => {
+                info!("Play next song");
+                if let Err(e) = self.handle_next_track().await {
+                    error!("Failed to play next track: {}", e);
+                }
+            }
+            Command::Previous => {
+                info!("Play previous song");
+                if let Err(e) = self.handle_previous_track().await {
+                    error!("Failed to play previous track: {}", e);
+                }
+            }
+            Command::Stop => {
+                if let Err(e) = self.handle_stop().await {
+                    error!("Failed to stop: {}", e);
+                }
+            }
+            Command::Reconnect => {
+                if let Err(e) = self.handle_reconnect().await {
+                    error!("Failed to reload the track: {}", e);
+                }
+            }
+            Command::ReleasePaused => {
+                if let Err(e) = self.handle_release_paused().await {
+                    error!("Failed to release paused track: {}", e);
+                }
+            }
+            Command::SetAudioTrack(index) => {
+                info!("Switch to audio track {}", index);
+                if let Err(e) = self.handle_set_audio_track(index).await {
+                    error!("Failed to switch audio track: {}", e);
+                }
+            }
+            Command::SetPlayMode(new_mode) => self.update_play_mode(|mode| *mode = new_mode).await,
+            Command::SetShuffle(on) => self.update_play_mode(|mode| mode.shuffle = on).await,
+            Command::SetRepeat(repeat) => self.update_play_mode(|mode| mode.repeat = repeat).await,
+            Command::SetRadio(radio) => self.set_radio(radio),
+            Command::SetChannels(channels) => self.set_channels(channels).await,
+            Command::SetPitch(semitones) => self.set_pitch(semitones).await,
+            Command::SwitchPlaylist(name, store) => {
+                info!("Switch to playlist {}", name);
+                if let Err(e) = self.handle_switch_playlist(&name, store).await {
+                    error!("Failed to switch to playlist {}: {}", name, e);
+                }
+            }
+            Command::SetPreview(preview) => self.set_preview(preview).await,
+            Command::Seek(position) => {
+                info!("Seek to {:?}", position);
+                if let Err(e) = self.handle_seek(position).await {
+                    error!("Failed to seek: {}", e);
+                }
+            }
+            Command::SetVolume(volume) => {
+                info!("Set volume to {}", volume);
+                if let Err(e) = self.fade_volume(volume).await {
+                    error!("Failed to set volume: {}", e);
+                }
+            }
+            Command::AdjustVolume(delta) => self.adjust_volume(delta).await,
+            Command::VolumeUp => self.adjust_volume(self.volume_step).await,
+            Command::VolumeDown => self.adjust_volume(-self.volume_step).await,
+            Command::ReloadPlaylist => {
+                if let Err(e) = self.handle_reload_playlist().await {
+                    error!("Failed to reload playlist: {}", e);
+                }
+            }
+        }
+    }
 
-async fn handle_previous_track(
-    play_mode: Arc<RwLock<PlayMode>>,
-    pipeline: &Pipeline,
-    client: &Client,
-) -> Result<(), App> {
-    let current_play_mode = *play_mode.read().await;
-    let mode = if current_play_mode == PlayMode::Repeat {
-        PlayMode::Loop
-    } else {
-        current_play_mode
-    };
-    move_to_previous_track(mode).await?;
-    play_track(pipeline, client).await
-}
+    async fn adjust_volume(&self, delta: f64) {
+        let volume = *self.volume.read().await + delta;
+        info!("Adjust volume by {} to {}", delta, volume);
+        if let Err(e) = self.fade_volume(volume).await {
+            error!("Failed to adjust volume: {}", e);
+        }
+    }
 
-async fn handle_reload_playlist() -> Result<(), App> {
-    let current_index = CURRENT_TRACK_INDEX.load(Ordering::SeqCst);
-    let current_track = get_current_track().await;
-
-    load(&format!(
-        "{}/.config/rosesong/playlists/playlist.toml",
-        std::env::var("HOME").expect("Failed to get HOME environment variable")
-    ))
-    .await?;
-
-    let should_play = {
-        let playlist = PLAYLIST.read().await;
-        let playlist = playlist.as_ref().unwrap();
-        if let Ok(current_track) = current_track {
-            if let Some(new_index) = playlist.find_track_index(&current_track.bvid) {
-                set_current_track_index(new_index).await.ok();
-                info!(
-                    "Current track found in the new playlist, index set to {}",
-                    new_index
-                );
-                false
-            } else {
-                info!("Current track not found in the new playlist, resetting playback");
-                let track_count = playlist.tracks.len();
-                let new_index = if current_index < track_count {
-                    current_index
-                } else {
-                    track_count - 1
-                };
-                set_current_track_index(new_index).await.ok();
-                true
+    /// Ramps the volume to `target` over `VOLUME_FADE` to avoid clicks.
+    async fn fade_volume(&self, target: f64) -> Result<(), App> {
+        let target = target.clamp(0.0, 1.0);
+        let start = *self.volume.read().await;
+        self.ramp_volume(start, target, VOLUME_FADE).await?;
+        *self.volume.write().await = target;
+        self.changes.send_replace(());
+        Ok(())
+    }
+
+    /// Changes the backend volume from `start` to `target` in even steps
+    /// over `duration`, leaving the volume setting alone.
+    async fn ramp_volume(&self, start: f64, target: f64, duration: Duration) -> Result<(), App> {
+        let steps = u32::try_from(duration.as_millis() / FADE_STEP.as_millis())
+            .unwrap_or(u32::MAX)
+            .max(1);
+        for step in 1..=steps {
+            let volume = start + (target - start) * f64::from(step) / f64::from(steps);
+            self.backend.set_volume(volume)?;
+            tokio::time::sleep(duration / steps).await;
+        }
+        Ok(())
+    }
+
+    /// Fades the sound out over `fade_out` when something is playing, then
+    /// runs `silence`, which pauses or stops the backend, and puts the
+    /// backend volume back for the next start.
+    async fn fade_out_then(&self, silence: impl FnOnce() -> Result<(), App>) -> Result<(), App> {
+        let playing = *self.state.read().await == PlaybackState::Playing;
+        if !playing || self.fade_out.is_zero() {
+            return silence();
+        }
+        let volume = *self.volume.read().await;
+        let faded = self.ramp_volume(volume, 0.0, self.fade_out).await;
+        let silenced = silence();
+        self.backend.set_volume(volume)?;
+        faded.and(silenced)
+    }
+
+    async fn handle_play(&self) -> Result<(), App> {
+        if matches!(
+            *self.state.read().await,
+            PlaybackState::Stopped | PlaybackState::Idle
+        ) {
+            return self.play_track().await;
+        }
+        if *self.state.read().await == PlaybackState::Paused && self.url_expiring().await {
+            let position = self.backend.position().unwrap_or_default();
+            return self.refresh_url(position, false).await;
+        }
+        if self.transition(Event::Resume).await.is_some() {
+            self.backend.play()?;
+        }
+        Ok(())
+    }
+
+    async fn handle_pause(&self) -> Result<(), App> {
+        if !self.fade_on_pause || self.state.read().await.on(Event::Pause).is_none() {
+            if self.transition(Event::Pause).await.is_some() {
+                self.backend.pause()?;
             }
+            return Ok(());
+        }
+        // The fade needs the state to still be `Playing`, so the backend is
+        // paused first and the state follows
+        self.fade_out_then(|| self.backend.pause()).await?;
+        self.transition(Event::Pause).await;
+        Ok(())
+    }
+
+    #[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+    async fn handle_toggle_pause(&self) -> Result<(), App> {
+        if *self.state.read().await == PlaybackState::Playing {
+            self.handle_pause().await
         } else {
-            false
+            self.handle_play().await
         }
-    };
+    }
 
-    if should_play {
-        let pipeline = Arc::new(gstreamer::Pipeline::new());
-        let client = Arc::new(Client::new());
-        play_track(&pipeline, &client).await?;
+    async fn handle_seek(&self, position: Duration) -> Result<(), App> {
+        let state = *self.state.read().await;
+        let loaded = matches!(state, PlaybackState::Playing | PlaybackState::Paused);
+        if loaded && self.url_expiring().await {
+            return self
+                .refresh_url(position, state == PlaybackState::Paused)
+                .await;
+        }
+        self.backend.seek(position)
     }
 
-    Ok(())
-}
+    /// Whether the URL of the loaded stream expires within
+    /// `URL_REFRESH_MARGIN`, after which Bilibili answers 403 when the
+    /// backend reconnects to resume or seek.
+    async fn url_expiring(&self) -> bool {
+        let Some(deadline) = *self.url_deadline.read().await else {
+            return false;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        deadline.saturating_sub(now) < URL_REFRESH_MARGIN.as_secs()
+    }
 
-async fn handle_playlist_is_empty(pipeline: &Pipeline, client: &Client) -> Result<(), App> {
-    load(&format!(
-        "{}/.config/rosesong/playlists/playlist.toml",
-        std::env::var("HOME").expect("Failed to get HOME environment variable")
-    ))
-    .await?;
+    /// Reloads the current track with a freshly fetched URL, continuing at
+    /// `position`, and leaves it `paused` or playing.
+    async fn refresh_url(&self, position: Duration, paused: bool) -> Result<(), App> {
+        let track = self.playlist.current_track().await?;
+        info!(
+            "The stream URL of {} is about to expire, fetching a new one",
+            track.bvid
+        );
+        *self.continue_at.write().await = Some(ContinueAt {
+            cid: track.cid,
+            position: position.as_secs(),
+            paused,
+        });
+        self.play_track().await
+    }
 
-    info!("Set track");
-    set_current_track_index(0).await.ok();
-    play_track(pipeline, client).await
-}
+    async fn handle_stop(&self) -> Result<(), App> {
+        if self.state.read().await.on(Event::Stop).is_none() {
+            self.transition(Event::Stop).await;
+            return Ok(());
+        }
+        // Like pausing, the fade needs the state to still be `Playing`
+        self.fade_out_then(|| self.backend.stop()).await?;
+        self.transition(Event::Stop).await;
+        Ok(())
+    }
 
-async fn play_track(pipeline: &Pipeline, client: &Client) -> Result<(), App> {
-    pipeline
-        .set_state(gstreamer::State::Null)
-        .map_err(|_| App::State("Failed to set pipeline to Null".to_string()))?;
+    /// Waits until playback has stopped, for at most the fade-out and
+    /// `timeout` more, so quitting does not cut the fade short.
+    pub async fn wait_stopped(&self, timeout: Duration) {
+        let mut changes = self.changes.subscribe();
+        let stopped = async {
+            while matches!(
+                *self.state.read().await,
+                PlaybackState::Playing | PlaybackState::Paused | PlaybackState::Loading
+            ) {
+                if changes.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(self.fade_out + timeout, stopped).await;
+    }
+
+    /// Retries the track that failed to load, continuing from where it
+    /// stalled when the watchdog recorded that.
+    async fn handle_reconnect(&self) -> Result<(), App> {
+        if !self.interrupted.load(Ordering::Relaxed)
+            || *self.state.read().await != PlaybackState::Stopped
+        {
+            return Ok(());
+        }
+        info!("Network is back, reloading the interrupted track");
+        self.play_track().await
+    }
+
+    /// Stops a paused track so the backend frees its pipeline, remembering
+    /// the position so the next `Play` continues from it.
+    async fn handle_release_paused(&self) -> Result<(), App> {
+        if *self.state.read().await != PlaybackState::Paused {
+            return Ok(());
+        }
+        if let (Ok(track), Some(position)) =
+            (self.playlist.current_track().await, self.backend.position())
+        {
+            info!("Release paused {} at {:?}", track.bvid, position);
+            *self.continue_at.write().await = Some(ContinueAt {
+                cid: track.cid,
+                position: position.as_secs(),
+                paused: false,
+            });
+        }
+        self.handle_stop().await
+    }
 
-    for element in pipeline.children() {
-        pipeline
-            .remove(&element)
-            .map_err(|_| App::Element("Failed to remove element from pipeline".to_string()))?;
+    /// Reloads the current track with another of its audio streams,
+    /// continuing from the same position.
+    async fn handle_set_audio_track(&self, index: usize) -> Result<(), App> {
+        let track = self.playlist.current_track().await?;
+        if let Some(position) = self.backend.position() {
+            *self.continue_at.write().await = Some(ContinueAt {
+                cid: track.cid.clone(),
+                position: position.as_secs(),
+                paused: false,
+            });
+        }
+        *self.stream_choice.write().await = Some((track.cid, index));
+        self.play_track().await
     }
 
-    pipeline
-        .set_state(gstreamer::State::Ready)
-        .map_err(|_| App::State("Failed to set pipeline to Ready".to_string()))?;
+    async fn handle_play_bvid(&self, new_bvid: &str) -> Result<(), App> {
+        self.playlist.leave_season();
+        self.playlist.leave_later();
+        if let Some(index) = self.playlist.find_track_index(new_bvid).await {
+            self.playlist.set_current_index(index);
+        } else {
+            error!("Track with bvid {} not found in the playlist", new_bvid);
+        }
+
+        self.play_track().await
+    }
 
-    let track = get_current_track().await?;
-    let url = fetch_and_verify_audio_url(client, &track.bvid, &track.cid).await?;
+    async fn handle_play_season(&self, id: &str) -> Result<(), App> {
+        info!("Play season {}", id);
+        self.playlist.leave_later();
+        self.playlist.start_season(id).await?;
+        self.play_track().await
+    }
 
-    set_pipeline_uri_with_headers(pipeline, &url).await?;
+    async fn play_later(&self) {
+        let track = match self.playlist.start_later() {
+            Ok(Some(track)) => track,
+            Ok(None) => {
+                warn!("The later queue is empty");
+                return;
+            }
+            Err(e) => {
+                error!("Failed to read the later queue: {}", e);
+                return;
+            }
+        };
+        info!("Play {} from the later queue", track.bvid);
+        if let Err(e) = self.play_track().await {
+            error!("Failed to play track: {}", e);
+        }
+    }
+
+    /// Moves to a related video of the current track that is not in the
+    /// playlist yet, adding it after the current track. Returns whether
+    /// there was one.
+    async fn continue_radio(&self) -> bool {
+        let Ok(current) = self.playlist.current_track().await else {
+            return false;
+        };
+        if current.kind != TrackKind::Video {
+            return false;
+        }
+        let related = fetch_related(
+            self.client.as_ref(),
+            &current.bvid,
+            self.radio_music_only,
+            &self.blacklist,
+        );
+        let related = match related.await {
+            Ok(related) => related,
+            Err(e) => {
+                warn!("Radio continues with the playlist: {}", e);
+                return false;
+            }
+        };
+        for track in related {
+            if self.playlist.find_track_index(&track.bvid).await.is_some() {
+                continue;
+            }
+            info!("Radio continues with {} ({})", track.title, track.bvid);
+            return match self.playlist.insert_after_current(track).await {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Failed to add related video: {}", e);
+                    false
+                }
+            };
+        }
+        info!("No new related video, radio continues with the playlist");
+        false
+    }
+
+    fn set_radio(&self, radio: bool) {
+        info!("Turn radio mode {}", if radio { "on" } else { "off" });
+        self.radio.store(radio, Ordering::Relaxed);
+        self.changes.send_replace(());
+    }
+
+    async fn set_preview(&self, preview: Option<Duration>) {
+        info!("Set preview length to {:?}", preview);
+        *self.preview.write().await = preview;
+    }
+
+    async fn set_channels(&self, channels: Channels) {
+        info!("Set channels to {}", channels.as_str());
+        if let Err(e) = self.backend.set_channels(channels) {
+            error!("Failed to set channels: {}", e);
+            return;
+        }
+        *self.channels.write().await = channels;
+        self.changes.send_replace(());
+    }
+
+    async fn set_pitch(&self, semitones: i32) {
+        info!("Set pitch to {} semitones", semitones);
+        let live = match self.backend.set_pitch(semitones) {
+            Ok(live) => live,
+            Err(e) => {
+                error!("Failed to set pitch: {}", e);
+                return;
+            }
+        };
+        *self.pitch.write().await = semitones;
+        self.changes.send_replace(());
+        let loaded = matches!(
+            *self.state.read().await,
+            PlaybackState::Playing | PlaybackState::Paused
+        );
+        if live || !loaded {
+            return;
+        }
+        // Reload the track like when switching its audio stream
+        if let Ok(track) = self.playlist.current_track().await {
+            if let Some(position) = self.backend.position() {
+                *self.continue_at.write().await = Some(ContinueAt {
+                    cid: track.cid,
+                    position: position.as_secs(),
+                    paused: false,
+                });
+            }
+        }
+        if let Err(e) = self.play_track().await {
+            error!("Failed to reload the track with the new pitch: {}", e);
+        }
+    }
+
+    async fn update_play_mode(&self, update: impl FnOnce(&mut PlayMode)) {
+        update(&mut *self.play_mode.write().await);
+        self.changes.send_replace(());
+    }
+
+    /// Play mode used for manual skips: they keep the shuffle order but
+    /// always move on, wrapping around at either end.
+    async fn skip_mode(&self) -> PlayMode {
+        PlayMode {
+            repeat: Repeat::All,
+            ..*self.play_mode.read().await
+        }
+    }
+
+    async fn handle_next_track(&self) -> Result<(), App> {
+        self.playlist
+            .move_to_next_track(self.skip_mode().await)
+            .await?;
+        self.play_track().await
+    }
+
+    async fn handle_previous_track(&self) -> Result<(), App> {
+        self.playlist
+            .move_to_previous_track(self.skip_mode().await)
+            .await?;
+        self.play_track().await
+    }
+
+    async fn handle_reload_playlist(&self) -> Result<(), App> {
+        let keep_removed = self.remove_policy == RemovePolicy::Finish;
+        let was_idle = *self.state.read().await == PlaybackState::Idle;
+        let diff = self.playlist.reload(keep_removed).await?;
+        if self.playlist.len().await == 0 {
+            // A removed track that should finish goes idle at its end
+            if !keep_removed || *self.state.read().await == PlaybackState::Stopped {
+                self.enter_idle().await;
+            }
+            return Ok(());
+        }
+        if was_idle {
+            self.playlist.set_current_index(0);
+            if self.autoplay_on_add {
+                info!("Tracks added to the empty playlist, starting playback");
+                return self.play_track().await;
+            }
+            self.transition(Event::Filled).await;
+            return Ok(());
+        }
+        if !diff.current_removed {
+            return Ok(());
+        }
+        info!(
+            "Current track removed, moving on to track {}",
+            self.playlist.current_index()
+        );
+        self.play_track().await
+    }
+
+    async fn handle_switch_playlist(
+        &self,
+        name: &str,
+        store: Arc<dyn PlaylistStore>,
+    ) -> Result<(), App> {
+        if store.is_empty()? {
+            warn!("Playlist {} is empty, not switching", name);
+            return Ok(());
+        }
+        self.playlist.switch(name, store).await?;
+        self.play_track().await
+    }
+
+    /// Stops whatever plays and waits in `Idle` for tracks to be added.
+    async fn enter_idle(&self) {
+        if *self.state.read().await == PlaybackState::Idle {
+            return;
+        }
+        info!("Playlist is empty, waiting for tracks");
+        self.begin_load();
+        if let Err(e) = self.backend.stop() {
+            error!("Failed to stop the backend: {}", e);
+        }
+        self.playlist.leave_later();
+        self.playlist.set_current_index(0);
+        self.transition(Event::Emptied).await;
+    }
+
+    /// Loads the current track into the backend and starts playing it,
+    /// moving through `Loading` to `Playing` or back to `Stopped` on failure.
+    /// A track reloaded to continue paused ends up `Paused` instead.
+    ///
+    /// Commands and the end of a track can start loads concurrently; only the
+    /// latest session touches the backend and the state once it is done.
+    async fn play_track(&self) -> Result<(), App> {
+        if self.playlist.len().await == 0 {
+            self.enter_idle().await;
+            return Ok(());
+        }
+        self.playlist.skip_disabled().await?;
+        let session = self.begin_load();
+        self.transition(Event::Load).await;
+        let result = match self.playlist.current_track().await {
+            Ok(track) => self.start_track(&track, &session).await,
+            Err(e) => Err(e),
+        };
+        if !self.is_current_load(&session) {
+            info!("Load session {} was superseded", session.id);
+            return Ok(());
+        }
+        let event = match result {
+            Ok(true) => Event::LoadedPaused,
+            Ok(false) => Event::Loaded,
+            Err(_) => Event::LoadFailed,
+        };
+        self.transition(event).await;
+        self.interrupted.store(
+            !matches!(result, Ok(_) | Err(App::Cancelled)),
+            Ordering::Relaxed,
+        );
+        match result {
+            Err(App::Cancelled) => {
+                info!("Loading the track was cancelled");
+                Ok(())
+            }
+            Err(e) => {
+                self.report_error(&e).await;
+                Err(e)
+            }
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Keeps `error` for `rsg status` and tells whoever listens that the
+    /// current track failed to load.
+    async fn report_error(&self, error: &App) {
+        let error = PlaybackError {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| {
+                    i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX)
+                }),
+            track: self.playlist.current_track().await.ok(),
+            message: error.to_string(),
+        };
+        *self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(error.clone());
+        let _ = self.errors.send(error);
+        self.changes.send_replace(());
+    }
+
+    /// Records how long the loaded stream takes to start playing, which is
+    /// when the backend reports a position past zero.
+    fn spawn_startup_probe(&self, session: LoadSession) {
+        let audio = self.clone();
+        let loaded = Instant::now();
+
+        task::spawn(async move {
+            for _ in 0..STARTUP_PROBE_ATTEMPTS {
+                if session.token.is_cancelled() {
+                    return;
+                }
+                if audio
+                    .backend
+                    .position()
+                    .is_some_and(|position| position > Duration::ZERO)
+                {
+                    audio.metrics.record(Latency::Pipeline, loaded.elapsed());
+                    audio
+                        .metrics
+                        .record(Latency::Total, session.started.elapsed());
+                    info!(
+                        "Session {} started playing after {:?}",
+                        session.id,
+                        session.started.elapsed()
+                    );
+                    return;
+                }
+                tokio::time::sleep(STARTUP_PROBE_INTERVAL).await;
+            }
+        });
+    }
+
+    pub fn perf_stats(&self) -> PerfStats {
+        self.metrics.snapshot()
+    }
+
+    pub async fn debug_info(&self) -> DebugInfo {
+        let buffer = self.backend.buffer_stats();
+        DebugInfo {
+            rss_bytes: crate::memory::rss_bytes(),
+            playlist_tracks: self.playlist.len().await,
+            saved_positions: self.positions.read().await.len(),
+            latency_samples: self.metrics.sample_count(),
+            download_rate: buffer.download_rate,
+            buffered: buffer.buffered.map(|buffered| buffered.as_secs_f64()),
+            stalls: self.health.stalls(),
+            reconnects: self.health.reconnects(),
+            cdn_host: self.health.host(),
+        }
+    }
+
+    /// Releases spare capacity of the long-lived caches.
+    pub async fn shrink_caches(&self) {
+        self.playlist.shrink_to_fit().await;
+        self.positions
+            .write()
+            .await
+            .retain(|_, position| *position > 0);
+        if let Some(stream) = self.stream.write().await.as_mut() {
+            stream.codec.shrink_to_fit();
+        }
+    }
+
+    /// Tracks of the playlist being played, in order.
+    #[cfg_attr(not(feature = "mpd"), allow(dead_code))]
+    pub async fn tracks(&self) -> Vec<Track> {
+        self.playlist.tracks().await
+    }
+
+    pub async fn audio_tracks(&self) -> Vec<AudioTrack> {
+        self.streams.read().await.clone()
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        *self.state.read().await == PlaybackState::Paused
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Arc<LatencyMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Notified whenever the playback state, track or play mode changes.
+    pub fn subscribe_changes(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Notified whenever a track fails to load.
+    pub fn subscribe_errors(&self) -> broadcast::Receiver<PlaybackError> {
+        self.errors.subscribe()
+    }
+
+    pub async fn status(&self) -> Status {
+        let state = *self.state.read().await;
+        let track = if matches!(state, PlaybackState::Stopped | PlaybackState::Idle) {
+            None
+        } else {
+            self.playlist.current_track().await.ok()
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let duration = track.as_ref().map_or(0.0, |track| track.duration as f64);
+        let position = self
+            .backend
+            .position()
+            .map_or(0.0, |position| position.as_secs_f64());
+        let remaining = match &track {
+            Some(track) => self.remaining(track, position).await,
+            None => 0,
+        };
+        let play_mode = *self.play_mode.read().await;
+        let next_track = match track {
+            Some(_) => self.playlist.next_track(play_mode).await,
+            None => None,
+        };
+        Status {
+            state: state.as_str().to_string(),
+            mode: play_mode.legacy_name().to_string(),
+            shuffle: play_mode.shuffle,
+            repeat: play_mode.repeat.as_str().to_string(),
+            radio: self.radio.load(Ordering::Relaxed),
+            channels: self.channels.read().await.as_str().to_string(),
+            pitch: *self.pitch.read().await,
+            track,
+            position,
+            duration,
+            remaining,
+            volume: *self.volume.read().await,
+            playlist: self.playlist.name(),
+            stream: self.stream_info().await,
+            next_track,
+            last_error: self
+                .last_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        }
+    }
+
+    /// Whole seconds, rounded up, from `position` until `track` stops, or `0`
+    /// when its length is not known.
+    async fn remaining(&self, track: &Track, position: f64) -> u64 {
+        let end = match self.early_end(track).await {
+            Some(end) => end.as_secs_f64(),
+            #[allow(clippy::cast_precision_loss)]
+            None => track.duration as f64,
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let remaining = (end - position).max(0.0).ceil() as u64;
+        remaining
+    }
+
+    /// Format reported by the API, refined with what the decoder reports.
+    async fn stream_info(&self) -> Option<StreamInfo> {
+        let api = self.stream.read().await.clone();
+        let Some(decoded) = self.backend.stream_info() else {
+            return api;
+        };
+        let api = api.unwrap_or_default();
+        Some(StreamInfo {
+            codec: if decoded.codec.is_empty() {
+                api.codec
+            } else {
+                decoded.codec
+            },
+            bitrate: decoded.bitrate.or(api.bitrate),
+            sample_rate: decoded.sample_rate.or(api.sample_rate),
+            channels: decoded.channels.or(api.channels),
+            output: decoded.output,
+        })
+    }
+
+    /// Fetches the stream of `track` and loads it, returning whether it was
+    /// left paused.
+    async fn start_track(&self, track: &Track, session: &LoadSession) -> Result<bool, App> {
+        self.backend.stop()?;
+        let fetch_started = Instant::now();
+        let choice = match &*self.stream_choice.read().await {
+            Some((cid, index)) if *cid == track.cid => StreamChoice::Index(*index),
+            _ => StreamChoice::Policy(self.stream_policy),
+        };
+        let fetch = fetch_and_verify_audio_url(&self.client, track, choice, &self.network);
+        let (streams, selected) = tokio::select! {
+            biased;
+            () = session.token.cancelled() => return Err(App::Cancelled),
+            streams = fetch => streams?,
+        };
+        self.metrics.record(Latency::Fetch, fetch_started.elapsed());
+        // Checking the session and loading happen under one lock, so an older
+        // session whose fetch finished late cannot replace a newer track.
+        let load_guard = self.load_lock.lock().await;
+        if !self.is_current_load(session) {
+            return Err(App::Cancelled);
+        }
+        let stream = &streams[selected];
+        info!(
+            "Stream for {}: codec {}, {} kbps",
+            track.bvid,
+            stream.codec,
+            stream.bandwidth.unwrap_or_default() / 1000
+        );
+        if stream.codec.starts_with("ec-3") || stream.codec.starts_with("ac-3") {
+            warn!(
+                "{} is a Dolby stream, which the player backend may not be able to decode",
+                track.bvid
+            );
+        }
+        *self.streams.write().await = streams
+            .iter()
+            .enumerate()
+            .map(|(index, stream)| AudioTrack {
+                id: stream.id,
+                codec: stream.codec.clone(),
+                bitrate: stream.bandwidth,
+                selected: index == selected,
+            })
+            .collect();
+        *self.stream.write().await = Some(StreamInfo {
+            codec: stream.codec.clone(),
+            bitrate: stream.bandwidth,
+            ..StreamInfo::default()
+        });
+        *self.url_deadline.write().await = stream.deadline();
+        let mirror = match &*self.mirror.read().await {
+            Some((cid, mirror)) if *cid == track.cid => *mirror,
+            _ => 0,
+        };
+        let url = stream.mirror_url(mirror);
+        let cdn_host = host(url);
+        if mirror > 0 {
+            warn!("Loading {} from CDN host {}", track.bvid, cdn_host);
+        }
+        self.health.set_host(cdn_host);
+        self.backend
+            .set_duration(Duration::from_secs(track.duration));
+        let continued = self
+            .continue_at
+            .write()
+            .await
+            .take()
+            .filter(|at| at.cid == track.cid);
+        let paused = continued.as_ref().is_some_and(|at| at.paused);
+        self.backend.load(url, paused)?;
+        drop(load_guard);
+        self.spawn_startup_probe(session.clone());
+
+        let continued = continued.map(|at| at.position);
+        let saved = if self.is_resumable(track) {
+            self.positions.read().await.get(&track.cid).copied()
+        } else {
+            None
+        };
+        let start = Some(track.play_start()).filter(|start| *start > 0);
+        let Some(position) = continued.or(saved).or(start) else {
+            return Ok(paused);
+        };
+        // The backend can only seek once the stream is ready, which is when
+        // it starts reporting a position.
+        for _ in 0..RESUME_POLL_ATTEMPTS {
+            if session.token.is_cancelled() {
+                return Err(App::Cancelled);
+            }
+            if self.backend.position().is_some() {
+                info!("Resume {} at {}s", track.bvid, position);
+                self.backend.seek(Duration::from_secs(position))?;
+                return Ok(paused);
+            }
+            tokio::time::sleep(RESUME_POLL_INTERVAL).await;
+        }
+        warn!(
+            "Track {} did not become seekable, starting from the beginning",
+            track.bvid
+        );
+        Ok(paused)
+    }
+}
 
-    pipeline
-        .set_state(gstreamer::State::Playing)
-        .map_err(|_| App::State("Failed to set pipeline to Playing".to_string()))?;
-    Ok(())
+/// The download rate and buffer level in `stats`, for logs.
+fn describe_buffer(stats: BufferStats) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let rate = stats.download_rate.map_or_else(
+        || "download rate unknown".to_string(),
+        |rate| format!("downloading at {:.1} KB/s", rate as f64 / 1024.0),
+    );
+    let buffered = stats.buffered.map_or_else(
+        || "buffer level unknown".to_string(),
+        |buffered| format!("{:.1}s buffered", buffered.as_secs_f64()),
+    );
+    format!("{rate}, {buffered}")
 }
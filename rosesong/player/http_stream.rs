@@ -0,0 +1,91 @@
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, CONTENT_RANGE, RANGE};
+use std::io::{self, Read, Seek, SeekFrom};
+
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+/// Seekable reader over an HTTP resource that fetches it in ranged chunks,
+/// so decoders can start before the whole file has been downloaded.
+pub struct HttpStream {
+    client: Client,
+    url: String,
+    length: u64,
+    position: u64,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl HttpStream {
+    pub fn open(url: &str, headers: HeaderMap) -> io::Result<Self> {
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(io::Error::other)?;
+        let mut stream = HttpStream {
+            client,
+            url: url.to_string(),
+            length: 0,
+            position: 0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+        };
+        stream.length = stream.fetch(0)?;
+        Ok(stream)
+    }
+
+    /// Fills the buffer with the chunk starting at `start` and returns the
+    /// total length of the resource.
+    fn fetch(&mut self, start: u64) -> io::Result<u64> {
+        let end = start + CHUNK_SIZE - 1;
+        let response = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={start}-{end}"))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .map_err(io::Error::other)?;
+        let length = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit('/').next())
+            .and_then(|total| total.parse().ok())
+            .or(response.content_length())
+            .unwrap_or(0);
+        self.buffer = response.bytes().map_err(io::Error::other)?.to_vec();
+        self.buffer_start = start;
+        Ok(length)
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length {
+            return Ok(0);
+        }
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            self.fetch(self.position)?;
+        }
+        let offset =
+            usize::try_from(self.position - self.buffer_start).map_err(io::Error::other)?;
+        let available = &self.buffer[offset..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+        Ok(self.position)
+    }
+}
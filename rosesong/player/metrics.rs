@@ -0,0 +1,121 @@
+use rosesong_core::ipc::{LatencySummary, PerfStats};
+use std::collections::VecDeque;
+#[cfg(feature = "metrics")]
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Only the most recent samples are kept, so the percentiles follow the
+/// current network conditions.
+const MAX_SAMPLES: usize = 200;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Latency {
+    Fetch,
+    Pipeline,
+    Total,
+}
+
+/// Timings of recent track starts.
+#[derive(Debug, Default)]
+pub struct LatencyMetrics {
+    samples: Mutex<[VecDeque<Duration>; 3]>,
+}
+
+impl LatencyMetrics {
+    pub fn record(&self, latency: Latency, duration: Duration) {
+        let mut samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        let samples = &mut samples[latency as usize];
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(duration);
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(VecDeque::len)
+            .sum()
+    }
+
+    pub fn snapshot(&self) -> PerfStats {
+        let samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        PerfStats {
+            fetch: summarize(&samples[Latency::Fetch as usize]),
+            pipeline: summarize(&samples[Latency::Pipeline as usize]),
+            total: summarize(&samples[Latency::Total as usize]),
+        }
+    }
+
+    /// The snapshot in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    pub fn prometheus(&self) -> String {
+        let stats = self.snapshot();
+        let mut output = String::new();
+        for (name, help, summary) in [
+            (
+                "fetch",
+                "Time to get and verify the stream URL",
+                &stats.fetch,
+            ),
+            (
+                "pipeline",
+                "Time from loading the stream to audio playing",
+                &stats.pipeline,
+            ),
+            (
+                "total",
+                "Time from a track change to audio playing",
+                &stats.total,
+            ),
+        ] {
+            let metric = format!("rosesong_{name}_latency_seconds");
+            let _ = writeln!(output, "# HELP {metric} {help}.");
+            let _ = writeln!(output, "# TYPE {metric} summary");
+            let _ = writeln!(
+                output,
+                "{metric}{{quantile=\"0.5\"}} {}",
+                summary.p50_ms / 1000.0
+            );
+            let _ = writeln!(
+                output,
+                "{metric}{{quantile=\"0.95\"}} {}",
+                summary.p95_ms / 1000.0
+            );
+            let _ = writeln!(output, "{metric}_sum {}", summary.sum_ms / 1000.0);
+            let _ = writeln!(output, "{metric}_count {}", summary.count);
+        }
+        output
+    }
+}
+
+fn summarize(samples: &VecDeque<Duration>) -> LatencySummary {
+    let mut sorted: Vec<f64> = samples
+        .iter()
+        .map(|duration| duration.as_secs_f64() * 1000.0)
+        .collect();
+    sorted.sort_by(f64::total_cmp);
+    LatencySummary {
+        count: sorted.len(),
+        sum_ms: sorted.iter().sum(),
+        p50_ms: percentile(&sorted, 0.5),
+        p95_ms: percentile(&sorted, 0.95),
+    }
+}
+
+/// Nearest-rank percentile of already sorted values.
+fn percentile(sorted: &[f64], quantile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
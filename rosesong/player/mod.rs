@@ -1,5 +1,11 @@
+pub mod backend;
 pub mod gst_logic;
+pub mod health;
+#[cfg(feature = "rodio")]
+pub mod http_stream;
+pub mod metrics;
 pub mod network;
 pub mod playlist;
+pub mod state;
 pub use gst_logic::Audio;
 pub use gst_logic::Command;
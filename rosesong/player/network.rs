@@ -1,137 +1,175 @@
-use crate::bilibili::fetch_audio_url::fetch_audio_url;
+use crate::bilibili::fetch_audio_url::{fetch_track_streams, host, AudioStream, StreamChoice};
+use crate::bilibili::ytdlp;
 use crate::error::App;
-use glib::object::ObjectExt;
-use gstreamer::prelude::{ElementExt, ElementExtManual, GstBinExtManual, PadExt};
-use gstreamer::Pipeline;
-use log::{error, info};
-use reqwest::header::{ACCEPT, RANGE, USER_AGENT};
+use log::{error, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, RANGE};
 use reqwest::Client;
-use tokio::time::{sleep, Duration};
+use rosesong_core::settings::NetworkSettings;
+use rosesong_core::storage::Track;
+use tokio::time::{sleep, timeout, Duration};
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const POOL_MAX_IDLE_PER_HOST: usize = 4;
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+const HTTP2_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Builds the client shared by the API requests and stream verification.
+///
+/// Idle connections are kept alive and reused, and HTTP/2 is negotiated
+/// where the server offers it, so skipping tracks does not pay for a new
+/// TLS handshake every time.
+pub fn build_client(
+    settings: &NetworkSettings,
+    headers: &[(String, String)],
+) -> Result<Client, App> {
+    let mut builder = Client::builder()
+        .default_headers(header_map(headers)?)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .http2_adaptive_window(true)
+        .http2_keep_alive_interval(HTTP2_KEEPALIVE)
+        .http2_keep_alive_while_idle(true);
+    if settings.request_timeout > 0 {
+        builder = builder.timeout(Duration::from_secs(settings.request_timeout));
+    }
+    Ok(builder.build()?)
+}
+
+/// Builds the default headers for HTTP clients from the configured ones.
+pub fn header_map(headers: &[(String, String)]) -> Result<HeaderMap, App> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| App::HeaderValue(format!("{name}: {e}")))?;
+        map.insert(name, HeaderValue::from_str(value)?);
+    }
+    Ok(map)
+}
 
 pub async fn verify_audio_url(client: &Client, url: &str) -> Result<bool, App> {
     let response = client
         .get(url)
-        .header(USER_AGENT, "Mozilla/5.0 BiliDroid/..* (bbcallen@gmail.com)")
         .header(ACCEPT, "*/*")
         .header(RANGE, "bytes=0-1024")
-        .header("Referer", "https://www.bilibili.com")
         .send()
         .await
         .map_err(|e| App::Network(e.to_string()))?;
+    let success = response.status().is_success();
+    // Reading the small body to the end lets the connection go back to the
+    // pool instead of being closed.
+    let _ = response.bytes().await;
 
-    Ok(response.status().is_success())
+    Ok(success)
 }
 
+/// Fetches the audio streams of the track and checks the chosen one can be
+/// downloaded, retrying API and download failures separately as
+/// configured. Returns all streams and the index of the chosen one.
 pub async fn fetch_and_verify_audio_url(
     client: &Client,
-    bvid: &str,
-    cid: &str,
-) -> Result<String, App> {
-    const MAX_RETRIES: u32 = 3;
-    const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
-    let mut retry_delay = INITIAL_RETRY_DELAY;
+    track: &Track,
+    choice: StreamChoice,
+    settings: &NetworkSettings,
+) -> Result<(Vec<AudioStream>, usize), App> {
+    let attempts = fetch_with_retries(client, track, choice, settings);
+    if settings.timeout == 0 {
+        return attempts.await;
+    }
+    timeout(Duration::from_secs(settings.timeout), attempts)
+        .await
+        .map_err(|_| {
+            App::Fetch(format!(
+                "Timed out after {}s fetching audio URL",
+                settings.timeout
+            ))
+        })?
+}
 
-    for attempt in 1..=MAX_RETRIES {
-        match fetch_audio_url(client, bvid, cid).await {
-            Ok(url) => match verify_audio_url(client, &url).await {
-                Ok(true) => return Ok(url),
-                Ok(false) => {
-                    info!("Verification failed for URL: {}", url);
-                }
-                Err(e) => {
-                    error!("Error verifying URL: {}", e);
+async fn fetch_with_retries(
+    client: &Client,
+    track: &Track,
+    choice: StreamChoice,
+    settings: &NetworkSettings,
+) -> Result<(Vec<AudioStream>, usize), App> {
+    let mut fetch_failures = 0;
+    let mut verify_failures = 0;
+
+    loop {
+        let (policy, failures) = match fetch_track_streams(client, track).await {
+            Ok(mut streams) => {
+                let index = choice.pick(&streams);
+                match verify_stream(client, &mut streams[index]).await {
+                    Ok(true) => return Ok((streams, index)),
+                    Ok(false) => {
+                        info!("Verification failed for URL: {}", streams[index].url);
+                        verify_failures += 1;
+                        (&settings.verify, verify_failures)
+                    }
+                    Err(e) => {
+                        error!("Error verifying URL: {}", e);
+                        verify_failures += 1;
+                        (&settings.verify, verify_failures)
+                    }
                 }
-            },
+            }
             Err(e) => {
                 error!("Error fetching audio URL: {}", e);
+                fetch_failures += 1;
+                (&settings.fetch, fetch_failures)
             }
+        };
+
+        if failures >= policy.attempts {
+            if settings.ytdlp_fallback {
+                return fetch_with_ytdlp(client, track, settings).await;
+            }
+            return Err(App::Fetch(
+                "Max retries reached for fetching and verifying audio URL".to_string(),
+            ));
         }
-        if attempt < MAX_RETRIES {
-            info!("Retrying... Attempt {}/{}", attempt, MAX_RETRIES);
-            sleep(retry_delay).await;
-            // Exponential backoff
-            retry_delay *= 2;
-        }
+        info!("Retrying... Attempt {}/{}", failures + 1, policy.attempts);
+        // Exponential backoff
+        sleep(policy.backoff(failures)).await;
     }
-
-    Err(App::Fetch(
-        "Max retries reached for fetching and verifying audio URL".to_string(),
-    ))
 }
 
-pub async fn set_pipeline_uri_with_headers(pipeline: &Pipeline, url: &str) -> Result<(), App> {
-    let source = gstreamer::ElementFactory::make("souphttpsrc")
-        .build()
-        .map_err(|_| App::Element("Failed to create souphttpsrc element".to_string()))?;
-    source.set_property("location", url);
-
-    let mut headers = gstreamer::Structure::new_empty("headers");
-    headers.set(
-        "User-Agent",
-        "Mozilla/5.0 BiliDroid/..* (bbcallen@gmail.com)",
-    );
-    headers.set("Referer", "https://www.bilibili.com");
-    source.set_property("extra-headers", &headers);
-
-    let decodebin = gstreamer::ElementFactory::make("decodebin")
-        .build()
-        .map_err(|_| App::Element("Failed to create decodebin element".to_string()))?;
-
-    pipeline
-        .add_many([&source, &decodebin])
-        .map_err(|_| App::Pipeline("Failed to add elements to pipeline".to_string()))?;
-    source
-        .link(&decodebin)
-        .map_err(|_| App::Link("Failed to link source to decodebin".to_string()))?;
-
-    let pipeline_weak = pipeline.downgrade();
-
-    decodebin.connect_pad_added(move |_, src_pad| {
-        if let Some(pipeline) = pipeline_weak.upgrade() {
-            let audioconvert = gstreamer::ElementFactory::make("audioconvert")
-                .build()
-                .expect("Failed to create audioconvert element");
-            let audioresample = gstreamer::ElementFactory::make("audioresample")
-                .build()
-                .expect("Failed to create audioresample element");
-            let autoaudiosink = gstreamer::ElementFactory::make("autoaudiosink")
-                .build()
-                .expect("Failed to create autoaudiosink element");
-
-            pipeline
-                .add_many([&audioconvert, &audioresample, &autoaudiosink])
-                .expect("Failed to add elements to pipeline");
-
-            audioconvert
-                .sync_state_with_parent()
-                .expect("Failed to sync_state_with_parent for audioconvert");
-            audioresample
-                .sync_state_with_parent()
-                .expect("Failed to sync_state_with_parent for audioresample");
-            autoaudiosink
-                .sync_state_with_parent()
-                .expect("Failed to sync_state_with_parent for autoaudiosink");
-
-            let audio_pad = audioconvert
-                .static_pad("sink")
-                .expect("Failed to get static pad");
-            src_pad.link(&audio_pad).expect("Failed to link pads");
-
-            audioconvert
-                .link(&audioresample)
-                .expect("Failed to link audioconvert to audioresample");
-            audioresample
-                .link(&autoaudiosink)
-                .expect("Failed to link audioresample to autoaudiosink");
-
-            info!("Pipeline elements linked successfully");
-        } else {
-            error!("Failed to upgrade pipeline reference");
+/// Checks `stream` can be downloaded from its main CDN host or, failing
+/// that, from one of its backups, which then becomes its main URL.
+async fn verify_stream(client: &Client, stream: &mut AudioStream) -> Result<bool, App> {
+    let result = verify_audio_url(client, &stream.url).await;
+    if matches!(result, Ok(true)) {
+        return result;
+    }
+    for index in 0..stream.backup_urls.len() {
+        let backup = &stream.backup_urls[index];
+        if matches!(verify_audio_url(client, backup).await, Ok(true)) {
+            warn!(
+                "CDN host {} failed, switching to backup {}",
+                host(&stream.url),
+                host(backup)
+            );
+            std::mem::swap(&mut stream.url, &mut stream.backup_urls[index]);
+            return Ok(true);
         }
-    });
+    }
+    result
+}
 
-    pipeline
-        .set_state(gstreamer::State::Playing)
-        .map_err(|_| App::State("Failed to set pipeline to Playing".to_string()))?;
-    Ok(())
+/// The last resort once the API keeps failing, for example under risk
+/// control: the stream yt-dlp finds on the page of the track.
+async fn fetch_with_ytdlp(
+    client: &Client,
+    track: &Track,
+    settings: &NetworkSettings,
+) -> Result<(Vec<AudioStream>, usize), App> {
+    info!("Falling back to yt-dlp for {}", track.bvid);
+    let stream =
+        ytdlp::fetch_stream(&settings.ytdlp_path, track, &settings.request_headers()).await?;
+    if !verify_audio_url(client, &stream.url).await? {
+        return Err(App::Fetch(
+            "The stream found by yt-dlp cannot be downloaded".to_string(),
+        ));
+    }
+    Ok((vec![stream], 0))
 }
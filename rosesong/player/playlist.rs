@@ -1,117 +1,1113 @@
 use crate::error::App;
 use rand::seq::IteratorRandom;
-use serde::Deserialize;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::LazyLock;
+use rosesong_core::settings::ShuffleSettings;
+use rosesong_core::storage::{self, PlaylistStore};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock as StdRwLock};
 use tokio::sync::RwLock;
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct Track {
-    pub bvid: String,
-    pub cid: String,
-}
+pub use rosesong_core::storage::Track;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Playlist {
     pub tracks: Vec<Track>,
+    index: HashMap<String, usize>,
+}
+
+/// Difference between two versions of a playlist, keyed by bvid.
+#[derive(Debug, Default)]
+pub struct PlaylistDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub reordered: bool,
+    /// The current track was among the removed ones.
+    pub current_removed: bool,
+}
+
+impl PlaylistDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && !self.reordered
+    }
+}
+
+/// The run of tracks that differs between two versions of a playlist:
+/// tracks `start..old_end` of the old one became `start..new_end` of the
+/// new one, and those around it stayed as they were.
+#[derive(Clone, Copy, Debug)]
+struct Splice {
+    start: usize,
+    old_end: usize,
+    new_end: usize,
 }
 
 impl Playlist {
-    pub async fn load_from_file(file_path: &str) -> Result<Self, App> {
-        log::info!("Loading playlist");
-        let content = tokio::fs::read_to_string(file_path).await?;
-        let playlist: Playlist = toml::from_str(&content)?;
-        Ok(playlist)
+    pub fn new(tracks: Vec<Track>) -> Self {
+        let mut playlist = Playlist {
+            tracks,
+            index: HashMap::new(),
+        };
+        playlist.rebuild_index();
+        playlist
+    }
+
+    /// Maps each bvid to its first occurrence in the playlist.
+    fn rebuild_index(&mut self) {
+        self.index.clear();
+        for (i, track) in self.tracks.iter().enumerate() {
+            self.index.entry(track.bvid.clone()).or_insert(i);
+        }
     }
 
-    pub fn get_current_track(&self, index: usize) -> Result<Track, App> {
+    /// Turns the tracks into `other`'s by replacing only the run that
+    /// differs, returning what changed between the two.
+    pub fn apply(&mut self, mut other: Playlist) -> PlaylistDiff {
+        let old_bvids: HashSet<&str> = self.tracks.iter().map(|t| t.bvid.as_str()).collect();
+        let new_bvids: HashSet<&str> = other.tracks.iter().map(|t| t.bvid.as_str()).collect();
+        let mut diff = PlaylistDiff {
+            added: other
+                .tracks
+                .iter()
+                .filter(|t| !old_bvids.contains(t.bvid.as_str()))
+                .map(|t| t.bvid.clone())
+                .collect(),
+            removed: self
+                .tracks
+                .iter()
+                .filter(|t| !new_bvids.contains(t.bvid.as_str()))
+                .map(|t| t.bvid.clone())
+                .collect(),
+            reordered: false,
+            current_removed: false,
+        };
+        let splice = self.splice_to(&other);
+        let changed = splice.start < splice.old_end || splice.start < splice.new_end;
+        diff.reordered = diff.added.is_empty() && diff.removed.is_empty() && changed;
+        if !diff.is_empty() {
+            self.tracks.splice(
+                splice.start..splice.old_end,
+                other.tracks.drain(splice.start..splice.new_end),
+            );
+            // The tracks now are `other`'s, so is the index
+            self.index = other.index;
+        }
+        diff
+    }
+
+    /// Where these tracks and `other`'s differ, found by skipping the
+    /// tracks both start and end with.
+    fn splice_to(&self, other: &Playlist) -> Splice {
+        let start = self
+            .tracks
+            .iter()
+            .zip(&other.tracks)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let end = self.tracks[start..]
+            .iter()
+            .rev()
+            .zip(other.tracks[start..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        Splice {
+            start,
+            old_end: self.tracks.len() - end,
+            new_end: other.tracks.len() - end,
+        }
+    }
+
+    /// Index in `other` of the track at `index`, or `None` when `other` no
+    /// longer has it. Tracks within the changed run are matched by bvid, the
+    /// n-th copy of a video there to its n-th copy in `other`.
+    fn position_in(&self, index: usize, other: &Playlist, splice: Splice) -> Option<usize> {
+        if index < splice.start {
+            return Some(index);
+        }
+        if index >= splice.old_end {
+            return (index < self.tracks.len()).then(|| index - splice.old_end + splice.new_end);
+        }
+        let bvid = &self.tracks[index].bvid;
+        let copy = self.tracks[splice.start..index]
+            .iter()
+            .filter(|track| &track.bvid == bvid)
+            .count();
+        other.tracks[splice.start..splice.new_end]
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| &track.bvid == bvid)
+            .nth(copy)
+            .map(|(i, _)| splice.start + i)
+    }
+
+    pub fn get_track(&self, index: usize) -> Result<Track, App> {
         self.tracks
             .get(index)
             .cloned()
             .ok_or_else(|| App::DataParsing("Track index out of bounds".to_string()))
     }
 
-    pub fn move_to_next_track(&mut self, play_mode: PlayMode) -> Result<usize, App> {
-        let current_index = CURRENT_TRACK_INDEX.load(Ordering::SeqCst);
-        let new_index = match play_mode {
-            PlayMode::Loop => (current_index + 1) % self.tracks.len(),
-            PlayMode::Shuffle => {
-                let mut rng = rand::thread_rng();
-                (0..self.tracks.len())
-                    .choose(&mut rng)
-                    .ok_or_else(|| App::DataParsing("Failed to choose random track".to_string()))?
+    fn is_enabled(&self, index: usize) -> bool {
+        self.tracks.get(index).is_some_and(|track| !track.disabled)
+    }
+
+    fn random_index(&self) -> Result<usize, App> {
+        let mut rng = rand::thread_rng();
+        (0..self.tracks.len())
+            .filter(|i| self.is_enabled(*i))
+            .choose(&mut rng)
+            .ok_or_else(|| App::DataParsing("All tracks are disabled".to_string()))
+    }
+
+    /// A random enabled track to follow the one at `current_index`,
+    /// skipping those played in this round unless `new_round` is set. Picks
+    /// one that follows the shuffle rules when there is any.
+    fn random_shuffled_index(
+        &self,
+        current_index: usize,
+        history: &ShuffleHistory,
+        new_round: bool,
+    ) -> Option<usize> {
+        let current_owner = self.tracks.get(current_index).map(|track| &track.owner);
+        let candidates: Vec<usize> = (0..self.tracks.len())
+            .filter(|i| self.is_enabled(*i))
+            .filter(|i| new_round || !history.played.contains(&self.tracks[*i].bvid))
+            .collect();
+        let mut rng = rand::thread_rng();
+        candidates
+            .iter()
+            .copied()
+            .filter(|i| {
+                let track = &self.tracks[*i];
+                let same_owner = Some(&track.owner) == current_owner;
+                let repeats_owner = history.rules.avoid_same_owner && same_owner;
+                !repeats_owner && !history.recent.contains(&track.bvid)
+            })
+            .choose(&mut rng)
+            .or_else(|| candidates.into_iter().choose(&mut rng))
+    }
+
+    /// The first enabled track from `index` on, wrapping around.
+    pub fn enabled_from(&self, index: usize) -> Result<usize, App> {
+        let len = self.tracks.len();
+        (0..len)
+            .map(|offset| (index + offset) % len)
+            .find(|i| self.is_enabled(*i))
+            .ok_or_else(|| App::DataParsing("All tracks are disabled".to_string()))
+    }
+
+    /// Index in `other` of the track at `index`, or of the first one after
+    /// it, wrapping around, that `other` still contains.
+    fn successor_in(&self, index: usize, other: &Playlist) -> Option<usize> {
+        let splice = self.splice_to(other);
+        let index = index.min(self.tracks.len());
+        (index..self.tracks.len())
+            .chain(0..index)
+            .find_map(|i| self.position_in(i, other, splice))
+    }
+
+    fn ensure_not_empty(&self) -> Result<(), App> {
+        if self.tracks.is_empty() {
+            return Err(App::DataParsing("Playlist is empty".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Index of the enabled track after the one at `current_index`, or `None`
+    /// once the end is reached with repeat off. Shuffling plays every track
+    /// before starting over, following the rules in `history`.
+    pub fn next_index(
+        &self,
+        current_index: usize,
+        play_mode: PlayMode,
+        history: &mut ShuffleHistory,
+    ) -> Result<Option<usize>, App> {
+        self.ensure_not_empty()?;
+        if play_mode.repeat == Repeat::One && self.is_enabled(current_index) {
+            return Ok(Some(current_index));
+        }
+        if play_mode.shuffle {
+            if let Some(track) = self.tracks.get(current_index) {
+                history.record(&track.bvid);
+            }
+            if let Some(index) = self.random_shuffled_index(current_index, history, false) {
+                return Ok(Some(index));
+            }
+            history.played.clear();
+            return match play_mode.repeat {
+                Repeat::Off => Ok(None),
+                _ => self
+                    .random_shuffled_index(current_index, history, true)
+                    .map(Some)
+                    .ok_or_else(|| App::DataParsing("All tracks are disabled".to_string())),
+            };
+        }
+        let next = (current_index + 1..self.tracks.len()).find(|i| self.is_enabled(*i));
+        match next {
+            Some(next) => Ok(Some(next)),
+            None if play_mode.repeat == Repeat::Off => Ok(None),
+            None => self.enabled_from(0).map(Some),
+        }
+    }
+
+    /// Whether moving on from `current_index` stops or starts over, every
+    /// track having been played. With `detached` the current index already
+    /// points at the track after the one playing.
+    fn ends_round(
+        &self,
+        current_index: usize,
+        play_mode: PlayMode,
+        history: &ShuffleHistory,
+        detached: bool,
+    ) -> bool {
+        if play_mode.repeat == Repeat::One {
+            return false;
+        }
+        if play_mode.shuffle {
+            let mut history = history.clone();
+            if let Some(track) = self.tracks.get(current_index) {
+                history.record(&track.bvid);
             }
-            PlayMode::Repeat => current_index,
+            return self
+                .random_shuffled_index(current_index, &history, false)
+                .is_none();
+        }
+        let first = if detached {
+            current_index
+        } else {
+            current_index + 1
         };
-        CURRENT_TRACK_INDEX.store(new_index, Ordering::SeqCst);
-        Ok(new_index)
+        !(first..self.tracks.len()).any(|i| self.is_enabled(i))
+    }
+
+    pub fn previous_index(&self, current_index: usize, play_mode: PlayMode) -> Result<usize, App> {
+        self.ensure_not_empty()?;
+        if play_mode.repeat == Repeat::One && self.is_enabled(current_index) {
+            return Ok(current_index);
+        }
+        if play_mode.shuffle {
+            return self.random_index();
+        }
+        let len = self.tracks.len();
+        (1..=len)
+            .map(|offset| (current_index % len + len - offset) % len)
+            .find(|i| self.is_enabled(*i))
+            .ok_or_else(|| App::DataParsing("All tracks are disabled".to_string()))
     }
 
-    pub fn move_to_previous_track(&mut self, play_mode: PlayMode) -> Result<usize, App> {
-        let current_index = CURRENT_TRACK_INDEX.load(Ordering::SeqCst);
-        let new_index = match play_mode {
-            PlayMode::Loop => {
-                if current_index == 0 {
-                    self.tracks.len() - 1
+    pub fn find_track_index(&self, bvid: &str) -> Option<usize> {
+        self.index.get(bvid).copied()
+    }
+
+    /// Index of the track of season `id` that comes right after the one at
+    /// `current_index`, or right before it when `forward` is false. A
+    /// current track outside the season is followed by its first track.
+    fn season_neighbor(&self, id: &str, current_index: usize, forward: bool) -> Option<usize> {
+        let current = self
+            .tracks
+            .get(current_index)
+            .and_then(|track| track.season.as_ref())
+            .filter(|season| season.id == id)
+            .map(|season| season.index);
+        let episodes = self.tracks.iter().enumerate().filter_map(|(i, track)| {
+            if track.disabled {
+                return None;
+            }
+            let season = track.season.as_ref().filter(|season| season.id == id)?;
+            Some((season.index, i))
+        });
+        match (current, forward) {
+            (None, _) => episodes.min(),
+            (Some(current), true) => episodes.filter(|(index, _)| *index > current).min(),
+            (Some(current), false) => episodes.filter(|(index, _)| *index < current).max(),
+        }
+        .map(|(_, i)| i)
+    }
+}
+
+/// Owns the in-memory playlist, the current track index and the store they
+/// are loaded from. Shared by the player tasks through an `Arc`.
+#[derive(Debug)]
+pub struct PlaylistManager {
+    playlist: RwLock<Playlist>,
+    current_index: AtomicUsize,
+    fingerprint: AtomicU64,
+    source: StdRwLock<(String, Arc<dyn PlaylistStore>)>,
+    /// The playing track after it was removed from the playlist, kept until
+    /// it is left; the current index then points at the track following it.
+    detached: Mutex<Option<Track>>,
+    /// Track taken from the `rsg later` queue that plays now; the current
+    /// index stays on the playlist track to come back to.
+    later: Mutex<Option<Track>>,
+    /// Season played through in order, regardless of the play mode.
+    season: Mutex<Option<String>>,
+    shuffle: Mutex<ShuffleHistory>,
+    /// Bvid of the shuffled pick to follow the current track, made early
+    /// so it can be announced.
+    upcoming: Mutex<Option<String>>,
+}
+
+/// What has been played while shuffling, and the rules for what comes next.
+#[derive(Clone, Debug, Default)]
+pub struct ShuffleHistory {
+    rules: ShuffleSettings,
+    /// Tracks already played in this round, by bvid.
+    played: HashSet<String>,
+    /// The last `rules.no_repeat_recent` tracks played, oldest first.
+    recent: VecDeque<String>,
+}
+
+impl ShuffleHistory {
+    fn record(&mut self, bvid: &str) {
+        self.played.insert(bvid.to_string());
+        if self.rules.no_repeat_recent == 0 {
+            return;
+        }
+        self.recent.retain(|recent| recent != bvid);
+        self.recent.push_back(bvid.to_string());
+        while self.recent.len() > self.rules.no_repeat_recent {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Records moving from `current` to `next`, a pick made on a copy of
+    /// this history, starting a new round like the pick did when `next` was
+    /// already played in this one.
+    fn advance(&mut self, current: Option<&str>, next: &str) {
+        if let Some(current) = current {
+            self.record(current);
+        }
+        if self.played.contains(next) {
+            self.played.clear();
+        }
+    }
+}
+
+impl PlaylistManager {
+    pub fn new(name: &str, store: Arc<dyn PlaylistStore>) -> Self {
+        PlaylistManager {
+            playlist: RwLock::new(Playlist::new(Vec::new())),
+            current_index: AtomicUsize::new(0),
+            fingerprint: AtomicU64::new(0),
+            source: StdRwLock::new((name.to_string(), store)),
+            detached: Mutex::new(None),
+            later: Mutex::new(None),
+            season: Mutex::new(None),
+            shuffle: Mutex::new(ShuffleHistory::default()),
+            upcoming: Mutex::new(None),
+        }
+    }
+
+    pub fn set_shuffle_rules(&self, rules: ShuffleSettings) {
+        self.shuffle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rules = rules;
+    }
+
+    fn store(&self) -> Arc<dyn PlaylistStore> {
+        Arc::clone(&self.source.read().unwrap_or_else(PoisonError::into_inner).1)
+    }
+
+    /// Name of the playlist currently loaded.
+    pub fn name(&self) -> String {
+        self.source
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+            .clone()
+    }
+
+    /// Replaces the playlist with the one called `name`, starting from its
+    /// first track.
+    pub async fn switch(&self, name: &str, store: Arc<dyn PlaylistStore>) -> Result<(), App> {
+        *self.source.write().unwrap_or_else(PoisonError::into_inner) = (name.to_string(), store);
+        self.load().await?;
+        self.set_current_index(0);
+        Ok(())
+    }
+
+    pub async fn load(&self) -> Result<(), App> {
+        log::info!("Loading playlist");
+        let store = self.store();
+        let fingerprint = store.fingerprint()?;
+        let playlist = Playlist::new(store.load()?);
+        *self.playlist.write().await = playlist; // Replace the old playlist with the new one
+        self.fingerprint.store(fingerprint, Ordering::SeqCst);
+        self.take_detached();
+        self.leave_later();
+        self.leave_season();
+        Ok(())
+    }
+
+    /// Reloads the playlist from the store, skipping the load entirely when the
+    /// store is unchanged and only replacing the tracks that changed.
+    ///
+    /// The current index follows the current track by position, so of
+    /// several copies of a video the one playing stays current. If it was
+    /// removed, the index moves to the first remaining track after it, and
+    /// with `keep_removed` the removed track stays current until it is left.
+    pub async fn reload(&self, keep_removed: bool) -> Result<PlaylistDiff, App> {
+        let store = self.store();
+        let fingerprint = store.fingerprint()?;
+        if fingerprint == self.fingerprint.load(Ordering::SeqCst) {
+            log::info!("Playlist unchanged, skipping reload");
+            return Ok(PlaylistDiff::default());
+        }
+        let new_playlist = Playlist::new(store.load()?);
+        let mut playlist = self.playlist.write().await;
+        let current_index = self.current_index();
+        let (current_track, new_index) = if let Some(track) = self.detached() {
+            // A removed track that is still playing may have been put back
+            let index = new_playlist.find_track_index(&track.bvid);
+            (Some(track), index)
+        } else {
+            let splice = playlist.splice_to(&new_playlist);
+            (
+                playlist.tracks.get(current_index).cloned(),
+                playlist.position_in(current_index, &new_playlist, splice),
+            )
+        };
+        let successor = playlist
+            .successor_in(current_index, &new_playlist)
+            .unwrap_or(0);
+        let mut diff = playlist.apply(new_playlist);
+        drop(playlist);
+        self.fingerprint.store(fingerprint, Ordering::SeqCst);
+        if let Some(track) = current_track {
+            if let Some(index) = new_index {
+                self.set_current_index(index);
+            } else {
+                self.set_current_index(successor);
+                // A track from the `rsg later` queue keeps playing either way
+                if keep_removed || self.later().is_some() {
+                    *self.detached.lock().unwrap_or_else(PoisonError::into_inner) = Some(track);
                 } else {
-                    current_index - 1
+                    diff.current_removed = true;
                 }
             }
-            PlayMode::Shuffle => {
-                let mut rng = rand::thread_rng();
-                (0..self.tracks.len())
-                    .choose(&mut rng)
-                    .ok_or_else(|| App::DataParsing("Failed to choose random track".to_string()))?
+        }
+        log::info!(
+            "Playlist reloaded: {} added, {} removed",
+            diff.added.len(),
+            diff.removed.len()
+        );
+        Ok(diff)
+    }
+
+    /// Stores `track` right after the current one and moves to it, for
+    /// radio mode.
+    pub async fn insert_after_current(&self, track: Track) -> Result<usize, App> {
+        let store = self.store();
+        self.leave_later();
+        let mut tracks = store.load()?;
+        // A removed track that is still playing is followed by the one at
+        // the current index
+        let position = if self.detached().is_some() {
+            self.current_index()
+        } else {
+            self.current_index() + 1
+        }
+        .min(tracks.len());
+        let bvid = track.bvid.clone();
+        tracks.insert(position, track);
+        store.save(&tracks)?;
+        self.reload(false).await?;
+        let index = self
+            .find_track_index(&bvid)
+            .await
+            .ok_or_else(|| App::DataParsing(format!("Track {bvid} was not stored")))?;
+        self.set_current_index(index);
+        Ok(index)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.playlist.read().await.tracks.len()
+    }
+
+    #[cfg_attr(not(feature = "mpd"), allow(dead_code))]
+    pub async fn tracks(&self) -> Vec<Track> {
+        self.playlist.read().await.tracks.clone()
+    }
+
+    /// Gives back memory left over from larger playlists loaded earlier.
+    pub async fn shrink_to_fit(&self) {
+        let mut playlist = self.playlist.write().await;
+        playlist.tracks.shrink_to_fit();
+        playlist.index.shrink_to_fit();
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current_index.load(Ordering::SeqCst)
+    }
+
+    /// Moves to the track at `index`, leaving a removed track that was still
+    /// playing.
+    pub fn set_current_index(&self, index: usize) {
+        self.current_index.store(index, Ordering::SeqCst);
+        self.take_detached();
+        self.upcoming
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    fn detached(&self) -> Option<Track> {
+        self.detached
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn take_detached(&self) -> Option<Track> {
+        self.detached
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    fn later(&self) -> Option<Track> {
+        self.later
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn take_later(&self) -> Option<Track> {
+        self.later
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Goes back to the playlist track the `rsg later` queue interrupted.
+    pub fn leave_later(&self) {
+        self.take_later();
+    }
+
+    /// Moves to the first track of the `rsg later` queue, removing it from
+    /// the queue. Returns `None` when the queue is empty.
+    pub fn start_later(&self) -> Result<Option<Track>, App> {
+        let store = storage::open_later()?;
+        let mut tracks = store.load()?;
+        if tracks.is_empty() {
+            return Ok(None);
+        }
+        let track = tracks.remove(0);
+        store.save(&tracks)?;
+        *self.later.lock().unwrap_or_else(PoisonError::into_inner) = Some(track.clone());
+        Ok(Some(track))
+    }
+
+    /// The track [`Self::start_later`] would move to.
+    fn peek_later() -> Option<Track> {
+        storage::open_later().ok()?.load().ok()?.into_iter().next()
+    }
+
+    /// Whether the track playing is the last of the playlist in `play_mode`,
+    /// so the `rsg later` queue plays before it stops or starts over.
+    async fn at_end(&self, play_mode: PlayMode) -> bool {
+        let playlist = self.playlist.read().await;
+        let current_index = self.current_index();
+        let in_season = self
+            .season
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .is_some_and(|id| playlist.season_neighbor(&id, current_index, true).is_some());
+        let history = self
+            .shuffle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        !in_season
+            && playlist.ends_round(
+                current_index,
+                play_mode,
+                &history,
+                self.detached().is_some(),
+            )
+    }
+
+    /// Moves past the current track if it is disabled, unless it is a
+    /// removed track or one from the `rsg later` queue that is still playing.
+    pub async fn skip_disabled(&self) -> Result<(), App> {
+        if self.detached().is_some() || self.later().is_some() {
+            return Ok(());
+        }
+        let current_index = self.current_index();
+        let index = self.playlist.read().await.enabled_from(current_index)?;
+        if index != current_index {
+            self.set_current_index(index);
+        }
+        Ok(())
+    }
+
+    pub async fn current_track(&self) -> Result<Track, App> {
+        if let Some(track) = self.later().or_else(|| self.detached()) {
+            return Ok(track);
+        }
+        self.playlist.read().await.get_track(self.current_index())
+    }
+
+    pub async fn find_track_index(&self, bvid: &str) -> Option<usize> {
+        self.playlist.read().await.find_track_index(bvid)
+    }
+
+    /// Moves to the first track of season `id` and keeps playing the season
+    /// in order until its last track or until another track is picked.
+    pub async fn start_season(&self, id: &str) -> Result<usize, App> {
+        let index = self
+            .playlist
+            .read()
+            .await
+            .season_neighbor(id, usize::MAX, true)
+            .ok_or_else(|| App::DataParsing(format!("No tracks of season {id}")))?;
+        self.set_current_index(index);
+        *self.season.lock().unwrap_or_else(PoisonError::into_inner) = Some(id.to_string());
+        Ok(index)
+    }
+
+    pub fn leave_season(&self) {
+        self.season
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    /// Moves to the neighbouring track of the season being played, leaving
+    /// the season at either end of it.
+    async fn move_in_season(&self, forward: bool) -> Option<usize> {
+        let id = self
+            .season
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()?;
+        let neighbor =
+            self.playlist
+                .read()
+                .await
+                .season_neighbor(&id, self.current_index(), forward);
+        match neighbor {
+            Some(index) => self.set_current_index(index),
+            None => self.leave_season(),
+        }
+        neighbor
+    }
+
+    /// Moves to the track that plays next, returning `None` and staying put
+    /// once the end is reached with repeat off. The `rsg later` queue plays
+    /// through once the playlist is done, before it stops or starts over.
+    pub async fn move_to_next_track(&self, play_mode: PlayMode) -> Result<Option<usize>, App> {
+        let left_later = self.take_later().is_some();
+        if (left_later || self.at_end(play_mode).await) && self.start_later()?.is_some() {
+            return Ok(Some(self.current_index()));
+        }
+        // The current index already points at the track after a removed one
+        if self.take_detached().is_some() && !play_mode.shuffle {
+            let index = self.current_index();
+            let index = self.playlist.read().await.enabled_from(index)?;
+            self.set_current_index(index);
+            return Ok(Some(index));
+        }
+        if play_mode.repeat != Repeat::One {
+            if let Some(index) = self.move_in_season(true).await {
+                return Ok(Some(index));
+            }
+        }
+        let playlist = self.playlist.read().await;
+        let current_index = self.current_index();
+        let upcoming = self.upcoming_index(&playlist, play_mode, false);
+        let mut history = self.shuffle.lock().unwrap_or_else(PoisonError::into_inner);
+        let new_index = match upcoming {
+            Some(index) => {
+                let current = playlist.tracks.get(current_index).map(|t| t.bvid.as_str());
+                history.advance(current, &playlist.tracks[index].bvid);
+                Some(index)
             }
-            PlayMode::Repeat => current_index,
+            None => playlist.next_index(current_index, play_mode, &mut history)?,
         };
-        CURRENT_TRACK_INDEX.store(new_index, Ordering::SeqCst);
+        drop(history);
+        drop(playlist);
+        if let Some(index) = new_index {
+            self.set_current_index(index);
+        }
         Ok(new_index)
     }
 
-    pub fn find_track_index(&self, bvid: &str) -> Option<usize> {
-        self.tracks.iter().position(|track| track.bvid == bvid)
+    /// The track [`Self::move_to_next_track`] would move to, without
+    /// moving. A shuffled pick is made now and kept until it is played or
+    /// another track is picked.
+    pub async fn next_track(&self, play_mode: PlayMode) -> Option<Track> {
+        if self.later().is_some() || self.at_end(play_mode).await {
+            if let Some(track) = Self::peek_later() {
+                return Some(track);
+            }
+        }
+        let playlist = self.playlist.read().await;
+        let current_index = self.current_index();
+        let season = self
+            .season
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .filter(|_| play_mode.repeat != Repeat::One);
+        let index = if self.detached().is_some() && !play_mode.shuffle {
+            playlist.enabled_from(current_index).ok()
+        } else if let Some(index) =
+            season.and_then(|id| playlist.season_neighbor(&id, current_index, true))
+        {
+            Some(index)
+        } else if let Some(index) = self.upcoming_index(&playlist, play_mode, true) {
+            Some(index)
+        } else {
+            let mut history = ShuffleHistory::default();
+            playlist
+                .next_index(current_index, play_mode, &mut history)
+                .ok()
+                .flatten()
+        };
+        index.and_then(|index| playlist.get_track(index).ok())
     }
-}
 
-pub static PLAYLIST: LazyLock<RwLock<Result<Playlist, App>>> =
-    LazyLock::new(|| RwLock::new(Ok(Playlist { tracks: Vec::new() })));
-pub static CURRENT_TRACK_INDEX: LazyLock<AtomicUsize> = LazyLock::new(|| AtomicUsize::new(0));
+    /// Index of the kept shuffled pick while shuffling, making one first
+    /// when `pick` is set and there is none.
+    fn upcoming_index(
+        &self,
+        playlist: &Playlist,
+        play_mode: PlayMode,
+        pick: bool,
+    ) -> Option<usize> {
+        if !play_mode.shuffle || play_mode.repeat == Repeat::One {
+            return None;
+        }
+        let mut upcoming = self.upcoming.lock().unwrap_or_else(PoisonError::into_inner);
+        let kept = upcoming
+            .as_deref()
+            .and_then(|bvid| playlist.find_track_index(bvid))
+            .filter(|index| playlist.is_enabled(*index));
+        if kept.is_some() || !pick {
+            return kept;
+        }
+        // A copy, the real history only changes once the pick is played
+        let mut history = self
+            .shuffle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let index = playlist
+            .next_index(self.current_index(), play_mode, &mut history)
+            .ok()
+            .flatten()?;
+        *upcoming = Some(playlist.tracks[index].bvid.clone());
+        Some(index)
+    }
 
-pub async fn load(file_path: &str) -> Result<(), App> {
-    let playlist = Playlist::load_from_file(file_path).await?;
-    let mut playlist_lock = PLAYLIST.write().await;
-    *playlist_lock = Ok(playlist); // Replace the old playlist with the new one
-    Ok(())
+    pub async fn move_to_previous_track(&self, play_mode: PlayMode) -> Result<usize, App> {
+        self.take_detached();
+        // Back from the `rsg later` queue to the track it interrupted
+        if self.take_later().is_some() {
+            return Ok(self.current_index());
+        }
+        if let Some(index) = self.move_in_season(false).await {
+            return Ok(index);
+        }
+        let new_index = self
+            .playlist
+            .read()
+            .await
+            .previous_index(self.current_index(), play_mode)?;
+        self.set_current_index(new_index);
+        Ok(new_index)
+    }
 }
 
-pub async fn get_current_track() -> Result<Track, App> {
-    let playlist = PLAYLIST.read().await;
-    let playlist = playlist.as_ref().map_err(std::clone::Clone::clone)?;
-    let index = CURRENT_TRACK_INDEX.load(Ordering::SeqCst);
-    playlist.get_current_track(index)
+/// Order and repetition of playback, like MPRIS `Shuffle` and
+/// `LoopStatus`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayMode {
+    pub shuffle: bool,
+    pub repeat: Repeat,
 }
 
-pub async fn move_to_next_track(play_mode: PlayMode) -> Result<usize, App> {
-    let mut playlist = PLAYLIST.write().await;
-    let playlist = playlist.as_mut().map_err(|e| e.clone())?;
-    playlist.move_to_next_track(play_mode)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    /// Stop after the last track.
+    Off,
+    /// Play the current track again.
+    One,
+    /// Start over after the last track.
+    All,
 }
 
-pub async fn move_to_previous_track(play_mode: PlayMode) -> Result<usize, App> {
-    let mut playlist = PLAYLIST.write().await;
-    let playlist = playlist.as_mut().map_err(|e| e.clone())?;
-    playlist.move_to_previous_track(play_mode)
+impl Default for PlayMode {
+    fn default() -> Self {
+        PlayMode {
+            shuffle: false,
+            repeat: Repeat::All,
+        }
+    }
 }
 
-pub async fn set_current_track_index(index: usize) -> Result<(), App> {
-    CURRENT_TRACK_INDEX.store(index, Ordering::SeqCst);
-    Ok(())
+impl PlayMode {
+    /// Parses the form written by `name`, such as `shuffle-off`, or one of
+    /// the older `Loop`, `Shuffle` and `Repeat`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        let (shuffle, repeat) = match mode {
+            "Loop" => (false, Repeat::All),
+            "Shuffle" => (true, Repeat::All),
+            "Repeat" => (false, Repeat::One),
+            _ => {
+                let (order, repeat) = mode.split_once('-')?;
+                let shuffle = match order {
+                    "sequential" => false,
+                    "shuffle" => true,
+                    _ => return None,
+                };
+                (shuffle, Repeat::parse(repeat)?)
+            }
+        };
+        Some(PlayMode { shuffle, repeat })
+    }
+
+    pub fn name(self) -> String {
+        let order = if self.shuffle {
+            "shuffle"
+        } else {
+            "sequential"
+        };
+        format!("{order}-{}", self.repeat.as_str())
+    }
+
+    /// The closest of the older `Loop`, `Shuffle` and `Repeat`.
+    pub fn legacy_name(self) -> &'static str {
+        match (self.shuffle, self.repeat) {
+            (_, Repeat::One) => "Repeat",
+            (true, _) => "Shuffle",
+            (false, _) => "Loop",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum PlayMode {
-    Loop,
-    Shuffle,
-    Repeat,
+impl Repeat {
+    pub fn parse(repeat: &str) -> Option<Self> {
+        match repeat {
+            "off" => Some(Repeat::Off),
+            "one" => Some(Repeat::One),
+            "all" => Some(Repeat::All),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Repeat::Off => "off",
+            Repeat::One => "one",
+            Repeat::All => "all",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlayMode, Playlist, PlaylistManager, Repeat, Track};
+    use rosesong_core::error::Core;
+    use rosesong_core::storage::{PlaylistInfo, PlaylistStore};
+    use std::sync::{Arc, Mutex};
+
+    /// Playlist kept in memory, whose fingerprint changes on every save.
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        tracks: Mutex<(Vec<Track>, u64)>,
+    }
+
+    impl PlaylistStore for MemoryStore {
+        fn load(&self) -> Result<Vec<Track>, Core> {
+            Ok(self.tracks.lock().unwrap().0.clone())
+        }
+
+        fn save(&self, tracks: &[Track]) -> Result<(), Core> {
+            let mut stored = self.tracks.lock().unwrap();
+            *stored = (tracks.to_vec(), stored.1 + 1);
+            Ok(())
+        }
+
+        fn fingerprint(&self) -> Result<u64, Core> {
+            Ok(self.tracks.lock().unwrap().1)
+        }
+
+        fn info(&self) -> Result<PlaylistInfo, Core> {
+            Ok(PlaylistInfo::default())
+        }
+
+        fn set_info(&self, _info: &PlaylistInfo) -> Result<(), Core> {
+            Ok(())
+        }
+    }
+
+    fn tracks(bvids: &[&str]) -> Vec<Track> {
+        bvids
+            .iter()
+            .map(|bvid| Track {
+                bvid: (*bvid).to_string(),
+                cid: "1".to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// A manager of the playlist `bvids` playing the track at `current`,
+    /// with the store it reloads from.
+    async fn manager(bvids: &[&str], current: usize) -> (PlaylistManager, Arc<MemoryStore>) {
+        let store = Arc::new(MemoryStore::default());
+        store.save(&tracks(bvids)).unwrap();
+        let manager = PlaylistManager::new("test", Arc::clone(&store) as Arc<dyn PlaylistStore>);
+        manager.load().await.unwrap();
+        manager.set_current_index(current);
+        (manager, store)
+    }
+
+    async fn current_bvid(manager: &PlaylistManager) -> String {
+        manager.current_track().await.unwrap().bvid
+    }
+
+    const SEQUENTIAL: PlayMode = PlayMode {
+        shuffle: false,
+        repeat: Repeat::All,
+    };
+
+    #[tokio::test]
+    async fn removing_the_playing_track_moves_on_with_the_next_policy() {
+        let (manager, store) = manager(&["a", "b", "c"], 1).await;
+        store.save(&tracks(&["a", "c"])).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(diff.current_removed);
+        assert_eq!(diff.removed, ["b"]);
+        assert_eq!(manager.current_index(), 1);
+        assert_eq!(current_bvid(&manager).await, "c");
+    }
+
+    #[tokio::test]
+    async fn removing_the_playing_track_lets_it_finish_with_the_finish_policy() {
+        let (manager, store) = manager(&["a", "b", "c"], 1).await;
+        store.save(&tracks(&["a", "c"])).unwrap();
+        let diff = manager.reload(true).await.unwrap();
+        assert!(!diff.current_removed);
+        assert_eq!(current_bvid(&manager).await, "b");
+
+        // Its end leads to the track that followed it
+        assert_eq!(
+            manager.move_to_next_track(SEQUENTIAL).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(current_bvid(&manager).await, "c");
+    }
+
+    #[tokio::test]
+    async fn removing_the_last_track_wraps_around() {
+        let (manager, store) = manager(&["a", "b", "c"], 2).await;
+        store.save(&tracks(&["a", "b"])).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(diff.current_removed);
+        assert_eq!(current_bvid(&manager).await, "a");
+    }
+
+    #[tokio::test]
+    async fn other_removals_keep_the_playing_track() {
+        let (manager, store) = manager(&["a", "b", "c"], 2).await;
+        store.save(&tracks(&["b", "c"])).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(!diff.current_removed);
+        assert_eq!(manager.current_index(), 1);
+        assert_eq!(current_bvid(&manager).await, "c");
+    }
+
+    #[tokio::test]
+    async fn clearing_the_playlist_leaves_nothing_to_play() {
+        let (manager, store) = manager(&["a", "b"], 1).await;
+        store.save(&[]).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(diff.current_removed);
+        assert_eq!(manager.len().await, 0);
+        assert!(manager.current_track().await.is_err());
+        assert!(manager.move_to_previous_track(SEQUENTIAL).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_cleared_playlist_still_finishes_the_playing_track_with_the_finish_policy() {
+        let (manager, store) = manager(&["a", "b"], 1).await;
+        store.save(&[]).unwrap();
+        manager.reload(true).await.unwrap();
+        assert_eq!(manager.len().await, 0);
+        assert_eq!(current_bvid(&manager).await, "b");
+
+        // Going idle drops it
+        manager.set_current_index(0);
+        assert!(manager.current_track().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn an_unchanged_store_is_not_reloaded() {
+        let (manager, _store) = manager(&["a", "b"], 1).await;
+        let diff = manager.reload(false).await.unwrap();
+        assert!(diff.is_empty() && !diff.current_removed);
+        assert_eq!(current_bvid(&manager).await, "b");
+    }
+
+    #[test]
+    fn successor_is_the_first_remaining_track_from_the_index_on() {
+        let old = Playlist::new(tracks(&["a", "b", "c", "d"]));
+        let new = Playlist::new(tracks(&["d", "a"]));
+        assert_eq!(old.successor_in(1, &new), Some(0));
+        assert_eq!(old.successor_in(3, &new), Some(0));
+        assert_eq!(old.successor_in(0, &new), Some(1));
+        assert_eq!(old.successor_in(9, &new), Some(1));
+        assert_eq!(old.successor_in(1, &Playlist::new(Vec::new())), None);
+    }
+
+    #[tokio::test]
+    async fn the_playing_copy_of_a_duplicated_track_stays_current() {
+        let (manager, store) = manager(&["a", "b", "a"], 2).await;
+        store.save(&tracks(&["a", "b", "c", "a"])).unwrap();
+        manager.reload(false).await.unwrap();
+        assert_eq!(manager.current_index(), 3);
+
+        store.save(&tracks(&["b", "c", "a"])).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(!diff.current_removed);
+        assert_eq!(manager.current_index(), 2);
+
+        // Moved to the front
+        store.save(&tracks(&["a", "b", "c"])).unwrap();
+        let diff = manager.reload(false).await.unwrap();
+        assert!(!diff.current_removed);
+        assert_eq!(manager.current_index(), 0);
+    }
+
+    #[test]
+    fn applying_replaces_only_the_tracks_that_changed() {
+        let mut playlist = Playlist::new(tracks(&["a", "b", "c", "d"]));
+        let new = Playlist::new(tracks(&["a", "x", "d"]));
+        let splice = playlist.splice_to(&new);
+        assert_eq!((splice.start, splice.old_end, splice.new_end), (1, 3, 2));
+        let diff = playlist.apply(new);
+        assert_eq!(diff.added, ["x"]);
+        assert_eq!(diff.removed, ["b", "c"]);
+        assert_eq!(playlist.tracks, tracks(&["a", "x", "d"]));
+        assert_eq!(playlist.find_track_index("d"), Some(2));
+    }
+
+    #[test]
+    fn a_duplicated_bvid_resolves_to_its_first_occurrence() {
+        let playlist = Playlist::new(tracks(&["a", "b", "a"]));
+        assert_eq!(playlist.find_track_index("a"), Some(0));
+        assert_eq!(playlist.find_track_index("b"), Some(1));
+    }
+
+    #[test]
+    fn an_empty_playlist_has_no_next_or_previous_track() {
+        let playlist = Playlist::new(Vec::new());
+        let mut history = super::ShuffleHistory::default();
+        assert!(playlist.next_index(0, SEQUENTIAL, &mut history).is_err());
+        assert!(playlist.previous_index(0, SEQUENTIAL).is_err());
+    }
 }
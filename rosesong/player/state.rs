@@ -0,0 +1,172 @@
+/// Playback state of the player, kept apart from the pipeline and the playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    /// The playlist is empty, so there is nothing to play.
+    Idle,
+    Stopped,
+    Loading,
+    Playing,
+    Paused,
+}
+
+/// Something that happened to the player and may move it to another state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A track started loading (fetching the URL and building the pipeline).
+    Load,
+    /// The pipeline reached `Playing` for the loaded track.
+    Loaded,
+    /// The track was loaded to stay paused, as it was before reloading.
+    LoadedPaused,
+    /// Fetching or starting the track failed.
+    LoadFailed,
+    Pause,
+    Resume,
+    Stop,
+    /// The current track reached its end.
+    EndOfStream,
+    /// The last track was removed from the playlist.
+    Emptied,
+    /// Tracks were added to the empty playlist.
+    Filled,
+}
+
+impl PlaybackState {
+    /// Returns the state reached by handling `event`, or `None` if the event
+    /// is not valid in the current state.
+    pub fn on(self, event: Event) -> Option<PlaybackState> {
+        use PlaybackState::{Idle, Loading, Paused, Playing, Stopped};
+        match (self, event) {
+            (_, Event::Load) => Some(Loading),
+            (Loading, Event::Loaded) | (Paused, Event::Resume) => Some(Playing),
+            (Playing, Event::Pause) | (Loading, Event::LoadedPaused) => Some(Paused),
+            (Loading, Event::LoadFailed)
+            | (Loading | Playing | Paused, Event::Stop)
+            | (Playing, Event::EndOfStream)
+            | (Idle, Event::Filled) => Some(Stopped),
+            (Stopped | Loading | Playing | Paused, Event::Emptied) => Some(Idle),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaybackState::Idle => "idle",
+            PlaybackState::Stopped => "stopped",
+            PlaybackState::Loading => "loading",
+            PlaybackState::Playing => "playing",
+            PlaybackState::Paused => "paused",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use super::Event::{
+        Emptied, EndOfStream, Filled, Load, LoadFailed, Loaded, LoadedPaused, Pause, Resume, Stop,
+    };
+    use super::PlaybackState::{self, Idle, Loading, Paused, Playing, Stopped};
+
+    const STATES: [PlaybackState; 5] = [Idle, Stopped, Loading, Playing, Paused];
+    const EVENTS: [Event; 10] = [
+        Load,
+        Loaded,
+        LoadedPaused,
+        LoadFailed,
+        Pause,
+        Resume,
+        Stop,
+        EndOfStream,
+        Emptied,
+        Filled,
+    ];
+
+    /// The state reached from each state in `STATES` on each event in
+    /// `EVENTS`, in the same order; `None` marks a rejected event.
+    const TRANSITIONS: [[Option<PlaybackState>; 10]; 5] = [
+        // Load, Loaded, LoadedPaused, LoadFailed, Pause, Resume, Stop, EndOfStream, Emptied, Filled
+        [
+            Some(Loading),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(Stopped),
+        ],
+        [
+            Some(Loading),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(Idle),
+            None,
+        ],
+        [
+            Some(Loading),
+            Some(Playing),
+            Some(Paused),
+            Some(Stopped),
+            None,
+            None,
+            Some(Stopped),
+            None,
+            Some(Idle),
+            None,
+        ],
+        [
+            Some(Loading),
+            None,
+            None,
+            None,
+            Some(Paused),
+            None,
+            Some(Stopped),
+            Some(Stopped),
+            Some(Idle),
+            None,
+        ],
+        [
+            Some(Loading),
+            None,
+            None,
+            None,
+            None,
+            Some(Playing),
+            Some(Stopped),
+            None,
+            Some(Idle),
+            None,
+        ],
+    ];
+
+    #[test]
+    fn every_transition_matches_the_table() {
+        for (state, row) in STATES.into_iter().zip(TRANSITIONS) {
+            for (event, expected) in EVENTS.into_iter().zip(row) {
+                assert_eq!(
+                    state.on(event),
+                    expected,
+                    "{} on {:?}",
+                    state.as_str(),
+                    event
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_full_playback_cycle_is_accepted() {
+        let events = [Filled, Load, Loaded, Pause, Resume, EndOfStream, Emptied];
+        let state = events.into_iter().try_fold(Idle, PlaybackState::on);
+        assert_eq!(state, Some(Idle));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::player::{Audio, Command};
+use chrono::{Datelike, Local, NaiveTime, Weekday};
+use log::{error, info, warn};
+use rosesong_core::settings::{ScheduleRule, Settings};
+use rosesong_core::storage::{self, DEFAULT_PLAYLIST};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Switches to the playlist of the first matching schedule rule, or back to
+/// the default playlist when no rule matches, until the stop signal fires.
+pub async fn run_scheduler(
+    settings: Settings,
+    audio: Audio,
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) {
+    let mut stop_receiver = stop_signal.subscribe();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut last_target = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop_receiver.changed() => break,
+        }
+
+        let now = Local::now();
+        let target = active_playlist(&settings.schedule, now.weekday(), now.time())
+            .unwrap_or(DEFAULT_PLAYLIST)
+            .to_string();
+        // Only act when the schedule itself changes, so a playlist picked by
+        // hand stays until the next rule boundary.
+        if last_target.as_ref() == Some(&target) {
+            continue;
+        }
+        last_target = Some(target.clone());
+        if audio.status().await.playlist == target {
+            continue;
+        }
+
+        match storage::open_named(&settings.storage, &target) {
+            Ok(store) => {
+                info!("Schedule switching to playlist {}", target);
+                if command_sender
+                    .send(Command::SwitchPlaylist(target, Arc::from(store)))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(e) => error!("Failed to open scheduled playlist {}: {}", target, e),
+        }
+    }
+}
+
+fn active_playlist(rules: &[ScheduleRule], weekday: Weekday, time: NaiveTime) -> Option<&str> {
+    rules
+        .iter()
+        .find(|rule| rule_matches(rule, weekday, time))
+        .map(|rule| rule.playlist.as_str())
+}
+
+fn rule_matches(rule: &ScheduleRule, weekday: Weekday, time: NaiveTime) -> bool {
+    let (Some(start), Some(end)) = (parse_time(&rule.start), parse_time(&rule.end)) else {
+        warn!("Ignoring schedule rule with invalid time: {:?}", rule);
+        return false;
+    };
+    let day_matches = rule.days.is_empty()
+        || rule
+            .days
+            .iter()
+            .any(|day| day.parse::<Weekday>().is_ok_and(|day| day == weekday));
+    let time_matches = if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    };
+    day_matches && time_matches
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
@@ -0,0 +1,86 @@
+use crate::error::App;
+use crate::player::{Audio, Command};
+use futures_util::StreamExt;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use zbus::zvariant::OwnedFd;
+use zbus::{proxy, Connection};
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PAUSE_POLL_ATTEMPTS: u32 = 20;
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Login1Manager {
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Pauses playback before the system suspends and, if it was playing,
+/// reloads the track and continues after resume, since the stream has
+/// usually been cut off by then. Runs until the stop signal fires.
+pub async fn run_sleep_listener(
+    audio: Audio,
+    resume: bool,
+    command_sender: mpsc::Sender<Command>,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let connection = Connection::system().await?;
+    let login = Login1ManagerProxy::new(&connection).await?;
+    let mut sleep_signals = login.receive_prepare_for_sleep().await?;
+    // A delay lock holds off the suspend until the track is paused
+    let mut inhibitor = Some(take_inhibitor(&login).await?);
+    let mut was_playing = false;
+    info!("Listening for system sleep");
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            Some(signal) = sleep_signals.next() => {
+                if signal.args()?.start {
+                    info!("System is going to sleep, pausing");
+                    was_playing = audio.status().await.state == "playing";
+                    command_sender.send(Command::Pause).await?;
+                    for _ in 0..PAUSE_POLL_ATTEMPTS {
+                        if !was_playing || audio.is_paused().await {
+                            break;
+                        }
+                        tokio::time::sleep(PAUSE_POLL_INTERVAL).await;
+                    }
+                    inhibitor = None;
+                } else {
+                    info!("System woke up");
+                    command_sender.send(Command::ReleasePaused).await?;
+                    if resume && was_playing {
+                        command_sender.send(Command::Play).await?;
+                    }
+                    match take_inhibitor(&login).await {
+                        Ok(fd) => inhibitor = Some(fd),
+                        Err(e) => warn!("Failed to take sleep inhibitor: {}", e),
+                    }
+                }
+            }
+            _ = stop_receiver.changed() => break,
+        }
+    }
+
+    drop(inhibitor);
+    Ok(())
+}
+
+async fn take_inhibitor(login: &Login1ManagerProxy<'_>) -> Result<OwnedFd, App> {
+    Ok(login
+        .inhibit(
+            "sleep",
+            "rosesong",
+            "Pause playback before suspend",
+            "delay",
+        )
+        .await?)
+}
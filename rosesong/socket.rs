@@ -0,0 +1,101 @@
+use crate::access::AccessPolicy;
+use crate::error::App;
+use crate::transport::{DefaultTransport, Peer, Transport};
+use futures_util::StreamExt;
+use log::{error, info};
+use rosesong_core::ipc::{
+    Request, RpcRequest, RpcResponse, PARSE_ERROR, PERMISSION_DENIED, SERVER_ERROR,
+};
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::task;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+
+/// Longest request a client may send; the connection is closed on longer
+/// ones, so nobody can make the daemon buffer an endless line.
+const MAX_REQUEST_LENGTH: usize = 64 * 1024;
+
+/// Something that can answer control requests arriving over the socket.
+pub trait RequestHandler: Clone + Send + Sync + 'static {
+    fn handle(
+        &self,
+        request: Request,
+    ) -> impl Future<Output = Result<serde_json::Value, String>> + Send;
+}
+
+/// Serves line-delimited JSON-RPC requests on the platform's control
+/// transport until the stop signal fires, refusing those `access` does not
+/// allow.
+pub async fn run_socket_server<H: RequestHandler>(
+    handler: H,
+    access: AccessPolicy,
+    stop_signal: watch::Sender<()>,
+) -> Result<(), App> {
+    let mut transport = DefaultTransport::bind().await?;
+
+    let mut stop_receiver = stop_signal.subscribe();
+    loop {
+        tokio::select! {
+            accepted = transport.accept() => {
+                let stream = accepted?;
+                let peer = DefaultTransport::peer(&stream);
+                let handler = handler.clone();
+                let access = access.clone();
+                task::spawn(async move {
+                    if let Err(e) = serve_connection(stream, peer, &access, handler).await {
+                        error!("Control socket connection error: {}", e);
+                    }
+                });
+            }
+            _ = stop_receiver.changed() => {
+                info!("Stop signal received, shutting down control socket...");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn serve_connection<S, H>(
+    stream: S,
+    peer: Option<Peer>,
+    access: &AccessPolicy,
+    handler: H,
+) -> Result<(), App>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: RequestHandler,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_REQUEST_LENGTH));
+    while let Some(line) = lines.next().await {
+        let line = match line {
+            Ok(line) => line,
+            Err(LinesCodecError::Io(e)) => return Err(e.into()),
+            Err(LinesCodecError::MaxLineLengthExceeded) => {
+                return Err(App::DataParsing(format!(
+                    "request longer than {MAX_REQUEST_LENGTH} bytes"
+                )))
+            }
+        };
+        let response = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(rpc) if !access.allows(peer, &rpc.request) => RpcResponse::error(
+                Some(rpc.id),
+                PERMISSION_DENIED,
+                "Permission denied: this user may only query the player".to_string(),
+            ),
+            Ok(rpc) => match handler.handle(rpc.request).await {
+                Ok(result) => RpcResponse::result(rpc.id, result),
+                Err(message) => RpcResponse::error(Some(rpc.id), SERVER_ERROR, message),
+            },
+            Err(e) => RpcResponse::error(None, PARSE_ERROR, e.to_string()),
+        };
+        let mut response =
+            serde_json::to_string(&response).map_err(|e| App::DataParsing(e.to_string()))?;
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
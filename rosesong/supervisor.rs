@@ -0,0 +1,27 @@
+use log::error;
+use std::future::Future;
+use std::time::Duration;
+use tokio::task;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs the task built by `make`, building and running it again whenever it
+/// panics, so a single bad command or track cannot leave the daemon running
+/// but unresponsive. A task that returns is not restarted.
+pub fn supervise<F, Fut>(name: &'static str, make: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    task::spawn(async move {
+        loop {
+            match task::spawn(make()).await {
+                Err(e) if e.is_panic() => {
+                    error!("{} panicked, restarting it: {}", name, e);
+                    tokio::time::sleep(RESTART_DELAY).await;
+                }
+                _ => break,
+            }
+        }
+    });
+}
@@ -0,0 +1,129 @@
+use crate::error::App;
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Who is on the other end of a connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Peer {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Local transport the control server accepts connections on: a Unix domain
+/// socket on Unix-like systems and a named pipe on Windows.
+pub trait Transport: Sized + Send {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Starts listening, failing if another rosesong instance already is.
+    fn bind() -> impl Future<Output = Result<Self, App>> + Send;
+
+    fn accept(&mut self) -> impl Future<Output = Result<Self::Stream, App>> + Send;
+
+    /// The user behind `stream`, where the platform tells.
+    fn peer(stream: &Self::Stream) -> Option<Peer>;
+}
+
+#[cfg(unix)]
+pub use unix::UnixSocketTransport as DefaultTransport;
+#[cfg(windows)]
+pub use windows::NamedPipeTransport as DefaultTransport;
+
+#[cfg(unix)]
+mod unix {
+    use super::{Peer, Transport};
+    use crate::error::App;
+    use log::info;
+    use rosesong_core::ipc;
+    use std::path::PathBuf;
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub struct UnixSocketTransport {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Transport for UnixSocketTransport {
+        type Stream = UnixStream;
+
+        async fn bind() -> Result<Self, App> {
+            let path = ipc::socket_path()?;
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(App::Io(format!(
+                    "Another rosesong instance is listening on {}",
+                    path.display()
+                )));
+            }
+            let _ = std::fs::remove_file(&path);
+            let shared = ipc::is_socket_shared();
+            if let (true, Some(dir)) = (shared, path.parent()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            // Anyone may connect to a shared socket, what they may do is
+            // checked per request
+            #[cfg(target_os = "linux")]
+            if shared {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))?;
+            }
+            info!("Listening for control requests on {}", path.display());
+            Ok(UnixSocketTransport { listener, path })
+        }
+
+        async fn accept(&mut self) -> Result<UnixStream, App> {
+            let (stream, _) = self.listener.accept().await?;
+            Ok(stream)
+        }
+
+        fn peer(stream: &UnixStream) -> Option<Peer> {
+            let credentials = stream.peer_cred().ok()?;
+            Some(Peer {
+                uid: credentials.uid(),
+                gid: credentials.gid(),
+            })
+        }
+    }
+
+    impl Drop for UnixSocketTransport {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{Peer, Transport};
+    use crate::error::App;
+    use log::info;
+    use rosesong_core::ipc::PIPE_NAME;
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+
+    pub struct NamedPipeTransport {
+        next: NamedPipeServer,
+    }
+
+    impl Transport for NamedPipeTransport {
+        type Stream = NamedPipeServer;
+
+        async fn bind() -> Result<Self, App> {
+            let next = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(PIPE_NAME)
+                .map_err(|e| App::Io(format!("Another rosesong instance may be running: {e}")))?;
+            info!("Listening for control requests on {}", PIPE_NAME);
+            Ok(NamedPipeTransport { next })
+        }
+
+        async fn accept(&mut self) -> Result<NamedPipeServer, App> {
+            self.next.connect().await?;
+            let connected =
+                std::mem::replace(&mut self.next, ServerOptions::new().create(PIPE_NAME)?);
+            Ok(connected)
+        }
+
+        fn peer(_stream: &NamedPipeServer) -> Option<Peer> {
+            None
+        }
+    }
+}
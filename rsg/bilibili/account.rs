@@ -0,0 +1,134 @@
+use super::video_info::ApiResponse;
+use crate::error::App;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client;
+use rosesong_core::bilibili::limiter;
+use rosesong_core::settings::NetworkSettings;
+use serde::Deserialize;
+
+const NAV_API_URL: &str = "https://api.bilibili.com/x/web-interface/nav";
+const FOLDERS_API_URL: &str = "https://api.bilibili.com/x/v3/fav/folder/created/list-all";
+const FAVORITE_API_URL: &str = "https://api.bilibili.com/x/v3/fav/resource/deal";
+const LIKE_API_URL: &str = "https://api.bilibili.com/x/web-interface/archive/like";
+const COIN_API_URL: &str = "https://api.bilibili.com/x/web-interface/coin/add";
+
+/// Resource type of videos in the favorites API.
+const VIDEO_TYPE: u8 = 2;
+
+#[derive(Deserialize)]
+struct Nav {
+    #[serde(rename = "isLogin")]
+    is_login: bool,
+    #[serde(default)]
+    mid: u64,
+}
+
+/// A favorites folder of the logged-in user.
+#[derive(Deserialize)]
+pub struct Folder {
+    pub id: u64,
+    pub title: String,
+    pub media_count: u64,
+    /// Whether the video the folders were listed for is already in it.
+    #[serde(default)]
+    pub fav_state: u8,
+}
+
+#[derive(Deserialize)]
+struct FolderList {
+    #[serde(default)]
+    list: Option<Vec<Folder>>,
+}
+
+/// A Bilibili account, logged in through the `Cookie` set in the network
+/// headers of the settings. Its `bili_jct` cookie is the csrf token the
+/// write APIs ask for.
+pub struct Account {
+    client: Client,
+    csrf: String,
+}
+
+impl Account {
+    pub fn from_settings(network: &NetworkSettings) -> Result<Self, App> {
+        let csrf = network
+            .csrf_token()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                App::InvalidInput(
+                    "需要登录 B 站：请在 settings.toml 的 [network.headers] 中设置包含 SESSDATA 和 bili_jct 的 Cookie"
+                        .to_string(),
+                )
+            })?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in network.request_headers() {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| App::InvalidInput(format!("无效的请求头 {name}: {e}")))?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| App::InvalidInput(format!("无效的请求头 {name}: {e}")))?;
+            headers.insert(name, value);
+        }
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok(Account { client, csrf })
+    }
+
+    /// Id of the logged-in user, failing when the cookie has expired.
+    async fn mid(&self) -> Result<u64, App> {
+        let nav: ApiResponse<Nav> = limiter::get(&self.client, NAV_API_URL).await?;
+        match nav.into_data() {
+            Ok(nav) if nav.is_login => Ok(nav.mid),
+            _ => Err(App::InvalidInput(
+                "B 站登录已失效，请更新 settings.toml 中的 Cookie".to_string(),
+            )),
+        }
+    }
+
+    /// Favorites folders of the user, the default one first, each telling
+    /// whether the video `aid` is in it.
+    pub async fn folders(&self, aid: u64) -> Result<Vec<Folder>, App> {
+        let mid = self.mid().await?;
+        let url = format!("{FOLDERS_API_URL}?up_mid={mid}&type={VIDEO_TYPE}&rid={aid}");
+        let response: ApiResponse<FolderList> = limiter::get(&self.client, &url).await?;
+        Ok(response.into_data()?.list.unwrap_or_default())
+    }
+
+    /// Adds the video `aid` to the favorites folder `folder`.
+    pub async fn add_to_folder(&self, aid: u64, folder: u64) -> Result<(), App> {
+        let form = [
+            ("rid", aid.to_string()),
+            ("type", VIDEO_TYPE.to_string()),
+            ("add_media_ids", folder.to_string()),
+            ("del_media_ids", String::new()),
+        ];
+        self.post(FAVORITE_API_URL, &form).await
+    }
+
+    /// Likes the video `aid`, or takes the like back when `like` is false.
+    pub async fn like(&self, aid: u64, like: bool) -> Result<(), App> {
+        let form = [
+            ("aid", aid.to_string()),
+            ("like", if like { "1" } else { "2" }.to_string()),
+        ];
+        self.post(LIKE_API_URL, &form).await
+    }
+
+    /// Gives the video `aid` `coins` coins, one or two, liking it as well
+    /// when `also_like` is set.
+    pub async fn coin(&self, aid: u64, coins: u8, also_like: bool) -> Result<(), App> {
+        let form = [
+            ("aid", aid.to_string()),
+            ("multiply", coins.to_string()),
+            ("select_like", u8::from(also_like).to_string()),
+        ];
+        self.post(COIN_API_URL, &form).await
+    }
+
+    /// Posts `form` to a write API, adding the csrf token it asks for.
+    async fn post(&self, url: &str, form: &[(&str, String)]) -> Result<(), App> {
+        let mut form = form.to_vec();
+        form.push(("csrf", self.csrf.clone()));
+        let response: ApiResponse<serde_json::Value> =
+            limiter::post(&self.client, url, &form).await?;
+        response.check()
+    }
+}
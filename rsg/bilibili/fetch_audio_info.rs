@@ -1,18 +1,96 @@
+use super::resolve::VideoRef;
 use crate::error::App;
-use reqwest::Client;
+use rosesong_core::bilibili::api::BilibiliApi;
+use rosesong_core::bilibili::{cache, limiter};
+use rosesong_core::storage::{SeasonPosition, TrackKind};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct Owner {
+    /// Id of the uploader, `0` when unknown.
+    #[serde(default)]
+    pub mid: u64,
     pub name: String,
 }
 
+#[derive(Deserialize)]
+pub struct VideoPage {
+    pub cid: i64,
+    pub page: u32,
+    pub part: String,
+    pub duration: u64,
+}
+
 #[derive(Deserialize)]
 pub struct VideoData {
     pub bvid: String,
     pub title: String,
     pub cid: i64,
+    pub duration: u64,
     pub owner: Owner,
+    /// Unix time the video was published.
+    #[serde(default)]
+    pub pubdate: i64,
+    #[serde(default)]
+    pub pages: Vec<VideoPage>,
+    #[serde(default)]
+    ugc_season: Option<UgcSeason>,
+    #[serde(skip)]
+    pub kind: TrackKind,
+    #[serde(skip)]
+    pub season: Option<SeasonPosition>,
+}
+
+/// The collection (合集) a video belongs to.
+#[derive(Deserialize)]
+struct UgcSeason {
+    id: i64,
+    #[serde(default)]
+    sections: Vec<UgcSection>,
+}
+
+#[derive(Deserialize)]
+struct UgcSection {
+    #[serde(default)]
+    episodes: Vec<UgcEpisode>,
+}
+
+#[derive(Deserialize)]
+struct UgcEpisode {
+    bvid: String,
+}
+
+impl UgcSeason {
+    fn position_of(&self, bvid: &str) -> Option<SeasonPosition> {
+        let bvids = self
+            .sections
+            .iter()
+            .flat_map(|section| &section.episodes)
+            .map(|episode| episode.bvid.as_str());
+        let index = bvids.clone().position(|b| b == bvid)?;
+        Some(SeasonPosition {
+            id: self.id.to_string(),
+            index: u32::try_from(index + 1).ok()?,
+            total: u32::try_from(bvids.count()).ok()?,
+        })
+    }
+}
+
+impl VideoData {
+    /// Narrows the video down to one part of a multi-part video.
+    pub fn select_page(&mut self, page: u32) -> Result<(), App> {
+        let part = self
+            .pages
+            .iter()
+            .find(|part| part.page == page)
+            .ok_or_else(|| App::InvalidInput(format!("视频 {} 没有第 {page} P", self.bvid)))?;
+        self.cid = part.cid;
+        self.duration = part.duration;
+        if self.pages.len() > 1 {
+            self.title = format!("{} - {}", self.title, part.part);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize)]
@@ -20,67 +98,377 @@ struct ApiResponse<T> {
     data: T,
 }
 
-pub async fn fetch_video_data(client: &Client, bvid: &str) -> Result<VideoData, App> {
+pub async fn fetch_video_data(api: &impl BilibiliApi, bvid: &str) -> Result<VideoData, App> {
     let url = format!("https://api.bilibili.com/x/web-interface/view?bvid={bvid}");
-    let response = client.get(&url).send().await.map_err(|e| {
-        eprintln!("Failed to send request to {url}: {e}");
-        App::HttpRequest(e)
-    })?;
-    let mut api_response: ApiResponse<VideoData> = response.json().await.map_err(|e| {
-        eprintln!("Failed to parse response from {url}: {e}");
-        App::HttpRequest(e)
-    })?;
-    api_response.data.bvid = bvid.to_string();
-    Ok(api_response.data)
+    let api_response: ApiResponse<VideoData> = cache::get(&format!("view-{bvid}"), api, &url)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to fetch {url}: {e}");
+            App::from(e)
+        })?;
+    let mut video_data = api_response.data;
+    video_data.bvid = bvid.to_string();
+    video_data.season = video_data
+        .ugc_season
+        .as_ref()
+        .and_then(|season| season.position_of(bvid));
+    Ok(video_data)
+}
+
+#[derive(Deserialize)]
+struct SongInfo {
+    id: i64,
+    title: String,
+    #[serde(default)]
+    uid: u64,
+    uname: String,
+    duration: u64,
+    #[serde(default)]
+    passtime: i64,
+}
+
+/// Fetches a song of the audio section (au 号) as a track with `bvid` set
+/// to `au<sid>`.
+pub async fn fetch_song_data(api: &impl BilibiliApi, sid: &str) -> Result<VideoData, App> {
+    let url = format!("https://www.bilibili.com/audio/music-service-c/web/song/info?sid={sid}");
+    let api_response: ApiResponse<Option<SongInfo>> =
+        limiter::get(api, &url).await.map_err(|e| {
+            eprintln!("Failed to fetch {url}: {e}");
+            App::from(e)
+        })?;
+    let song = api_response
+        .data
+        .ok_or_else(|| App::InvalidInput(format!("没有找到音频 au{sid}")))?;
+    Ok(VideoData {
+        bvid: format!("au{}", song.id),
+        title: song.title,
+        cid: song.id,
+        duration: song.duration,
+        owner: Owner {
+            mid: song.uid,
+            name: song.uname,
+        },
+        pubdate: song.passtime,
+        pages: Vec::new(),
+        ugc_season: None,
+        kind: TrackKind::Audio,
+        season: None,
+    })
+}
+
+/// An episode (ep 号) or a whole season (ss 号) of bangumi, documentaries,
+/// concerts or courses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EpisodeRef {
+    Episode(String),
+    Season(String),
+}
+
+/// Extracts an ep or ss id from `ep123`, `ss456` or a link such as
+/// `https://www.bilibili.com/bangumi/play/ep123`.
+pub fn parse_episode(input: &str) -> Option<EpisodeRef> {
+    let lower = input.trim().to_ascii_lowercase();
+    for (prefix, make) in [
+        ("ep", EpisodeRef::Episode as fn(String) -> EpisodeRef),
+        ("ss", EpisodeRef::Season),
+    ] {
+        let Some(start) = lower.find(prefix) else {
+            continue;
+        };
+        let digits: String = lower[start + 2..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        if !digits.is_empty() {
+            return Some(make(digits));
+        }
+    }
+    None
+}
+
+#[derive(Deserialize)]
+struct SeasonResponse {
+    result: Option<Season>,
+}
+
+#[derive(Deserialize)]
+struct Season {
+    #[serde(rename = "season_id")]
+    id: i64,
+    #[serde(rename = "season_title")]
+    title: String,
+    #[serde(default)]
+    up_info: Option<Owner>,
+    #[serde(default)]
+    episodes: Vec<Episode>,
+}
+
+#[derive(Deserialize)]
+struct Episode {
+    id: i64,
+    cid: i64,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    long_title: String,
+    /// Milliseconds.
+    #[serde(default)]
+    duration: u64,
+    #[serde(default)]
+    pub_time: i64,
+}
+
+/// Fetches one episode, or every episode of a season, as tracks with
+/// `bvid` set to `ep<ep_id>`.
+pub async fn fetch_episodes(
+    api: &impl BilibiliApi,
+    episode: &EpisodeRef,
+) -> Result<Vec<VideoData>, App> {
+    let query = match episode {
+        EpisodeRef::Episode(id) => format!("ep_id={id}"),
+        EpisodeRef::Season(id) => format!("season_id={id}"),
+    };
+    let url = format!("https://api.bilibili.com/pgc/view/web/season?{query}");
+    let season = limiter::get::<SeasonResponse>(api, &url)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to fetch {url}: {e}");
+            App::from(e)
+        })?
+        .result
+        .ok_or_else(|| App::InvalidInput("没有找到相关的剧集".to_string()))?;
+    let owner = season
+        .up_info
+        .map_or_else(|| season.title.clone(), |owner| owner.name);
+    let total = u32::try_from(season.episodes.len()).unwrap_or(u32::MAX);
+    let episodes: Vec<VideoData> = season
+        .episodes
+        .into_iter()
+        .zip(1..)
+        .filter(|(ep, _)| match episode {
+            EpisodeRef::Episode(id) => ep.id.to_string() == *id,
+            EpisodeRef::Season(_) => true,
+        })
+        .map(|(ep, index)| {
+            let name = if ep.long_title.is_empty() {
+                ep.title
+            } else {
+                ep.long_title
+            };
+            VideoData {
+                bvid: format!("ep{}", ep.id),
+                title: format!("{} - {}", season.title, name),
+                cid: ep.cid,
+                duration: ep.duration / 1000,
+                owner: Owner {
+                    mid: 0,
+                    name: owner.clone(),
+                },
+                pubdate: ep.pub_time,
+                pages: Vec::new(),
+                ugc_season: None,
+                kind: TrackKind::Episode,
+                season: Some(SeasonPosition {
+                    id: format!("ss{}", season.id),
+                    index,
+                    total,
+                }),
+            }
+        })
+        .collect();
+    if episodes.is_empty() {
+        return Err(App::InvalidInput("没有找到相关的剧集".to_string()));
+    }
+    Ok(episodes)
 }
 
-pub async fn fetch_bvids_from_fid(client: &Client, fid: &str) -> Result<Vec<String>, App> {
-    let url = format!("https://api.bilibili.com/x/v3/fav/resource/ids?media_id={fid}");
-    let response = client.get(&url).send().await.map_err(|e| {
-        eprintln!("Failed to send request to {url}: {e}");
-        App::HttpRequest(e)
+/// Extracts the song id from `au123`, `123` or an audio page link such as
+/// `https://www.bilibili.com/audio/au123`.
+pub fn parse_auid(input: &str) -> Option<String> {
+    let input = input.trim();
+    if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(input.to_string());
+    }
+    // The `au` of `/audio/` in a link has no digits after it
+    let lower = input.to_ascii_lowercase();
+    lower.match_indices("au").find_map(|(start, _)| {
+        let digits: String = lower[start + 2..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        (!digits.is_empty()).then_some(digits)
+    })
+}
+
+/// Items of a favorites folder per page; the list API allows at most 20.
+const FAVORITE_PAGE_SIZE: u32 = 20;
+
+#[derive(Deserialize)]
+struct FavoritePage {
+    #[serde(default)]
+    medias: Option<Vec<FavoriteMedia>>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct FavoriteMedia {
+    id: i64,
+    /// `2` for videos, `12` for songs of the audio section.
+    #[serde(rename = "type")]
+    media_type: u32,
+    /// Bit 0 is set once the item is no longer available.
+    attr: u32,
+    title: String,
+    #[serde(default)]
+    bvid: String,
+    duration: u64,
+    #[serde(default)]
+    pubtime: i64,
+    upper: Owner,
+    #[serde(default)]
+    ugc: Option<FavoriteUgc>,
+}
+
+#[derive(Deserialize)]
+struct FavoriteUgc {
+    first_cid: i64,
+}
+
+impl FavoriteMedia {
+    fn into_video_data(self) -> Option<VideoData> {
+        if self.attr & 1 != 0 {
+            return None;
+        }
+        let (bvid, cid, kind) = match self.media_type {
+            2 => (self.bvid, self.ugc?.first_cid, TrackKind::Video),
+            12 => (format!("au{}", self.id), self.id, TrackKind::Audio),
+            _ => return None,
+        };
+        Some(VideoData {
+            bvid,
+            title: self.title,
+            cid,
+            duration: self.duration,
+            owner: self.upper,
+            pubdate: self.pubtime,
+            pages: Vec::new(),
+            ugc_season: None,
+            kind,
+            season: None,
+        })
+    }
+}
+
+/// Fetches every item of a favorites folder page by page, with the title,
+/// owner and duration the list already carries.
+pub async fn fetch_favorite(api: &impl BilibiliApi, fid: &str) -> Result<Vec<VideoData>, App> {
+    let mut video_data_list = Vec::new();
+    let mut skipped = 0;
+    for pn in 1.. {
+        let url = format!(
+            "https://api.bilibili.com/x/v3/fav/resource/list?media_id={fid}&pn={pn}&ps={FAVORITE_PAGE_SIZE}&platform=web"
+        );
+        let api_response: ApiResponse<Option<FavoritePage>> =
+            limiter::get(api, &url).await.map_err(|e| {
+                eprintln!("Failed to fetch {url}: {e}");
+                App::from(e)
+            })?;
+        let page = api_response
+            .data
+            .ok_or_else(|| App::InvalidInput("提供的 fid 无效或收藏夹不公开".to_string()))?;
+        for media in page.medias.unwrap_or_default() {
+            match media.into_video_data() {
+                Some(video_data) => video_data_list.push(video_data),
+                None => skipped += 1,
+            }
+        }
+        println!("已获取 {} 首", video_data_list.len());
+        if !page.has_more {
+            break;
+        }
+    }
+    if skipped > 0 {
+        println!("已跳过 {skipped} 个失效或不支持的收藏");
+    }
+    if video_data_list.is_empty() {
+        return Err(App::InvalidInput(
+            "提供的 fid 无效或没有找到相关的视频".to_string(),
+        ));
+    }
+    Ok(video_data_list)
+}
+
+/// Fetches the bvids of the latest issue of the weekly music chart (全站音乐榜).
+pub async fn fetch_weekly_bvids(api: &impl BilibiliApi) -> Result<Vec<String>, App> {
+    let url = "https://api.bilibili.com/x/copyright-music-publicity/toplist/all_period?list_type=1";
+    let json: serde_json::Value = limiter::get(api, url).await.map_err(|e| {
+        eprintln!("Failed to fetch {url}: {e}");
+        App::from(e)
     })?;
-    let json: serde_json::Value = response.json().await.map_err(|e| {
-        eprintln!("Failed to parse response from {url}: {e}");
-        App::HttpRequest(e)
+    // Periods are grouped by year; the latest issue has the largest ID
+    let list_id = json["data"]["list"]
+        .as_object()
+        .into_iter()
+        .flat_map(|years| years.values())
+        .filter_map(serde_json::Value::as_array)
+        .flatten()
+        .filter_map(|period| period["ID"].as_u64())
+        .max()
+        .ok_or_else(|| App::DataParsing("没有找到每周必听榜单".to_string()))?;
+
+    let url = format!(
+        "https://api.bilibili.com/x/copyright-music-publicity/toplist/music_list?list_id={list_id}"
+    );
+    let json: serde_json::Value = limiter::get(api, &url).await.map_err(|e| {
+        eprintln!("Failed to fetch {url}: {e}");
+        App::from(e)
     })?;
-    let bvids: Vec<String> = json["data"]
+    let bvids: Vec<String> = json["data"]["list"]
         .as_array()
-        .ok_or_else(|| {
-            eprintln!("Failed to find 'data' array in response from {url}");
-            App::DataParsing("数据中缺少 bvids 数组".to_string())
-        })?
+        .ok_or_else(|| App::DataParsing("每周必听榜单数据中缺少歌曲列表".to_string()))?
         .iter()
-        .filter_map(|v| v["bvid"].as_str().map(String::from))
+        .filter_map(|v| v["creation_bvid"].as_str().map(String::from))
         .collect();
 
     if bvids.is_empty() {
-        return Err(App::InvalidInput(
-            "提供的 fid 无效或没有找到相关的视频".to_string(),
-        ));
+        return Err(App::InvalidInput("每周必听榜单为空".to_string()));
     }
 
     Ok(bvids)
 }
 
+/// Where `rsg add` takes its videos from.
+pub enum VideoSource {
+    Favorite(String),
+    Video(VideoRef),
+    /// A song of the audio section, by sid.
+    Song(String),
+    Episodes(EpisodeRef),
+    Weekly,
+}
+
 pub async fn get_video_data(
-    client: &Client,
-    fid: Option<&str>,
-    bvid: Option<&str>,
+    api: &impl BilibiliApi,
+    source: VideoSource,
 ) -> Result<Vec<VideoData>, App> {
     let mut video_data_list = Vec::new();
 
-    if let Some(fid) = fid {
-        let bvids = fetch_bvids_from_fid(client, fid).await?;
-        for bvid in bvids {
-            let video_data = fetch_video_data(client, &bvid).await?;
-            video_data_list.push(video_data);
+    let bvids = match source {
+        VideoSource::Favorite(fid) => return fetch_favorite(api, &fid).await,
+        VideoSource::Video(VideoRef { bvid, page }) => {
+            let mut video_data = fetch_video_data(api, &bvid).await?;
+            if let Some(page) = page {
+                video_data.select_page(page)?;
+            }
+            return Ok(vec![video_data]);
         }
-    } else if let Some(bvid) = bvid {
-        let video_data = fetch_video_data(client, bvid).await?;
+        VideoSource::Song(sid) => return Ok(vec![fetch_song_data(api, &sid).await?]),
+        VideoSource::Episodes(episode) => return fetch_episodes(api, &episode).await,
+        VideoSource::Weekly => fetch_weekly_bvids(api).await?,
+    };
+    for bvid in bvids {
+        let video_data = fetch_video_data(api, &bvid).await?;
         video_data_list.push(video_data);
-    } else {
-        return Err(App::InvalidInput("请提供正确的 fid 或 bvid".to_string()));
     }
 
     if video_data_list.is_empty() {
@@ -91,3 +479,102 @@ pub async fn get_video_data(
 
     Ok(video_data_list)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::configure_for_tests;
+    use super::super::resolve::VideoRef;
+    use super::{get_video_data, VideoSource};
+    use rosesong_core::bilibili::mock::MockApi;
+    use rosesong_core::storage::TrackKind;
+    use serde_json::{json, Value};
+
+    fn video_media(bvid: &str, attr: u32) -> Value {
+        json!({
+            "id": 1,
+            "type": 2,
+            "attr": attr,
+            "title": bvid,
+            "bvid": bvid,
+            "duration": 180,
+            "upper": { "mid": 7, "name": "up" },
+            "ugc": { "first_cid": 11 },
+        })
+    }
+
+    #[tokio::test]
+    async fn favorites_are_imported_page_by_page() {
+        configure_for_tests();
+        let api = MockApi::new()
+            .json(
+                "fav/resource/list?media_id=42&pn=1",
+                json!({ "code": 0, "data": {
+                    "medias": [video_media("BV1aaaaaaaaa", 0), video_media("BV1bbbbbbbbb", 1)],
+                    "has_more": true,
+                }}),
+            )
+            .json(
+                "fav/resource/list?media_id=42&pn=2",
+                json!({ "code": 0, "data": {
+                    "medias": [
+                        video_media("BV1ccccccccc", 0),
+                        { "id": 99, "type": 12, "attr": 0, "title": "song", "duration": 60,
+                          "upper": { "name": "singer" } },
+                    ],
+                    "has_more": false,
+                }}),
+            );
+        let videos = get_video_data(&api, VideoSource::Favorite("42".to_string()))
+            .await
+            .unwrap();
+        let bvids: Vec<&str> = videos.iter().map(|video| video.bvid.as_str()).collect();
+        // The second item is no longer available
+        assert_eq!(bvids, ["BV1aaaaaaaaa", "BV1ccccccccc", "au99"]);
+        assert_eq!(videos[2].kind, TrackKind::Audio);
+        assert_eq!(api.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn an_empty_favorites_folder_is_an_error() {
+        configure_for_tests();
+        let api = MockApi::new().json(
+            "fav/resource/list",
+            json!({ "code": 0, "data": { "medias": null, "has_more": false } }),
+        );
+        let videos = get_video_data(&api, VideoSource::Favorite("1".to_string())).await;
+        assert!(videos.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_linked_part_narrows_the_video_down() {
+        configure_for_tests();
+        let api = MockApi::new().json(
+            "view?bvid=BV17x411w7KC",
+            json!({ "code": 0, "data": {
+                "bvid": "BV17x411w7KC",
+                "title": "concert",
+                "cid": 1,
+                "duration": 600,
+                "owner": { "mid": 1, "name": "up" },
+                "pages": [
+                    { "cid": 1, "page": 1, "part": "opening", "duration": 100 },
+                    { "cid": 2, "page": 2, "part": "encore", "duration": 500 },
+                ],
+            }}),
+        );
+        let source = VideoSource::Video(VideoRef {
+            bvid: "BV17x411w7KC".to_string(),
+            page: Some(2),
+        });
+        let videos = get_video_data(&api, source).await.unwrap();
+        assert_eq!(videos[0].cid, 2);
+        assert_eq!(videos[0].duration, 500);
+        assert_eq!(videos[0].title, "concert - encore");
+
+        let source = VideoSource::Video(VideoRef {
+            bvid: "BV17x411w7KC".to_string(),
+            page: Some(3),
+        });
+        assert!(get_video_data(&api, source).await.is_err());
+    }
+}
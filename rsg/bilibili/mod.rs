@@ -1 +1,17 @@
+pub mod account;
 pub mod fetch_audio_info;
+pub mod resolve;
+pub mod video_info;
+
+/// Lifts the rate limit and turns the metadata cache off, so that tests
+/// neither wait nor write to the cache directory.
+#[cfg(test)]
+pub fn configure_for_tests() {
+    let network = rosesong_core::settings::NetworkSettings {
+        qps: 0.0,
+        metadata_ttl: 0,
+        ..Default::default()
+    };
+    rosesong_core::bilibili::limiter::configure(&network);
+    rosesong_core::bilibili::cache::configure(&network);
+}
@@ -0,0 +1,119 @@
+use crate::error::App;
+use rosesong_core::bilibili::api::BilibiliApi;
+use rosesong_core::bilibili::{aid_to_bvid, MAX_AID};
+
+/// A video and, for multi-part videos, the part (分P) that was linked.
+pub struct VideoRef {
+    pub bvid: String,
+    pub page: Option<u32>,
+}
+
+/// Turns a bvid, an av number (`av170001`), a video URL, a b23.tv short link
+/// or a whole share text containing one into a bvid.
+pub async fn resolve_bvid(api: &impl BilibiliApi, input: &str) -> Result<String, App> {
+    Ok(resolve_video(api, input).await?.bvid)
+}
+
+/// Like [`resolve_bvid`], also keeping the `p=` part number of the link.
+/// Short links are followed over the network.
+pub async fn resolve_video(api: &impl BilibiliApi, input: &str) -> Result<VideoRef, App> {
+    let input = input.trim();
+    let target = match extract_short_link(input) {
+        Some(url) => api.final_url(&url).await?,
+        None => input.to_string(),
+    };
+    let bvid = parse_bvid(&target)
+        .or_else(|| parse_aid(&target).map(aid_to_bvid))
+        .ok_or_else(|| App::InvalidInput(format!("无法识别的 bvid、av 号或链接: {input}")))?;
+    Ok(VideoRef {
+        bvid,
+        page: parse_page(&target),
+    })
+}
+
+/// Finds a b23.tv link in `text`, such as the one in the text copied from
+/// the app's share button.
+fn extract_short_link(text: &str) -> Option<String> {
+    let start = text.find("b23.tv/")?;
+    let path: String = text[start + "b23.tv/".len()..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect();
+    (!path.is_empty()).then(|| format!("https://b23.tv/{path}"))
+}
+
+/// Reads the `p` query parameter of a video URL.
+fn parse_page(url: &str) -> Option<u32> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("p="))
+        .and_then(|page| page.parse().ok())
+        .filter(|page| *page > 0)
+}
+
+/// Finds a `BV` followed by ten alphanumerics anywhere in `text`.
+fn parse_bvid(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    (0..bytes.len().saturating_sub(11)).find_map(|start| {
+        let candidate = &bytes[start..start + 12];
+        let is_bvid = candidate[..2].eq_ignore_ascii_case(b"BV")
+            && candidate[2..].iter().all(u8::is_ascii_alphanumeric)
+            && !bytes.get(start + 12).is_some_and(u8::is_ascii_alphanumeric);
+        is_bvid.then(|| format!("BV{}", &text[start + 2..start + 12]))
+    })
+}
+
+/// Finds an `av` number such as `av170001` anywhere in `text`.
+fn parse_aid(text: &str) -> Option<u64> {
+    let lower = text.to_ascii_lowercase();
+    lower.match_indices("av").find_map(|(start, _)| {
+        let digits: String = lower[start + 2..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok().filter(|aid| *aid > 0 && *aid < MAX_AID)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_video;
+    use rosesong_core::bilibili::mock::MockApi;
+
+    #[tokio::test]
+    async fn bvids_av_numbers_and_links_are_resolved() {
+        let api = MockApi::new();
+        for (input, bvid, page) in [
+            ("BV17x411w7KC", "BV17x411w7KC", None),
+            ("av170001", "BV17x411w7KC", None),
+            (
+                "https://www.bilibili.com/video/BV17x411w7KC/?p=3&t=10",
+                "BV17x411w7KC",
+                Some(3),
+            ),
+        ] {
+            let video = resolve_video(&api, input).await.unwrap();
+            assert_eq!((video.bvid.as_str(), video.page), (bvid, page), "{input}");
+        }
+        assert!(api.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn short_links_in_share_texts_are_followed() {
+        let api = MockApi::new().redirect(
+            "https://b23.tv/abc123",
+            "https://www.bilibili.com/video/BV17x411w7KC?p=2",
+        );
+        let video = resolve_video(&api, "【某个视频】 https://b23.tv/abc123 分享自哔哩哔哩")
+            .await
+            .unwrap();
+        assert_eq!(video.bvid, "BV17x411w7KC");
+        assert_eq!(video.page, Some(2));
+    }
+
+    #[tokio::test]
+    async fn unknown_input_is_refused() {
+        assert!(resolve_video(&MockApi::new(), "hello").await.is_err());
+    }
+}
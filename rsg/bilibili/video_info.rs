@@ -0,0 +1,134 @@
+use super::fetch_audio_info::Owner;
+use crate::error::App;
+use rosesong_core::bilibili::api::BilibiliApi;
+use rosesong_core::bilibili::{cache, limiter};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct Stat {
+    pub view: u64,
+    pub like: u64,
+    pub reply: u64,
+}
+
+#[derive(Deserialize)]
+pub struct VideoInfo {
+    pub aid: u64,
+    pub title: String,
+    pub desc: String,
+    pub pubdate: i64,
+    pub owner: Owner,
+    pub stat: Stat,
+}
+
+#[derive(Deserialize)]
+pub struct Member {
+    pub uname: String,
+}
+
+#[derive(Deserialize)]
+pub struct Content {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct Reply {
+    pub member: Member,
+    pub content: Content,
+    pub like: u64,
+}
+
+#[derive(Deserialize)]
+struct ReplyPage {
+    #[serde(default)]
+    replies: Option<Vec<Reply>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ApiResponse<T> {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+impl<T> ApiResponse<T> {
+    pub(super) fn into_data(self) -> Result<T, App> {
+        match self.data {
+            Some(data) if self.code == 0 => Ok(data),
+            _ => Err(App::DataParsing(format!(
+                "B 站接口返回错误 {}: {}",
+                self.code, self.message
+            ))),
+        }
+    }
+
+    /// Fails unless the API reported success, for calls whose `data` is
+    /// empty.
+    pub(super) fn check(self) -> Result<(), App> {
+        if self.code == 0 {
+            Ok(())
+        } else {
+            Err(App::DataParsing(format!(
+                "B 站接口返回错误 {}: {}",
+                self.code, self.message
+            )))
+        }
+    }
+}
+
+pub async fn fetch_video_info(api: &impl BilibiliApi, bvid: &str) -> Result<VideoInfo, App> {
+    let url = format!("https://api.bilibili.com/x/web-interface/view?bvid={bvid}");
+    let api_response: ApiResponse<VideoInfo> = cache::get(&format!("view-{bvid}"), api, &url)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to fetch {url}: {e}");
+            App::from(e)
+        })?;
+    api_response.into_data()
+}
+
+/// Fetches the first `count` replies of a video, sorted by likes.
+pub async fn fetch_hot_replies(
+    api: &impl BilibiliApi,
+    aid: u64,
+    count: usize,
+) -> Result<Vec<Reply>, App> {
+    let url = format!("https://api.bilibili.com/x/v2/reply?type=1&oid={aid}&sort=1&ps={count}");
+    let api_response: ApiResponse<ReplyPage> = limiter::get(api, &url).await.map_err(|e| {
+        eprintln!("Failed to fetch {url}: {e}");
+        App::from(e)
+    })?;
+    let mut replies = api_response.into_data()?.replies.unwrap_or_default();
+    replies.truncate(count);
+    Ok(replies)
+}
+
+/// A chapter ("看点") of a video, with bounds in seconds.
+#[derive(Deserialize)]
+pub struct Chapter {
+    pub from: u64,
+    pub to: u64,
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+struct PlayerInfo {
+    #[serde(default)]
+    view_points: Option<Vec<Chapter>>,
+}
+
+/// Fetches the chapters the uploader marked on one part of a video, which
+/// is empty for most videos.
+pub async fn fetch_chapters(
+    api: &impl BilibiliApi,
+    bvid: &str,
+    cid: &str,
+) -> Result<Vec<Chapter>, App> {
+    let url = format!("https://api.bilibili.com/x/player/v2?bvid={bvid}&cid={cid}");
+    let api_response: ApiResponse<PlayerInfo> = limiter::get(api, &url).await.map_err(|e| {
+        eprintln!("Failed to fetch {url}: {e}");
+        App::from(e)
+    })?;
+    Ok(api_response.into_data()?.view_points.unwrap_or_default())
+}
@@ -0,0 +1,158 @@
+use crate::bilibili::fetch_audio_info::{
+    get_video_data, parse_auid, parse_episode, VideoData, VideoSource,
+};
+use crate::bilibili::resolve::resolve_video;
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{
+    notify_playlist_change, open_store, store_videos, DurationRange, ImportOptions, SortOrder,
+    StdResult,
+};
+use reqwest::{Client, Url};
+use rosesong_core::settings::Settings;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Start of the compressed bookmark backups Firefox keeps in its profile.
+const MOZ_LZ4_MAGIC: &[u8] = b"mozLz40\0";
+
+/// Imports the bilibili videos, songs and episodes bookmarked in the Firefox
+/// or Chrome bookmarks file at `path` into the current playlist, only those
+/// in the folders called `folder` when given.
+pub async fn import_bookmarks(
+    path: &Path,
+    folder: Option<&str>,
+    proxy: &PlayerClient,
+) -> StdResult<()> {
+    let bytes = tokio::fs::read(path).await?;
+    if bytes.starts_with(MOZ_LZ4_MAGIC) {
+        return Err(App::InvalidInput(
+            "这是 Firefox 压缩过的书签备份，请在“管理书签 → 导入和备份 → 备份”中导出 JSON 文件"
+                .to_string(),
+        ));
+    }
+    let root: Value = serde_json::from_slice(&bytes)
+        .map_err(|e| App::InvalidInput(format!("无法解析书签文件 {}: {e}", path.display())))?;
+    let nodes = match folder {
+        Some(name) => {
+            let mut folders = Vec::new();
+            find_folders(&root, name, &mut folders);
+            if folders.is_empty() {
+                return Err(App::InvalidInput(format!("书签中没有名为 {name} 的文件夹")));
+            }
+            folders
+        }
+        None => vec![&root],
+    };
+    let mut links = Vec::new();
+    for node in nodes {
+        collect_links(node, &mut links);
+    }
+    let mut seen = HashSet::new();
+    links.retain(|link| is_bilibili(link) && seen.insert(link.clone()));
+    if links.is_empty() {
+        println!("书签中没有 B 站链接");
+        return Ok(());
+    }
+
+    println!("在书签中找到 {} 个 B 站链接，正在获取相关信息", links.len());
+    let client = Client::new();
+    let mut videos = Vec::new();
+    let mut failed_count = 0;
+    for link in &links {
+        match fetch_link(&client, link).await {
+            Ok(found) => videos.extend(found),
+            Err(e) => {
+                eprintln!("跳过 {link}: {e}");
+                failed_count += 1;
+            }
+        }
+    }
+    if failed_count > 0 {
+        println!("{failed_count} 个链接无法导入");
+    }
+    if videos.is_empty() {
+        return Ok(());
+    }
+    let settings = Settings::load()?;
+    let options = ImportOptions {
+        duration_range: DurationRange::default(),
+        sort: SortOrder::Source,
+        replace: false,
+        new_tracks: settings.import.new_tracks,
+        blacklist: settings.blacklist,
+    };
+    let store = open_store()?;
+    let old_fingerprint = store.fingerprint()?;
+    store_videos(store.as_ref(), videos, &options)?;
+    if old_fingerprint != store.fingerprint()? {
+        notify_playlist_change(proxy).await?;
+    }
+    Ok(())
+}
+
+/// Children of a bookmark folder. Chrome keeps its top folders under
+/// `roots` instead.
+fn children(node: &Value) -> impl Iterator<Item = &Value> {
+    let children = node.get("children").and_then(Value::as_array).into_iter();
+    let roots = node.get("roots").and_then(Value::as_object).into_iter();
+    children
+        .flatten()
+        .chain(roots.flat_map(serde_json::Map::values))
+}
+
+/// Folders under `node` called `name`, with Chrome's `name` or Firefox's
+/// `title`, not looking inside the ones found.
+fn find_folders<'a>(node: &'a Value, name: &str, folders: &mut Vec<&'a Value>) {
+    let title = node.get("name").or_else(|| node.get("title"));
+    if node.get("children").is_some() && title.and_then(Value::as_str) == Some(name) {
+        folders.push(node);
+        return;
+    }
+    for child in children(node) {
+        find_folders(child, name, folders);
+    }
+}
+
+/// Addresses of the bookmarks under `node`, in the order they are shown.
+fn collect_links(node: &Value, links: &mut Vec<String>) {
+    if let Some(url) = node
+        .get("url")
+        .or_else(|| node.get("uri"))
+        .and_then(Value::as_str)
+    {
+        links.push(url.to_string());
+    }
+    for child in children(node) {
+        collect_links(child, links);
+    }
+}
+
+fn is_bilibili(link: &str) -> bool {
+    Url::parse(link)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .is_some_and(|host| {
+            ["bilibili.com", "b23.tv"]
+                .iter()
+                .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+        })
+}
+
+/// The videos a bookmarked page is about: an episode or season, a song of
+/// the audio section or a video.
+async fn fetch_link(client: &Client, link: &str) -> StdResult<Vec<VideoData>> {
+    let source = if link.contains("/bangumi/") || link.contains("/cheese/") {
+        parse_episode(link).map(VideoSource::Episodes)
+    } else if link.contains("/audio/") {
+        parse_auid(link).map(VideoSource::Song)
+    } else {
+        None
+    };
+    let source = match source {
+        Some(source) => source,
+        None => VideoSource::Video(resolve_video(client, link).await?),
+    };
+    get_video_data(client, source).await
+}
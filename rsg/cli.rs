@@ -1,35 +1,40 @@
 mod bilibili;
+mod bookmarks;
+mod client;
+mod ctl;
+mod edit;
 mod error;
+mod info;
+mod later;
+mod related;
+mod search;
+mod share;
+mod status;
+mod style;
+mod subsonic;
+mod top;
+mod triple;
+mod undo;
+mod validate;
+mod widget;
 
-use bilibili::fetch_audio_info::get_video_data;
-use clap::{Parser, Subcommand};
+use bilibili::fetch_audio_info::{
+    get_video_data, parse_auid, parse_episode, VideoData, VideoSource,
+};
+use bilibili::resolve::{resolve_bvid, resolve_video};
+use clap::{ArgGroup, Parser, Subcommand};
+use client::PlayerClient;
 use error::App;
-use serde::{Deserialize, Serialize};
+use rosesong_core::settings::{BlacklistSettings, InsertPosition, Settings};
+use rosesong_core::storage::{self, PlaylistInfo, PlaylistStore, Track, DEFAULT_PLAYLIST};
 use std::collections::HashSet;
-use std::path::Path;
-use tokio::{fs, io::AsyncBufReadExt, io::AsyncWriteExt, process::Command};
-use zbus::{proxy, Connection};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use tokio::{fs, io::AsyncBufReadExt, process::Command};
 
 type StdResult<T> = std::result::Result<T, App>;
 
-#[proxy(
-    interface = "org.rosesong.Player",
-    default_service = "org.rosesong.Player",
-    default_path = "/org/rosesong/Player"
-)]
-trait MyPlayer {
-    async fn play(&self) -> zbus::Result<()>;
-    async fn play_bvid(&self, bvid: &str) -> zbus::Result<()>;
-    async fn pause(&self) -> zbus::Result<()>;
-    async fn next(&self) -> zbus::Result<()>;
-    async fn previous(&self) -> zbus::Result<()>;
-    async fn stop(&self) -> zbus::Result<()>;
-    async fn set_mode(&self, mode: &str) -> zbus::Result<()>;
-    async fn playlist_change(&self) -> zbus::Result<()>;
-    async fn test_connection(&self) -> zbus::Result<()>;
-    async fn playlist_is_empty(&self) -> zbus::Result<()>;
-}
-
 #[derive(Parser)]
 #[command(
     name = "rsg",
@@ -39,6 +44,12 @@ trait MyPlayer {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[arg(
+        long = "json",
+        global = true,
+        help = "以 JSON 输出：status 输出完整的播放状态（包括 last_error），widget 输出带 text/class/tooltip 字段的 JSON（适用于 waybar），出错时输出 error.code 和 error.message"
+    )]
+    json: bool,
 }
 
 #[derive(Subcommand)]
@@ -55,32 +66,162 @@ enum Commands {
     #[command(about = "播放上一首歌曲")]
     Previous,
 
-    #[command(about = "停止 RoseSong")]
+    #[command(about = "停止播放，RoseSong 保持运行")]
     Stop,
 
+    #[command(about = "退出 RoseSong")]
+    Quit,
+
     #[command(about = "设置播放模式")]
     Mode(ModeCommand),
 
+    #[command(about = "电台模式：每首歌播完后自动续播 B 站推荐的相关视频")]
+    Radio(RadioCommand),
+
+    #[command(about = "声道选择：只播左声道、右声道，或混合为单声道")]
+    Channel(ChannelCommand),
+
+    #[command(about = "升降调（变调不变速），例如 rsg pitch +2")]
+    Pitch(PitchCommand),
+
+    #[command(about = "跳转到当前歌曲的指定位置")]
+    Seek(SeekCommand),
+
+    #[command(about = "设置音量")]
+    Volume(VolumeCommand),
+
+    #[command(about = "显示当前播放状态")]
+    Status(StatusCommand),
+
+    #[command(about = "单行键盘控制：空格暂停/播放，n/p 切歌，+/- 音量，q 退出")]
+    Ctl,
+
+    #[command(about = "全屏显示播放状态、切歌延迟和守护进程的日志与事件，按键同 rsg ctl")]
+    Top,
+
+    #[command(about = "输出供 waybar/polybar 等状态栏使用的单行状态")]
+    Widget(WidgetCommand),
+
+    #[command(about = "显示当前或指定歌曲的视频简介、播放量和热门评论")]
+    Info(InfoCommand),
+
+    #[command(about = "输出当前歌曲的 B 站链接，方便分享")]
+    Share(ShareCommand),
+
+    #[command(about = "把当前或指定视频收藏到 B 站收藏夹（需要登录）")]
+    Fav(FavCommand),
+
+    #[command(about = "在 B 站给当前或指定视频点赞（需要登录）")]
+    Like(LikeCommand),
+
+    #[command(about = "在 B 站给当前或指定视频投币（需要登录）")]
+    Coin(CoinCommand),
+
+    #[command(about = "列出与当前或指定视频相关的推荐，可直接播放或加入播放列表")]
+    Related(RelatedCommand),
+
+    #[command(about = "列出当前或指定歌曲的章节（看点）")]
+    Chapters(ChaptersCommand),
+
     #[command(about = "添加歌曲到播放列表")]
     Add(AddCommand),
 
+    #[command(
+        about = "稍后听：把视频加入一次性队列，当前歌单播完后播放，播过即从队列移除；不带参数时列出队列"
+    )]
+    Later(LaterCommand),
+
     #[command(about = "在播放列表中查找歌曲")]
     Find(FindCommand),
 
     #[command(about = "从播放列表中删除歌曲")]
     Delete(DeleteCommand),
 
+    #[command(about = "禁用歌曲：保留在播放列表中，但播放时跳过")]
+    Disable(DisableCommand),
+
+    #[command(about = "恢复被禁用的歌曲")]
+    Enable(DisableCommand),
+
+    #[command(about = "设置歌曲开头和结尾跳过的秒数")]
+    Skip(SkipCommand),
+
+    #[command(about = "设置歌曲的起止时间，只播放中间的部分")]
+    Trim(TrimCommand),
+
     #[command(about = "显示播放列表")]
     Playlist,
 
+    #[command(about = "显示歌单统计，或用 --perf 显示切歌延迟")]
+    Stats(StatsCommand),
+
+    #[command(about = "列出当前歌曲的所有音频流")]
+    AudioTracks,
+
+    #[command(about = "切换当前歌曲播放的音频流")]
+    AudioTrack(AudioTrackCommand),
+
     #[command(about = "启动 RoseSong")]
-    Start,
+    Start(StartCommand),
+
+    #[command(about = "将播放列表导出为 TOML 文件")]
+    Export(ExportCommand),
+
+    #[command(about = "从 TOML 文件或浏览器书签导入播放列表")]
+    Import(ImportCommand),
+
+    #[command(about = "用 $EDITOR 编辑歌单，保存退出后校验并写回")]
+    Edit(EditCommand),
+
+    #[command(about = "撤销歌单最近一次的修改，例如添加、删除或排序")]
+    Undo(UndoCommand),
+
+    #[command(about = "重做最近一次撤销的修改")]
+    Redo(UndoCommand),
+
+    #[command(about = "检查设置和歌单文件的格式，指出出错的行和修复方法")]
+    Validate(ValidateCommand),
+
+    #[command(
+        about = "按标题在 Subsonic/Navidrome 服务器上查找歌单中的歌曲，可保存为服务器上的歌单"
+    )]
+    Subsonic(SubsonicCommand),
+
+    /// Candidates for shell completion scripts, one `value\tdescription`
+    /// per line.
+    #[command(name = "_complete", hide = true)]
+    Complete(CompleteCommand),
+}
+
+#[derive(Parser)]
+struct StartCommand {
+    #[arg(long, value_name = "NAME", help = "播放指定歌单，而不是默认歌单")]
+    playlist: Option<String>,
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "从歌单中的第 N 首开始播放")]
+    index: Option<u64>,
+    #[arg(long, value_parser = ["loop", "shuffle", "repeat"], help = "播放模式")]
+    mode: Option<String>,
+    #[arg(long, help = "启动后立即返回，不等待 RoseSong 就绪")]
+    no_wait: bool,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "等待 RoseSong 就绪的最长秒数"
+    )]
+    timeout: u64,
 }
 
 #[derive(Parser)]
 struct PlayCommand {
-    #[arg(short = 'b', long = "bvid", help = "要播放的 bvid")]
+    #[arg(short = 'b', long = "bvid", help = "要播放的 bvid、av 号或视频链接")]
     bvid: Option<String>,
+    #[arg(
+        long = "season",
+        conflicts_with = "bvid",
+        help = "按顺序连播歌单中属于该合集的歌曲：合集 ID，番剧为 ss 号"
+    )]
+    season: Option<String>,
 }
 
 #[derive(Parser)]
@@ -91,14 +232,233 @@ struct ModeCommand {
     shuffle_mode: bool,
     #[arg(short = 'r', long = "repeat", action = clap::ArgAction::SetTrue, help = "设置播放模式为单曲循环")]
     repeat_mode: bool,
+    #[arg(long, value_parser = ["sequential", "shuffle"], conflicts_with_all = ["loop_mode", "shuffle_mode", "repeat_mode"], help = "播放顺序：sequential 顺序，shuffle 随机，不改变循环策略")]
+    order: Option<String>,
+    #[arg(long = "repeat-mode", value_parser = ["off", "one", "all"], conflicts_with_all = ["loop_mode", "shuffle_mode", "repeat_mode"], help = "循环策略：off 播完停止，one 单曲循环，all 列表循环，不改变播放顺序")]
+    repeat_policy: Option<String>,
+    #[arg(
+        short = 'p',
+        long = "preview",
+        value_name = "SECONDS",
+        help = "试听模式：每首只播放前若干秒，设为 0 关闭"
+    )]
+    preview: Option<u64>,
+}
+
+#[derive(Parser)]
+struct SeekCommand {
+    #[arg(
+        help = "跳转位置，如 90、1:30、1m30s",
+        required_unless_present = "chapter",
+        conflicts_with = "chapter"
+    )]
+    position: Option<String>,
+    #[arg(
+        short = 'c',
+        long = "chapter",
+        help = "跳转到第几个章节，见 rsg chapters"
+    )]
+    chapter: Option<usize>,
+}
+
+#[derive(Parser)]
+struct VolumeCommand {
+    #[arg(
+        help = "音量（0-100），或以 +/- 开头相对调整，如 +5、-5",
+        allow_hyphen_values = true,
+        value_parser = parse_volume_change
+    )]
+    volume: VolumeChange,
+}
+
+#[derive(Clone, Copy)]
+enum VolumeChange {
+    Set(u8),
+    Adjust(i8),
+}
+
+fn parse_volume_change(input: &str) -> Result<VolumeChange, String> {
+    let error = || format!("无效的音量: {input}");
+    if input.starts_with(['+', '-']) {
+        let delta: i8 = input.parse().map_err(|_| error())?;
+        if delta.unsigned_abs() > 100 {
+            return Err(error());
+        }
+        Ok(VolumeChange::Adjust(delta))
+    } else {
+        let volume: u8 = input.parse().map_err(|_| error())?;
+        if volume > 100 {
+            return Err(error());
+        }
+        Ok(VolumeChange::Set(volume))
+    }
+}
+
+#[derive(Parser)]
+struct StatusCommand {
+    #[arg(
+        short = 'f',
+        long = "follow",
+        help = "持续刷新播放进度，按 Ctrl-C 退出"
+    )]
+    follow: bool,
+    #[arg(long = "debug", help = "同时显示守护进程的内存占用和内部缓存大小")]
+    debug: bool,
+}
+
+#[derive(Parser)]
+struct WidgetCommand {
+    #[arg(
+        long = "format",
+        default_value = "{title} - {owner}",
+        help = "输出格式，可用 {title} {owner} {bvid} {position} {duration} {remaining}（剩余时间） {state} {mode} {playlist} {next}（下一首的标题）"
+    )]
+    format: String,
+    #[arg(short = 'w', long = "watch", help = "持续输出，状态变化时打印新的一行")]
+    watch: bool,
+}
+
+#[derive(Parser)]
+struct InfoCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要查看的 bvid、av 号或视频链接，默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
+    #[arg(
+        short = 'r',
+        long = "replies",
+        default_value_t = 3,
+        help = "显示的热门评论条数，设为 0 不显示"
+    )]
+    replies: usize,
+}
+
+#[derive(Parser)]
+struct FavCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要收藏的 bvid、av 号或视频链接，默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
+    #[arg(
+        short = 'f',
+        long = "folder",
+        help = "收藏夹的名称或 id，默认为默认收藏夹"
+    )]
+    folder: Option<String>,
+    #[arg(short = 'l', long = "list", help = "只列出收藏夹，✓ 表示已收藏该视频")]
+    list: bool,
+}
+
+#[derive(Parser)]
+struct LikeCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要点赞的 bvid、av 号或视频链接，默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
+    #[arg(long = "undo", help = "取消点赞")]
+    undo: bool,
+}
+
+#[derive(Parser)]
+struct CoinCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要投币的 bvid、av 号或视频链接，默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
+    #[arg(
+        short = 'n',
+        long = "count",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=2),
+        help = "投币数量，1 或 2"
+    )]
+    count: u8,
+    #[arg(long = "like", help = "同时点赞")]
+    like: bool,
+}
+
+#[derive(Parser)]
+struct RelatedCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要查看推荐的 bvid、av 号或视频链接，默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
+    #[arg(long = "all", help = "也列出非音乐分区的推荐")]
+    all: bool,
+}
+
+#[derive(Parser)]
+struct ChaptersCommand {
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要查看的 bvid、av 号或视频链接（支持分P），默认为当前播放的歌曲"
+    )]
+    bvid: Option<String>,
 }
 
 #[derive(Parser)]
 struct AddCommand {
     #[arg(short = 'f', long = "fid", help = "要导入的收藏夹 ID")]
     fid: Option<String>,
-    #[arg(short = 'b', long = "bvid", help = "要导入的 bvid")]
+    #[arg(
+        short = 'b',
+        long = "bvid",
+        help = "要导入的 bvid、av 号、视频链接或整段分享文本（支持 b23.tv 短链和分P）"
+    )]
     bvid: Option<String>,
+    #[arg(
+        short = 'a',
+        long = "auid",
+        help = "要导入的音频区歌曲：au 号或音频页面链接"
+    )]
+    auid: Option<String>,
+    #[arg(
+        short = 'e',
+        long = "episode",
+        help = "要导入的番剧、纪录片或课程：ep 号导入单集，ss 号导入整季，也可以是播放页链接"
+    )]
+    episode: Option<String>,
+    #[arg(
+        short = 'w',
+        long = "weekly",
+        help = "导入最新一期每周必听（全站音乐榜）"
+    )]
+    weekly: bool,
+    #[arg(
+        short = 'p',
+        long = "playlist",
+        help = "导入到指定名称的歌单（playlists 目录下的 <名称>.toml），默认为当前歌单"
+    )]
+    playlist: Option<String>,
+    #[arg(long = "replace", help = "清空目标歌单后再导入")]
+    replace: bool,
+    #[arg(
+        long = "max-duration",
+        help = "只导入时长不超过该值的视频，如 10m、1h30m、90s"
+    )]
+    max_duration: Option<String>,
+    #[arg(long = "min-duration", help = "只导入时长不少于该值的视频，如 1m、30s")]
+    min_duration: Option<String>,
+    #[arg(long = "play", help = "导入完成后立即播放导入的第一首歌曲")]
+    play: bool,
+    #[arg(
+        long = "sort",
+        value_parser = ["fav-order", "pubdate", "title"],
+        default_value = "fav-order",
+        help = "导入顺序：收藏夹内顺序、发布时间（新的在前）或标题"
+    )]
+    sort: String,
 }
 
 #[derive(Parser)]
@@ -107,7 +467,11 @@ struct FindCommand {
     bvid: Option<String>,
     #[arg(short = 'c', long = "cid", help = "按 cid 查找")]
     cid: Option<String>,
-    #[arg(short = 't', long = "title", help = "按标题查找")]
+    #[arg(
+        short = 't',
+        long = "title",
+        help = "按标题查找，忽略大小写和全半角，也可以用拼音首字母"
+    )]
     title: Option<String>,
     #[arg(short = 'o', long = "owner", help = "按作者查找")]
     owner: Option<String>,
@@ -125,36 +489,291 @@ struct DeleteCommand {
     all: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
-struct Track {
+#[derive(Parser)]
+struct RadioCommand {
+    #[arg(value_parser = ["on", "off"], help = "开启或关闭，省略时显示当前状态")]
+    state: Option<String>,
+}
+
+#[derive(Parser)]
+struct ChannelCommand {
+    #[arg(
+        value_parser = ["left", "right", "mix", "stereo"],
+        help = "left 只播左声道，right 只播右声道，mix 混合为单声道，stereo 恢复立体声；省略时显示当前设置"
+    )]
+    channels: Option<String>,
+}
+
+#[derive(Parser)]
+struct PitchCommand {
+    #[arg(
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-6..=6),
+        help = "升高（正数）或降低（负数）的半音数，范围 -6 到 +6，0 恢复原调；省略时显示当前设置"
+    )]
+    semitones: Option<i32>,
+}
+
+#[derive(Parser)]
+struct ShareCommand {
+    #[arg(short = 't', long = "time", help = "链接带上当前播放进度（t= 参数）")]
+    time: bool,
+    #[arg(
+        long = "qr",
+        help = "在终端显示链接的二维码，用手机扫码在 B 站 App 打开"
+    )]
+    qr: bool,
+    #[arg(
+        short = 'c',
+        long = "copy",
+        help = "同时复制到剪贴板（Wayland 下用 wl-copy，X11 下用 xclip）"
+    )]
+    copy: bool,
+}
+
+#[derive(Parser)]
+struct DisableCommand {
+    #[arg(short = 'b', long = "bvid", help = "歌曲的 bvid")]
     bvid: String,
-    cid: String,
-    title: String,
-    owner: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Playlist {
-    tracks: Vec<Track>,
+#[derive(Parser)]
+struct SkipCommand {
+    #[arg(short = 'b', long = "bvid", help = "歌曲的 bvid，省略时为当前歌曲")]
+    bvid: Option<String>,
+    #[arg(long = "intro", help = "跳过开头的秒数，0 表示不跳过")]
+    intro: Option<u64>,
+    #[arg(long = "outro", help = "跳过结尾的秒数，0 表示不跳过")]
+    outro: Option<u64>,
+}
+
+#[derive(Parser)]
+struct TrimCommand {
+    #[arg(short = 'b', long = "bvid", help = "歌曲的 bvid，省略时为当前歌曲")]
+    bvid: Option<String>,
+    #[arg(
+        long = "start",
+        help = "开始播放的位置，例如 0:30 或 30s，0 表示从头播放"
+    )]
+    start: Option<String>,
+    #[arg(long = "end", help = "切到下一首的位置，例如 4:10，0 表示播放到结尾")]
+    end: Option<String>,
+}
+
+#[derive(Parser)]
+struct StatsCommand {
+    #[arg(
+        long = "perf",
+        help = "显示最近切歌的延迟（获取音频地址、开始出声）的 P50/P95"
+    )]
+    perf: bool,
+}
+
+#[derive(Parser)]
+struct AudioTrackCommand {
+    #[command(subcommand)]
+    action: AudioTrackAction,
+}
+
+#[derive(Subcommand)]
+enum AudioTrackAction {
+    #[command(about = "切换到 rsg audio-tracks 列出的第 N 条音频流")]
+    Set {
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        n: u32,
+    },
+}
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct LaterCommand {
+    #[command(subcommand)]
+    action: Option<LaterAction>,
+    #[arg(help = "要加入队列的 bvid、av 号、视频链接或 au 号，可以有多个")]
+    inputs: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum LaterAction {
+    #[command(about = "立即开始播放稍后听队列，播完后回到歌单")]
+    Play,
+    #[command(about = "清空稍后听队列")]
+    Clear,
+}
+
+#[derive(Parser)]
+struct ExportCommand {
+    #[arg(help = "导出文件路径")]
+    path: String,
+}
+
+#[derive(Parser)]
+struct EditCommand {
+    #[arg(
+        short = 'p',
+        long = "playlist",
+        help = "要编辑的歌单名称，默认为当前歌单"
+    )]
+    playlist: Option<String>,
+}
+
+#[derive(Parser)]
+struct UndoCommand {
+    #[arg(
+        short = 'p',
+        long = "playlist",
+        help = "要修改的歌单名称，默认为当前歌单"
+    )]
+    playlist: Option<String>,
+}
+
+#[derive(Parser)]
+struct ValidateCommand {
+    #[arg(help = "要检查的文件，省略时检查 settings.toml 和 playlists 目录下的所有歌单")]
+    path: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct SubsonicCommand {
+    #[arg(
+        short = 'p',
+        long = "playlist",
+        help = "要匹配的歌单名称，默认为当前歌单"
+    )]
+    playlist: Option<String>,
+    #[arg(
+        long = "create",
+        help = "把匹配到的歌曲保存为服务器上的歌单，同名歌单会被覆盖"
+    )]
+    create: bool,
+    #[arg(
+        long = "name",
+        help = "服务器上的歌单名称，默认与 -p 相同，没有 -p 时为 rosesong"
+    )]
+    name: Option<String>,
+}
+
+#[derive(Parser)]
+#[command(group(ArgGroup::new("source").required(true).args(["toml", "bookmarks"])))]
+struct ImportCommand {
+    #[arg(long = "toml", help = "要导入的 TOML 播放列表文件")]
+    toml: Option<String>,
+    #[arg(
+        long = "bookmarks",
+        value_name = "FILE",
+        help = "从浏览器书签导入其中的 B 站视频、音频和剧集链接：Firefox 在“管理书签”中备份出的 JSON，或 Chrome/Edge 用户目录下的 Bookmarks 文件"
+    )]
+    bookmarks: Option<PathBuf>,
+    #[arg(
+        long = "folder",
+        value_name = "NAME",
+        requires = "bookmarks",
+        help = "只导入该名称的书签文件夹（含子文件夹）中的链接"
+    )]
+    folder: Option<String>,
+}
+
+#[derive(Parser)]
+struct CompleteCommand {
+    #[arg(value_parser = ["bvid"])]
+    kind: String,
+    #[arg(default_value = "")]
+    prefix: String,
 }
 
 #[tokio::main]
-async fn main() -> StdResult<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    let connection = Connection::session().await?;
-    let proxy = MyPlayerProxy::new(&connection).await?;
-    handle_command(cli, proxy).await
+    if let Commands::Complete(complete_cmd) = &cli.command {
+        complete(&complete_cmd.prefix);
+        return ExitCode::SUCCESS;
+    }
+    let json = cli.json;
+    // A broken settings file is reported by the command that needs it
+    if let Ok(settings) = Settings::load() {
+        rosesong_core::bilibili::limiter::configure(&settings.network);
+        rosesong_core::bilibili::cache::configure(&settings.network);
+    }
+    let result = match PlayerClient::connect().await {
+        Ok(proxy) => handle_command(cli, proxy).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(&e, json);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Prints `error` to stderr, or as `{"error": {"code", "message"}}` to
+/// stdout with `--json`.
+fn report_error(error: &App, json: bool) {
+    let mut message = error.to_string();
+    if let Some(source) = std::error::Error::source(error) {
+        message = format!("{message}: {source}");
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "error": {
+                    "code": error.code(),
+                    "exit_code": error.exit_code(),
+                    "message": message,
+                }
+            })
+        );
+    } else {
+        eprintln!("{message}");
+    }
 }
 
-async fn handle_command(cli: Cli, proxy: MyPlayerProxy<'_>) -> StdResult<()> {
+async fn handle_command(cli: Cli, proxy: PlayerClient) -> StdResult<()> {
     match cli.command {
         Commands::Play(play_cmd) => handle_play_command(play_cmd, &proxy).await,
         Commands::Pause => handle_pause_command(&proxy).await,
         Commands::Next => handle_next_command(&proxy).await,
         Commands::Previous => handle_previous_command(&proxy).await,
         Commands::Stop => handle_stop_command(&proxy).await,
+        Commands::Quit => handle_quit_command(&proxy).await,
         Commands::Mode(mode_cmd) => handle_mode_command(mode_cmd, &proxy).await,
-        Commands::Add(add_cmd) => add_tracks(add_cmd.fid, add_cmd.bvid, &proxy).await,
+        Commands::Radio(radio_cmd) => handle_radio_command(radio_cmd.state, &proxy).await,
+        Commands::Channel(channel_cmd) => {
+            handle_channel_command(channel_cmd.channels, &proxy).await
+        }
+        Commands::Pitch(pitch_cmd) => handle_pitch_command(pitch_cmd.semitones, &proxy).await,
+        Commands::Seek(seek_cmd) => handle_seek_command(seek_cmd, &proxy).await,
+        Commands::Volume(volume_cmd) => handle_volume_command(volume_cmd.volume, &proxy).await,
+        Commands::Status(status_cmd) => handle_status_command(status_cmd, &proxy, cli.json).await,
+        Commands::Ctl => ctl::run_ctl(&proxy).await,
+        Commands::Top => top::run_top(&proxy).await,
+        Commands::Widget(widget_cmd) => {
+            widget::show_widget(&proxy, &widget_cmd.format, cli.json, widget_cmd.watch).await
+        }
+        Commands::Info(info_cmd) => handle_info_command(info_cmd, &proxy).await,
+        Commands::Share(share_cmd) => {
+            share::share(&proxy, share_cmd.time, share_cmd.qr, share_cmd.copy).await
+        }
+        Commands::Fav(fav_cmd) => {
+            triple::favorite(&proxy, fav_cmd.bvid, fav_cmd.folder, fav_cmd.list).await
+        }
+        Commands::Like(like_cmd) => triple::like(&proxy, like_cmd.bvid, like_cmd.undo).await,
+        Commands::Coin(coin_cmd) => {
+            triple::coin(&proxy, coin_cmd.bvid, coin_cmd.count, coin_cmd.like).await
+        }
+        Commands::Related(related_cmd) => {
+            related::show_related(&proxy, related_cmd.bvid, related_cmd.all).await
+        }
+        Commands::Chapters(chapters_cmd) => handle_chapters_command(chapters_cmd, &proxy).await,
+        Commands::Add(add_cmd) => add_tracks(add_cmd, &proxy).await,
+        Commands::Later(later_cmd) => match later_cmd.action {
+            Some(LaterAction::Play) => later::play_later(&proxy).await,
+            Some(LaterAction::Clear) => later::clear_later(),
+            None if later_cmd.inputs.is_empty() => later::show_later(),
+            None => later::queue_later(&later_cmd.inputs).await,
+        },
         Commands::Delete(delete_cmd) => {
             delete_tracks(
                 delete_cmd.bvid,
@@ -166,85 +785,140 @@ async fn handle_command(cli: Cli, proxy: MyPlayerProxy<'_>) -> StdResult<()> {
             .await
         }
         Commands::Find(find_cmd) => {
-            find_track(find_cmd.bvid, find_cmd.cid, find_cmd.title, find_cmd.owner).await
+            find_track(
+                find_cmd.bvid,
+                find_cmd.cid,
+                find_cmd.title,
+                find_cmd.owner,
+                &proxy,
+            )
+            .await
+        }
+        Commands::Disable(disable_cmd) => set_disabled(&disable_cmd.bvid, true, &proxy).await,
+        Commands::Enable(enable_cmd) => set_disabled(&enable_cmd.bvid, false, &proxy).await,
+        Commands::Skip(skip_cmd) => set_skip(skip_cmd, &proxy).await,
+        Commands::Trim(trim_cmd) => trim_track(trim_cmd, &proxy).await,
+        Commands::Playlist => display_playlist(&proxy).await,
+        Commands::Stats(stats_cmd) => handle_stats_command(stats_cmd.perf, &proxy).await,
+        Commands::AudioTracks => show_audio_tracks(&proxy).await,
+        Commands::AudioTrack(audio_track_cmd) => match audio_track_cmd.action {
+            AudioTrackAction::Set { n } => set_audio_track(n, &proxy).await,
+        },
+        Commands::Start(start_cmd) => start_rosesong(start_cmd, &proxy).await,
+        Commands::Export(export_cmd) => export_playlist(&export_cmd.path).await,
+        Commands::Edit(edit_cmd) => edit::edit(edit_cmd.playlist, &proxy).await,
+        Commands::Undo(undo_cmd) => undo::step(undo_cmd.playlist, false, &proxy).await,
+        Commands::Redo(redo_cmd) => undo::step(redo_cmd.playlist, true, &proxy).await,
+        Commands::Validate(validate_cmd) => validate::validate(validate_cmd.path),
+        Commands::Subsonic(subsonic_cmd) => {
+            subsonic::sync(
+                subsonic_cmd.playlist,
+                subsonic_cmd.name,
+                subsonic_cmd.create,
+            )
+            .await
         }
-        Commands::Playlist => display_playlist().await,
-        Commands::Start => start_rosesong(&proxy).await,
+        Commands::Import(import_cmd) => match (import_cmd.toml, import_cmd.bookmarks) {
+            (Some(toml), _) => import_playlist(&toml, &proxy).await,
+            (None, Some(path)) => {
+                bookmarks::import_bookmarks(&path, import_cmd.folder.as_deref(), &proxy).await
+            }
+            (None, None) => Ok(()),
+        },
+        Commands::Complete(_) => Ok(()),
     }
 }
 
-async fn handle_play_command(play_cmd: PlayCommand, proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if let Some(bvid) = play_cmd.bvid {
-        if !is_rosesong_running(proxy).await? {
-            eprintln!("rosesong 没有处于运行状态");
-        } else if is_playlist_empty().await? {
-            eprintln!("当前播放列表为空，请先添加歌曲");
-        } else {
-            proxy.play_bvid(&bvid).await?;
-            println!("播放指定bvid");
+async fn handle_play_command(play_cmd: PlayCommand, proxy: &PlayerClient) -> StdResult<()> {
+    if let Some(season) = play_cmd.season {
+        ensure_running(proxy).await?;
+        let id = find_season(&open_store()?.load()?, &season)
+            .ok_or_else(|| App::InvalidInput(format!("当前歌单中没有属于合集 {season} 的歌曲")))?;
+        proxy.play_season(&id).await?;
+        println!("开始连播合集 {id}");
+    } else if let Some(bvid) = play_cmd.bvid {
+        ensure_playable(proxy).await?;
+        let bvid = resolve_bvid(&reqwest::Client::new(), &bvid).await?;
+        let tracks = open_store()?.load()?;
+        if tracks
+            .iter()
+            .any(|track| track.bvid == bvid && track.disabled)
+        {
+            return Err(App::InvalidInput(format!(
+                "{bvid} 已被禁用，使用 rsg enable -b {bvid} 恢复"
+            )));
         }
-    } else if !is_rosesong_running(proxy).await? {
-        eprintln!("rosesong 没有处于运行状态");
-    } else if is_playlist_empty().await? {
-        eprintln!("当前播放列表为空，请先添加歌曲");
+        proxy.play_bvid(&bvid).await?;
+        println!("播放指定bvid");
     } else {
+        ensure_playable(proxy).await?;
         proxy.play().await?;
         println!("继续播放");
     }
     Ok(())
 }
 
-async fn handle_pause_command(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if !is_rosesong_running(proxy).await? {
-        eprintln!("rosesong 没有处于运行状态");
-    } else if is_playlist_empty().await? {
-        eprintln!("当前播放列表为空，请先添加歌曲");
-    } else {
-        proxy.pause().await?;
-        println!("暂停播放");
-    }
+async fn handle_pause_command(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    proxy.pause().await?;
+    println!("暂停播放");
     Ok(())
 }
 
-async fn handle_next_command(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if !is_rosesong_running(proxy).await? {
-        eprintln!("rosesong 没有处于运行状态");
-    } else if is_playlist_empty().await? {
-        eprintln!("当前播放列表为空，请先添加歌曲");
-    } else {
-        proxy.next().await?;
-        println!("播放下一首");
-    }
+async fn handle_next_command(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    proxy.next().await?;
+    println!("播放下一首");
     Ok(())
 }
 
-async fn handle_previous_command(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if !is_rosesong_running(proxy).await? {
-        eprintln!("rosesong 没有处于运行状态");
-    } else if is_playlist_empty().await? {
-        eprintln!("当前播放列表为空，请先添加歌曲");
-    } else {
-        proxy.previous().await?;
-        println!("播放上一首");
-    }
+async fn handle_previous_command(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    proxy.previous().await?;
+    println!("播放上一首");
     Ok(())
 }
 
-async fn handle_stop_command(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if is_rosesong_running(proxy).await? {
-        proxy.stop().await?;
-        println!("rosesong已退出");
-    } else {
-        eprintln!("rosesong 没有处于运行状态");
-    }
+async fn handle_stop_command(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    proxy.stop().await?;
+    println!("已停止播放，使用 rsg play 继续");
+    Ok(())
+}
+
+async fn handle_quit_command(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    proxy.quit().await?;
+    println!("rosesong已退出");
     Ok(())
 }
 
-async fn handle_mode_command(mode_cmd: ModeCommand, proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
-    if !is_rosesong_running(proxy).await? {
-        eprintln!("rosesong 没有处于运行状态");
-    } else if is_playlist_empty().await? {
-        eprintln!("当前播放列表为空，请先添加歌曲");
+async fn handle_mode_command(mode_cmd: ModeCommand, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    if let Some(seconds) = mode_cmd.preview {
+        proxy.set_preview(seconds).await?;
+        if seconds == 0 {
+            println!("已关闭试听模式");
+        } else {
+            println!("试听模式：每首播放 {seconds} 秒");
+        }
+    } else if mode_cmd.order.is_some() || mode_cmd.repeat_policy.is_some() {
+        if let Some(order) = mode_cmd.order {
+            proxy.set_shuffle(order == "shuffle").await?;
+        }
+        if let Some(repeat) = mode_cmd.repeat_policy {
+            proxy.set_repeat(&repeat).await?;
+        }
+        let status = proxy.status().await?;
+        println!(
+            "播放顺序：{}，循环策略：{}",
+            if status.shuffle { "随机" } else { "顺序" },
+            match status.repeat.as_str() {
+                "off" => "播完停止",
+                "one" => "单曲循环",
+                _ => "列表循环",
+            }
+        );
     } else if mode_cmd.loop_mode {
         proxy.set_mode("Loop").await?;
         println!("设置为循环播放");
@@ -255,41 +929,252 @@ async fn handle_mode_command(mode_cmd: ModeCommand, proxy: &MyPlayerProxy<'_>) -
         proxy.set_mode("Repeat").await?;
         println!("设置为单曲循环");
     } else {
-        eprintln!("没有这个播放模式");
+        return Err(App::InvalidInput("没有这个播放模式".to_string()));
+    }
+    Ok(())
+}
+
+async fn handle_radio_command(state: Option<String>, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let enabled = match state.as_deref() {
+        Some(state) => {
+            let enabled = state == "on";
+            proxy.set_radio(enabled).await?;
+            enabled
+        }
+        None => proxy.status().await?.radio,
+    };
+    if enabled {
+        println!("电台模式已开启，播完一首后会续播相关推荐");
+    } else {
+        println!("电台模式已关闭");
+    }
+    Ok(())
+}
+
+async fn handle_channel_command(channels: Option<String>, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let channels = match channels {
+        Some(channels) => {
+            proxy.set_channels(&channels).await?;
+            channels
+        }
+        None => proxy.status().await?.channels,
+    };
+    let description = match channels.as_str() {
+        "left" => "只播放左声道",
+        "right" => "只播放右声道",
+        "mix" => "左右声道混合为单声道",
+        _ => "立体声",
+    };
+    println!("声道：{description}");
+    Ok(())
+}
+
+async fn handle_pitch_command(semitones: Option<i32>, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let semitones = match semitones {
+        Some(semitones) => {
+            proxy.set_pitch(semitones).await?;
+            semitones
+        }
+        None => proxy.status().await?.pitch,
+    };
+    match semitones {
+        0 => println!("音调：原调"),
+        semitones if semitones > 0 => println!("音调：升 {semitones} 个半音"),
+        semitones => println!("音调：降 {} 个半音", -semitones),
+    }
+    Ok(())
+}
+
+async fn handle_seek_command(seek_cmd: SeekCommand, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+
+    let seconds = if let Some(chapter) = seek_cmd.chapter {
+        let Some(track) = proxy.status().await?.track else {
+            eprintln!("当前没有正在播放的歌曲");
+            return Ok(());
+        };
+        let chapters =
+            bilibili::video_info::fetch_chapters(&reqwest::Client::new(), &track.bvid, &track.cid)
+                .await?;
+        let Some(target) = chapter.checked_sub(1).and_then(|index| chapters.get(index)) else {
+            return Err(App::InvalidInput(format!(
+                "当前歌曲共有 {} 个章节，没有第 {chapter} 个",
+                chapters.len()
+            )));
+        };
+        println!("跳转到章节 {chapter}: {}", target.content);
+        target.from
+    } else {
+        parse_duration(seek_cmd.position.as_deref().unwrap_or_default())?
+    };
+
+    #[allow(clippy::cast_precision_loss)]
+    proxy.seek(seconds as f64).await?;
+    println!("跳转到 {}", format_duration(seconds));
+    Ok(())
+}
+
+async fn handle_chapters_command(
+    chapters_cmd: ChaptersCommand,
+    proxy: &PlayerClient,
+) -> StdResult<()> {
+    let (bvid, cid) = if let Some(input) = chapters_cmd.bvid {
+        let client = reqwest::Client::new();
+        let video = resolve_video(&client, &input).await?;
+        let mut data = bilibili::fetch_audio_info::fetch_video_data(&client, &video.bvid).await?;
+        if let Some(page) = video.page {
+            data.select_page(page)?;
+        }
+        (data.bvid, data.cid.to_string())
+    } else if is_rosesong_running(proxy).await? {
+        let Some(track) = proxy.status().await?.track else {
+            eprintln!("当前没有正在播放的歌曲");
+            return Ok(());
+        };
+        (track.bvid, track.cid)
+    } else {
+        return Err(App::NotRunning);
+    };
+    info::show_chapters(&bvid, &cid).await
+}
+
+async fn handle_volume_command(volume: VolumeChange, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    match volume {
+        VolumeChange::Set(volume) => {
+            proxy.set_volume(f64::from(volume) / 100.0).await?;
+            println!("音量设置为 {volume}");
+        }
+        VolumeChange::Adjust(delta) => {
+            proxy.adjust_volume(f64::from(delta) / 100.0).await?;
+            println!("音量调整 {delta:+}");
+        }
+    }
+    Ok(())
+}
+
+async fn handle_status_command(
+    status_cmd: StatusCommand,
+    proxy: &PlayerClient,
+    json: bool,
+) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    if status_cmd.debug && !json {
+        status::show_debug_info(proxy).await?;
+    }
+    status::show_status(proxy, status_cmd.follow, json).await
+}
+
+async fn handle_info_command(info_cmd: InfoCommand, proxy: &PlayerClient) -> StdResult<()> {
+    let bvid = if let Some(bvid) = info_cmd.bvid {
+        resolve_bvid(&reqwest::Client::new(), &bvid).await?
+    } else if is_rosesong_running(proxy).await? {
+        let Some(track) = proxy.status().await?.track else {
+            eprintln!("当前没有正在播放的歌曲");
+            return Ok(());
+        };
+        track.bvid
+    } else {
+        return Err(App::NotRunning);
+    };
+    info::show_info(&bvid, info_cmd.replies).await
+}
+
+/// Fails with `App::NotRunning` unless rosesong answers.
+async fn ensure_running(proxy: &PlayerClient) -> StdResult<()> {
+    if is_rosesong_running(proxy).await? {
+        Ok(())
+    } else {
+        Err(App::NotRunning)
+    }
+}
+
+/// Fails unless rosesong is running and the playlist has tracks to play.
+async fn ensure_playable(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    if is_playlist_empty()? {
+        return Err(App::PlaylistEmpty);
     }
     Ok(())
 }
 
-async fn is_rosesong_running(proxy: &MyPlayerProxy<'_>) -> StdResult<bool> {
+async fn is_rosesong_running(proxy: &PlayerClient) -> StdResult<bool> {
     match proxy.test_connection().await {
         Ok(()) => Ok(true),
         Err(_) => Ok(false),
     }
 }
 
-async fn is_playlist_empty() -> StdResult<bool> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    if !Path::new(&playlist_path).exists() {
-        return Ok(true);
+/// Prints the bvids of the current playlist starting with `prefix`, each
+/// with its title. Errors print nothing so a shell completion stays quiet.
+fn complete(prefix: &str) {
+    let Ok(tracks) = open_store().and_then(|store| Ok(store.load()?)) else {
+        return;
+    };
+    let prefix = prefix.to_ascii_lowercase();
+    for track in tracks
+        .iter()
+        .filter(|track| track.bvid.to_ascii_lowercase().starts_with(&prefix))
+    {
+        println!("{}\t{}", track.bvid, track.title.replace(['\t', '\n'], " "));
     }
-    let content = fs::read_to_string(&playlist_path).await.map_err(App::Io)?;
-    Ok(content.trim().is_empty())
 }
 
-async fn initialize_directories() -> StdResult<String> {
-    let home_dir = std::env::var("HOME")?;
-    let required_dirs = [format!("{home_dir}/.config/rosesong/playlists")];
-    for dir in &required_dirs {
-        fs::create_dir_all(dir).await?;
+/// Season id of the tracks matching `input`, which may leave out the `ss`
+/// prefix of bangumi seasons.
+fn find_season(tracks: &[Track], input: &str) -> Option<String> {
+    let input = input.trim();
+    let prefixed = format!("ss{input}");
+    let ids = tracks
+        .iter()
+        .filter_map(|track| track.season.as_ref())
+        .map(|season| season.id.as_str());
+    let found = [input, prefixed.as_str()]
+        .into_iter()
+        .find(|candidate| ids.clone().any(|id| id == *candidate))
+        .map(str::to_string);
+    found
+}
+
+fn is_playlist_empty() -> StdResult<bool> {
+    Ok(open_store()?.is_empty()?)
+}
+
+fn open_store() -> StdResult<Box<dyn PlaylistStore>> {
+    let settings = Settings::load()?;
+    Ok(storage::open(&settings.storage)?)
+}
+
+/// Tells a running rosesong that the playlist changed, if it is running.
+async fn notify_playlist_change(proxy: &PlayerClient) -> StdResult<()> {
+    if let Ok(is_running) = is_rosesong_running(proxy).await {
+        if is_running {
+            if is_playlist_empty()? {
+                proxy.playlist_is_empty().await?;
+            } else {
+                proxy.playlist_change().await?;
+            }
+        }
     }
-    let playlist_path = format!("{home_dir}/.config/rosesong/playlists/playlist.toml");
-    if !Path::new(&playlist_path).exists() {
-        fs::write(&playlist_path, "").await?;
+    Ok(())
+}
+
+/// Like [`notify_playlist_change`] for the playlist called `name`, which a
+/// running rosesong may not be playing.
+async fn notify_named_playlist_change(proxy: &PlayerClient, name: &str) -> StdResult<()> {
+    if name == DEFAULT_PLAYLIST {
+        notify_playlist_change(proxy).await
+    } else if is_rosesong_running(proxy).await? {
+        proxy.playlist_change().await
+    } else {
+        Ok(())
     }
-    Ok(format!("{home_dir}/.config/rosesong/playlists"))
 }
 
-async fn start_rosesong(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
+async fn start_rosesong(start_cmd: StartCommand, proxy: &PlayerClient) -> StdResult<()> {
     if is_rosesong_running(proxy).await? {
         println!("RoseSong 当前已经处于运行状态");
         return Ok(());
@@ -299,7 +1184,7 @@ async fn start_rosesong(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
     let exe_dir = current_exe_path.parent().ok_or_else(|| {
         App::InvalidInput("Failed to get the directory of the executable".to_string())
     })?;
-    let rosesong_path = exe_dir.join("rosesong");
+    let rosesong_path = exe_dir.join(format!("rosesong{}", std::env::consts::EXE_SUFFIX));
 
     if !rosesong_path.exists() {
         return Err(App::InvalidInput(
@@ -307,75 +1192,328 @@ async fn start_rosesong(proxy: &MyPlayerProxy<'_>) -> StdResult<()> {
         ));
     }
 
-    let child = Command::new(rosesong_path).spawn().map_err(App::Io)?;
-    println!("RoseSong 成功启动，进程 ID: {:?}", child.id());
-    Ok(())
+    let mut command = Command::new(rosesong_path);
+    if let Some(playlist) = start_cmd.playlist {
+        command.arg("--playlist").arg(playlist);
+    }
+    if let Some(index) = start_cmd.index {
+        command.arg("--index").arg(index.to_string());
+    }
+    if let Some(mode) = start_cmd.mode {
+        command.arg("--mode").arg(mode);
+    }
+    let mut child = command.spawn().map_err(App::Io)?;
+    if start_cmd.no_wait {
+        println!("RoseSong 成功启动，进程 ID: {:?}", child.id());
+        return Ok(());
+    }
+
+    // The daemon answers only once its control channels are registered, so
+    // commands run right after `rsg start` no longer race it.
+    let deadline = Instant::now() + Duration::from_secs(start_cmd.timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(App::InvalidInput(format!(
+                "RoseSong 启动后立即退出（{status}），请查看日志"
+            )));
+        }
+        if is_rosesong_running(&PlayerClient::connect().await?).await? {
+            println!("RoseSong 成功启动，进程 ID: {:?}", child.id());
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(App::InvalidInput(format!(
+                "等待 {} 秒后 RoseSong 仍未就绪",
+                start_cmd.timeout
+            )));
+        }
+        tokio::time::sleep(START_POLL_INTERVAL).await;
+    }
 }
 
-async fn add_tracks(
-    fid: Option<String>,
-    bvid: Option<String>,
-    proxy: &MyPlayerProxy<'_>,
-) -> StdResult<()> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    let old_content = fs::read_to_string(&playlist_path).await.unwrap_or_default();
-    import_favorite_or_bvid(fid, bvid).await?;
-    let new_content = fs::read_to_string(&playlist_path).await.unwrap_or_default();
-    if old_content != new_content {
-        if let Ok(is_running) = is_rosesong_running(proxy).await {
-            if is_running {
-                proxy.playlist_change().await?;
-            }
+async fn add_tracks(add_cmd: AddCommand, proxy: &PlayerClient) -> StdResult<()> {
+    let duration_range = DurationRange {
+        min: add_cmd
+            .min_duration
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?,
+        max: add_cmd
+            .max_duration
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?,
+    };
+    let source = if add_cmd.weekly {
+        VideoSource::Weekly
+    } else if let Some(fid) = add_cmd.fid {
+        VideoSource::Favorite(fid)
+    } else if let Some(bvid) = add_cmd.bvid.as_deref() {
+        VideoSource::Video(resolve_video(&reqwest::Client::new(), bvid).await?)
+    } else if let Some(auid) = add_cmd.auid.as_deref() {
+        VideoSource::Song(
+            parse_auid(auid).ok_or_else(|| App::InvalidInput(format!("无效的 au 号: {auid}")))?,
+        )
+    } else if let Some(episode) = add_cmd.episode.as_deref() {
+        VideoSource::Episodes(
+            parse_episode(episode)
+                .ok_or_else(|| App::InvalidInput(format!("无效的 ep/ss 号: {episode}")))?,
+        )
+    } else {
+        return Err(App::InvalidInput(
+            "请提供正确的 fid、bvid、au 号或 ep/ss 号".to_string(),
+        ));
+    };
+    let store = match add_cmd.playlist.as_deref() {
+        Some(name) => storage::open_named(&Settings::load()?.storage, name)?,
+        None => open_store()?,
+    };
+    let old_fingerprint = store.fingerprint()?;
+    let sort = match add_cmd.sort.as_str() {
+        "pubdate" => SortOrder::Pubdate,
+        "title" => SortOrder::Title,
+        _ => SortOrder::Source,
+    };
+    let settings = Settings::load()?;
+    let options = ImportOptions {
+        duration_range,
+        sort,
+        replace: add_cmd.replace,
+        new_tracks: settings.import.new_tracks,
+        blacklist: settings.blacklist,
+    };
+    let first_bvid = import_videos(store.as_ref(), source, options).await?;
+    let is_running = is_rosesong_running(proxy).await.unwrap_or(false);
+    if is_running && old_fingerprint != store.fingerprint()? {
+        proxy.playlist_change().await?;
+    }
+    if let (true, Some(bvid)) = (add_cmd.play, first_bvid) {
+        let target = add_cmd.playlist.as_deref().unwrap_or(DEFAULT_PLAYLIST);
+        if !is_running {
+            eprintln!("rosesong 没有处于运行状态，未开始播放");
+        } else if proxy.status().await?.playlist == target {
+            proxy.play_bvid(&bvid).await?;
+            println!("开始播放 {bvid}");
+        } else {
+            eprintln!("歌单 {target} 不是正在播放的歌单，未开始播放");
         }
     }
     Ok(())
 }
 
-async fn import_favorite_or_bvid(fid: Option<String>, bvid: Option<String>) -> StdResult<()> {
+/// Gives `track` the metadata of `fresh`, keeping what the user set for it.
+fn refresh_track(track: &mut Track, fresh: &Track) {
+    *track = Track {
+        disabled: track.disabled,
+        skip_intro: track.skip_intro,
+        skip_outro: track.skip_outro,
+        start: track.start,
+        end: track.end,
+        note: std::mem::take(&mut track.note),
+        ..fresh.clone()
+    };
+}
+
+#[derive(Clone, Copy, Default)]
+struct DurationRange {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl DurationRange {
+    fn contains(self, duration: u64) -> bool {
+        self.min.map_or(true, |min| duration >= min) && self.max.map_or(true, |max| duration <= max)
+    }
+}
+
+/// Parses a duration such as `90`, `90s`, `10m`, `1h30m` or `1:30` into seconds.
+fn parse_duration(input: &str) -> StdResult<u64> {
+    let invalid = || App::InvalidInput(format!("无效的时长: {input}"));
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Ok(seconds);
+    }
+    if input.contains(':') {
+        return input.split(':').try_fold(0u64, |total, part| {
+            let value = part.parse::<u64>().map_err(|_| invalid())?;
+            total
+                .checked_mul(60)
+                .and_then(|total| total.checked_add(value))
+                .ok_or_else(invalid)
+        });
+    }
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        let unit = match c.to_ascii_lowercase() {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+fn format_duration(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SortOrder {
+    /// The order the source lists them in.
+    Source,
+    /// Newest first.
+    Pubdate,
+    Title,
+}
+
+struct ImportOptions {
+    duration_range: DurationRange,
+    sort: SortOrder,
+    /// Empty the playlist first.
+    replace: bool,
+    new_tracks: InsertPosition,
+    blacklist: BlacklistSettings,
+}
+
+async fn import_videos(
+    store: &dyn PlaylistStore,
+    source: VideoSource,
+    options: ImportOptions,
+) -> StdResult<Option<String>> {
     let client = reqwest::Client::new();
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
     println!("正在获取相关信息");
-    let video_data_list = get_video_data(&client, fid.as_deref(), bvid.as_deref()).await?;
+    let video_data_list = get_video_data(&client, source).await?;
+    store_videos(store, video_data_list, &options)
+}
+
+/// Adds the videos of `video_data_list` that `options` let through to
+/// `store`. Returns the bvid of the one to play first, if any was left.
+fn store_videos(
+    store: &dyn PlaylistStore,
+    mut video_data_list: Vec<VideoData>,
+    options: &ImportOptions,
+) -> StdResult<Option<String>> {
+    match options.sort {
+        SortOrder::Source => {}
+        SortOrder::Pubdate => video_data_list.sort_by_key(|video| std::cmp::Reverse(video.pubdate)),
+        SortOrder::Title => video_data_list.sort_by(|a, b| a.title.cmp(&b.title)),
+    }
+    let fetched_count = video_data_list.len();
     let mut new_tracks = Vec::new();
+    let mut blocked_count = 0;
     for video_data in video_data_list {
+        if !options.duration_range.contains(video_data.duration) {
+            continue;
+        }
+        if options
+            .blacklist
+            .blocks(video_data.owner.mid, &video_data.title)
+        {
+            blocked_count += 1;
+            continue;
+        }
         new_tracks.push(Track {
             bvid: video_data.bvid.clone(),
             cid: video_data.cid.to_string().clone(),
             title: video_data.title.clone(),
             owner: video_data.owner.name.clone(),
+            duration: video_data.duration,
+            kind: video_data.kind,
+            season: video_data.season.clone(),
+            disabled: false,
+            skip_intro: 0,
+            skip_outro: 0,
+            start: 0,
+            end: 0,
+            note: String::new(),
         });
     }
-    let mut existing_tracks = if Path::new(&playlist_path).exists() {
-        let content = fs::read_to_string(&playlist_path).await.map_err(App::Io)?;
-        toml::from_str::<Playlist>(&content).map_or_else(|_| Vec::new(), |playlist| playlist.tracks)
-    } else {
+    let out_of_range_count = fetched_count - new_tracks.len() - blocked_count;
+    if out_of_range_count > 0 {
+        println!("已按时长过滤掉 {out_of_range_count} 个视频");
+    }
+    if blocked_count > 0 {
+        println!("已按黑名单过滤掉 {blocked_count} 个视频");
+    }
+    if new_tracks.is_empty() {
+        // Only blame the filters when they are what left nothing
+        if fetched_count > 0 {
+            println!("没有符合条件的视频");
+        }
+        return Ok(None);
+    }
+    let mut existing_tracks = if options.replace {
         Vec::new()
+    } else {
+        store.load()?
     };
     let existing_bvids: HashSet<_> = existing_tracks
         .iter()
         .map(|track| track.bvid.clone())
         .collect();
+    // Prefer a track that was not in the playlist yet
+    let first_bvid = new_tracks
+        .iter()
+        .find(|track| !existing_bvids.contains(&track.bvid))
+        .unwrap_or(&new_tracks[0])
+        .bvid
+        .clone();
     for track in &mut existing_tracks {
         if let Some(new_track) = new_tracks.iter().find(|t| t.bvid == track.bvid) {
-            *track = new_track.clone();
+            refresh_track(track, new_track);
         }
     }
+    // Where the next new track goes when not appending
+    let mut cursor = 0;
     for new_track in new_tracks {
-        if !existing_bvids.contains(&new_track.bvid) {
+        if existing_bvids.contains(&new_track.bvid) {
+            if options.new_tracks == InsertPosition::Source {
+                if let Some(index) = existing_tracks
+                    .iter()
+                    .position(|t| t.bvid == new_track.bvid)
+                {
+                    cursor = index + 1;
+                }
+            }
+            continue;
+        }
+        if options.new_tracks == InsertPosition::End {
             existing_tracks.push(new_track);
+        } else {
+            existing_tracks.insert(cursor, new_track);
+            cursor += 1;
         }
     }
-    let playlist = Playlist {
-        tracks: existing_tracks,
-    };
-    let toml_content = toml::to_string(&playlist)
-        .map_err(|_| App::DataParsing("Failed to serialize tracks to TOML".to_string()))?;
-    let mut file = fs::File::create(&playlist_path).await.map_err(App::Io)?;
-    file.write_all(toml_content.as_bytes())
-        .await
-        .map_err(App::Io)?;
+    store.save(&existing_tracks)?;
     println!("导入成功");
-    Ok(())
+    Ok(Some(first_bvid))
 }
 
 async fn delete_tracks(
@@ -383,37 +1521,24 @@ async fn delete_tracks(
     cid: Option<String>,
     owner: Option<String>,
     all: bool,
-    proxy: &MyPlayerProxy<'_>,
+    proxy: &PlayerClient,
 ) -> StdResult<()> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    let old_content = fs::read_to_string(&playlist_path).await.unwrap_or_default();
-    perform_deletion(bvid, cid, owner, all).await?;
-    let new_content = fs::read_to_string(&playlist_path).await.unwrap_or_default();
-    if old_content != new_content {
-        if let Ok(is_running) = is_rosesong_running(proxy).await {
-            if is_running {
-                if is_playlist_empty().await? {
-                    proxy.playlist_is_empty().await?;
-                } else {
-                    proxy.playlist_change().await?;
-                }
-            }
-        }
+    let store = open_store()?;
+    let old_fingerprint = store.fingerprint()?;
+    perform_deletion(store.as_ref(), bvid, cid, owner, all).await?;
+    if old_fingerprint != store.fingerprint()? {
+        notify_playlist_change(proxy).await?;
     }
     Ok(())
 }
 
 async fn perform_deletion(
+    store: &dyn PlaylistStore,
     bvid: Option<String>,
     cid: Option<String>,
     owner: Option<String>,
     all: bool,
 ) -> StdResult<()> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    if !Path::new(&playlist_path).exists() {
-        eprintln!("播放列表文件不存在");
-        return Ok(());
-    }
     if all {
         println!("即将清空播放列表，是否确认删除所有歌曲？(y/n)");
         let mut confirmation = String::new();
@@ -423,39 +1548,24 @@ async fn perform_deletion(
             .await
             .expect("Failed to read line");
         if confirmation.trim().eq_ignore_ascii_case("y") {
-            fs::write(&playlist_path, "").await.map_err(App::Io)?;
+            store.save(&[])?;
             println!("播放列表已清空");
         } else {
             println!("取消清空操作");
         }
         return Ok(());
     }
-    let content = fs::read_to_string(&playlist_path).await.map_err(App::Io)?;
-    let mut playlist: Playlist = toml::from_str(&content)
-        .map_err(|_| App::DataParsing("Failed to parse playlist.toml".to_string()))?;
+    let mut tracks = store.load()?;
     let mut tracks_to_delete: Vec<Track> = Vec::new();
     if let Some(bvid) = bvid {
-        tracks_to_delete.extend(
-            playlist
-                .tracks
-                .iter()
-                .filter(|track| track.bvid == bvid)
-                .cloned(),
-        );
+        tracks_to_delete.extend(tracks.iter().filter(|track| track.bvid == bvid).cloned());
     }
     if let Some(cid) = cid {
-        tracks_to_delete.extend(
-            playlist
-                .tracks
-                .iter()
-                .filter(|track| track.cid == cid)
-                .cloned(),
-        );
+        tracks_to_delete.extend(tracks.iter().filter(|track| track.cid == cid).cloned());
     }
     if let Some(owner) = owner {
         tracks_to_delete.extend(
-            playlist
-                .tracks
+            tracks
                 .iter()
                 .filter(|track| track.owner.contains(&owner))
                 .cloned(),
@@ -476,15 +1586,8 @@ async fn perform_deletion(
         .await
         .expect("Failed to read line");
     if confirmation.trim().eq_ignore_ascii_case("y") {
-        playlist
-            .tracks
-            .retain(|track| !tracks_to_delete.contains(track));
-        let toml_content = toml::to_string(&playlist)
-            .map_err(|_| App::DataParsing("Failed to serialize tracks to TOML".to_string()))?;
-        let mut file = fs::File::create(&playlist_path).await.map_err(App::Io)?;
-        file.write_all(toml_content.as_bytes())
-            .await
-            .map_err(App::Io)?;
+        tracks.retain(|track| !tracks_to_delete.contains(track));
+        store.save(&tracks)?;
         println!("删除成功");
     } else {
         println!("取消删除操作");
@@ -497,69 +1600,318 @@ async fn find_track(
     cid: Option<String>,
     title: Option<String>,
     owner: Option<String>,
+    proxy: &PlayerClient,
 ) -> StdResult<()> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    if !Path::new(&playlist_path).exists() {
-        eprintln!("播放列表文件不存在");
-        return Ok(());
-    }
-    let content = fs::read_to_string(&playlist_path).await.map_err(App::Io)?;
-    let playlist: Playlist = toml::from_str(&content)
-        .map_err(|_| App::DataParsing("Failed to parse playlist.toml".to_string()))?;
-    let mut results = playlist.tracks.clone();
+    let mut results: Vec<(usize, Track)> = open_store()?.load()?.into_iter().enumerate().collect();
     if let Some(bvid) = bvid {
-        results.retain(|track| track.bvid == bvid);
+        results.retain(|(_, track)| track.bvid == bvid);
     }
     if let Some(cid) = cid {
-        results.retain(|track| track.cid == cid);
+        results.retain(|(_, track)| track.cid == cid);
     }
     if let Some(title) = title {
-        results.retain(|track| track.title.contains(&title));
+        results.retain(|(_, track)| search::matches(&track.title, &title));
     }
     if let Some(owner) = owner {
-        results.retain(|track| track.owner.contains(&owner));
+        results.retain(|(_, track)| search::matches(&track.owner, &owner));
     }
     if results.is_empty() {
         println!("没有找到符合条件的track");
     } else {
-        for track in results {
+        print_track_table(&results, current_bvid(proxy).await.as_deref());
+    }
+    Ok(())
+}
+
+/// Bvid of the track being played, if the daemon is running and playing.
+async fn current_bvid(proxy: &PlayerClient) -> Option<String> {
+    proxy.status().await.ok()?.track.map(|track| track.bvid)
+}
+
+const START_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const TITLE_COLUMNS: usize = 40;
+const OWNER_COLUMNS: usize = 16;
+
+/// Marks the track `bvid` as skipped during playback, or clears the mark.
+async fn set_disabled(bvid: &str, disabled: bool, proxy: &PlayerClient) -> StdResult<()> {
+    let store = open_store()?;
+    let mut tracks = store.load()?;
+    let track = tracks
+        .iter_mut()
+        .find(|track| track.bvid == bvid)
+        .ok_or_else(|| App::InvalidInput(format!("播放列表中没有 {bvid}")))?;
+    let title = track.title.clone();
+    if track.disabled == disabled {
+        println!(
+            "《{title}》已经是{}状态",
+            if disabled { "禁用" } else { "启用" }
+        );
+        return Ok(());
+    }
+    track.disabled = disabled;
+    store.save(&tracks)?;
+    notify_playlist_change(proxy).await?;
+    if disabled {
+        println!("已禁用《{title}》，播放时将跳过");
+    } else {
+        println!("已恢复《{title}》");
+    }
+    Ok(())
+}
+
+/// Sets the seconds skipped at the start and end of a track, or prints them
+/// when neither is given.
+async fn set_skip(skip_cmd: SkipCommand, proxy: &PlayerClient) -> StdResult<()> {
+    let bvid = bvid_or_current(skip_cmd.bvid, proxy).await?;
+    let store = open_store()?;
+    let mut tracks = store.load()?;
+    let track = find_stored(&mut tracks, &bvid)?;
+    if skip_cmd.intro.is_none() && skip_cmd.outro.is_none() {
+        println!(
+            "《{}》跳过开头 {} 秒、结尾 {} 秒",
+            track.title, track.skip_intro, track.skip_outro
+        );
+        return Ok(());
+    }
+    if let Some(intro) = skip_cmd.intro {
+        track.skip_intro = intro;
+    }
+    if let Some(outro) = skip_cmd.outro {
+        track.skip_outro = outro;
+    }
+    check_play_range(track)?;
+    let message = format!(
+        "《{}》将跳过开头 {} 秒、结尾 {} 秒",
+        track.title, track.skip_intro, track.skip_outro
+    );
+    store.save(&tracks)?;
+    notify_playlist_change(proxy).await?;
+    println!("{message}");
+    Ok(())
+}
+
+/// Sets the part of a track that is played, or prints it when neither end
+/// is given.
+async fn trim_track(trim_cmd: TrimCommand, proxy: &PlayerClient) -> StdResult<()> {
+    let bvid = bvid_or_current(trim_cmd.bvid, proxy).await?;
+    let store = open_store()?;
+    let mut tracks = store.load()?;
+    let track = find_stored(&mut tracks, &bvid)?;
+    if trim_cmd.start.is_none() && trim_cmd.end.is_none() {
+        println!("《{}》{}", track.title, describe_trim(track));
+        return Ok(());
+    }
+    if let Some(start) = &trim_cmd.start {
+        track.start = parse_duration(start)?;
+    }
+    if let Some(end) = &trim_cmd.end {
+        track.end = parse_duration(end)?;
+    }
+    check_play_range(track)?;
+    let message = format!("《{}》将{}", track.title, describe_trim(track));
+    store.save(&tracks)?;
+    notify_playlist_change(proxy).await?;
+    println!("{message}");
+    Ok(())
+}
+
+fn describe_trim(track: &Track) -> String {
+    let end = if track.end > 0 {
+        format_duration(track.end)
+    } else {
+        "结尾".to_string()
+    };
+    format!("从 {} 播放到 {end}", format_duration(track.start))
+}
+
+/// The bvid given with `-b`, or else the one playing.
+async fn bvid_or_current(bvid: Option<String>, proxy: &PlayerClient) -> StdResult<String> {
+    match bvid {
+        Some(bvid) => Ok(bvid),
+        None => current_bvid(proxy)
+            .await
+            .ok_or_else(|| App::InvalidInput("当前没有正在播放的歌曲，请用 -b 指定".to_string())),
+    }
+}
+
+fn find_stored<'a>(tracks: &'a mut [Track], bvid: &str) -> StdResult<&'a mut Track> {
+    tracks
+        .iter_mut()
+        .find(|track| track.bvid == bvid)
+        .ok_or_else(|| App::InvalidInput(format!("播放列表中没有 {bvid}")))
+}
+
+/// Fails when trimming and skipping leave nothing of `track` to play.
+fn check_play_range(track: &Track) -> StdResult<()> {
+    let start = track.play_start();
+    let end = track
+        .play_end()
+        .or((track.duration > 0).then_some(track.duration));
+    match end {
+        Some(end) if end <= start => Err(App::InvalidInput(format!(
+            "开始位置 {} 不早于结束位置 {}，没有可以播放的部分",
+            format_duration(start),
+            format_duration(end)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Prints tracks as an aligned table with their 0-based playlist index,
+/// highlighting the one that is playing and greying out disabled ones.
+fn print_track_table(tracks: &[(usize, Track)], current: Option<&str>) {
+    println!(
+        "{}",
+        style::dim(&format!(
+            "  {:>4}  {:<12}  {}  {}  {}",
+            "#",
+            "bvid",
+            style::fit("标题", TITLE_COLUMNS),
+            style::fit("UP 主", OWNER_COLUMNS),
+            "时长"
+        ))
+    );
+    for (index, track) in tracks {
+        let title = style::fit(&track.title, TITLE_COLUMNS);
+        let owner = style::fit(&track.owner, OWNER_COLUMNS);
+        let duration = format_duration(track.duration);
+        if current == Some(track.bvid.as_str()) {
+            println!(
+                "{}",
+                style::current(&format!(
+                    "▶ {:>4}  {:<12}  {title}  {owner}  {duration}",
+                    index + 1,
+                    track.bvid
+                ))
+            );
+        } else if track.disabled {
+            println!(
+                "{}",
+                style::dim(&format!(
+                    "✕ {:>4}  {:<12}  {title}  {owner}  {duration}",
+                    index + 1,
+                    track.bvid
+                ))
+            );
+        } else {
             println!(
-                "bvid: {}, cid: {}, title: {}, owner: {}",
-                track.bvid, track.cid, track.title, track.owner
+                "  {:>4}  {}  {}  {}  {}",
+                index + 1,
+                style::dim(&format!("{:<12}", track.bvid)),
+                style::title(&title),
+                style::owner(&owner),
+                duration
             );
         }
+        if !track.note.is_empty() {
+            println!("{}", style::dim(&format!("        ✎ {}", track.note)));
+        }
+    }
+}
+
+async fn handle_stats_command(perf: bool, proxy: &PlayerClient) -> StdResult<()> {
+    if !perf {
+        let tracks = open_store()?.load()?;
+        let total: u64 = tracks.iter().map(|track| track.duration).sum();
+        let owners: HashSet<&str> = tracks.iter().map(|track| track.owner.as_str()).collect();
+        println!("歌曲数: {}", tracks.len());
+        println!("UP 主数: {}", owners.len());
+        println!("总时长: {}", format_duration(total));
+        return Ok(());
+    }
+
+    ensure_running(proxy).await?;
+    let stats = proxy.perf_stats().await?;
+    for (name, summary) in [
+        ("获取地址", &stats.fetch),
+        ("开始出声", &stats.pipeline),
+        ("切歌总计", &stats.total),
+    ] {
+        println!(
+            "{name}: {} 次, P50 {:.0} ms, P95 {:.0} ms",
+            summary.count, summary.p50_ms, summary.p95_ms
+        );
     }
     Ok(())
 }
 
-async fn display_playlist() -> StdResult<()> {
-    let playlist_path = initialize_directories().await?.clone() + "/playlist.toml";
-    if !Path::new(&playlist_path).exists() {
-        eprintln!("播放列表文件不存在");
+/// Name Bilibili gives the audio quality with this id.
+fn audio_quality_name(id: u32) -> String {
+    match id {
+        30216 => "64K".to_string(),
+        30232 => "132K".to_string(),
+        30280 => "192K".to_string(),
+        30250 => "杜比全景声".to_string(),
+        30251 => "Hi-Res 无损".to_string(),
+        _ => id.to_string(),
+    }
+}
+
+async fn show_audio_tracks(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let tracks = proxy.audio_tracks().await?;
+    if tracks.is_empty() {
+        println!("当前没有正在播放的歌曲");
         return Ok(());
     }
-    let content = fs::read_to_string(&playlist_path).await.map_err(App::Io)?;
-    let playlist: Playlist = toml::from_str(&content)
-        .map_err(|_| App::DataParsing("Failed to parse playlist.toml".to_string()))?;
-    let tracks = playlist.tracks;
+    for (index, track) in tracks.iter().enumerate() {
+        let line = format!(
+            "{:>2}. {:<12} {:<12} {} kbps",
+            index + 1,
+            audio_quality_name(track.id),
+            track.codec,
+            track.bitrate.unwrap_or_default() / 1000
+        );
+        if track.selected {
+            println!("{}", style::current(&format!("▶ {line}")));
+        } else {
+            println!("  {line}");
+        }
+    }
+    Ok(())
+}
+
+async fn set_audio_track(n: u32, proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let count = proxy.audio_tracks().await?.len();
+    if n as usize > count {
+        return Err(App::InvalidInput(format!("当前歌曲只有 {count} 条音频流")));
+    }
+    proxy.set_audio_track(n - 1).await?;
+    println!("已切换到第 {n} 条音频流");
+    Ok(())
+}
+
+fn print_playlist_info(info: &PlaylistInfo) {
+    if info.is_empty() {
+        return;
+    }
+    if !info.name.is_empty() {
+        println!("{}", style::title(&info.name));
+    }
+    if !info.description.is_empty() {
+        println!("{}", info.description);
+    }
+    if !info.cover.is_empty() {
+        println!("{}", style::dim(&format!("封面: {}", info.cover)));
+    }
+    println!();
+}
+
+async fn display_playlist(proxy: &PlayerClient) -> StdResult<()> {
+    let store = open_store()?;
+    print_playlist_info(&store.info()?);
+    let tracks: Vec<(usize, Track)> = store.load()?.into_iter().enumerate().collect();
+    let current = current_bvid(proxy).await;
     let total_tracks = tracks.len();
     let page_size = 10;
-    let total_pages = (total_tracks + page_size - 1) / page_size;
+    let total_pages = total_tracks.div_ceil(page_size);
     let mut current_page = 1;
     loop {
         let start = (current_page - 1) * page_size;
         let end = (start + page_size).min(total_tracks);
         println!("第 {current_page} 页，共 {total_pages} 页");
-        for (i, track) in tracks[start..end].iter().enumerate() {
-            println!(
-                "{}. bvid: {}, cid: {}, title: {}, owner: {}",
-                start + i + 1,
-                track.bvid,
-                track.cid,
-                track.title,
-                track.owner
-            );
-        }
+        print_track_table(&tracks[start..end], current.as_deref());
         println!("\n请输入页码（1-{total_pages}），或输入 'q' 退出：");
         let mut input = String::new();
         let mut stdin = tokio::io::BufReader::new(tokio::io::stdin());
@@ -577,3 +1929,56 @@ async fn display_playlist() -> StdResult<()> {
     }
     Ok(())
 }
+
+async fn export_playlist(path: &str) -> StdResult<()> {
+    let store = open_store()?;
+    let tracks = store.load()?;
+    fs::write(path, storage::playlist_to_toml(&store.info()?, &tracks)?).await?;
+    println!("已导出 {} 首歌曲到 {path}", tracks.len());
+    Ok(())
+}
+
+async fn import_playlist(path: &str, proxy: &PlayerClient) -> StdResult<()> {
+    let (info, imported) = storage::parse_playlist(&fs::read_to_string(path).await?)?;
+    let store = open_store()?;
+    // A shared playlist brings what it is about into a playlist without one
+    if !info.is_empty() && store.info()?.is_empty() {
+        store.set_info(&info)?;
+    }
+    let mut tracks = store.load()?;
+    let existing_bvids: HashSet<_> = tracks.iter().map(|track| track.bvid.clone()).collect();
+    let new_tracks: Vec<Track> = imported
+        .into_iter()
+        .filter(|track| !existing_bvids.contains(&track.bvid))
+        .collect();
+    if new_tracks.is_empty() {
+        println!("没有需要导入的新歌曲");
+        return Ok(());
+    }
+    println!("导入 {} 首歌曲", new_tracks.len());
+    tracks.extend(new_tracks);
+    store.save(&tracks)?;
+    notify_playlist_change(proxy).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+
+    #[test]
+    fn durations_parse_in_every_form() {
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert_eq!(parse_duration("1h30m").unwrap(), 5400);
+        assert_eq!(parse_duration("10m5s").unwrap(), 605);
+        assert_eq!(parse_duration("1:02:03").unwrap(), 3723);
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("10m5").is_err());
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}s1s", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}:0", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::error::App;
+use rosesong_core::ipc::{
+    AudioTrack, DebugInfo, PerfStats, Request, RpcRequest, RpcResponse, Status,
+};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use zbus::proxy;
+
+type StdResult<T> = std::result::Result<T, App>;
+
+#[proxy(
+    interface = "org.rosesong.Player",
+    default_service = "org.rosesong.Player",
+    default_path = "/org/rosesong/Player"
+)]
+trait MyPlayer {
+    async fn play(&self) -> zbus::Result<()>;
+    async fn play_bvid(&self, bvid: &str) -> zbus::Result<()>;
+    async fn play_season(&self, id: &str) -> zbus::Result<()>;
+    async fn play_later(&self) -> zbus::Result<()>;
+    async fn pause(&self) -> zbus::Result<()>;
+    async fn next(&self) -> zbus::Result<()>;
+    async fn previous(&self) -> zbus::Result<()>;
+    async fn stop(&self) -> zbus::Result<()>;
+    async fn quit(&self) -> zbus::Result<()>;
+    async fn set_mode(&self, mode: &str) -> zbus::Result<()>;
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()>;
+    async fn set_repeat(&self, repeat: &str) -> zbus::Result<()>;
+    async fn set_radio(&self, enabled: bool) -> zbus::Result<()>;
+    async fn set_channels(&self, channels: &str) -> zbus::Result<()>;
+    async fn set_pitch(&self, semitones: i32) -> zbus::Result<()>;
+    async fn set_preview(&self, seconds: u64) -> zbus::Result<()>;
+    async fn seek(&self, position: f64) -> zbus::Result<()>;
+    async fn set_volume(&self, volume: f64) -> zbus::Result<()>;
+    async fn adjust_volume(&self, delta: f64) -> zbus::Result<()>;
+    async fn volume_up(&self) -> zbus::Result<()>;
+    async fn volume_down(&self) -> zbus::Result<()>;
+    async fn playlist_change(&self) -> zbus::Result<()>;
+    async fn test_connection(&self) -> zbus::Result<()>;
+    async fn playlist_is_empty(&self) -> zbus::Result<()>;
+    async fn status(&self) -> zbus::Result<String>;
+    async fn perf_stats(&self) -> zbus::Result<String>;
+    async fn debug_info(&self) -> zbus::Result<String>;
+    async fn audio_tracks(&self) -> zbus::Result<String>;
+    async fn set_audio_track(&self, index: u32) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn status_changed(&self, status: String) -> zbus::Result<()>;
+}
+
+/// Talks to the daemon's JSON-RPC control socket (a named pipe on Windows),
+/// one connection per call.
+pub struct SocketClient {
+    next_id: AtomicU64,
+}
+
+impl SocketClient {
+    pub fn new() -> Self {
+        SocketClient {
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    async fn call(&self, request: Request) -> StdResult<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reader, mut writer) = tokio::io::split(connect_control().await?);
+
+        let mut line = serde_json::to_string(&RpcRequest::new(id, request))
+            .map_err(|e| App::DataParsing(e.to_string()))?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+
+        let mut response = String::new();
+        BufReader::new(reader).read_line(&mut response).await?;
+        let response: RpcResponse =
+            serde_json::from_str(&response).map_err(|e| App::DataParsing(e.to_string()))?;
+        match response.error {
+            Some(error) => Err(App::Socket(error.message)),
+            None => Ok(response.result.unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+async fn connect_dbus() -> Option<MyPlayerProxy<'static>> {
+    let connection = zbus::Connection::session().await.ok()?;
+    let proxy = MyPlayerProxy::new(&connection).await.ok()?;
+    let socket_exists = rosesong_core::ipc::socket_path().is_ok_and(|path| path.exists());
+    if !socket_exists {
+        return Some(proxy);
+    }
+    let dbus = zbus::fdo::DBusProxy::new(&connection).await.ok()?;
+    let name = zbus::names::BusName::try_from("org.rosesong.Player").ok()?;
+    dbus.name_has_owner(name).await.ok()?.then_some(proxy)
+}
+
+#[cfg(unix)]
+async fn connect_control() -> StdResult<impl AsyncRead + AsyncWrite> {
+    let path = rosesong_core::ipc::socket_path()?;
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(windows)]
+async fn connect_control() -> StdResult<impl AsyncRead + AsyncWrite> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    Ok(ClientOptions::new().open(rosesong_core::ipc::PIPE_NAME)?)
+}
+
+/// Connection to a running rosesong, over D-Bus or the control socket.
+pub enum PlayerClient {
+    DBus(MyPlayerProxy<'static>),
+    Socket(SocketClient),
+}
+
+impl PlayerClient {
+    /// Prefers D-Bus and falls back to the control socket when there is no
+    /// session bus or the daemon is only reachable through the socket.
+    pub async fn connect() -> StdResult<Self> {
+        #[cfg(not(any(target_os = "macos", windows)))]
+        if let Some(proxy) = connect_dbus().await {
+            return Ok(PlayerClient::DBus(proxy));
+        }
+        Ok(PlayerClient::Socket(SocketClient::new()))
+    }
+
+    async fn request(&self, request: Request) -> StdResult<Value> {
+        match self {
+            PlayerClient::DBus(proxy) => call_dbus(proxy, request).await,
+            PlayerClient::Socket(client) => client.call(request).await,
+        }
+    }
+
+    async fn call(&self, request: Request) -> StdResult<()> {
+        self.request(request).await.map(drop)
+    }
+
+    pub async fn play(&self) -> StdResult<()> {
+        self.call(Request::Play).await
+    }
+
+    pub async fn play_bvid(&self, bvid: &str) -> StdResult<()> {
+        self.call(Request::PlayBvid {
+            bvid: bvid.to_string(),
+        })
+        .await
+    }
+
+    pub async fn play_season(&self, id: &str) -> StdResult<()> {
+        self.call(Request::PlaySeason { id: id.to_string() }).await
+    }
+
+    pub async fn play_later(&self) -> StdResult<()> {
+        self.call(Request::PlayLater).await
+    }
+
+    pub async fn pause(&self) -> StdResult<()> {
+        self.call(Request::Pause).await
+    }
+
+    pub async fn next(&self) -> StdResult<()> {
+        self.call(Request::Next).await
+    }
+
+    pub async fn previous(&self) -> StdResult<()> {
+        self.call(Request::Previous).await
+    }
+
+    pub async fn stop(&self) -> StdResult<()> {
+        self.call(Request::Stop).await
+    }
+
+    pub async fn quit(&self) -> StdResult<()> {
+        self.call(Request::Quit).await
+    }
+
+    pub async fn set_mode(&self, mode: &str) -> StdResult<()> {
+        self.call(Request::SetMode {
+            mode: mode.to_string(),
+        })
+        .await
+    }
+
+    pub async fn set_shuffle(&self, shuffle: bool) -> StdResult<()> {
+        self.call(Request::SetShuffle { shuffle }).await
+    }
+
+    pub async fn set_repeat(&self, repeat: &str) -> StdResult<()> {
+        self.call(Request::SetRepeat {
+            repeat: repeat.to_string(),
+        })
+        .await
+    }
+
+    pub async fn set_radio(&self, enabled: bool) -> StdResult<()> {
+        self.call(Request::SetRadio { enabled }).await
+    }
+
+    pub async fn set_channels(&self, channels: &str) -> StdResult<()> {
+        self.call(Request::SetChannels {
+            channels: channels.to_string(),
+        })
+        .await
+    }
+
+    pub async fn set_pitch(&self, semitones: i32) -> StdResult<()> {
+        self.call(Request::SetPitch { semitones }).await
+    }
+
+    pub async fn set_preview(&self, seconds: u64) -> StdResult<()> {
+        self.call(Request::SetPreview { seconds }).await
+    }
+
+    pub async fn seek(&self, position: f64) -> StdResult<()> {
+        self.call(Request::Seek { position }).await
+    }
+
+    pub async fn set_volume(&self, volume: f64) -> StdResult<()> {
+        self.call(Request::SetVolume { volume }).await
+    }
+
+    pub async fn adjust_volume(&self, delta: f64) -> StdResult<()> {
+        self.call(Request::AdjustVolume { delta }).await
+    }
+
+    pub async fn playlist_change(&self) -> StdResult<()> {
+        self.call(Request::PlaylistChange).await
+    }
+
+    pub async fn test_connection(&self) -> StdResult<()> {
+        self.call(Request::TestConnection).await
+    }
+
+    pub async fn playlist_is_empty(&self) -> StdResult<()> {
+        self.call(Request::PlaylistIsEmpty).await
+    }
+
+    /// Stream of status change signals, or `None` over the control socket,
+    /// which has no notifications and must be polled instead.
+    pub async fn status_changes(&self) -> StdResult<Option<StatusChangedStream<'static>>> {
+        match self {
+            PlayerClient::DBus(proxy) => Ok(Some(proxy.receive_status_changed().await?)),
+            PlayerClient::Socket(_) => Ok(None),
+        }
+    }
+
+    pub async fn status(&self) -> StdResult<Status> {
+        let status = self.request(Request::Status).await?;
+        serde_json::from_value(status).map_err(|e| App::DataParsing(e.to_string()))
+    }
+
+    pub async fn debug_info(&self) -> StdResult<DebugInfo> {
+        let info = self.request(Request::DebugInfo).await?;
+        serde_json::from_value(info).map_err(|e| App::DataParsing(e.to_string()))
+    }
+
+    pub async fn audio_tracks(&self) -> StdResult<Vec<AudioTrack>> {
+        let tracks = self.request(Request::AudioTracks).await?;
+        serde_json::from_value(tracks).map_err(|e| App::DataParsing(e.to_string()))
+    }
+
+    pub async fn set_audio_track(&self, index: u32) -> StdResult<()> {
+        self.call(Request::SetAudioTrack { index }).await
+    }
+
+    pub async fn perf_stats(&self) -> StdResult<PerfStats> {
+        let stats = self.request(Request::PerfStats).await?;
+        serde_json::from_value(stats).map_err(|e| App::DataParsing(e.to_string()))
+    }
+}
+
+async fn call_dbus(proxy: &MyPlayerProxy<'static>, request: Request) -> StdResult<Value> {
+    match request {
+        Request::TestConnection => proxy.test_connection().await?,
+        Request::Play => proxy.play().await?,
+        Request::PlayBvid { bvid } => proxy.play_bvid(&bvid).await?,
+        Request::PlaySeason { id } => proxy.play_season(&id).await?,
+        Request::PlayLater => proxy.play_later().await?,
+        Request::Pause => proxy.pause().await?,
+        Request::Next => proxy.next().await?,
+        Request::Previous => proxy.previous().await?,
+        Request::Stop => proxy.stop().await?,
+        Request::Quit => proxy.quit().await?,
+        Request::SetMode { mode } => proxy.set_mode(&mode).await?,
+        Request::SetShuffle { shuffle } => proxy.set_shuffle(shuffle).await?,
+        Request::SetRepeat { repeat } => proxy.set_repeat(&repeat).await?,
+        Request::SetRadio { enabled } => proxy.set_radio(enabled).await?,
+        Request::SetChannels { channels } => proxy.set_channels(&channels).await?,
+        Request::SetPitch { semitones } => proxy.set_pitch(semitones).await?,
+        Request::SetPreview { seconds } => proxy.set_preview(seconds).await?,
+        Request::Seek { position } => proxy.seek(position).await?,
+        Request::SetVolume { volume } => proxy.set_volume(volume).await?,
+        Request::AdjustVolume { delta } => proxy.adjust_volume(delta).await?,
+        Request::VolumeUp => proxy.volume_up().await?,
+        Request::VolumeDown => proxy.volume_down().await?,
+        Request::PlaylistChange => proxy.playlist_change().await?,
+        Request::PlaylistIsEmpty => proxy.playlist_is_empty().await?,
+        Request::Status => {
+            return serde_json::from_str(&proxy.status().await?)
+                .map_err(|e| App::DataParsing(e.to_string()));
+        }
+        Request::PerfStats => {
+            return serde_json::from_str(&proxy.perf_stats().await?)
+                .map_err(|e| App::DataParsing(e.to_string()));
+        }
+        Request::DebugInfo => {
+            return serde_json::from_str(&proxy.debug_info().await?)
+                .map_err(|e| App::DataParsing(e.to_string()));
+        }
+        Request::AudioTracks => {
+            return serde_json::from_str(&proxy.audio_tracks().await?)
+                .map_err(|e| App::DataParsing(e.to_string()));
+        }
+        Request::SetAudioTrack { index } => proxy.set_audio_track(index).await?,
+    }
+    Ok(Value::Null)
+}
@@ -0,0 +1,104 @@
+use crate::client::PlayerClient;
+use crate::status::status_line;
+use crate::{ensure_playable, style, StdResult};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const VOLUME_STEP: f64 = 0.05;
+pub const KEYS_HINT: &str = "␣ 暂停 n/p 切歌 +/- 音量 q 退出";
+
+/// Controls the player from single key presses while keeping the status on
+/// one line, without taking over the whole terminal.
+pub async fn run_ctl(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    terminal::enable_raw_mode()?;
+    let result = control_loop(proxy).await;
+    terminal::disable_raw_mode()?;
+    println!();
+    result
+}
+
+async fn control_loop(proxy: &PlayerClient) -> StdResult<()> {
+    let mut keys = read_keys();
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            Some(key) = keys.recv() => {
+                if is_quit(&key) {
+                    break;
+                }
+                if !handle_key(proxy, &key).await? {
+                    continue;
+                }
+            }
+        }
+        draw(proxy).await?;
+    }
+    Ok(())
+}
+
+/// Key presses read from the terminal, which must be in raw mode.
+pub fn read_keys() -> mpsc::UnboundedReceiver<KeyEvent> {
+    let (key_sender, keys) = mpsc::unbounded_channel();
+    // A plain thread, since a blocking task would keep the runtime from
+    // shutting down while it waits for the next key
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event {
+                if key.kind != KeyEventKind::Release && key_sender.send(key).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    keys
+}
+
+pub fn is_quit(key: &KeyEvent) -> bool {
+    // Raw mode turns Ctrl-C into an ordinary key press
+    let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+    ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+}
+
+/// Acts on a playback key. Returns whether `key` is one.
+pub async fn handle_key(proxy: &PlayerClient, key: &KeyEvent) -> StdResult<bool> {
+    match key.code {
+        KeyCode::Char(' ') => toggle_pause(proxy).await?,
+        KeyCode::Char('n') => proxy.next().await?,
+        KeyCode::Char('p') => proxy.previous().await?,
+        KeyCode::Char('+' | '=') => proxy.adjust_volume(VOLUME_STEP).await?,
+        KeyCode::Char('-') => proxy.adjust_volume(-VOLUME_STEP).await?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+async fn toggle_pause(proxy: &PlayerClient) -> StdResult<()> {
+    match proxy.status().await?.state.as_str() {
+        "playing" | "loading" => proxy.pause().await,
+        _ => proxy.play().await,
+    }
+}
+
+/// Redraws the status line, cut to the terminal width so it never wraps.
+async fn draw(proxy: &PlayerClient) -> StdResult<()> {
+    let status = proxy.status().await?;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let volume = (status.volume * 100.0).round() as u32;
+    let line = format!("{} 音量 {volume}% | {KEYS_HINT}", status_line(&status));
+    let columns = terminal::size().map_or(80, |(columns, _)| usize::from(columns));
+    let line = if style::width(&line) < columns {
+        line
+    } else {
+        style::fit(&line, columns.saturating_sub(1).max(2))
+    };
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\r{line}\x1b[K")?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{notify_named_playlist_change, undo, validate, StdResult};
+use rosesong_core::settings::Settings;
+use rosesong_core::storage::{self, DEFAULT_PLAYLIST};
+use std::path::Path;
+use tokio::io::AsyncBufReadExt;
+use tokio::process::Command;
+
+/// Opens a copy of the playlist `playlist`, or of the current one, in the
+/// editor and stores it once it checks out, unless the playlist was changed
+/// meanwhile. A copy that does not check out can be edited again.
+pub async fn edit(playlist: Option<String>, proxy: &PlayerClient) -> StdResult<()> {
+    let settings = Settings::load()?;
+    let name = playlist.as_deref().unwrap_or(DEFAULT_PLAYLIST);
+    let store = storage::open_named(&settings.storage, name)?;
+    let before = store.load()?;
+    let info = store.info()?;
+    let fingerprint = store.fingerprint()?;
+    let original = storage::playlist_to_toml(&info, &before)?;
+    let copy = std::env::temp_dir().join(format!("rosesong-{name}-{}.toml", std::process::id()));
+    tokio::fs::write(&copy, &original).await?;
+
+    let (new_info, after) = loop {
+        run_editor(&copy).await?;
+        let content = tokio::fs::read_to_string(&copy).await?;
+        if content == original {
+            tokio::fs::remove_file(&copy).await?;
+            println!("歌单没有修改");
+            return Ok(());
+        }
+        if validate::report_playlist(&copy, &content) {
+            break storage::parse_playlist(&content)?;
+        }
+        if !ask_edit_again().await? {
+            return Err(App::InvalidInput(format!(
+                "已放弃修改，编辑的内容保存在 {}",
+                copy.display()
+            )));
+        }
+    };
+
+    if store.fingerprint()? != fingerprint {
+        return Err(App::InvalidInput(format!(
+            "编辑期间歌单 {name} 被修改过，未写回，编辑的内容保存在 {}",
+            copy.display()
+        )));
+    }
+    store.save(&after)?;
+    if new_info != info {
+        store.set_info(&new_info)?;
+        println!("修改了歌单的名称、描述或封面");
+    }
+    tokio::fs::remove_file(&copy).await?;
+    undo::print_changes(&before, &after);
+    notify_named_playlist_change(proxy, name).await
+}
+
+/// Runs `$VISUAL` or `$EDITOR`, which may carry arguments such as
+/// `code --wait`, on `file` and waits for it to exit.
+async fn run_editor(file: &Path) -> StdResult<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(words)
+        .arg(file)
+        .status()
+        .await
+        .map_err(|e| App::InvalidInput(format!("无法启动编辑器 {program}: {e}")))?;
+    if !status.success() {
+        return Err(App::InvalidInput(format!(
+            "编辑器 {program} 异常退出，编辑的内容保存在 {}",
+            file.display()
+        )));
+    }
+    Ok(())
+}
+
+async fn ask_edit_again() -> StdResult<bool> {
+    println!("是否重新编辑？(y/n)");
+    let mut answer = String::new();
+    tokio::io::BufReader::new(tokio::io::stdin())
+        .read_line(&mut answer)
+        .await?;
+    Ok(!answer.trim().eq_ignore_ascii_case("n"))
+}
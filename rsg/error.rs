@@ -1,4 +1,5 @@
 use reqwest::Error as ReqwestError;
+use rosesong_core::error::Core;
 use std::io::Error as IoError;
 use thiserror::Error;
 use zbus::Error as ZbusError;
@@ -21,4 +22,40 @@ pub enum App {
     OneshotRecv(#[from] tokio::sync::oneshot::error::RecvError),
     #[error("Zbus error")]
     Zbus(#[from] ZbusError),
+    #[error("Control socket error: {0}")]
+    Socket(String),
+    #[error("Storage error: {0}")]
+    Storage(#[from] Core),
+    #[error("rosesong 没有处于运行状态")]
+    NotRunning,
+    #[error("当前播放列表为空，请先添加歌曲")]
+    PlaylistEmpty,
+}
+
+impl App {
+    /// Stable name of the kind of error, for scripts using `--json`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            App::NotRunning => "not_running",
+            App::PlaylistEmpty => "playlist_empty",
+            App::HttpRequest(_) | App::Storage(Core::Network(_)) => "network",
+            App::InvalidInput(_) => "invalid_input",
+            App::Storage(_) | App::Io(_) => "storage",
+            App::Zbus(_) | App::Socket(_) | App::OneshotRecv(_) => "ipc",
+            App::DataParsing(_) | App::EnvVar(_) | App::Utf8Conversion(_) => "other",
+        }
+    }
+
+    /// Exit status of `rsg` for this error; each `code` has its own.
+    pub fn exit_code(&self) -> u8 {
+        match self.code() {
+            "invalid_input" => 2,
+            "not_running" => 3,
+            "playlist_empty" => 4,
+            "network" => 5,
+            "storage" => 6,
+            "ipc" => 7,
+            _ => 1,
+        }
+    }
 }
@@ -0,0 +1,90 @@
+use crate::bilibili::video_info::{fetch_chapters, fetch_hot_replies, fetch_video_info};
+use crate::{format_duration, StdResult};
+use chrono::{DateTime, Local};
+use reqwest::Client;
+
+/// Prints the description, stats and top replies of a video.
+pub async fn show_info(bvid: &str, replies: usize) -> StdResult<()> {
+    let client = Client::new();
+    let info = fetch_video_info(&client, bvid).await?;
+
+    let published = DateTime::from_timestamp(info.pubdate, 0).map_or_else(
+        || "未知".to_string(),
+        |time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        },
+    );
+    println!("{}", info.title);
+    println!("UP 主: {}", info.owner.name);
+    println!("bvid: {bvid}");
+    println!("发布时间: {published}");
+    println!(
+        "播放: {}  点赞: {}  评论: {}",
+        format_count(info.stat.view),
+        format_count(info.stat.like),
+        format_count(info.stat.reply)
+    );
+
+    let desc = info.desc.trim();
+    if !desc.is_empty() && desc != "-" {
+        println!("\n简介:");
+        for line in desc.lines() {
+            println!("  {line}");
+        }
+    }
+
+    if replies == 0 {
+        return Ok(());
+    }
+    // Replies are a nice-to-have; the endpoint is rate limited more often
+    // than the video view, so a failure here only skips the section.
+    match fetch_hot_replies(&client, info.aid, replies).await {
+        Ok(list) if !list.is_empty() => {
+            println!("\n热门评论:");
+            for reply in list {
+                let message = reply.content.message.replace('\n', " ");
+                println!(
+                    "  {} (赞 {}): {message}",
+                    reply.member.uname,
+                    format_count(reply.like)
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("获取评论失败: {e}"),
+    }
+    Ok(())
+}
+
+/// Lists the chapters of one part of a video, numbered from 1 as accepted
+/// by `rsg seek --chapter`.
+pub async fn show_chapters(bvid: &str, cid: &str) -> StdResult<()> {
+    let chapters = fetch_chapters(&Client::new(), bvid, cid).await?;
+    if chapters.is_empty() {
+        println!("这个视频没有章节信息");
+        return Ok(());
+    }
+    for (index, chapter) in chapters.iter().enumerate() {
+        println!(
+            "{:>3}. {} - {}  {}",
+            index + 1,
+            format_duration(chapter.from),
+            format_duration(chapter.to),
+            chapter.content
+        );
+    }
+    Ok(())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_count(count: u64) -> String {
+    if count >= 100_000_000 {
+        format!("{:.1}亿", count as f64 / 100_000_000.0)
+    } else if count >= 10_000 {
+        format!("{:.1}万", count as f64 / 10_000.0)
+    } else {
+        count.to_string()
+    }
+}
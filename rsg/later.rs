@@ -0,0 +1,65 @@
+use crate::bilibili::fetch_audio_info::{parse_auid, VideoSource};
+use crate::bilibili::resolve::resolve_video;
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{
+    ensure_playable, import_videos, print_track_table, DurationRange, ImportOptions, SortOrder,
+    StdResult,
+};
+use rosesong_core::settings::{InsertPosition, Settings};
+use rosesong_core::storage;
+
+/// Adds the videos or songs at `inputs` to the end of the `rsg later`
+/// queue, which plays once the playlist is done and empties as it plays.
+pub async fn queue_later(inputs: &[String]) -> StdResult<()> {
+    let store = storage::open_later()?;
+    let blacklist = Settings::load()?.blacklist;
+    let client = reqwest::Client::new();
+    for input in inputs {
+        let source = match parse_auid(input) {
+            Some(auid) => VideoSource::Song(auid),
+            None => VideoSource::Video(resolve_video(&client, input).await?),
+        };
+        let options = ImportOptions {
+            duration_range: DurationRange::default(),
+            sort: SortOrder::Source,
+            replace: false,
+            new_tracks: InsertPosition::End,
+            blacklist: blacklist.clone(),
+        };
+        import_videos(store.as_ref(), source, options).await?;
+    }
+    println!("稍后听队列中共有 {} 首歌曲", store.load()?.len());
+    Ok(())
+}
+
+/// Lists the `rsg later` queue in the order it will play.
+pub fn show_later() -> StdResult<()> {
+    let tracks = storage::open_later()?.load()?;
+    if tracks.is_empty() {
+        println!("稍后听队列是空的，用 rsg later <链接> 添加");
+        return Ok(());
+    }
+    print_track_table(&tracks.into_iter().enumerate().collect::<Vec<_>>(), None);
+    Ok(())
+}
+
+/// Starts playing the `rsg later` queue right away instead of after the
+/// playlist.
+pub async fn play_later(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    if storage::open_later()?.is_empty()? {
+        return Err(App::InvalidInput(
+            "稍后听队列是空的，用 rsg later <链接> 添加".to_string(),
+        ));
+    }
+    proxy.play_later().await?;
+    println!("开始播放稍后听队列");
+    Ok(())
+}
+
+pub fn clear_later() -> StdResult<()> {
+    storage::open_later()?.save(&[])?;
+    println!("已清空稍后听队列");
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::bilibili::resolve::resolve_bvid;
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{
+    current_bvid, ensure_running, notify_playlist_change, open_store, print_track_table, StdResult,
+};
+use rosesong_core::bilibili::fetch_related;
+use rosesong_core::settings::Settings;
+use rosesong_core::storage::Track;
+use tokio::io::AsyncBufReadExt;
+
+/// Lists the videos related to `bvid`, or to the one playing, and plays or
+/// queues the one picked. Only music videos are listed unless `all` is set
+/// or radio mode is configured to take any, and never blacklisted ones.
+pub async fn show_related(proxy: &PlayerClient, bvid: Option<String>, all: bool) -> StdResult<()> {
+    let client = reqwest::Client::new();
+    let playing = current_bvid(proxy).await;
+    let bvid = match bvid {
+        Some(bvid) => resolve_bvid(&client, &bvid).await?,
+        None => playing
+            .clone()
+            .filter(|bvid| bvid.starts_with("BV"))
+            .ok_or_else(|| App::InvalidInput("当前没有正在播放的视频，请用 -b 指定".to_string()))?,
+    };
+    let settings = Settings::load()?;
+    let music_only = !all && settings.radio.music_only;
+    let related = fetch_related(&client, &bvid, music_only, &settings.blacklist).await?;
+    if related.is_empty() {
+        println!("没有相关推荐");
+        return Ok(());
+    }
+    let rows: Vec<(usize, Track)> = related.into_iter().enumerate().collect();
+    print_track_table(&rows, playing.as_deref());
+
+    println!("\n输入序号直接播放，输入 +序号 加入到当前歌曲之后（例如 +3），直接回车退出：");
+    let mut input = String::new();
+    tokio::io::BufReader::new(tokio::io::stdin())
+        .read_line(&mut input)
+        .await?;
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(());
+    }
+    let (queue, number) = match input.strip_prefix('+') {
+        Some(number) => (true, number),
+        None => (false, input),
+    };
+    let track = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| rows.get(number.checked_sub(1)?))
+        .map(|(_, track)| track.clone())
+        .ok_or_else(|| App::InvalidInput(format!("没有第 {number} 个推荐")))?;
+
+    let title = track.title.clone();
+    let bvid = track.bvid.clone();
+    let added = insert_after(track, playing.as_deref())?;
+    if added {
+        notify_playlist_change(proxy).await?;
+    }
+    if queue {
+        if added {
+            println!("已将《{title}》加入到当前歌曲之后");
+        } else {
+            println!("《{title}》已在播放列表中");
+        }
+    } else {
+        ensure_running(proxy).await?;
+        proxy.play_bvid(&bvid).await?;
+        println!("播放《{title}》");
+    }
+    Ok(())
+}
+
+/// Stores `track` right after the track `after`, or at the end when that is
+/// not in the playlist. Returns false when `track` was already there.
+fn insert_after(track: Track, after: Option<&str>) -> StdResult<bool> {
+    let store = open_store()?;
+    let mut tracks = store.load()?;
+    if tracks.iter().any(|t| t.bvid == track.bvid) {
+        return Ok(false);
+    }
+    let position = after
+        .and_then(|after| tracks.iter().position(|t| t.bvid == after))
+        .map_or(tracks.len(), |index| index + 1);
+    tracks.insert(position, track);
+    store.save(&tracks)?;
+    Ok(true)
+}
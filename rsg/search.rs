@@ -0,0 +1,84 @@
+use encoding_rs::GBK;
+
+/// First GB2312 code point of each pinyin initial. Level-1 hanzi in GB2312
+/// are sorted by pinyin, so the initial of a character is the last entry
+/// not greater than its code.
+const INITIALS: [(u16, char); 23] = [
+    (0xB0A1, 'a'),
+    (0xB0C5, 'b'),
+    (0xB2C1, 'c'),
+    (0xB4EE, 'd'),
+    (0xB6EA, 'e'),
+    (0xB7A2, 'f'),
+    (0xB8C1, 'g'),
+    (0xB9FE, 'h'),
+    (0xBBF7, 'j'),
+    (0xBFA6, 'k'),
+    (0xC0AC, 'l'),
+    (0xC2E8, 'm'),
+    (0xC4C3, 'n'),
+    (0xC5B6, 'o'),
+    (0xC5BE, 'p'),
+    (0xC6DA, 'q'),
+    (0xC8BB, 'r'),
+    (0xC8F6, 's'),
+    (0xCBFA, 't'),
+    (0xCDDA, 'w'),
+    (0xCEF4, 'x'),
+    (0xD1B9, 'y'),
+    (0xD4D1, 'z'),
+];
+const LEVEL_1_END: u16 = 0xD7F9;
+
+/// Whether `text` matches `query`, ignoring case and full-width forms, or
+/// by the pinyin initials of its hanzi, so "qfl" finds "起风了".
+pub fn matches(text: &str, query: &str) -> bool {
+    let query = normalize(query);
+    if normalize(text).contains(&query) {
+        return true;
+    }
+    query.chars().all(|c| c.is_ascii_alphanumeric()) && pinyin_initials(text).contains(&query)
+}
+
+/// Lowercases and folds full-width ASCII and the ideographic space to their
+/// half-width forms.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn pinyin_initials(text: &str) -> String {
+    normalize(text)
+        .chars()
+        .filter_map(|c| {
+            if c.is_ascii_alphanumeric() {
+                Some(c)
+            } else {
+                initial(c)
+            }
+        })
+        .collect()
+}
+
+fn initial(c: char) -> Option<char> {
+    let mut buffer = [0; 4];
+    let (bytes, _, had_errors) = GBK.encode(c.encode_utf8(&mut buffer));
+    if had_errors || bytes.len() != 2 {
+        return None;
+    }
+    let code = u16::from_be_bytes([bytes[0], bytes[1]]);
+    if !(INITIALS[0].0..LEVEL_1_END).contains(&code) {
+        return None;
+    }
+    INITIALS
+        .iter()
+        .rev()
+        .find(|(start, _)| code >= *start)
+        .map(|(_, initial)| *initial)
+}
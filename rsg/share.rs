@@ -0,0 +1,83 @@
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{ensure_playable, StdResult};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use rosesong_core::storage::{Track, TrackKind};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Prints the Bilibili link of the track being played, starting at the
+/// current position when `with_time` is set, followed by a QR code of it
+/// when `qr` is. Copies the link when `copy` is set.
+pub async fn share(proxy: &PlayerClient, with_time: bool, qr: bool, copy: bool) -> StdResult<()> {
+    ensure_playable(proxy).await?;
+    let status = proxy.status().await?;
+    let track = status
+        .track
+        .ok_or_else(|| App::InvalidInput("当前没有正在播放的歌曲".to_string()))?;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let position = with_time.then_some(status.position as u64);
+    let link = share_link(&track, position);
+    println!("{link}");
+    if qr {
+        println!("{}", qr_code(&link)?);
+    }
+    if copy {
+        copy_to_clipboard(&link).await?;
+        println!("已复制到剪贴板");
+    }
+    Ok(())
+}
+
+/// Page of `track` on Bilibili, with `t=` set to `position` in seconds for
+/// videos and episodes.
+fn share_link(track: &Track, position: Option<u64>) -> String {
+    let link = match track.kind {
+        TrackKind::Video => format!("https://www.bilibili.com/video/{}", track.bvid),
+        TrackKind::Audio => return format!("https://www.bilibili.com/audio/{}", track.bvid),
+        TrackKind::Episode => format!("https://www.bilibili.com/bangumi/play/{}", track.bvid),
+    };
+    match position {
+        Some(seconds) if seconds > 0 => format!("{link}?t={seconds}"),
+        _ => link,
+    }
+}
+
+/// Renders `text` as a QR code of half-height blocks. Like `qrencode -t UTF8`
+/// it draws the light modules, so it scans on terminals with a dark
+/// background.
+fn qr_code(text: &str) -> StdResult<String> {
+    let code = QrCode::new(text).map_err(|e| App::DataParsing(e.to_string()))?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Pipes `text` into `pbcopy` on macOS, `wl-copy` under Wayland and `xclip`
+/// elsewhere.
+async fn copy_to_clipboard(text: &str) -> StdResult<()> {
+    let (program, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("pbcopy", &[])
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wl-copy", &[])
+    } else {
+        ("xclip", &["-selection", "clipboard"])
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|_| App::InvalidInput(format!("复制失败：没有找到 {program}")))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+    if !child.wait().await?.success() {
+        return Err(App::InvalidInput(format!("复制失败：{program} 运行出错")));
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{format_duration, StdResult};
+use rosesong_core::ipc::{DebugInfo, OutputFormat, PlaybackError, Status, StreamInfo};
+use std::io::Write;
+use std::time::Duration;
+
+const BAR_WIDTH: usize = 20;
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Prints the current status once, or keeps redrawing it on one line until
+/// Ctrl-C when `follow` is set. With `json` the whole status is printed once
+/// as JSON instead.
+pub async fn show_status(proxy: &PlayerClient, follow: bool, json: bool) -> StdResult<()> {
+    if json {
+        let status = serde_json::to_string(&proxy.status().await?)
+            .map_err(|e| App::DataParsing(e.to_string()))?;
+        println!("{status}");
+        return Ok(());
+    }
+    if !follow {
+        let status = proxy.status().await?;
+        println!("{}", status_line(&status));
+        if let Some(stream) = status.stream.as_ref().filter(|_| status.track.is_some()) {
+            println!("音频: {}", stream_line(stream));
+            if let Some(output) = &stream.output {
+                println!("输出: {}", output_line(stream, output));
+            }
+        }
+        if let Some(next) = status
+            .next_track
+            .as_ref()
+            .filter(|_| status.track.is_some())
+        {
+            println!("接下来: {} - {}", next.title, next.owner);
+        }
+        if let Some(error) = &status.last_error {
+            println!("上次错误: {}", error_line(error));
+        }
+        return Ok(());
+    }
+
+    let mut interval = tokio::time::interval(FOLLOW_INTERVAL);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut stdout = std::io::stdout();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let line = match proxy.status().await {
+                    Ok(status) => status_line(&status),
+                    Err(_) => "rosesong 没有处于运行状态".to_string(),
+                };
+                print!("\r{line}\x1b[K");
+                stdout.flush()?;
+            }
+            _ = &mut ctrl_c => {
+                println!();
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// When and why a track last failed to play.
+fn error_line(error: &PlaybackError) -> String {
+    let time = chrono::DateTime::from_timestamp(error.time, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    match &error.track {
+        Some(track) => format!("{time} {}: {}", track.title, error.message),
+        None => format!("{time} {}", error.message),
+    }
+}
+
+/// Prints the daemon's memory use and the size of its internal caches.
+pub async fn show_debug_info(proxy: &PlayerClient) -> StdResult<()> {
+    let info = proxy.debug_info().await?;
+    #[allow(clippy::cast_precision_loss)]
+    let rss = info.rss_bytes.map_or_else(
+        || "未知".to_string(),
+        |bytes| format!("{:.1} MB", bytes as f64 / 1024.0 / 1024.0),
+    );
+    println!(
+        "内存: {rss}, 歌单曲目: {}, 播放进度缓存: {}, 延迟样本: {}",
+        info.playlist_tracks, info.saved_positions, info.latency_samples
+    );
+    println!("网络: {}", network_line(&info));
+    Ok(())
+}
+
+/// The CDN host, download rate and buffer level of the stream, and how
+/// often playback stalled.
+pub fn network_line(info: &DebugInfo) -> String {
+    let mut parts = Vec::new();
+    if !info.cdn_host.is_empty() {
+        parts.push(format!("CDN {}", info.cdn_host));
+    }
+    #[allow(clippy::cast_precision_loss)]
+    if let Some(rate) = info.download_rate {
+        parts.push(format!("下载 {:.1} KB/s", rate as f64 / 1024.0));
+    }
+    if let Some(buffered) = info.buffered {
+        parts.push(format!("缓冲 {buffered:.1} 秒"));
+    }
+    parts.push(format!("卡顿 {} 次", info.stalls));
+    parts.push(format!("重连 {} 次", info.reconnects));
+    parts.join(", ")
+}
+
+pub fn state_name(state: &str) -> &'static str {
+    match state {
+        "playing" => "播放中",
+        "paused" => "已暂停",
+        "loading" => "加载中",
+        "idle" => "列表为空",
+        _ => "已停止",
+    }
+}
+
+pub fn status_line(status: &Status) -> String {
+    let state = state_name(&status.state);
+    let Some(track) = &status.track else {
+        return format!("[{state}]");
+    };
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let (position, duration) = (status.position as u64, status.duration as u64);
+    let total = if duration == 0 {
+        "--:--".to_string()
+    } else {
+        format_duration(duration)
+    };
+    let episode = track
+        .season
+        .as_ref()
+        .map(|season| format!(" 第 {}/{} 集", season.index, season.total))
+        .unwrap_or_default();
+    format!(
+        "{} - {}{episode} {}/{} {} [{state}]",
+        track.title,
+        track.owner,
+        format_duration(position),
+        total,
+        progress_bar(status.position, status.duration)
+    )
+}
+
+pub fn stream_line(stream: &StreamInfo) -> String {
+    let mut parts = vec![if stream.codec.is_empty() {
+        "未知编码".to_string()
+    } else {
+        stream.codec.clone()
+    }];
+    if let Some(bitrate) = stream.bitrate {
+        parts.push(format!("{} kbps", bitrate / 1000));
+    }
+    if let Some(sample_rate) = stream.sample_rate {
+        parts.push(format!("{sample_rate} Hz"));
+    }
+    if let Some(channels) = stream.channels {
+        parts.push(format!("{channels} 声道"));
+    }
+    parts.join(", ")
+}
+
+/// The output format, noting when the stream is resampled to reach it.
+pub fn output_line(stream: &StreamInfo, output: &OutputFormat) -> String {
+    let mut parts = Vec::new();
+    if !output.format.is_empty() {
+        parts.push(output.format.clone());
+    }
+    if let Some(sample_rate) = output.sample_rate {
+        parts.push(format!("{sample_rate} Hz"));
+        if stream
+            .sample_rate
+            .is_some_and(|source_rate| source_rate != sample_rate)
+        {
+            parts.push("已重采样".to_string());
+        }
+    }
+    if parts.is_empty() {
+        "未知".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+fn progress_bar(position: f64, duration: f64) -> String {
+    let ratio = if duration > 0.0 {
+        (position / duration).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+    format!("{}{}", "▮".repeat(filled), "▯".repeat(BAR_WIDTH - filled))
+}
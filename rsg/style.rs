@@ -0,0 +1,81 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// Colors are used only when stdout is a terminal and `NO_COLOR` is unset
+/// (see <https://no-color.org>).
+fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("NO_COLOR").map_or(true, |value| value.is_empty())
+            && std::io::stdout().is_terminal()
+    })
+}
+
+fn paint(code: &str, text: &str) -> String {
+    if enabled() {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn title(text: &str) -> String {
+    paint("1", text)
+}
+
+pub fn owner(text: &str) -> String {
+    paint("36", text)
+}
+
+pub fn dim(text: &str) -> String {
+    paint("2", text)
+}
+
+/// The track that is playing right now.
+pub fn current(text: &str) -> String {
+    paint("1;32", text)
+}
+
+/// Columns `text` takes in a terminal, counting East Asian wide characters
+/// as two.
+pub fn width(text: &str) -> usize {
+    text.chars().map(|c| if is_wide(c) { 2 } else { 1 }).sum()
+}
+
+/// Pads `text` with spaces to exactly `columns`, cutting it short with `…`
+/// when it is wider.
+pub fn fit(text: &str, columns: usize) -> String {
+    if width(text) <= columns {
+        return format!("{text}{}", " ".repeat(columns - width(text)));
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = if is_wide(c) { 2 } else { 1 };
+        if used + char_width > columns - 1 {
+            break;
+        }
+        fitted.push(c);
+        used += char_width;
+    }
+    fitted.push('…');
+    used += 1;
+    fitted.push_str(&" ".repeat(columns - used));
+    fitted
+}
+
+fn is_wide(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x1100..=0x115F
+            | 0x2E80..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD
+    )
+}
@@ -0,0 +1,245 @@
+use crate::error::App;
+use crate::{open_store, style, StdResult};
+use reqwest::Client;
+use rosesong_core::settings::{Settings, SubsonicSettings};
+use rosesong_core::storage::{self, Track};
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Results asked for per search; the best of them is picked locally.
+const SEARCH_SIZE: &str = "5";
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    #[serde(rename = "subsonic-response")]
+    response: Response<T>,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    status: String,
+    #[serde(default)]
+    error: Option<ApiError>,
+    #[serde(flatten)]
+    data: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SearchData {
+    #[serde(rename = "searchResult3")]
+    result: SearchResult,
+}
+
+#[derive(Deserialize, Default)]
+struct SearchResult {
+    #[serde(default)]
+    song: Vec<Song>,
+}
+
+#[derive(Deserialize)]
+struct Song {
+    id: String,
+    title: String,
+    #[serde(default)]
+    artist: String,
+}
+
+#[derive(Deserialize)]
+struct PlaylistsData {
+    playlists: Playlists,
+}
+
+#[derive(Deserialize)]
+struct Playlists {
+    #[serde(default)]
+    playlist: Vec<RemotePlaylist>,
+}
+
+#[derive(Deserialize)]
+struct RemotePlaylist {
+    id: String,
+    name: String,
+}
+
+/// A Subsonic server, logged in with the hex-encoded password, which
+/// Navidrome and most other servers accept.
+struct Server {
+    client: Client,
+    url: String,
+    auth: Vec<(&'static str, String)>,
+}
+
+impl Server {
+    fn from_settings(subsonic: &SubsonicSettings) -> StdResult<Self> {
+        if subsonic.url.is_empty() || subsonic.user.is_empty() {
+            return Err(App::InvalidInput(
+                "请先在 settings.toml 的 [subsonic] 中设置 url、user 和 password".to_string(),
+            ));
+        }
+        let password = subsonic
+            .password
+            .bytes()
+            .fold(String::from("enc:"), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            });
+        Ok(Server {
+            client: Client::new(),
+            url: subsonic.url.trim_end_matches('/').to_string(),
+            auth: vec![
+                ("u", subsonic.user.clone()),
+                ("p", password),
+                ("v", "1.16.1".to_string()),
+                ("c", "rosesong".to_string()),
+                ("f", "json".to_string()),
+            ],
+        })
+    }
+
+    /// Calls the API method `method`, with `params` sent as a form so long
+    /// lists of songs fit.
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+    ) -> StdResult<Option<T>> {
+        let envelope: Envelope<T> = self
+            .client
+            .post(format!("{}/rest/{method}.view", self.url))
+            .query(&self.auth)
+            .form(params)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let response = envelope.response;
+        if response.status != "ok" {
+            let message = response
+                .error
+                .map_or_else(|| "未知错误".to_string(), |error| error.message);
+            return Err(App::InvalidInput(format!(
+                "Subsonic 服务器返回错误: {message}"
+            )));
+        }
+        Ok(response.data)
+    }
+
+    async fn search(&self, query: &str) -> StdResult<Vec<Song>> {
+        let params = [
+            ("query", query.to_string()),
+            ("songCount", SEARCH_SIZE.to_string()),
+            ("artistCount", "0".to_string()),
+            ("albumCount", "0".to_string()),
+        ];
+        let data: Option<SearchData> = self.call("search3", &params).await?;
+        Ok(data.map(|data| data.result.song).unwrap_or_default())
+    }
+
+    /// Makes the playlist `name` hold `songs`, replacing the one of that
+    /// name if there is one.
+    async fn save_playlist(&self, name: &str, songs: &[Song]) -> StdResult<()> {
+        let playlists: Option<PlaylistsData> = self.call("getPlaylists", &[]).await?;
+        let existing = playlists
+            .into_iter()
+            .flat_map(|data| data.playlists.playlist)
+            .find(|playlist| playlist.name == name);
+        let mut params = match existing {
+            Some(playlist) => vec![("playlistId", playlist.id)],
+            None => vec![("name", name.to_string())],
+        };
+        params.extend(songs.iter().map(|song| ("songId", song.id.clone())));
+        self.call::<serde_json::Value>("createPlaylist", &params)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Looks every track of the playlist `playlist`, or of the current one, up
+/// on the Subsonic server by title and prints which song each matches. With
+/// `create` the matched songs are saved as the server playlist `name`.
+pub async fn sync(playlist: Option<String>, name: Option<String>, create: bool) -> StdResult<()> {
+    let settings = Settings::load()?;
+    let server = Server::from_settings(&settings.subsonic)?;
+    let store = match playlist.as_deref() {
+        Some(playlist) => storage::open_named(&settings.storage, playlist)?,
+        None => open_store()?,
+    };
+    let tracks = store.load()?;
+    if tracks.is_empty() {
+        println!("播放列表为空");
+        return Ok(());
+    }
+
+    let mut matched = Vec::new();
+    for track in &tracks {
+        let song = find_song(&server, track).await?;
+        match &song {
+            Some(song) => println!(
+                "✓ {}  {} → {} - {}",
+                track.bvid,
+                style::title(&track.title),
+                song.artist,
+                song.title
+            ),
+            None => println!(
+                "{}",
+                style::dim(&format!("✗ {}  {}  未找到", track.bvid, track.title))
+            ),
+        }
+        matched.extend(song);
+    }
+    println!("\n匹配到 {}/{} 首", matched.len(), tracks.len());
+
+    if create {
+        if matched.is_empty() {
+            return Err(App::InvalidInput(
+                "没有匹配到任何歌曲，未创建歌单".to_string(),
+            ));
+        }
+        let name = name.or(playlist).unwrap_or_else(|| "rosesong".to_string());
+        server.save_playlist(&name, &matched).await?;
+        println!("已把 {} 首歌保存到 Subsonic 歌单「{name}」", matched.len());
+    }
+    Ok(())
+}
+
+/// The first search result whose title is part of the track's title or
+/// the other way round, searching by the song name the title quotes.
+async fn find_song(server: &Server, track: &Track) -> StdResult<Option<Song>> {
+    let query = song_name(&track.title);
+    if query.is_empty() {
+        return Ok(None);
+    }
+    let title = track.title.to_lowercase();
+    let query_lower = query.to_lowercase();
+    Ok(server.search(&query).await?.into_iter().find(|song| {
+        let song_title = song.title.to_lowercase();
+        !song_title.is_empty() && (title.contains(&song_title) || song_title.contains(&query_lower))
+    }))
+}
+
+/// The song name in a video title: what is quoted in 《》, or else the title
+/// without the 【】, [] and () tags uploaders add.
+fn song_name(title: &str) -> String {
+    if let Some((_, rest)) = title.split_once('《') {
+        if let Some((name, _)) = rest.split_once('》') {
+            return name.trim().to_string();
+        }
+    }
+    let mut name = String::new();
+    let mut depth = 0_usize;
+    for c in title.chars() {
+        match c {
+            '【' | '[' | '(' | '（' => depth += 1,
+            '】' | ']' | ')' | '）' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => name.push(c),
+            _ => {}
+        }
+    }
+    name.trim().to_string()
+}
@@ -0,0 +1,282 @@
+use crate::client::PlayerClient;
+use crate::ctl::{handle_key, is_quit, read_keys, KEYS_HINT};
+use crate::status::{network_line, output_line, state_name, status_line, stream_line};
+use crate::{ensure_running, format_duration, style, StdResult};
+use crossterm::{cursor, execute, terminal};
+use rosesong_core::ipc::{DebugInfo, PerfStats, Status};
+use rosesong_core::paths;
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// How much of the end of the log is shown when the dashboard opens.
+const LOG_BACKLOG_BYTES: u64 = 16 * 1024;
+const MAX_FEED_LINES: usize = 500;
+
+/// Shows a full-screen dashboard: the playback state on top and, below it,
+/// the daemon's log and the playback events seen since it opened, until `q`.
+pub async fn run_top(proxy: &PlayerClient) -> StdResult<()> {
+    ensure_running(proxy).await?;
+    let mut stdout = std::io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = top_loop(proxy).await;
+    execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+async fn top_loop(proxy: &PlayerClient) -> StdResult<()> {
+    let mut keys = read_keys();
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    let mut feed = Feed::new();
+    let mut last_status: Option<Status> = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            Some(key) = keys.recv() => {
+                if is_quit(&key) {
+                    break;
+                }
+                handle_key(proxy, &key).await?;
+            }
+        }
+        let status = proxy.status().await.ok();
+        if let Some(status) = &status {
+            feed.note_changes(last_status.as_ref(), status);
+        }
+        feed.read_log();
+        let perf = proxy.perf_stats().await.ok();
+        let debug = proxy.debug_info().await.ok();
+        draw(status.as_ref(), perf.as_ref(), debug.as_ref(), &feed)?;
+        last_status = status;
+    }
+    Ok(())
+}
+
+/// The lines under the playback state: what the daemon logs, with the
+/// playback events seen here mixed in as they happen.
+struct Feed {
+    lines: VecDeque<String>,
+    log: Option<LogTail>,
+}
+
+/// The part of the newest log file not read yet.
+struct LogTail {
+    path: PathBuf,
+    offset: u64,
+}
+
+impl Feed {
+    fn new() -> Self {
+        let mut feed = Feed {
+            lines: VecDeque::new(),
+            log: None,
+        };
+        if let Some(path) = newest_log() {
+            let length = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            let offset = length.saturating_sub(LOG_BACKLOG_BYTES);
+            feed.log = Some(LogTail { path, offset });
+            feed.read_log();
+            // The backlog most likely starts in the middle of a line
+            if offset > 0 {
+                feed.lines.pop_front();
+            }
+        } else {
+            feed.push("找不到 rosesong 的日志文件".to_string());
+        }
+        feed
+    }
+
+    fn push(&mut self, line: String) {
+        if self.lines.len() == MAX_FEED_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Adds the complete lines written to the log since the last read,
+    /// starting over when the log is rotated.
+    fn read_log(&mut self) {
+        let Some(path) = newest_log() else {
+            return;
+        };
+        let mut tail = match self.log.take() {
+            Some(tail) if tail.path == path => tail,
+            _ => LogTail { path, offset: 0 },
+        };
+        let mut text = String::new();
+        if let Ok(mut file) = std::fs::File::open(&tail.path) {
+            let length = file.metadata().map_or(0, |metadata| metadata.len());
+            if length < tail.offset {
+                tail.offset = 0;
+            }
+            if file.seek(SeekFrom::Start(tail.offset)).is_ok() {
+                let mut bytes = Vec::new();
+                let _ = file.read_to_end(&mut bytes);
+                let complete = bytes
+                    .iter()
+                    .rposition(|&byte| byte == b'\n')
+                    .map_or(0, |end| end + 1);
+                text = String::from_utf8_lossy(&bytes[..complete]).into_owned();
+                tail.offset += complete as u64;
+            }
+        }
+        self.log = Some(tail);
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            self.push(line.to_string());
+        }
+    }
+
+    /// Adds an event for each change between the `last` status and `status`
+    /// worth noting.
+    fn note_changes(&mut self, last: Option<&Status>, status: &Status) {
+        let time = chrono::Local::now().format("%H:%M:%S");
+        let track = |status: &Status| status.track.as_ref().map(|track| track.bvid.clone());
+        if let Some(track) = status
+            .track
+            .as_ref()
+            .filter(|_| last.map(track) != Some(track(status)))
+        {
+            self.push(format!(
+                "[{time}] 开始播放 {} - {}",
+                track.title, track.owner
+            ));
+        }
+        if last.map_or(true, |last| last.state != status.state) {
+            self.push(format!("[{time}] 状态: {}", state_name(&status.state)));
+        }
+        if let Some(last) = last.filter(|last| last.volume.to_bits() != status.volume.to_bits()) {
+            self.push(format!(
+                "[{time}] 音量: {} → {}",
+                percent(last.volume),
+                percent(status.volume)
+            ));
+        }
+    }
+}
+
+/// The log file the daemon wrote to last.
+fn newest_log() -> Option<PathBuf> {
+    std::fs::read_dir(paths::logs_dir().ok()?)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "log")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+fn percent(volume: f64) -> String {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let percent = (volume * 100.0).round() as u32;
+    format!("{percent}%")
+}
+
+/// The dashboard header, one entry per line.
+fn header(
+    status: Option<&Status>,
+    perf: Option<&PerfStats>,
+    debug: Option<&DebugInfo>,
+) -> Vec<String> {
+    let Some(status) = status else {
+        return vec!["rosesong 没有处于运行状态".to_string()];
+    };
+    let mut lines = vec![status_line(status)];
+    if let Some(stream) = status.stream.as_ref().filter(|_| status.track.is_some()) {
+        lines.push(match &stream.output {
+            Some(output) => format!(
+                "音频: {} → {}",
+                stream_line(stream),
+                output_line(stream, output)
+            ),
+            None => format!("音频: {}", stream_line(stream)),
+        });
+    }
+    let mode = if status.shuffle { "随机" } else { "顺序" };
+    let remaining = if status.track.is_some() && status.remaining > 0 {
+        format!("  剩余: {}", format_duration(status.remaining))
+    } else {
+        String::new()
+    };
+    lines.push(format!(
+        "音量: {}  模式: {mode}, 重复 {}  歌单: {}{remaining}",
+        percent(status.volume),
+        status.repeat,
+        status.playlist
+    ));
+    if let Some(next) = status
+        .next_track
+        .as_ref()
+        .filter(|_| status.track.is_some())
+    {
+        lines.push(format!("接下来: {} - {}", next.title, next.owner));
+    }
+    if let Some(debug) = debug {
+        lines.push(format!("网络: {}", network_line(debug)));
+    }
+    if let Some(perf) = perf {
+        lines.push(format!(
+            "切歌延迟: 获取地址 P50 {:.0} ms / P95 {:.0} ms, 开始出声 P50 {:.0} ms / P95 {:.0} ms",
+            perf.fetch.p50_ms, perf.fetch.p95_ms, perf.pipeline.p50_ms, perf.pipeline.p95_ms
+        ));
+    }
+    lines
+}
+
+/// Redraws the whole screen: the header, a rule, as much of the end of the
+/// feed as fits and the key hints on the last line.
+fn draw(
+    status: Option<&Status>,
+    perf: Option<&PerfStats>,
+    debug: Option<&DebugInfo>,
+    feed: &Feed,
+) -> StdResult<()> {
+    let (columns, rows) = terminal::size().map_or((80, 24), |(columns, rows)| {
+        (usize::from(columns), usize::from(rows))
+    });
+    let width = columns.saturating_sub(1).max(2);
+    let header = header(status, perf, debug);
+    let feed_rows = rows.saturating_sub(header.len() + 2);
+    let skipped = feed.lines.len().saturating_sub(feed_rows);
+
+    // Raw mode needs the carriage returns spelled out
+    let mut screen = String::from("\x1b[H");
+    for (index, line) in header.iter().enumerate() {
+        let line = style::fit(line, width);
+        if index == 0 {
+            screen.push_str(&style::title(&line));
+        } else {
+            screen.push_str(&line);
+        }
+        screen.push_str("\x1b[K\r\n");
+    }
+    let rule = "── 日志与事件 ";
+    let rule = format!(
+        "{rule}{}",
+        "─".repeat(width.saturating_sub(style::width(rule)))
+    );
+    screen.push_str(&style::dim(&style::fit(&rule, width)));
+    screen.push_str("\x1b[K\r\n");
+    for line in feed.lines.iter().skip(skipped) {
+        screen.push_str(&style::fit(line, width));
+        screen.push_str("\x1b[K\r\n");
+    }
+    for _ in feed.lines.len().min(feed_rows)..feed_rows {
+        screen.push_str("\x1b[K\r\n");
+    }
+    screen.push_str(&style::dim(&style::fit(KEYS_HINT, width)));
+    screen.push_str("\x1b[K");
+
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{screen}")?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use crate::bilibili::account::Account;
+use crate::bilibili::resolve::resolve_bvid;
+use crate::bilibili::video_info::{fetch_video_info, VideoInfo};
+use crate::client::PlayerClient;
+use crate::error::App;
+use crate::{ensure_running, StdResult};
+use reqwest::Client;
+use rosesong_core::settings::Settings;
+use rosesong_core::storage::TrackKind;
+
+/// Adds the video `bvid`, or the one playing, to the favorites folder named
+/// or numbered `folder`, the default folder when none is given. With `list`
+/// it only prints the folders.
+pub async fn favorite(
+    proxy: &PlayerClient,
+    bvid: Option<String>,
+    folder: Option<String>,
+    list: bool,
+) -> StdResult<()> {
+    let account = Account::from_settings(&Settings::load()?.network)?;
+    let video = target_video(proxy, bvid).await?;
+    let folders = account.folders(video.aid).await?;
+    if list {
+        for folder in &folders {
+            let marker = if folder.fav_state == 1 { "✓" } else { " " };
+            println!(
+                "{marker} {:<12} {} ({})",
+                folder.id, folder.title, folder.media_count
+            );
+        }
+        return Ok(());
+    }
+
+    let folder = match folder {
+        Some(name) => folders
+            .iter()
+            .find(|folder| folder.title == name || folder.id.to_string() == name)
+            .ok_or_else(|| App::InvalidInput(format!("没有名为「{name}」的收藏夹")))?,
+        None => folders
+            .first()
+            .ok_or_else(|| App::InvalidInput("没有找到收藏夹".to_string()))?,
+    };
+    if folder.fav_state == 1 {
+        println!("《{}》已在收藏夹「{}」中", video.title, folder.title);
+        return Ok(());
+    }
+    account.add_to_folder(video.aid, folder.id).await?;
+    println!("已将《{}》收藏到「{}」", video.title, folder.title);
+    Ok(())
+}
+
+/// Likes the video `bvid`, or the one playing, or takes the like back.
+pub async fn like(proxy: &PlayerClient, bvid: Option<String>, undo: bool) -> StdResult<()> {
+    let account = Account::from_settings(&Settings::load()?.network)?;
+    let video = target_video(proxy, bvid).await?;
+    account.like(video.aid, !undo).await?;
+    if undo {
+        println!("已取消点赞《{}》", video.title);
+    } else {
+        println!("已点赞《{}》", video.title);
+    }
+    Ok(())
+}
+
+/// Gives the video `bvid`, or the one playing, `coins` coins.
+pub async fn coin(
+    proxy: &PlayerClient,
+    bvid: Option<String>,
+    coins: u8,
+    also_like: bool,
+) -> StdResult<()> {
+    let account = Account::from_settings(&Settings::load()?.network)?;
+    let video = target_video(proxy, bvid).await?;
+    account.coin(video.aid, coins, also_like).await?;
+    let liked = if also_like { "并点赞" } else { "" };
+    println!(
+        "已给《{}》（UP 主: {}）投了 {coins} 个硬币{liked}",
+        video.title, video.owner.name
+    );
+    Ok(())
+}
+
+/// Video `bvid`, or the one playing, with the aid the account APIs take.
+async fn target_video(proxy: &PlayerClient, bvid: Option<String>) -> StdResult<VideoInfo> {
+    let client = Client::new();
+    let bvid = if let Some(bvid) = bvid {
+        resolve_bvid(&client, &bvid).await?
+    } else {
+        ensure_running(proxy).await?;
+        let track = proxy
+            .status()
+            .await?
+            .track
+            .ok_or_else(|| App::InvalidInput("当前没有正在播放的歌曲".to_string()))?;
+        if track.kind != TrackKind::Video {
+            return Err(App::InvalidInput(
+                "当前歌曲不是 B 站视频，无法操作".to_string(),
+            ));
+        }
+        track.bvid
+    };
+    fetch_video_info(&client, &bvid).await
+}
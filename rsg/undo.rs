@@ -0,0 +1,75 @@
+use crate::client::PlayerClient;
+use crate::{notify_named_playlist_change, style, StdResult};
+use rosesong_core::settings::Settings;
+use rosesong_core::storage::{self, Track, DEFAULT_PLAYLIST};
+use std::collections::HashSet;
+
+/// Takes back the last change of the playlist `playlist`, or of the current
+/// one, or makes the last undone change again with `redo`, and prints what
+/// changed.
+pub async fn step(playlist: Option<String>, redo: bool, proxy: &PlayerClient) -> StdResult<()> {
+    let settings = Settings::load()?;
+    let name = playlist.as_deref().unwrap_or(DEFAULT_PLAYLIST);
+    let step = if redo {
+        storage::redo(&settings.storage, name)?
+    } else {
+        storage::undo(&settings.storage, name)?
+    };
+    let Some((before, after)) = step else {
+        if redo {
+            println!("没有可以重做的修改");
+        } else {
+            println!("没有可以撤销的修改");
+        }
+        return Ok(());
+    };
+    println!("{}", if redo { "已重做" } else { "已撤销" });
+    print_changes(&before, &after);
+    notify_named_playlist_change(proxy, name).await
+}
+
+/// Prints which tracks `after` adds to and removes from `before`, or else
+/// whether their order or settings changed.
+pub fn print_changes(before: &[Track], after: &[Track]) {
+    let old: HashSet<&str> = before.iter().map(|track| track.bvid.as_str()).collect();
+    let new: HashSet<&str> = after.iter().map(|track| track.bvid.as_str()).collect();
+    let added: Vec<&Track> = after
+        .iter()
+        .filter(|track| !old.contains(track.bvid.as_str()))
+        .collect();
+    let removed: Vec<&Track> = before
+        .iter()
+        .filter(|track| !new.contains(track.bvid.as_str()))
+        .collect();
+    if !added.is_empty() {
+        println!("加入了 {} 首歌:", added.len());
+        for track in added {
+            println!("  + {}  {}", track.bvid, style::title(&track.title));
+        }
+    }
+    if !removed.is_empty() {
+        println!("移除了 {} 首歌:", removed.len());
+        for track in removed {
+            println!(
+                "{}",
+                style::dim(&format!("  - {}  {}", track.bvid, track.title))
+            );
+        }
+    }
+    if old == new {
+        let order = |tracks: &[Track]| {
+            tracks
+                .iter()
+                .map(|track| track.bvid.clone())
+                .collect::<Vec<_>>()
+        };
+        if order(before) != order(after) {
+            println!("调整了歌曲的顺序");
+        }
+        let changed = after.iter().filter(|track| !before.contains(track)).count();
+        if changed > 0 {
+            println!("修改了 {changed} 首歌的信息或设置");
+        }
+    }
+    println!("播放列表现有 {} 首歌", after.len());
+}
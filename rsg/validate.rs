@@ -0,0 +1,206 @@
+use crate::error::App;
+use crate::{style, StdResult};
+use rosesong_core::paths;
+use rosesong_core::settings::Settings;
+use rosesong_core::storage;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Something wrong in a file, at a 1-based line when it is known.
+struct Problem {
+    line: Option<usize>,
+    message: String,
+    hint: Option<&'static str>,
+}
+
+/// Checks `path`, or the settings and every playlist file, printing each
+/// problem with its line and a hint on fixing it. Fails when any is found.
+pub fn validate(path: Option<PathBuf>) -> StdResult<()> {
+    let files = match path {
+        Some(path) => vec![path],
+        None => default_files()?,
+    };
+    let mut problem_count = 0;
+    for file in &files {
+        let content = match std::fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) => {
+                println!("✗ {}: 无法读取: {e}", file.display());
+                problem_count += 1;
+                continue;
+            }
+        };
+        let problems = if is_settings(file) {
+            check_settings(&content)
+        } else {
+            check_playlist(&content)
+        };
+        if problems.is_empty() {
+            println!("✓ {}", file.display());
+        }
+        problem_count += problems.len();
+        for problem in &problems {
+            print_problem(file, &content, problem);
+        }
+    }
+    if problem_count > 0 {
+        return Err(App::InvalidInput(format!("共发现 {problem_count} 处问题")));
+    }
+    Ok(())
+}
+
+/// The settings file, if there is one, and the playlist files by name.
+fn default_files() -> StdResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let settings = paths::settings_file()?;
+    if settings.exists() {
+        files.push(settings);
+    }
+    let mut playlists: Vec<PathBuf> = match std::fs::read_dir(paths::playlists_dir()?) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    playlists.sort();
+    files.extend(playlists);
+    Ok(files)
+}
+
+/// Checks the playlist file `file` holding `content`, printing its problems
+/// like [`validate`]. Returns whether it has none.
+pub fn report_playlist(file: &Path, content: &str) -> bool {
+    let problems = check_playlist(content);
+    for problem in &problems {
+        print_problem(file, content, problem);
+    }
+    problems.is_empty()
+}
+
+fn is_settings(file: &Path) -> bool {
+    file.file_name().is_some_and(|name| name == "settings.toml")
+}
+
+fn check_settings(content: &str) -> Vec<Problem> {
+    match toml::from_str::<Settings>(content) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![toml_problem(content, &e)],
+    }
+}
+
+fn check_playlist(content: &str) -> Vec<Problem> {
+    let tracks = match storage::check_toml(content) {
+        Ok(tracks) => tracks,
+        Err(e) => return vec![toml_problem(content, &e)],
+    };
+    let mut problems = Vec::new();
+    if let Err(e) = storage::parse_toml(content) {
+        problems.push(Problem {
+            line: find_line(content, |line| line.starts_with("version")),
+            message: e.to_string(),
+            hint: Some("这个歌单由更新版本的 rosesong 写入，请升级 rosesong"),
+        });
+    }
+
+    // rosesong writes one `bvid = ...` line per track, in order; files
+    // written some other way only lose the line numbers
+    let bvid_lines: Vec<usize> = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with("bvid"))
+        .map(|(index, _)| index + 1)
+        .collect();
+    let line_of = |index: usize| (bvid_lines.len() == tracks.len()).then(|| bvid_lines[index]);
+    let mut seen = HashSet::new();
+    for (index, track) in tracks.iter().enumerate() {
+        if track.bvid.is_empty() || track.cid.is_empty() {
+            problems.push(Problem {
+                line: line_of(index),
+                message: "bvid 和 cid 不能为空".to_string(),
+                hint: Some("删掉这首歌，再用 rsg add 重新导入"),
+            });
+        } else if !seen.insert(&track.bvid) {
+            problems.push(Problem {
+                line: line_of(index),
+                message: format!("{} 重复出现", track.bvid),
+                hint: Some("每首歌只能出现一次，删掉重复的 [[tracks]] 段落"),
+            });
+        }
+        if track.end > 0 && track.end <= track.start {
+            problems.push(Problem {
+                line: line_of(index),
+                message: format!("{} 的 end 不晚于 start", track.bvid),
+                hint: Some("用 rsg trim 重新设置起止时间，end = 0 表示播放到结尾"),
+            });
+        }
+    }
+    problems
+}
+
+fn toml_problem(content: &str, error: &toml::de::Error) -> Problem {
+    let line = error
+        .span()
+        .and_then(|span| content.get(..span.start))
+        .map(|before| before.matches('\n').count() + 1);
+    let message = error.message().trim().to_string();
+    let hint = hint_for(&message);
+    Problem {
+        line,
+        message,
+        hint,
+    }
+}
+
+fn hint_for(message: &str) -> Option<&'static str> {
+    let hints = [
+        ("missing field", "补上缺少的字段，字段名见上面的错误信息"),
+        (
+            "invalid type",
+            "值的类型不对：字符串要加双引号（例如 cid = \"123\"），数字和 true/false 不加",
+        ),
+        ("unknown variant", "只能填写错误信息中列出的取值之一"),
+        (
+            "duplicate key",
+            "同一个表里的键只能出现一次，删掉重复的那一行",
+        ),
+        (
+            "invalid string",
+            "字符串要用成对的双引号括起来，其中的反斜杠和引号需要转义",
+        ),
+        (
+            "expected",
+            "检查这一行的引号和方括号是否成对，键和值之间是否有 =",
+        ),
+    ];
+    hints
+        .iter()
+        .find(|(pattern, _)| message.contains(pattern))
+        .map(|(_, hint)| *hint)
+}
+
+fn find_line(content: &str, matches: impl Fn(&str) -> bool) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| matches(line.trim_start()))
+        .map(|index| index + 1)
+}
+
+fn print_problem(file: &Path, content: &str, problem: &Problem) {
+    match problem.line {
+        Some(line) => {
+            println!("✗ {}:{line}: {}", file.display(), problem.message);
+            if let Some(text) = content.lines().nth(line - 1) {
+                println!("{}", style::dim(&format!("  {line:>5} | {text}")));
+            }
+        }
+        None => println!("✗ {}: {}", file.display(), problem.message),
+    }
+    if let Some(hint) = problem.hint {
+        println!("    提示: {hint}");
+    }
+}
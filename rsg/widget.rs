@@ -0,0 +1,126 @@
+use crate::client::{PlayerClient, StatusChangedStream};
+use crate::{format_duration, StdResult};
+use futures_util::StreamExt;
+use rosesong_core::ipc::Status;
+use serde_json::json;
+use std::io::Write;
+use std::time::Duration;
+
+/// How often watch mode refreshes the position between status signals.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Prints one line for status bars such as waybar or polybar. In watch mode a
+/// new line is printed whenever the rendered output changes.
+pub async fn show_widget(
+    proxy: &PlayerClient,
+    format: &str,
+    json_output: bool,
+    watch: bool,
+) -> StdResult<()> {
+    if !watch {
+        println!("{}", render(proxy, format, json_output).await);
+        return Ok(());
+    }
+
+    let mut changes = proxy.status_changes().await.ok().flatten();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    let mut stdout = std::io::stdout();
+    let mut last_line = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = next_change(&mut changes) => {}
+        }
+        let line = render(proxy, format, json_output).await;
+        if last_line.as_ref() != Some(&line) {
+            writeln!(stdout, "{line}")?;
+            stdout.flush()?;
+            last_line = Some(line);
+        }
+    }
+}
+
+async fn next_change(changes: &mut Option<StatusChangedStream<'static>>) {
+    match changes {
+        Some(stream) => {
+            if stream.next().await.is_none() {
+                *changes = None;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Renders the widget output, treating an unreachable daemon as stopped so
+/// the bar simply shows nothing.
+async fn render(proxy: &PlayerClient, format: &str, json_output: bool) -> String {
+    let status = proxy.status().await.unwrap_or_else(|_| Status {
+        state: "stopped".to_string(),
+        ..Status::default()
+    });
+    let text = if status.track.is_some() {
+        fill(format, &status)
+    } else {
+        String::new()
+    };
+    if !json_output {
+        return text;
+    }
+
+    let tooltip = if status.track.is_some() {
+        let mut tooltip = fill("{title}\n{owner}\n{position}/{duration}", &status);
+        if status.next_track.is_some() {
+            tooltip.push_str(&fill("\n接下来: {next}", &status));
+        }
+        tooltip
+    } else {
+        "RoseSong".to_string()
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let percentage = if status.duration > 0.0 {
+        (status.position / status.duration * 100.0).clamp(0.0, 100.0) as u64
+    } else {
+        0
+    };
+    json!({
+        "text": text,
+        "alt": status.state,
+        "class": status.state,
+        "tooltip": tooltip,
+        "percentage": percentage,
+    })
+    .to_string()
+}
+
+/// Replaces `{title}`, `{owner}`, `{bvid}`, `{position}`, `{duration}`,
+/// `{remaining}`, `{state}`, `{mode}`, `{playlist}` and `{next}` in
+/// `format`.
+fn fill(format: &str, status: &Status) -> String {
+    let (title, owner, bvid) = status.track.as_ref().map_or(("", "", ""), |track| {
+        (
+            track.title.as_str(),
+            track.owner.as_str(),
+            track.bvid.as_str(),
+        )
+    });
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let (position, duration) = (status.position as u64, status.duration as u64);
+    format
+        .replace("{title}", title)
+        .replace("{owner}", owner)
+        .replace("{bvid}", bvid)
+        .replace("{position}", &format_duration(position))
+        .replace("{duration}", &format_duration(duration))
+        .replace("{remaining}", &format_duration(status.remaining))
+        .replace("{state}", &status.state)
+        .replace("{mode}", &status.mode)
+        .replace("{playlist}", &status.playlist)
+        .replace("{next}", next_title(status))
+}
+
+fn next_title(status: &Status) -> &str {
+    status
+        .next_track
+        .as_ref()
+        .map_or("", |track| track.title.as_str())
+}
@@ -0,0 +1,207 @@
+//! End-to-end test of the daemon driven by `rsg` over D-Bus. The daemon
+//! runs with the `null` backend, so no sound card is needed, and with its
+//! config, cache and runtime directories in a temporary directory. It needs
+//! a session bus of its own, so it is ignored unless asked for:
+//!
+//! ```sh
+//! dbus-run-session -- cargo test --test dbus -- --ignored
+//! ```
+
+use rosesong_core::storage::{to_toml, Track};
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A daemon started with `rsg start` in a directory of its own, which is
+/// quit and removed when dropped.
+struct Session {
+    dir: PathBuf,
+}
+
+impl Session {
+    fn start() -> Self {
+        assert!(
+            std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some(),
+            "no session bus, run the test under dbus-run-session"
+        );
+        let dir = std::env::temp_dir().join(format!("rosesong-e2e-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for sub in ["config/rosesong/playlists", "cache", "run"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir.join("run"), std::fs::Permissions::from_mode(0o700))
+                .unwrap();
+        }
+        let session = Session { dir };
+        assert!(
+            !session.rsg(&["--json", "status"]).status.success(),
+            "a daemon already runs on this bus, use a bus of the test's own"
+        );
+        // The daemon inherits the output of `rsg start`, which would keep
+        // pipes open, so that goes to a file
+        let log = std::fs::File::create(session.dir.join("start.log")).unwrap();
+        let started = session
+            .command(&["start"])
+            .stdout(log.try_clone().unwrap())
+            .stderr(log)
+            .status()
+            .unwrap();
+        assert!(
+            started.success(),
+            "rsg start failed: {}",
+            std::fs::read_to_string(session.dir.join("start.log")).unwrap_or_default()
+        );
+        session
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rsg"));
+        command
+            .args(args)
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("XDG_CACHE_HOME", self.dir.join("cache"))
+            .env("XDG_RUNTIME_DIR", self.dir.join("run"))
+            .env("ROSESONG_BACKEND", "null")
+            .env_remove("ROSESONG_SOCKET");
+        command
+    }
+
+    fn rsg(&self, args: &[&str]) -> Output {
+        self.rsg_with_input(args, "")
+    }
+
+    /// Runs `rsg` with `input` typed in, for the commands that ask.
+    fn rsg_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn rsg_ok(&self, args: &[&str]) -> String {
+        self.rsg_ok_with_input(args, "")
+    }
+
+    fn rsg_ok_with_input(&self, args: &[&str], input: &str) -> String {
+        let output = self.rsg_with_input(args, input);
+        assert!(
+            output.status.success(),
+            "rsg {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    fn status(&self) -> Value {
+        serde_json::from_str(&self.rsg_ok(&["--json", "status"])).unwrap()
+    }
+
+    /// Polls the status until `done` holds for it, as commands are carried
+    /// out after `rsg` returns.
+    fn wait_for(&self, what: &str, done: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        loop {
+            let status = self.status();
+            if done(&status) {
+                return status;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for {what}: {status}"
+            );
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Imports a playlist of `bvids` through a TOML file.
+    fn import(&self, bvids: &[&str]) {
+        let tracks: Vec<Track> = bvids
+            .iter()
+            .map(|bvid| Track {
+                bvid: (*bvid).to_string(),
+                cid: "1".to_string(),
+                title: format!("title of {bvid}"),
+                owner: "up".to_string(),
+                duration: 120,
+                ..Default::default()
+            })
+            .collect();
+        let file = self.dir.join("import.toml");
+        std::fs::write(&file, to_toml(&tracks).unwrap()).unwrap();
+        self.rsg_ok(&["import", "--toml", file.to_str().unwrap()]);
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.rsg(&["quit"]);
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+#[ignore = "needs a session bus, run under dbus-run-session"]
+fn rsg_controls_the_daemon_over_dbus() {
+    let session = Session::start();
+    let status = session.status();
+    assert_eq!(status["state"], "idle");
+    assert!(status["track"].is_null());
+
+    session.rsg_ok(&["volume", "30"]);
+    session.wait_for("the volume", |status| status["volume"] == 0.3);
+
+    session.import(&["BV17x411w7KC", "BV1xx411c7mD"]);
+    session.wait_for("the imported tracks", |status| status["state"] != "idle");
+    let playlist = session.rsg_ok_with_input(&["playlist"], "q\n");
+    assert!(playlist.contains("BV17x411w7KC") && playlist.contains("BV1xx411c7mD"));
+
+    session.rsg_ok(&["mode", "--order", "shuffle", "--repeat-mode", "one"]);
+    session.wait_for("the play mode", |status| {
+        status["shuffle"] == true && status["repeat"] == "one"
+    });
+
+    // Loading fetches the stream URL from Bilibili, which fails offline;
+    // either way the player leaves the stopped state behind or says why
+    session.rsg_ok(&["play", "--bvid", "BV1xx411c7mD"]);
+    let status = session.wait_for("playback", |status| {
+        status["state"] == "playing" || !status["last_error"].is_null()
+    });
+    if status["state"] == "playing" {
+        assert_eq!(status["track"]["bvid"], "BV1xx411c7mD");
+        session.rsg_ok(&["pause"]);
+        session.wait_for("the pause", |status| status["state"] == "paused");
+        session.rsg_ok(&["stop"]);
+        session.wait_for("the stop", |status| status["state"] == "stopped");
+    }
+
+    session.rsg_ok_with_input(&["delete", "--all"], "y\n");
+    session.wait_for("the empty playlist", |status| status["state"] == "idle");
+
+    session.rsg_ok(&["quit"]);
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while session.rsg(&["--json", "status"]).status.success() {
+        assert!(Instant::now() < deadline, "the daemon did not quit");
+        thread::sleep(POLL_INTERVAL);
+    }
+}